@@ -2,6 +2,22 @@
 set -euo pipefail
 IFS=$'\n\t'
 
+# On Linux, contenant passes the host user's ids for claude to take, so files
+# the agent creates in the workspace are the host user's. This goes first:
+# the firewall rules below match claude's uid.
+if [ -n "${CONTENANT_HOST_UID:-}" ]; then
+    old_gid=$(id -g claude)
+    if [ "$CONTENANT_HOST_GID" != "$old_gid" ]; then
+        groupmod -o -g "$CONTENANT_HOST_GID" claude
+        find /home/claude -xdev -group "$old_gid" -exec chgrp -h claude {} +
+    fi
+    if [ "$CONTENANT_HOST_UID" != "$(id -u claude)" ]; then
+        # usermod hands over what claude owns in its home directory too
+        usermod -o -u "$CONTENANT_HOST_UID" claude
+    fi
+    unset CONTENANT_HOST_UID CONTENANT_HOST_GID old_gid
+fi
+
 # Preserve Docker DNS NAT rules before flushing
 DOCKER_DNS_RULES=$(iptables-save -t nat | grep "127\.0\.0\.11" || true)
 
@@ -12,7 +28,18 @@ iptables -t nat -F
 iptables -t nat -X
 iptables -t mangle -F
 iptables -t mangle -X
-ipset destroy allowed-domains 2>/dev/null || true
+for set in allowed-domains allowed-domains6 denied-domains denied-domains6; do
+    ipset destroy "$set" 2>/dev/null || true
+done
+
+# The container may have no IPv6 stack, in which case there's nothing to filter
+HAS_IPV6=false
+if ip6tables -S >/dev/null 2>&1; then
+    HAS_IPV6=true
+    ip6tables -F
+    ip6tables -X
+    ip6tables -t mangle -F
+fi
 
 # Restore Docker DNS resolution
 if [ -n "$DOCKER_DNS_RULES" ]; then
@@ -21,6 +48,38 @@ if [ -n "$DOCKER_DNS_RULES" ]; then
     echo "$DOCKER_DNS_RULES" | xargs -L 1 iptables -t nat
 fi
 
+# Load an IP list mounted by contenant into a pair of ipsets, split by family
+load_ipsets() {
+    local file=$1 set=$2
+    ipset create "$set" hash:net family inet
+    ipset create "${set}6" hash:net family inet6
+    [ -f "$file" ] || return 0
+    while IFS= read -r cidr; do
+        case "$cidr" in
+            "") ;;
+            *:*) ipset add "${set}6" "$cidr" -exist ;;
+            *) ipset add "$set" "$cidr" -exist ;;
+        esac
+    done < "$file"
+}
+load_ipsets /etc/contenant/allowed-ips allowed-domains
+load_ipsets /etc/contenant/denied-ips denied-domains
+
+# Log the agent's DNS queries and rejected packets for contenant to summarize
+# after the session. DNS is logged in mangle, before Docker's embedded DNS
+# rewrites the port.
+LOG_BLOCKED=false
+if [ -d /var/log/contenant ] && command -v tcpdump >/dev/null; then
+    LOG_BLOCKED=true
+    tcpdump -l -n -i nflog:1 > /var/log/contenant/blocked 2>/dev/null &
+    tcpdump -l -n -i nflog:2 > /var/log/contenant/dns 2>/dev/null &
+    iptables -t mangle -A OUTPUT -m owner --uid-owner claude -p udp --dport 53 -j NFLOG --nflog-group 2
+    iptables -A OUTPUT -m set --match-set denied-domains dst -j NFLOG --nflog-group 1
+fi
+
+# Denied IPs win over everything, so reject them first
+iptables -A OUTPUT -m set --match-set denied-domains dst -j REJECT --reject-with icmp-admin-prohibited
+
 # Allow DNS, SSH, and localhost before any restrictions
 iptables -A OUTPUT -p udp --dport 53 -j ACCEPT
 iptables -A INPUT -p udp --sport 53 -j ACCEPT
@@ -29,11 +88,48 @@ iptables -A INPUT -p tcp --sport 22 -m state --state ESTABLISHED -j ACCEPT
 iptables -A INPUT -i lo -j ACCEPT
 iptables -A OUTPUT -o lo -j ACCEPT
 
-# Load allowed IPs from the file mounted by contenant
-ipset create allowed-domains hash:net
-while IFS= read -r cidr; do
-    [ -n "$cidr" ] && ipset add allowed-domains "$cidr" -exist
-done < /etc/contenant/allowed-ips
+# DNS proxy: only allowlisted domains (and their subdomains) resolve, and the
+# answers are added to the ipsets so the firewall lets them through
+if [ -s /etc/contenant/dns-allowlist ]; then
+    UPSTREAM_DNS=$(awk '/^nameserver/ { print $2; exit }' /etc/resolv.conf)
+    {
+        echo "no-resolv"
+        echo "no-hosts"
+        echo "listen-address=127.0.0.1"
+        echo "bind-interfaces"
+        echo "address=/#/"
+        while IFS= read -r domain; do
+            [ -n "$domain" ] || continue
+            echo "server=/$domain/$UPSTREAM_DNS"
+            echo "ipset=/$domain/allowed-domains,allowed-domains6"
+        done < /etc/contenant/dns-allowlist
+        # Unqualified names are containers on the shared network
+        if [ -f /etc/contenant/shared-network ]; then
+            echo "server=//$UPSTREAM_DNS"
+        fi
+        # More specific than the allow entries above, so these win
+        if [ -f /etc/contenant/dns-denylist ]; then
+            while IFS= read -r domain; do
+                [ -n "$domain" ] && echo "address=/$domain/"
+            done < /etc/contenant/dns-denylist
+        fi
+    } > /run/contenant-dnsmasq.conf
+    dnsmasq --conf-file=/run/contenant-dnsmasq.conf --user=nobody --group=nogroup
+
+    # Rewrite in place: resolv.conf is a bind mount
+    echo "nameserver 127.0.0.1" > /etc/resolv.conf
+
+    # The agent may only query the proxy. Match the pre-NAT destination, since
+    # Docker's embedded DNS rewrites port 53 before the filter table sees it.
+    for proto in udp tcp; do
+        iptables -I OUTPUT 1 -m owner --uid-owner claude \
+            -m conntrack --ctproto "$proto" --ctorigdstport 53 ! --ctorigdst 127.0.0.1 -j REJECT
+    done
+    if [ "$HAS_IPV6" = true ]; then
+        ip6tables -A OUTPUT -m owner --uid-owner claude -p udp --dport 53 -j REJECT
+        ip6tables -A OUTPUT -m owner --uid-owner claude -p tcp --dport 53 -j REJECT
+    fi
+fi
 
 # Allow host network (for Docker communication)
 HOST_IP=$(ip route | grep default | cut -d" " -f3)
@@ -41,6 +137,36 @@ HOST_NETWORK=$(echo "$HOST_IP" | sed "s/\.[0-9]*$/.0\/24/")
 iptables -A INPUT -s "$HOST_NETWORK" -j ACCEPT
 iptables -A OUTPUT -d "$HOST_NETWORK" -j ACCEPT
 
+# Allow traffic within the shared network, so sidecar containers are reachable
+if [ -f /etc/contenant/shared-network ]; then
+    SHARED_DEV=$(ip route | awk '/^default/ { print $5; exit }')
+    SHARED_SUBNET=$(ip -o -f inet addr show dev "$SHARED_DEV" | awk '{ print $4; exit }')
+    iptables -A INPUT -s "$SHARED_SUBNET" -j ACCEPT
+    iptables -A OUTPUT -d "$SHARED_SUBNET" -j ACCEPT
+fi
+
+# With local network access, accept mDNS responses, which come from whichever
+# host answers rather than the multicast address the query went to
+if [ -f /etc/contenant/local-networks ]; then
+    iptables -A INPUT -p udp --sport 5353 -s 192.168.0.0/16 -j ACCEPT
+    iptables -A INPUT -p udp --sport 5353 -s 10.0.0.0/8 -j ACCEPT
+    iptables -A INPUT -p udp --sport 5353 -s 172.16.0.0/12 -j ACCEPT
+    if [ "$HAS_IPV6" = true ]; then
+        ip6tables -A INPUT -p udp --sport 5353 -s fe80::/10 -j ACCEPT
+    fi
+fi
+
+# Accept inbound connections to ports published on the host, such as dev servers
+if [ -f /etc/contenant/published-ports ]; then
+    while IFS= read -r port; do
+        [ -n "$port" ] || continue
+        iptables -A INPUT -p "${port#*/}" --dport "${port%/*}" -j ACCEPT
+        if [ "$HAS_IPV6" = true ]; then
+            ip6tables -A INPUT -p "${port#*/}" --dport "${port%/*}" -j ACCEPT
+        fi
+    done < /etc/contenant/published-ports
+fi
+
 # Default policy: drop everything
 iptables -P INPUT DROP
 iptables -P FORWARD DROP
@@ -54,7 +180,188 @@ iptables -A OUTPUT -m state --state ESTABLISHED,RELATED -j ACCEPT
 iptables -A OUTPUT -m set --match-set allowed-domains dst -j ACCEPT
 
 # Reject everything else with immediate feedback
+if [ "$LOG_BLOCKED" = true ]; then
+    iptables -A OUTPUT -j NFLOG --nflog-group 1
+fi
 iptables -A OUTPUT -j REJECT --reject-with icmp-admin-prohibited
 
-# Drop privileges and run Claude Code
-exec su -s /bin/bash claude -c "claude $*"
+# Same policy for IPv6. ICMPv6 stays open for neighbor discovery.
+if [ "$HAS_IPV6" = true ]; then
+    ip6tables -I OUTPUT 1 -m set --match-set denied-domains6 dst -j REJECT --reject-with icmp6-adm-prohibited
+    if [ "$LOG_BLOCKED" = true ]; then
+        ip6tables -I OUTPUT 1 -m set --match-set denied-domains6 dst -j NFLOG --nflog-group 1
+        ip6tables -t mangle -A OUTPUT -m owner --uid-owner claude -p udp --dport 53 -j NFLOG --nflog-group 2
+    fi
+    ip6tables -A OUTPUT -p udp --dport 53 -j ACCEPT
+    ip6tables -A INPUT -p udp --sport 53 -j ACCEPT
+    ip6tables -A OUTPUT -p tcp --dport 22 -j ACCEPT
+    ip6tables -A INPUT -p tcp --sport 22 -m state --state ESTABLISHED -j ACCEPT
+    ip6tables -A INPUT -i lo -j ACCEPT
+    ip6tables -A OUTPUT -o lo -j ACCEPT
+    ip6tables -A INPUT -p ipv6-icmp -j ACCEPT
+    ip6tables -A OUTPUT -p ipv6-icmp -j ACCEPT
+
+    ip6tables -P INPUT DROP
+    ip6tables -P FORWARD DROP
+    ip6tables -P OUTPUT DROP
+
+    ip6tables -A INPUT -m state --state ESTABLISHED,RELATED -j ACCEPT
+    ip6tables -A OUTPUT -m state --state ESTABLISHED,RELATED -j ACCEPT
+    ip6tables -A OUTPUT -m set --match-set allowed-domains6 dst -j ACCEPT
+    if [ "$LOG_BLOCKED" = true ]; then
+        ip6tables -A OUTPUT -j NFLOG --nflog-group 1
+    fi
+    ip6tables -A OUTPUT -j REJECT --reject-with icmp6-adm-prohibited
+fi
+
+# Refuse to start the agent if the firewall didn't take effect
+if ! iptables -S OUTPUT | grep -q -- "-P OUTPUT DROP"; then
+    echo "contenant: egress firewall was not applied; refusing to start" >&2
+    exit 1
+fi
+if [ "$HAS_IPV6" = true ] && ! ip6tables -S OUTPUT | grep -q -- "-P OUTPUT DROP"; then
+    echo "contenant: IPv6 egress firewall was not applied; refusing to start" >&2
+    exit 1
+fi
+
+# Cap the container's bandwidth on every interface but loopback: uploads with
+# a token bucket, downloads by dropping what arrives faster than the rate
+if [ -n "${CONTENANT_MAX_EGRESS:-}${CONTENANT_MAX_INGRESS:-}" ]; then
+    for dev in $(ip -o link show | awk -F': ' '{print $2}' | cut -d@ -f1); do
+        [ "$dev" != lo ] || continue
+        if [ -n "${CONTENANT_MAX_EGRESS:-}" ]; then
+            tc qdisc add dev "$dev" root tbf rate "$CONTENANT_MAX_EGRESS" burst 256kb latency 100ms
+        fi
+        if [ -n "${CONTENANT_MAX_INGRESS:-}" ]; then
+            tc qdisc add dev "$dev" handle ffff: ingress
+            tc filter add dev "$dev" parent ffff: protocol all u32 match u32 0 0 \
+                police rate "$CONTENANT_MAX_INGRESS" burst 256kb drop flowid :1
+        fi
+    done
+    unset CONTENANT_MAX_EGRESS CONTENANT_MAX_INGRESS
+fi
+
+# Run a command as claude with no capabilities left to regain: setpriv
+# empties the bounding and inheritable sets as it switches users, so not even
+# a setuid binary can get NET_ADMIN back to touch the firewall.
+AS_CLAUDE=(
+    env HOME=/home/claude USER=claude LOGNAME=claude SHELL=/bin/bash
+    setpriv --reuid=claude --regid=claude --init-groups
+    --inh-caps=-all --bounding-set=-all
+    -- /bin/bash
+)
+
+# For `contenant net-test`: try each target as the agent would, then exit
+if [ -f /etc/contenant/net-test ]; then
+    while IFS= read -r target; do
+        [ -n "$target" ] || continue
+        if "${AS_CLAUDE[@]}" -c 'timeout 5 bash -c "exec 3<>/dev/tcp/$1/443"' claude "$target" 2>/dev/null; then
+            echo "$target ok"
+        else
+            echo "$target blocked"
+        fi
+    done < /etc/contenant/net-test > /var/log/contenant/net-test
+    exit 0
+fi
+
+# Secrets arrive as a file each in a directory mounted for the length of
+# the run, which keeps them out of the container's config. File secrets go on
+# the tmpfs contenant mounts at /run/secrets; the rest are exported for the
+# agent to inherit.
+SECRETS_DIR=/run/contenant-secrets
+if [ -d "$SECRETS_DIR" ]; then
+    for secret in "$SECRETS_DIR"/files/*; do
+        [ -f "$secret" ] || continue
+        install -o claude -m 600 "$secret" "/run/secrets/${secret##*/}"
+    done
+    for secret in "$SECRETS_DIR"/env/*; do
+        [ -f "$secret" ] || continue
+        # Keeping any trailing newlines
+        value=$(cat "$secret"; printf x)
+        export "${secret##*/}=${value%x}"
+    done
+    unset secret value
+fi
+
+# With allow_docker, the host's Docker socket is mounted. Put claude in the
+# group that owns it, under whatever id the host uses; on Docker Desktop
+# that's root's, which is no more than the socket grants anyway.
+DOCKER_SOCKET=/var/run/docker.sock
+if [ -S "$DOCKER_SOCKET" ]; then
+    gid=$(stat -c %g "$DOCKER_SOCKET")
+    group=$(getent group "$gid" | cut -d: -f1 || true)
+    if [ -z "$group" ]; then
+        group=docker-host
+        groupadd -g "$gid" "$group" || true
+    fi
+    usermod -aG "$group" claude ||
+        echo "contenant: couldn't give the agent access to the Docker socket" >&2
+fi
+
+# Volumes over workspace.exclude paths are created owned by root
+if [ -n "${CONTENANT_WORKSPACE_EXCLUDE:-}" ]; then
+    for path in $CONTENANT_WORKSPACE_EXCLUDE; do
+        chown claude:claude "/workspace/$path"
+    done
+    unset CONTENANT_WORKSPACE_EXCLUDE
+fi
+
+# With workspace.mode: copy or sync, contenant mounts an archive of the
+# project instead of the project itself. Unpack it, and keep the directories
+# contenant hears back through out of the agent's reach. They're host
+# directories, so it's their parent in the container that's locked down.
+WORKSPACE_ARCHIVE=/etc/contenant/workspace.tar
+WORKSPACE_STATE=/var/lib/contenant
+WORKSPACE_OUT=$WORKSPACE_STATE/workspace
+WORKSPACE_SYNC=$WORKSPACE_STATE/sync
+EXEC=exec
+if [ -f "$WORKSPACE_ARCHIVE" ]; then
+    # With a read-only root, contenant mounts tmpfs at both
+    for dir in /workspace "$WORKSPACE_STATE"; do
+        if [ ! -w "$dir" ]; then
+            echo "contenant: $dir isn't writable; can't unpack the workspace copy" >&2
+            exit 1
+        fi
+    done
+    tar -xf "$WORKSPACE_ARCHIVE" -C /workspace
+    chown -R claude:claude /workspace
+    chown root:root "$WORKSPACE_STATE"
+    chmod 700 "$WORKSPACE_STATE"
+    # Stay around to hand the workspace back when the agent exits
+    EXEC=
+fi
+
+# Drop privileges and run Claude Code. The firewall must be fully configured
+# above: once running as claude, no capabilities remain to change it.
+#
+# When contenant mounts a session log, record the agent's output in it for
+# `contenant logs`: through `script` on a terminal so the agent keeps its
+# TTY, otherwise with `tee`. The log's path is passed as $0.
+SESSION_LOG=/var/log/contenant-session.log
+status=0
+if [ -f "$SESSION_LOG" ] && [ -t 0 ]; then
+    $EXEC "${AS_CLAUDE[@]}" -c 'exec script -qefc "$(printf "%q " claude "$@")" "$0"' "$SESSION_LOG" "$@" || status=$?
+elif [ -f "$SESSION_LOG" ]; then
+    $EXEC "${AS_CLAUDE[@]}" -c '
+        set -o pipefail
+        claude "$@" 2> >(tee -a "$0" >&2) | tee -a "$0"
+        status=$?
+        wait $!
+        exit $status
+    ' "$SESSION_LOG" "$@" || status=$?
+else
+    $EXEC "${AS_CLAUDE[@]}" -c 'exec claude "$@"' claude "$@" || status=$?
+fi
+
+if [ -d "$WORKSPACE_OUT" ]; then
+    # Skipping the volumes over workspace.exclude paths
+    tar --one-file-system -cf "$WORKSPACE_OUT/workspace.tar" -C /workspace .
+elif [ -d "$WORKSPACE_SYNC" ]; then
+    # Keep the container up until contenant has synced the last changes
+    touch "$WORKSPACE_SYNC/agent-exited"
+    for _ in $(seq 120); do
+        [ -f "$WORKSPACE_SYNC/flushed" ] && break
+        sleep 1
+    done
+fi
+exit "$status"
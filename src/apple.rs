@@ -0,0 +1,271 @@
+//! Backend for Apple's `container` CLI (macOS 15+), which runs each container
+//! in its own lightweight VM.
+//!
+//! Differences from Docker: bind mounts use `--mount` syntax, there's no
+//! `host-gateway` alias (the host is reached at the VM network's gateway),
+//! and Linux security modules don't apply. The egress firewall runs in the
+//! container's VM like anywhere else, but since `container` doesn't promise
+//! its kernel has netfilter and ipset or honors `NET_ADMIN`, each run first
+//! checks that a throwaway container can load rules.
+
+use std::io::IsTerminal;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::eyre::{OptionExt, Result, bail};
+use tracing::{info, warn};
+
+use crate::{Backend, BuildOptions, RunSpec, docker_build_args, logs};
+
+/// Host address as seen from containers on the default vmnet network.
+pub const DEFAULT_HOST_ADDRESS: &str = "192.168.64.1";
+
+/// Loads a drop policy and an ipset match the way the entrypoint does, then
+/// lists the rules that took effect.
+const FIREWALL_PROBE: &str = "iptables -P OUTPUT DROP && \
+    ipset create contenant-probe hash:net && \
+    iptables -A OUTPUT -m set --match-set contenant-probe dst -j ACCEPT && \
+    iptables -S OUTPUT";
+
+pub struct AppleContainer {
+    host_address: String,
+    /// The `container` CLI.
+    program: String,
+}
+
+impl Default for AppleContainer {
+    fn default() -> Self {
+        Self::with_host_address(DEFAULT_HOST_ADDRESS)
+    }
+}
+
+impl AppleContainer {
+    pub fn with_host_address(host_address: &str) -> Self {
+        Self {
+            host_address: host_address.to_string(),
+            program: "container".to_string(),
+        }
+    }
+
+    /// One that runs `program` in place of the `container` CLI.
+    #[cfg(test)]
+    pub(crate) fn with_program(program: &str) -> Self {
+        Self {
+            program: program.to_string(),
+            ..Self::default()
+        }
+    }
+
+    fn command(&self) -> Command {
+        Command::new(&self.program)
+    }
+}
+
+impl Backend for AppleContainer {
+    fn build(&self, tag: &str, path: &Path, options: &BuildOptions) -> Result<()> {
+        info!(tag, "Building image");
+
+        let path = path
+            .to_str()
+            .ok_or_eyre("build context path is not valid UTF-8")?;
+        let mut cmd = self.command();
+        cmd.args(docker_build_args(tag, path, options));
+        let status = match &options.log {
+            Some(log) => logs::tee(&mut cmd, log)?,
+            None => cmd.status()?,
+        };
+
+        if !status.success() {
+            bail!("container build failed");
+        }
+
+        Ok(())
+    }
+
+    fn tag(&self, source: &str, target: &str) -> Result<()> {
+        info!(source, target, "Tagging image");
+
+        let status = self
+            .command()
+            .args(["image", "tag", source, target])
+            .status()?;
+
+        if !status.success() {
+            bail!("container image tag failed");
+        }
+
+        Ok(())
+    }
+
+    fn run(&self, spec: &RunSpec) -> Result<i32> {
+        if !spec.security_opts().is_empty() {
+            warn!("Security options (AppArmor, SELinux, seccomp) don't apply to Apple containers");
+        }
+        if spec.network.is_some() {
+            warn!("Shared networks aren't supported for Apple containers; using the default");
+        }
+        if !spec.volumes.is_empty() {
+            warn!("Named volumes aren't supported for Apple containers; skipping them");
+        }
+
+        let mut cmd = self.command();
+        cmd.args(["run", "-i", "--rm"]);
+        if std::io::stdin().is_terminal() {
+            cmd.arg("-t");
+        }
+        if let Some(platform) = &spec.platform {
+            cmd.args(["--platform", platform]);
+        }
+        for (key, value) in &spec.labels {
+            cmd.args(["--label", &format!("{}={}", key, value)]);
+        }
+
+        for cap in spec.cap_drop() {
+            cmd.args(["--cap-drop", &cap]);
+        }
+        for cap in spec.cap_add() {
+            cmd.args(["--cap-add", &cap]);
+        }
+        if spec.read_only_root() {
+            cmd.arg("--read-only");
+        }
+        for (path, _) in spec.tmpfs() {
+            cmd.args(["--tmpfs", &path]);
+        }
+        // The container CLI takes no tmpfs options, so size and mode are
+        // the engine's defaults
+        for tmpfs in &spec.tmpfs_mounts {
+            cmd.args(["--tmpfs", &tmpfs.target]);
+        }
+        // No anonymous volumes, so excluded paths are in memory
+        for path in spec.excluded_paths() {
+            cmd.args(["--tmpfs", &path]);
+        }
+
+        for bind in spec.binds() {
+            cmd.args(["--mount", &to_mount_arg(&bind)]);
+        }
+        for port in &spec.ports {
+            cmd.args(["--publish", &port.to_string()]);
+        }
+
+        for (key, value) in &spec.env {
+            cmd.args(["-e", &format!("{}={}", key, value)]);
+        }
+        if let Some(env_file) = &spec.env_file {
+            cmd.arg("--env-file").arg(&env_file.path);
+        }
+
+        cmd.args(["-w", &spec.container_workdir(), &spec.image]);
+        cmd.args(&spec.args);
+
+        let status = cmd.status()?;
+
+        let Some(code) = status.code() else {
+            bail!("Container terminated by signal");
+        };
+
+        Ok(code)
+    }
+
+    fn host_address(&self) -> &str {
+        &self.host_address
+    }
+
+    // The entrypoint refuses to start the agent if its rules didn't load,
+    // but by then the session is set up; this finds out first
+    fn enforces_firewall(&self, image: &str) -> Result<bool> {
+        let output = self
+            .command()
+            .args([
+                "run",
+                "--rm",
+                "--cap-add",
+                "NET_ADMIN",
+                "--cap-add",
+                "NET_RAW",
+            ])
+            .args(["--entrypoint", "/bin/sh", image, "-c", FIREWALL_PROBE])
+            .output()?;
+        let rules = String::from_utf8_lossy(&output.stdout);
+        let applied = output.status.success()
+            && rules.lines().any(|rule| rule == "-P OUTPUT DROP")
+            && rules.contains("--match-set contenant-probe dst");
+        if !applied {
+            warn!(
+                stderr = %String::from_utf8_lossy(&output.stderr).trim(),
+                "The container VM couldn't load firewall rules"
+            );
+        }
+        Ok(applied)
+    }
+}
+
+/// Convert a Docker `source:target[:opts]` volume spec to `--mount` syntax.
+/// SELinux relabel options have no meaning here and are dropped.
+fn to_mount_arg(volume: &str) -> String {
+    let mut parts = volume.splitn(3, ':');
+    let source = parts.next().unwrap_or_default();
+    let target = parts.next().unwrap_or(source);
+    let readonly = parts
+        .next()
+        .is_some_and(|opts| opts.split(',').any(|o| o == "ro"));
+
+    let mut arg = format!("type=bind,source={},target={}", source, target);
+    if readonly {
+        arg.push_str(",readonly");
+    }
+    arg
+}
+
+/// A stand-in for the `container` CLI whose `run` prints `rules`, as the
+/// firewall probe would list them.
+#[cfg(test)]
+pub(crate) fn fake_container(dir: &Path, rules: &[&str]) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join("container");
+    std::fs::write(
+        &path,
+        format!(
+            "#!/bin/sh\n[ \"$1\" = run ] && cat <<'EOF'\n{}\nEOF\nexit 0\n",
+            rules.join("\n")
+        ),
+    )
+    .unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn firewall_is_enforced_only_when_the_probe_loads_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let loaded = [
+            "-P OUTPUT DROP",
+            "-A OUTPUT -m set --match-set contenant-probe dst -j ACCEPT",
+        ];
+        let apple = AppleContainer::with_program(&fake_container(dir.path(), &loaded));
+        assert!(apple.enforces_firewall("contenant:user").unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        let apple =
+            AppleContainer::with_program(&fake_container(dir.path(), &["-P OUTPUT ACCEPT"]));
+        assert!(!apple.enforces_firewall("contenant:user").unwrap());
+    }
+
+    #[test]
+    fn mount_arg_conversion() {
+        assert_eq!(
+            to_mount_arg("/host:/container"),
+            "type=bind,source=/host,target=/container"
+        );
+        assert_eq!(
+            to_mount_arg("/host:/container:ro,z"),
+            "type=bind,source=/host,target=/container,readonly"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+//! Host-side approval for bridge triggers with `confirm: true`: a prompt in
+//! the bridge's terminal when it has one, otherwise a dialog (`osascript` on
+//! macOS, `zenity` elsewhere). Anything that can't ask counts as a denial.
+
+use std::io::IsTerminal;
+use std::process::Stdio;
+
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::on_path;
+
+/// One prompt at a time, so answers go to the right request.
+static PROMPT: Mutex<()> = Mutex::const_new(());
+
+/// Ask whether to run `command` for trigger `name`, waiting for an answer.
+pub async fn approve(name: &str, command: &str, args: &[String]) -> bool {
+    let _prompt = PROMPT.lock().await;
+    let message = describe(name, command, args);
+
+    if std::io::stdin().is_terminal() {
+        return ask_terminal(message).await;
+    }
+    if cfg!(target_os = "macos") {
+        ask_osascript(&message).await
+    } else if on_path("zenity") {
+        ask_zenity(&message).await
+    } else {
+        warn!(trigger = %name, "No terminal or dialog to confirm the trigger with");
+        false
+    }
+}
+
+fn describe(name: &str, command: &str, args: &[String]) -> String {
+    let mut message = format!("Run trigger {}?\n\n  {}", name, command);
+    if !args.is_empty() {
+        message.push_str(&format!("\n\nwith arguments: {:?}", args));
+    }
+    message
+}
+
+fn approved(answer: &str) -> bool {
+    matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+}
+
+async fn ask_terminal(message: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        eprint!("{}\n[y/N] ", message);
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer).is_ok() && approved(&answer)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+async fn ask_osascript(message: &str) -> bool {
+    // The message is passed as an argument so it's never parsed as script
+    let output = Command::new("osascript")
+        .args([
+            "-e",
+            "on run argv",
+            "-e",
+            "display dialog (item 1 of argv) with title \"contenant\" \
+             buttons {\"Deny\", \"Run\"} default button \"Deny\"",
+            "-e",
+            "end run",
+            message,
+        ])
+        .stdin(Stdio::null())
+        .output()
+        .await;
+    output.is_ok_and(|output| {
+        output.status.success()
+            && String::from_utf8_lossy(&output.stdout).contains("button returned:Run")
+    })
+}
+
+async fn ask_zenity(message: &str) -> bool {
+    let status = Command::new("zenity")
+        .args([
+            "--question",
+            "--title=contenant",
+            "--no-markup",
+            "--ok-label=Run",
+            "--cancel-label=Deny",
+        ])
+        .arg(format!("--text={}", message))
+        .stdin(Stdio::null())
+        .status()
+        .await;
+    status.is_ok_and(|status| status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describes_command_and_args() {
+        assert_eq!(
+            describe("deploy", "make deploy", &[]),
+            "Run trigger deploy?\n\n  make deploy"
+        );
+        assert!(
+            describe("open", "code \"$1\"", &["a b".to_string()])
+                .ends_with("with arguments: [\"a b\"]")
+        );
+        assert!(approved("y\n"));
+        assert!(approved(" Yes "));
+        assert!(!approved("\n"));
+        assert!(!approved("no"));
+    }
+}
@@ -0,0 +1,440 @@
+//! `contenant doctor --security`: the project's effective config checked
+//! against a hardening checklist.
+//!
+//! Each [`Check`] passes, warns, or fails, and carries a weight; the score
+//! is the share of the total weight earned, with a warning worth half.
+//! Checks that don't pass say how to fix them.
+
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::config::{MountType, SecretScan, StackedConfig};
+use crate::ranges::RangeSource;
+
+/// Paths under the home directory that hold credentials or control how
+/// the host runs things.
+const SENSITIVE_HOME_PATHS: &[&str] = &[
+    ".ssh",
+    ".aws",
+    ".gnupg",
+    ".kube",
+    ".docker",
+    ".azure",
+    ".config/gcloud",
+    ".config/gh",
+    ".config/op",
+    ".git-credentials",
+    ".netrc",
+    ".npmrc",
+    ".pypirc",
+    ".bashrc",
+    ".zshrc",
+    ".profile",
+];
+
+/// Host paths the agent should never be able to write.
+const SYSTEM_PATHS: &[&str] = &[
+    "/", "/etc", "/usr", "/var", "/bin", "/sbin", "/lib", "/boot",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Pass => write!(f, "ok"),
+            Status::Warn => write!(f, "warn"),
+            Status::Fail => write!(f, "FAIL"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub weight: u32,
+    /// What the config does.
+    pub detail: String,
+    /// How to fix it, unless it passes.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn new(name: &'static str, weight: u32) -> Self {
+        Self {
+            name,
+            status: Status::Pass,
+            weight,
+            detail: String::new(),
+            hint: None,
+        }
+    }
+
+    fn pass(mut self, detail: impl Into<String>) -> Self {
+        self.detail = detail.into();
+        self
+    }
+
+    fn flag(mut self, status: Status, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        self.status = status;
+        self.detail = detail.into();
+        self.hint = Some(hint.into());
+        self
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Report {
+    /// Out of 100.
+    pub score: u32,
+    pub checks: Vec<Check>,
+}
+
+/// Check `config`, for a workspace at `workspace` and a host home directory
+/// at `home`.
+pub fn audit(config: &StackedConfig, workspace: &Path, home: &Path) -> Report {
+    let hardening = config.hardening();
+    let binds: Vec<(PathBuf, bool)> = config
+        .mounts()
+        .filter(|(mount, _)| mount.kind == MountType::Bind && !mount.source.is_empty())
+        .map(|(mount, config_dir)| (mount.host_source(config_dir), mount.readonly))
+        .collect();
+
+    let mut checks = vec![];
+
+    let check = Check::new("capabilities", 3);
+    checks.push(if hardening.drop_capabilities == Some(true) {
+        check.pass("Only the entrypoint's capabilities are granted")
+    } else {
+        check.flag(
+            Status::Warn,
+            "The container keeps Docker's default capabilities",
+            "Set hardening.drop_capabilities: true",
+        )
+    });
+
+    let check = Check::new("no-new-privileges", 2);
+    checks.push(if hardening.no_new_privileges == Some(true) {
+        check.pass("setuid binaries can't raise privileges")
+    } else {
+        check.flag(
+            Status::Warn,
+            "setuid binaries in the image can raise privileges",
+            "Set hardening.no_new_privileges: true",
+        )
+    });
+
+    let check = Check::new("read-only root", 1);
+    checks.push(if hardening.read_only_root == Some(true) {
+        check.pass("The image's filesystem is read-only")
+    } else {
+        check.flag(
+            Status::Warn,
+            "The agent can change the image's filesystem",
+            "Set hardening.read_only_root: true, with writable_paths for caches",
+        )
+    });
+
+    let socket_mounted = binds
+        .iter()
+        .any(|(source, _)| source.file_name().is_some_and(|name| name == "docker.sock"));
+    let check = Check::new("docker socket", 5);
+    checks.push(if config.allow_docker() || socket_mounted {
+        check.flag(
+            Status::Fail,
+            "The agent controls the Docker daemon, and through it the host",
+            "Unset allow_docker and remove Docker socket mounts; admins can set forbid_docker",
+        )
+    } else {
+        check.pass("The Docker socket isn't mounted")
+    });
+
+    checks.push(network_check(config));
+    checks.push(home_check(workspace, home, &binds));
+    checks.push(writable_check(workspace, home, &binds));
+
+    let check = Check::new("ssh agent", 2);
+    checks.push(if config.ssh_agent().forward == Some(true) {
+        check.flag(
+            Status::Warn,
+            "The agent can use every key in the forwarded SSH agent",
+            "Forward an agent holding only the keys it needs with ssh_agent.socket",
+        )
+    } else {
+        check.pass("The SSH agent isn't forwarded")
+    });
+
+    let check = Check::new("secret scanning", 1);
+    checks.push(match config.secret_scan() {
+        SecretScan::Block => check.pass("Runs are refused when credentials are found"),
+        SecretScan::Warn => check.flag(
+            Status::Warn,
+            "Credentials found in mounts are only reported",
+            "Set secret_scan: block",
+        ),
+        SecretScan::Off => check.flag(
+            Status::Warn,
+            "Mounted paths aren't checked for credentials",
+            "Set secret_scan: block",
+        ),
+    });
+
+    let check = Check::new("process limits", 1);
+    checks.push(if config.security().limits.pids.is_some() {
+        check.pass("The container's process count is capped")
+    } else {
+        check.flag(
+            Status::Warn,
+            "A fork bomb can exhaust the host's processes",
+            "Set security.limits.pids",
+        )
+    });
+
+    let total: u32 = checks.iter().map(|c| c.weight * 2).sum();
+    let earned: u32 = checks
+        .iter()
+        .map(|c| match c.status {
+            Status::Pass => c.weight * 2,
+            Status::Warn => c.weight,
+            Status::Fail => 0,
+        })
+        .sum();
+    Report {
+        score: (earned * 100 + total / 2) / total,
+        checks,
+    }
+}
+
+fn network_check(config: &StackedConfig) -> Check {
+    let check = Check::new("network", 4);
+    // A wildcard over a whole TLD, or everything, is no allowlist at all
+    let open: Vec<String> = config
+        .allowed_domains()
+        .into_iter()
+        .filter(|domain| {
+            domain == "*"
+                || domain
+                    .strip_prefix("*.")
+                    .is_some_and(|rest| !rest.contains('.'))
+        })
+        .collect();
+    if !open.is_empty() {
+        return check.flag(
+            Status::Fail,
+            format!("allowed_domains lets anything through: {}", open.join(", ")),
+            "List the domains the agent needs",
+        );
+    }
+    let mut broad = vec![];
+    if config.allow_local() {
+        broad.push("the local network (network.allow_local)".to_string());
+    }
+    for source in config.ip_ranges() {
+        match source {
+            RangeSource::Cloudflare => broad.push("all of Cloudflare's ranges".to_string()),
+            RangeSource::Fastly => broad.push("all of Fastly's ranges".to_string()),
+            RangeSource::Aws { services, regions } if services.is_empty() && regions.is_empty() => {
+                broad.push("all of AWS's ranges".to_string())
+            }
+            _ => {}
+        }
+    }
+    if broad.is_empty() {
+        check.pass("Egress is limited to the allowed domains")
+    } else {
+        check.flag(
+            Status::Warn,
+            format!("The firewall allows {}", broad.join(", ")),
+            "Narrow ip_ranges with services or regions, and turn off network.allow_local",
+        )
+    }
+}
+
+/// Whether `source` holds or is in a sensitive path under `home`.
+fn sensitive(source: &Path, home: &Path) -> bool {
+    SENSITIVE_HOME_PATHS.iter().any(|path| {
+        let path = home.join(path);
+        source.starts_with(&path) || path.starts_with(source)
+    })
+}
+
+fn home_check(workspace: &Path, home: &Path, binds: &[(PathBuf, bool)]) -> Check {
+    let check = Check::new("home mounts", 5);
+    if home.starts_with(workspace) {
+        return check.flag(
+            Status::Fail,
+            format!(
+                "The workspace, {}, contains your home directory",
+                workspace.display()
+            ),
+            "Run contenant from a project directory, or set workspace.path",
+        );
+    }
+    let whole: Vec<_> = binds
+        .iter()
+        .filter(|(source, _)| home.starts_with(source))
+        .map(|(source, _)| source.display().to_string())
+        .collect();
+    if !whole.is_empty() {
+        return check.flag(
+            Status::Fail,
+            format!("Your home directory is mounted: {}", whole.join(", ")),
+            "Mount only the files the agent needs",
+        );
+    }
+    let credentials: Vec<_> = binds
+        .iter()
+        .filter(|(source, _)| sensitive(source, home))
+        .map(|(source, _)| source.display().to_string())
+        .collect();
+    if credentials.is_empty() {
+        check.pass("Nothing sensitive in your home directory is mounted")
+    } else {
+        check.flag(
+            Status::Warn,
+            format!(
+                "Credentials or shell config are mounted: {}",
+                credentials.join(", ")
+            ),
+            "Pass credentials with secrets, or mount only what's needed",
+        )
+    }
+}
+
+fn writable_check(workspace: &Path, home: &Path, binds: &[(PathBuf, bool)]) -> Check {
+    let check = Check::new("writable mounts", 3);
+    let writable: Vec<&PathBuf> = binds
+        .iter()
+        .filter(|(_, readonly)| !readonly)
+        .map(|(source, _)| source)
+        .collect();
+    let dangerous: Vec<_> = writable
+        .iter()
+        .filter(|source| {
+            sensitive(source, home)
+                || SYSTEM_PATHS
+                    .iter()
+                    .any(|path| source.as_path() == Path::new(path))
+        })
+        .map(|source| source.display().to_string())
+        .collect();
+    if !dangerous.is_empty() {
+        return check.flag(
+            Status::Fail,
+            format!("The agent can write to {}", dangerous.join(", ")),
+            "Mount them read-only",
+        );
+    }
+    let outside: Vec<_> = writable
+        .iter()
+        .filter(|source| !source.starts_with(workspace))
+        .map(|source| source.display().to_string())
+        .collect();
+    if outside.is_empty() {
+        check.pass("Only the workspace is writable")
+    } else {
+        check.flag(
+            Status::Warn,
+            format!(
+                "The agent can write outside the workspace: {}",
+                outside.join(", ")
+            ),
+            "Mount them read-only unless the agent needs to change them",
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ConfigSource;
+
+    fn config(yaml: &str) -> StackedConfig {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str(yaml).unwrap(),
+            PathBuf::from("/home/me/project/.contenant"),
+        );
+        config
+    }
+
+    fn status(report: &Report, name: &str) -> Status {
+        report
+            .checks
+            .iter()
+            .find(|c| c.name == name)
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn a_hardened_config_scores_full_marks() {
+        let report = audit(
+            &config(
+                r#"
+hardening:
+  drop_capabilities: true
+  no_new_privileges: true
+  read_only_root: true
+secret_scan: block
+security:
+  limits:
+    pids: 512
+mounts:
+  - source: /home/me/.cache/pip
+    target: /home/claude/.cache/pip
+"#,
+            ),
+            Path::new("/home/me/project"),
+            Path::new("/home/me"),
+        );
+        assert!(
+            report.checks.iter().all(|c| c.status == Status::Pass),
+            "{:?}",
+            report
+        );
+        assert_eq!(report.score, 100);
+    }
+
+    #[test]
+    fn flags_sockets_home_mounts_and_open_networks() {
+        let home = Path::new("/home/me");
+        let report = audit(
+            &config(
+                r#"
+allow_docker: true
+allowed_domains: ["*.com"]
+mounts:
+  - source: /home/me/.aws
+    readonly: false
+"#,
+            ),
+            Path::new("/home/me/project"),
+            home,
+        );
+        assert_eq!(status(&report, "docker socket"), Status::Fail);
+        assert_eq!(status(&report, "network"), Status::Fail);
+        assert_eq!(status(&report, "home mounts"), Status::Warn);
+        assert_eq!(status(&report, "writable mounts"), Status::Fail);
+        assert!(report.score < 50, "{}", report.score);
+
+        let report = audit(&config("{}"), home, home);
+        assert_eq!(status(&report, "home mounts"), Status::Fail);
+
+        let report = audit(
+            &config("mounts:\n  - source: /home\n"),
+            Path::new("/home/me/project"),
+            home,
+        );
+        assert_eq!(status(&report, "home mounts"), Status::Fail);
+    }
+}
@@ -0,0 +1,217 @@
+//! Short-lived AWS credentials from the host's SSO login.
+//!
+//! With `aws.profile`, `contenant run` exports the profile's session
+//! credentials with `aws configure export-credentials`, running
+//! `aws sso login` first if the SSO session has expired, and mounts an AWS
+//! config for the same profile at [`CONTAINER_DIR`]. When the bridge is
+//! running, that config's `credential_process` is [`HELPER`], which asks the
+//! bridge for fresh credentials whenever the SDK finds them near expiry.
+//! Without the bridge, the exported credentials go in a credentials file
+//! instead and last until they expire.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr, bail, eyre};
+use serde::Deserialize;
+
+/// Where each run's AWS config is mounted.
+pub const CONTAINER_DIR: &str = "/run/contenant-aws";
+
+/// The `credential_process` baked into the image.
+pub const HELPER: &str = "/usr/local/bin/contenant-aws-credentials";
+
+/// State subdirectory holding each run's AWS config.
+pub const RUNS_DIR: &str = "aws-runs";
+
+/// `aws configure export-credentials --format process` output, which is
+/// also what `credential_process` prints.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    #[serde(default)]
+    pub session_token: Option<String>,
+    #[serde(default)]
+    pub expiration: Option<String>,
+}
+
+/// The profile's credentials as `credential_process` JSON, logging in
+/// through the browser first if the SSO session has expired.
+pub fn export(profile: &str) -> Result<String> {
+    if let Some(json) = export_credentials(profile)? {
+        return Ok(json);
+    }
+    eprintln!(
+        "contenant: AWS profile {} isn't logged in; running aws sso login",
+        profile
+    );
+    let status = Command::new("aws")
+        .args(["sso", "login", "--profile", profile])
+        .stdin(Stdio::null())
+        .status()
+        .wrap_err("Couldn't run aws sso login")?;
+    if !status.success() {
+        bail!("aws sso login --profile {} failed", profile);
+    }
+    export_credentials(profile)?
+        .ok_or_else(|| eyre!("Couldn't export credentials for AWS profile {}", profile))
+}
+
+/// `None` if the CLI runs but can't export, e.g. when logged out.
+fn export_credentials(profile: &str) -> Result<Option<String>> {
+    let output = Command::new("aws")
+        .args(["configure", "export-credentials", "--format", "process"])
+        .args(["--profile", profile])
+        .stderr(Stdio::null())
+        .output()
+        .wrap_err("Couldn't run the AWS CLI; install it or unset aws.profile")?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let json = String::from_utf8(output.stdout)?.trim().to_string();
+    if let Ok(credentials) = serde_json::from_str::<Credentials>(&json) {
+        crate::redact::register(&credentials.secret_access_key);
+        if let Some(token) = &credentials.session_token {
+            crate::redact::register(token);
+        }
+    }
+    Ok(Some(json))
+}
+
+/// The profile's region, if it has one.
+pub fn region(profile: &str) -> Option<String> {
+    let output = Command::new("aws")
+        .args(["configure", "get", "region", "--profile", profile])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let region = String::from_utf8(output.stdout).ok()?.trim().to_string();
+    (output.status.success() && !region.is_empty()).then_some(region)
+}
+
+/// One run's AWS config, removed when this is dropped.
+pub struct RunConfig {
+    pub dir: PathBuf,
+    profile: String,
+    credentials: bool,
+}
+
+impl RunConfig {
+    /// Write the config for `profile` under `runs_dir`. With `credentials`,
+    /// they're written to a credentials file; otherwise the config asks
+    /// [`HELPER`] for them.
+    pub fn write(
+        runs_dir: &Path,
+        profile: &str,
+        region: Option<&str>,
+        credentials: Option<&str>,
+    ) -> Result<Self> {
+        let dir = runs_dir.join(crate::bridge::random_hex()?);
+        fs::create_dir_all(&dir)?;
+        // Readable by the container user, whose uid may not match ours
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        let run = Self {
+            dir,
+            profile: profile.to_string(),
+            credentials: credentials.is_some(),
+        };
+
+        let mut config = match profile {
+            "default" => "[default]\n".to_string(),
+            _ => format!("[profile {}]\n", profile),
+        };
+        if credentials.is_none() {
+            config.push_str(&format!("credential_process = {}\n", HELPER));
+        }
+        if let Some(region) = region {
+            config.push_str(&format!("region = {}\n", region));
+        }
+        fs::write(run.dir.join("config"), config)?;
+
+        if let Some(credentials) = credentials {
+            let credentials: Credentials = serde_json::from_str(credentials)
+                .wrap_err("The AWS CLI exported credentials contenant couldn't read")?;
+            let mut file = format!(
+                "[{}]\naws_access_key_id = {}\naws_secret_access_key = {}\n",
+                profile, credentials.access_key_id, credentials.secret_access_key
+            );
+            if let Some(token) = &credentials.session_token {
+                file.push_str(&format!("aws_session_token = {}\n", token));
+            }
+            let path = run.dir.join("credentials");
+            fs::write(&path, file)?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+        }
+        Ok(run)
+    }
+
+    /// Variables pointing the SDKs and CLI at the mounted files.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            (
+                "AWS_CONFIG_FILE".to_string(),
+                format!("{}/config", CONTAINER_DIR),
+            ),
+            ("AWS_PROFILE".to_string(), self.profile.clone()),
+        ];
+        if self.credentials {
+            env.push((
+                "AWS_SHARED_CREDENTIALS_FILE".to_string(),
+                format!("{}/credentials", CONTAINER_DIR),
+            ));
+        }
+        env
+    }
+}
+
+impl Drop for RunConfig {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPORTED: &str = r#"{"Version": 1, "AccessKeyId": "ASIAEXAMPLE", "SecretAccessKey": "secret", "SessionToken": "token", "Expiration": "2026-10-16T12:00:00+00:00"}"#;
+
+    #[test]
+    fn asks_the_helper_for_credentials_or_writes_them_down() {
+        let runs = tempfile::tempdir().unwrap();
+
+        let run = RunConfig::write(runs.path(), "dev", Some("us-east-1"), None).unwrap();
+        assert_eq!(
+            fs::read_to_string(run.dir.join("config")).unwrap(),
+            format!(
+                "[profile dev]\ncredential_process = {}\nregion = us-east-1\n",
+                HELPER
+            )
+        );
+        assert!(!run.dir.join("credentials").exists());
+        assert_eq!(run.env().len(), 2);
+        let dir = run.dir.clone();
+        drop(run);
+        assert!(!dir.exists());
+
+        let run = RunConfig::write(runs.path(), "default", None, Some(EXPORTED)).unwrap();
+        assert_eq!(
+            fs::read_to_string(run.dir.join("config")).unwrap(),
+            "[default]\n"
+        );
+        assert_eq!(
+            fs::read_to_string(run.dir.join("credentials")).unwrap(),
+            "[default]\naws_access_key_id = ASIAEXAMPLE\naws_secret_access_key = secret\naws_session_token = token\n"
+        );
+        assert!(run.env().contains(&(
+            "AWS_SHARED_CREDENTIALS_FILE".to_string(),
+            "/run/contenant-aws/credentials".to_string()
+        )));
+
+        assert!(RunConfig::write(runs.path(), "dev", None, Some("{}")).is_err());
+    }
+}
@@ -1,33 +1,756 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::fs;
+use std::future::IntoFuture;
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, PathBuf};
 use std::process::Stdio;
 use std::sync::Arc;
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
 
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::body::Body;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
 use axum::{Json, Router};
-use color_eyre::eyre::Result;
-use serde::Serialize;
-use tokio::net::TcpListener;
-use tokio::process::Command;
-use tracing::info;
-
-pub async fn serve(port: u16, triggers: HashMap<String, String>) -> Result<()> {
-    let app = Router::new()
-        .route("/triggers/{name}", axum::routing::post(trigger))
-        .with_state(Arc::new(triggers));
+use color_eyre::eyre::{Result, WrapErr, bail};
+use futures_util::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::process::{Child, Command};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tracing::{info, warn};
+
+use crate::approval;
+use crate::aws;
+use crate::clipboard::Clipboard;
+use crate::config::{BridgeConfig, Trigger, TriggerCommand};
+use crate::github;
+use crate::host_events::{self, EventLog, HostEvent, HostEventKind};
+use crate::tls::BridgeTls;
+use crate::webhook::{self, Webhooks};
+
+/// Trigger invoked on the host when a queued task finishes.
+pub const NOTIFY_TRIGGER: &str = "notify";
+
+/// State subdirectory holding hashes of the tokens of running sessions.
+pub const TOKENS_DIR: &str = "bridge-tokens";
+
+/// State subdirectory holding the bridge socket, mounted into containers at
+/// [`CONTAINER_SOCKET_DIR`].
+pub const SOCKET_DIR: &str = "bridge";
+pub const SOCKET_NAME: &str = "bridge.sock";
+pub const CONTAINER_SOCKET_DIR: &str = "/run/contenant";
+
+/// State file holding the key `contenant run` registers projects with,
+/// rewritten each time the bridge starts.
+pub const REGISTRATION_KEY_FILE: &str = "bridge-registration-key";
+
+const DEFAULT_ARTIFACTS_MAX_MB: u64 = 100;
+
+/// How long `GET /events` waits for an event by default, and at most.
+const DEFAULT_EVENTS_TIMEOUT_SECS: u64 = 30;
+const MAX_EVENTS_TIMEOUT_SECS: u64 = 60;
+
+pub enum Listen {
+    /// TCP on `bridge.address` (127.0.0.1 by default), reached via the
+    /// backend's host address.
+    Tcp(SocketAddr),
+    /// A unix socket, reached through a bind mount.
+    Unix(PathBuf),
+    /// HTTPS on the address, terminated by `socat` and relayed to a unix
+    /// socket only we can reach.
+    Tls(SocketAddr, BridgeTls),
+}
+
+impl Listen {
+    pub fn from_config(config: &BridgeConfig, app_dirs: &xdg::BaseDirectories) -> Result<Self> {
+        if config.socket.unwrap_or(false) {
+            let socket_dir = app_dirs.create_state_directory(SOCKET_DIR)?;
+            return Ok(Listen::Unix(socket_dir.join(SOCKET_NAME)));
+        }
+        let ip: IpAddr = match &config.address {
+            Some(address) => address
+                .parse()
+                .wrap_err_with(|| format!("Invalid bridge.address {:?}", address))?,
+            None => Ipv4Addr::LOCALHOST.into(),
+        };
+        let addr = SocketAddr::new(ip, config.port);
+        Ok(if config.uses_tls() {
+            Listen::Tls(addr, BridgeTls::open(config, app_dirs)?)
+        } else {
+            Listen::Tcp(addr)
+        })
+    }
+}
+
+/// Serve the bridge, accepting requests bearing a token issued into
+/// `tokens_dir` by a running session. Projects register their triggers
+/// with the key written to `key_path`.
+pub async fn serve(
+    listen: Listen,
+    config: BridgeConfig,
+    tokens_dir: PathBuf,
+    key_path: PathBuf,
+) -> Result<()> {
+    if let Listen::Tls(_, tls) = &listen {
+        if !crate::on_path("socat") {
+            bail!("bridge.tls needs socat on PATH to serve TLS");
+        }
+        tls.ensure_server_cert(&config)?;
+    }
+    let key = random_hex()?;
+    crate::redact::register(&key);
+    fs::write(&key_path, &key)?;
+    fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600))?;
 
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    let listener = TcpListener::bind(addr).await?;
-    info!(%addr, "Bridge server listening");
+    let webhooks = Arc::new(Webhooks {
+        urls: config.webhooks,
+        secret: config.webhook_secret,
+    });
+    let projects = Arc::new(Projects {
+        webhooks: webhooks.clone(),
+        github: github::Tokens::new(config.github_app),
+        ..Default::default()
+    });
+    let mut app = Router::new()
+        .route(
+            "/projects/{id}/triggers/{name}",
+            axum::routing::post(project_trigger),
+        )
+        .route(
+            "/projects/{id}/triggers/{name}/stream",
+            axum::routing::post(project_trigger_stream),
+        )
+        .route(
+            "/projects/{id}/triggers",
+            axum::routing::get(list_project_triggers),
+        )
+        .route("/projects/{id}/events", axum::routing::get(project_events))
+        .route(
+            "/projects/{id}/git-credential",
+            axum::routing::post(git_credential),
+        )
+        .route(
+            "/projects/{id}/aws-credentials",
+            axum::routing::get(aws_credentials),
+        )
+        .with_state(projects.clone())
+        .route("/triggers", axum::routing::get(list_triggers))
+        .route("/triggers/{name}", axum::routing::post(trigger))
+        .route(
+            "/triggers/{name}/stream",
+            axum::routing::post(trigger_stream),
+        )
+        .with_state(Arc::new(
+            Triggers::new(config.triggers).with_webhooks(None, webhooks),
+        ))
+        .route("/open-url", axum::routing::post(open_url))
+        .with_state(Arc::new(config.open_urls));
+    if let Some(dir) = config.artifacts_dir {
+        let max_mb = config.artifacts_max_mb.unwrap_or(DEFAULT_ARTIFACTS_MAX_MB);
+        app = app.merge(
+            Router::new()
+                .route("/artifacts/{*path}", axum::routing::put(upload_artifact))
+                .with_state(Arc::new(Artifacts {
+                    dir: PathBuf::from(dir),
+                    max_bytes: max_mb * 1024 * 1024,
+                })),
+        );
+    }
+    if config.clipboard.unwrap_or(false) {
+        app = app.route(
+            "/clipboard",
+            axum::routing::get(clipboard_read).post(clipboard_write),
+        );
+    }
+    let app = app
+        .route_layer(middleware::from_fn_with_state(
+            Arc::new(tokens_dir),
+            require_token,
+        ))
+        // Session tokens can't register commands; only the host can
+        .merge(
+            Router::new()
+                .route(
+                    "/internal/projects/{id}",
+                    axum::routing::put(accept_registration),
+                )
+                .route(
+                    "/internal/projects/{id}/events",
+                    axum::routing::post(push_event),
+                )
+                .route("/internal/events", axum::routing::post(forward_event))
+                .route_layer(middleware::from_fn_with_state(
+                    Arc::new(key),
+                    require_registration_key,
+                ))
+                .with_state(projects),
+        );
 
-    axum::serve(listener, app).await?;
+    match listen {
+        Listen::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            info!(%addr, "Bridge server listening");
+            axum::serve(listener, app).await?;
+        }
+        Listen::Unix(path) => {
+            // Left behind if a previous bridge didn't shut down cleanly
+            let _ = fs::remove_file(&path);
+            let listener = UnixListener::bind(&path)?;
+            // The container user's uid may not match ours; access is limited
+            // by the mount and the token instead
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o666))?;
+            info!(path = %path.display(), "Bridge server listening");
+            axum::serve(listener, app).await?;
+        }
+        Listen::Tls(addr, tls) => {
+            let path = tls.plain_socket();
+            let _ = fs::remove_file(&path);
+            let listener = UnixListener::bind(&path)?;
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+            let mut relay = tls_relay(addr, &tls)?;
+            info!(%addr, client_certs = tls.client_certs, "Bridge server listening with TLS");
+            tokio::select! {
+                result = axum::serve(listener, app).into_future() => result?,
+                status = relay.wait() => bail!("socat stopped serving TLS: {}", status?),
+            }
+        }
+    }
 
     Ok(())
 }
 
+/// Start `socat` accepting TLS on `addr` and relaying each connection to the
+/// bridge's plain socket, checking client certificates against the CA if
+/// required.
+fn tls_relay(addr: SocketAddr, tls: &BridgeTls) -> Result<Child> {
+    let family = if addr.is_ipv6() { ",pf=ip6" } else { "" };
+    let listen = format!(
+        "OPENSSL-LISTEN:{},bind={},reuseaddr,fork{},cert={},key={},cafile={},verify={}",
+        addr.port(),
+        addr.ip(),
+        family,
+        tls.server_cert().display(),
+        tls.server_key().display(),
+        tls.ca_cert().display(),
+        u8::from(tls.client_certs),
+    );
+    Ok(Command::new("socat")
+        .arg(listen)
+        .arg(format!("UNIX-CONNECT:{}", tls.plain_socket().display()))
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()?)
+}
+
+/// Run a trigger command synchronously on the host with extra env vars,
+/// for host-side callers that don't go through the HTTP server.
+pub fn run_trigger_blocking(trigger: &Trigger, name: &str, env: &[(&str, String)]) -> Result<i32> {
+    info!(trigger = %name, command = %trigger.command, "Executing trigger");
+
+    let status = trigger_command(trigger, name, &[])
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .envs(&trigger.env)
+        .stdin(Stdio::null())
+        .status()?;
+
+    Ok(status.code().unwrap_or(-1))
+}
+
+/// The trigger's command line in its `cwd`. `args` become `$1..` of a shell
+/// script, whose `$0` is the trigger name, or are appended to an argv.
+fn trigger_command(trigger: &Trigger, name: &str, args: &[String]) -> std::process::Command {
+    let mut command = match &trigger.command {
+        TriggerCommand::Shell(script) => {
+            let mut command = std::process::Command::new("sh");
+            command.arg("-c").arg(script).arg(name);
+            command
+        }
+        TriggerCommand::Exec(argv) => {
+            let (program, rest) = argv.split_first().map_or(("", &[][..]), |(p, r)| (p, r));
+            let mut command = std::process::Command::new(program);
+            command.args(rest);
+            command
+        }
+    };
+    command.args(args);
+    if let Some(cwd) = &trigger.cwd {
+        command.current_dir(cwd);
+    }
+    command
+}
+
+/// Host-side access to a running bridge's internal endpoints, with the key
+/// it wrote at startup.
+pub struct BridgeClient {
+    listen: Listen,
+    key: String,
+}
+
+impl BridgeClient {
+    /// A client for the bridge, or `None` if one has never been started.
+    pub fn connect(config: &BridgeConfig, app_dirs: &xdg::BaseDirectories) -> Result<Option<Self>> {
+        let Some(key_path) = app_dirs
+            .get_state_file(REGISTRATION_KEY_FILE)
+            .filter(|p| p.exists())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            listen: Listen::from_config(config, app_dirs)?,
+            key: fs::read_to_string(key_path)?.trim().to_string(),
+        }))
+    }
+
+    /// Register a project's triggers, so its sessions can call them under
+    /// `/projects/{id}/triggers`, the directory to watch for changes, if
+    /// any, the GitHub repos its sessions may get tokens for, and the AWS
+    /// profile they get credentials from.
+    pub fn register_project(
+        &self,
+        project_id: &str,
+        triggers: &HashMap<String, Trigger>,
+        watch: Option<&std::path::Path>,
+        github_repos: &[String],
+        aws_profile: Option<&str>,
+    ) -> Result<()> {
+        let path = format!("/internal/projects/{}", encode_segment(project_id));
+        let registration = Registration {
+            triggers: triggers.clone(),
+            watch: watch.map(|p| p.to_path_buf()),
+            github_repos: github_repos.to_vec(),
+            aws_profile: aws_profile.map(str::to_string),
+        };
+        self.request("PUT", &path, &serde_json::to_vec(&registration)?)
+    }
+
+    /// Queue an event for a project's sessions to pick up from `/events`.
+    pub fn push_event(&self, project_id: &str, event: &HostEventKind) -> Result<()> {
+        let path = format!("/internal/projects/{}/events", encode_segment(project_id));
+        self.request("POST", &path, &serde_json::to_vec(event)?)
+    }
+
+    /// Hand an event to the bridge to forward to its webhooks.
+    pub fn send_event(&self, event: &webhook::Event) -> Result<()> {
+        self.request("POST", "/internal/events", &serde_json::to_vec(event)?)
+    }
+
+    fn request(&self, method: &str, path: &str, body: &[u8]) -> Result<()> {
+        let mut curl = std::process::Command::new("curl");
+        curl.args([
+            "-fsS",
+            "--max-time",
+            "5",
+            "-X",
+            method,
+            "--data-binary",
+            "@-",
+        ])
+        .args(["-H", "Content-Type: application/json"])
+        .arg("-H")
+        .arg(format!("Authorization: Bearer {}", self.key));
+        let base = match &self.listen {
+            Listen::Tcp(addr) => {
+                let ip = match addr.ip() {
+                    IpAddr::V4(ip) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+                    IpAddr::V6(ip) if ip.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+                    ip => ip,
+                };
+                format!("http://{}", SocketAddr::new(ip, addr.port()))
+            }
+            Listen::Unix(socket) => {
+                curl.arg("--unix-socket").arg(socket);
+                "http://localhost".to_string()
+            }
+            // The plain socket behind TLS is ours, so skip the certificates
+            Listen::Tls(_, tls) => {
+                curl.arg("--unix-socket").arg(tls.plain_socket());
+                "http://localhost".to_string()
+            }
+        };
+        let mut child = curl
+            .arg(format!("{}{}", base, path))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            use std::io::Write;
+            stdin.write_all(body)?;
+        }
+        if !child.wait()?.success() {
+            bail!("Bridge didn't accept {} {}", method, path);
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encode a URL path segment.
+pub fn encode_segment(segment: &str) -> String {
+    segment
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+pub(crate) fn random_hex() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// A bearer token for one session, valid while this value is alive.
+///
+/// Only a hash of the token is written to the tokens directory, so reading
+/// the directory doesn't reveal usable tokens. The file holds the session's
+/// project id, limiting the token to that project's triggers.
+pub struct BridgeToken {
+    pub value: String,
+    path: PathBuf,
+}
+
+impl BridgeToken {
+    pub fn issue(tokens_dir: &std::path::Path, project_id: &str) -> Result<Self> {
+        let value = random_hex()?;
+        crate::redact::register(&value);
+        let path = tokens_dir.join(token_hash(&value));
+        fs::write(&path, project_id)?;
+        Ok(Self { value, path })
+    }
+
+    /// The file that makes the token valid while it exists.
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+impl Drop for BridgeToken {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// The project of a session's token, or `None` if it isn't valid.
+fn token_project(tokens_dir: &std::path::Path, token: &str) -> Option<String> {
+    fs::read_to_string(tokens_dir.join(token_hash(token))).ok()
+}
+
+/// Whether a token for `project` may request `path`: anything outside
+/// `/projects`, and only its own project's routes inside.
+fn token_allows(project: &str, path: &str) -> bool {
+    match path.strip_prefix("/projects/") {
+        Some(rest) => rest.split('/').next() == Some(&encode_segment(project)),
+        None => true,
+    }
+}
+
 // --- HTTP handlers ---
 
+async fn require_token(
+    State(tokens_dir): State<Arc<PathBuf>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let Some(project) = token.and_then(|token| token_project(&tokens_dir, token)) else {
+        warn!(path = %request.uri().path(), "Rejected request without a valid bridge token");
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+    if !token_allows(&project, request.uri().path()) {
+        warn!(path = %request.uri().path(), project, "Rejected request for another project");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    next.run(request).await
+}
+
+async fn require_registration_key(
+    State(key): State<Arc<String>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if provided != Some(key.as_str()) {
+        warn!(path = %request.uri().path(), "Rejected registration without the bridge key");
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    next.run(request).await
+}
+
+/// What `contenant run` registers for its project.
+#[derive(Deserialize, Serialize)]
+struct Registration {
+    triggers: HashMap<String, Trigger>,
+    /// Project directory to report file changes in.
+    #[serde(default)]
+    watch: Option<PathBuf>,
+    /// `owner/repo`s the project's sessions may get GitHub tokens for.
+    #[serde(default)]
+    github_repos: Vec<String>,
+    /// AWS profile the project's sessions get credentials from.
+    #[serde(default)]
+    aws_profile: Option<String>,
+}
+
+/// Triggers, GitHub repos, and AWS profiles registered by each project's
+/// `contenant run`, and the events queued for its sessions.
+#[derive(Default)]
+struct Projects {
+    triggers: RwLock<HashMap<String, Arc<Triggers>>>,
+    events: RwLock<HashMap<String, Arc<EventLog>>>,
+    watching: Mutex<HashSet<String>>,
+    webhooks: Arc<Webhooks>,
+    github_repos: RwLock<HashMap<String, Vec<String>>>,
+    github: github::Tokens,
+    aws_profiles: RwLock<HashMap<String, String>>,
+    /// Held while exporting AWS credentials, so an expired SSO session opens
+    /// one login in the browser rather than one per request.
+    aws_export: tokio::sync::Mutex<()>,
+}
+
+impl Projects {
+    fn get(&self, id: &str) -> Option<Arc<Triggers>> {
+        self.triggers.read().unwrap().get(id).cloned()
+    }
+
+    /// The project's event log, created on first use so events pushed before
+    /// a session starts aren't lost.
+    fn events(&self, id: &str) -> Arc<EventLog> {
+        if let Some(log) = self.events.read().unwrap().get(id) {
+            return log.clone();
+        }
+        self.events
+            .write()
+            .unwrap()
+            .entry(id.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+async fn accept_registration(
+    State(projects): State<Arc<Projects>>,
+    Path(id): Path<String>,
+    Json(registration): Json<Registration>,
+) -> StatusCode {
+    let Registration {
+        triggers,
+        watch,
+        github_repos,
+        aws_profile,
+    } = registration;
+    info!(project = %id, triggers = triggers.len(), "Registered project triggers");
+    projects
+        .github_repos
+        .write()
+        .unwrap()
+        .insert(id.clone(), github_repos);
+    match aws_profile {
+        Some(profile) => projects
+            .aws_profiles
+            .write()
+            .unwrap()
+            .insert(id.clone(), profile),
+        None => projects.aws_profiles.write().unwrap().remove(&id),
+    };
+    // One watcher per project, however many sessions register it
+    if let Some(dir) = watch
+        && projects.watching.lock().unwrap().insert(id.clone())
+    {
+        info!(project = %id, dir = %dir.display(), "Watching project for changes");
+        tokio::spawn(host_events::watch(dir, projects.events(&id)));
+    }
+    let triggers =
+        Triggers::new(triggers).with_webhooks(Some(id.clone()), projects.webhooks.clone());
+    projects
+        .triggers
+        .write()
+        .unwrap()
+        .insert(id, Arc::new(triggers));
+    StatusCode::NO_CONTENT
+}
+
+/// Answer a git credential request from one of the project's sessions with
+/// a token for the repo, if the project registered it.
+async fn git_credential(
+    State(projects): State<Arc<Projects>>,
+    Path(id): Path<String>,
+    request: String,
+) -> Response {
+    let Some(repo) = github::requested_repo(&request) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    let registered = projects
+        .github_repos
+        .read()
+        .unwrap()
+        .get(&id)
+        .is_some_and(|repos| repos.contains(&repo));
+    if !registered {
+        warn!(project = %id, repo, "Rejected GitHub token for an unregistered repo");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    match projects.github.token(&repo).await {
+        Ok(token) => {
+            info!(project = %id, repo, "Issued GitHub token");
+            format!("username=x-access-token\npassword={}\n", token).into_response()
+        }
+        Err(err) => {
+            warn!(project = %id, repo, err = format!("{:#}", err), "Failed to mint GitHub token");
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+    }
+}
+
+/// Fresh credentials for the project's AWS profile, as JSON for the
+/// container's `credential_process`.
+async fn aws_credentials(
+    State(projects): State<Arc<Projects>>,
+    Path(id): Path<String>,
+) -> Response {
+    let Some(profile) = projects.aws_profiles.read().unwrap().get(&id).cloned() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let _export = projects.aws_export.lock().await;
+    let exported = tokio::task::spawn_blocking({
+        let profile = profile.clone();
+        move || aws::export(&profile)
+    })
+    .await;
+    match exported {
+        Ok(Ok(credentials)) => {
+            info!(project = %id, profile, "Issued AWS credentials");
+            ([(header::CONTENT_TYPE, "application/json")], credentials).into_response()
+        }
+        Ok(Err(err)) => {
+            warn!(project = %id, profile, err = format!("{:#}", err), "Failed to export AWS credentials");
+            StatusCode::BAD_GATEWAY.into_response()
+        }
+        Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+    }
+}
+
+/// Queue an event from the host, e.g. `contenant event`, for the project's
+/// sessions.
+async fn push_event(
+    State(projects): State<Arc<Projects>>,
+    Path(id): Path<String>,
+    Json(event): Json<HostEventKind>,
+) -> StatusCode {
+    projects.events(&id).push(event);
+    StatusCode::ACCEPTED
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    /// Last event id seen; without it, only events from now on are returned.
+    since: Option<u64>,
+    timeout: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct EventsResponse {
+    /// Pass as `since` to get the events after these.
+    next: u64,
+    events: Vec<HostEvent>,
+}
+
+/// Long-poll for the project's host events after `since`, answering with an
+/// empty list if none arrive within the timeout.
+async fn project_events(
+    State(projects): State<Arc<Projects>>,
+    Path(id): Path<String>,
+    Query(query): Query<EventsQuery>,
+) -> Json<EventsResponse> {
+    let log = projects.events(&id);
+    // Ids start over with the bridge, so a later `since` is from before
+    // a restart and everything queued since is new
+    let since = match query.since {
+        Some(since) if since <= log.last_id() => since,
+        Some(_) => 0,
+        None => log.last_id(),
+    };
+    let timeout = query
+        .timeout
+        .unwrap_or(DEFAULT_EVENTS_TIMEOUT_SECS)
+        .min(MAX_EVENTS_TIMEOUT_SECS);
+    let events = log.wait_since(since, Duration::from_secs(timeout)).await;
+    let next = events.last().map_or(since, |e| e.id);
+    Json(EventsResponse { next, events })
+}
+
+/// Forward an event from `contenant run` to the webhooks.
+async fn forward_event(
+    State(projects): State<Arc<Projects>>,
+    Json(event): Json<webhook::Event>,
+) -> StatusCode {
+    projects.webhooks.send(&event);
+    StatusCode::ACCEPTED
+}
+
+async fn project_trigger(
+    State(projects): State<Arc<Projects>>,
+    Path((id, name)): Path<(String, String)>,
+    body: Option<Json<TriggerRequest>>,
+) -> (StatusCode, Json<TriggerResponse>) {
+    let Some(triggers) = projects.get(&id) else {
+        return (StatusCode::NOT_FOUND, Json(TriggerResponse::default()));
+    };
+    trigger(State(triggers), Path(name), body).await
+}
+
+async fn project_trigger_stream(
+    State(projects): State<Arc<Projects>>,
+    Path((id, name)): Path<(String, String)>,
+    body: Option<Json<TriggerRequest>>,
+) -> Response {
+    let Some(triggers) = projects.get(&id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    trigger_stream(State(triggers), Path(name), body).await
+}
+
+/// Optional request body, only accepted by triggers with `allow_args`.
+#[derive(Debug, Default, Deserialize)]
+struct TriggerRequest {
+    /// Positional parameters `$1..$n` of the command.
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: HashMap<String, String>,
+    stdin: Option<String>,
+}
+
+impl TriggerRequest {
+    fn is_empty(&self) -> bool {
+        self.args.is_empty() && self.env.is_empty() && self.stdin.is_none()
+    }
+}
+
 #[derive(Default, Serialize)]
 struct TriggerResponse {
     exit_code: Option<i32>,
@@ -35,23 +758,389 @@ struct TriggerResponse {
     stderr: Option<String>,
 }
 
+#[derive(Debug, PartialEq, Serialize)]
+struct TriggerInfo {
+    name: String,
+    description: Option<String>,
+    allow_args: bool,
+    confirm: bool,
+}
+
+/// The triggers a session can call, sorted by name.
+async fn list_triggers(State(triggers): State<Arc<Triggers>>) -> Json<Vec<TriggerInfo>> {
+    let mut infos: Vec<_> = triggers
+        .triggers
+        .iter()
+        .map(|(name, trigger)| TriggerInfo {
+            name: name.clone(),
+            description: trigger.description.clone(),
+            allow_args: trigger.allow_args,
+            confirm: trigger.confirm,
+        })
+        .collect();
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    Json(infos)
+}
+
+async fn list_project_triggers(
+    State(projects): State<Arc<Projects>>,
+    Path(id): Path<String>,
+) -> Result<Json<Vec<TriggerInfo>>, StatusCode> {
+    let triggers = projects.get(&id).ok_or(StatusCode::NOT_FOUND)?;
+    Ok(list_triggers(State(triggers)).await)
+}
+
+/// Whether the container may set `name`: a plain identifier that doesn't
+/// change how the shell or dynamic linker runs the command.
+fn env_name_allowed(name: &str) -> bool {
+    const RESERVED: &[&str] = &["PATH", "IFS", "ENV", "BASH_ENV", "SHELL", "HOME"];
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED.contains(&name)
+        && !name.starts_with("LD_")
+        && !name.starts_with("DYLD_")
+}
+
+/// The configured triggers, with a slot per concurrent run for those with
+/// `max_concurrent`.
+struct Triggers {
+    triggers: HashMap<String, Trigger>,
+    slots: HashMap<String, Arc<Semaphore>>,
+    /// Registering project, included in webhook events.
+    project: Option<String>,
+    webhooks: Arc<Webhooks>,
+}
+
+impl Triggers {
+    fn new(triggers: HashMap<String, Trigger>) -> Self {
+        let slots = triggers
+            .iter()
+            .filter_map(|(name, trigger)| {
+                let max = trigger.max_concurrent?;
+                Some((name.clone(), Arc::new(Semaphore::new(max))))
+            })
+            .collect();
+        Self {
+            triggers,
+            slots,
+            project: None,
+            webhooks: Arc::default(),
+        }
+    }
+
+    fn with_webhooks(self, project: Option<String>, webhooks: Arc<Webhooks>) -> Self {
+        Self {
+            project,
+            webhooks,
+            ..self
+        }
+    }
+
+    fn invoked(&self, name: &str) {
+        self.webhooks.send(&webhook::Event::TriggerInvoked {
+            project: self.project.clone(),
+            trigger: name.to_string(),
+        });
+    }
+}
+
+/// A checked request, holding its trigger's concurrency slot until dropped.
+struct Checked<'a> {
+    trigger: &'a Trigger,
+    request: TriggerRequest,
+    _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// The trigger `name` and the request to run it with, or the status to
+/// reject the request with.
+fn check_request<'a>(
+    triggers: &'a Triggers,
+    name: &str,
+    body: Option<Json<TriggerRequest>>,
+) -> Result<Checked<'a>, StatusCode> {
+    let Some(trigger) = triggers.triggers.get(name) else {
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    let request = body.map(|Json(request)| request).unwrap_or_default();
+    if !trigger.allow_args && !request.is_empty() {
+        warn!(trigger = %name, "Rejected arguments for trigger without allow_args");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if let Some(key) = request.env.keys().find(|k| !env_name_allowed(k)) {
+        warn!(trigger = %name, key = %key, "Rejected environment variable");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let slot = match triggers.slots.get(name) {
+        Some(slots) => match slots.clone().try_acquire_owned() {
+            Ok(slot) => Some(slot),
+            Err(_) => {
+                warn!(trigger = %name, "Rejected trigger at max_concurrent");
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+        },
+        None => None,
+    };
+    Ok(Checked {
+        trigger,
+        request,
+        _slot: slot,
+    })
+}
+
+/// Whether the trigger may run: always, unless it has `confirm` and the
+/// host user denies it.
+async fn confirmed(trigger: &Trigger, name: &str, request: &TriggerRequest) -> bool {
+    if !trigger.confirm {
+        return true;
+    }
+    let approved = approval::approve(name, &trigger.command.to_string(), &request.args).await;
+    if !approved {
+        warn!(trigger = %name, "Trigger denied on the host");
+    }
+    approved
+}
+
+/// Run `future` to completion, or give up after `timeout_secs`.
+async fn with_timeout<F: Future>(timeout_secs: Option<u64>, future: F) -> Option<F::Output> {
+    match timeout_secs {
+        Some(secs) => tokio::time::timeout(Duration::from_secs(secs), future)
+            .await
+            .ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Kill a trigger and everything it started, which `kill_on_drop` alone
+/// misses: it only kills the shell.
+fn kill_group(pid: Option<u32>) {
+    if let Some(pid) = pid {
+        // SAFETY: killpg has no memory safety requirements
+        unsafe {
+            libc::killpg(pid as libc::pid_t, libc::SIGKILL);
+        }
+    }
+}
+
+/// Start the trigger with stdout and stderr piped, feeding it the request's
+/// stdin.
+async fn spawn_trigger(
+    trigger: &Trigger,
+    name: &str,
+    request: TriggerRequest,
+) -> std::io::Result<Child> {
+    info!(trigger = %name, command = %trigger.command, args = ?request.args, "Executing trigger");
+
+    let mut child = Command::from(trigger_command(trigger, name, &request.args))
+        .envs(&request.env)
+        .envs(&trigger.env)
+        .stdin(if request.stdin.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // Its own process group, so a timeout can kill the whole tree
+        .process_group(0)
+        .kill_on_drop(true)
+        .spawn()?;
+
+    if let (Some(input), Some(mut stdin)) = (request.stdin, child.stdin.take()) {
+        // A command that exits without reading its input isn't an error
+        let _ = stdin.write_all(input.as_bytes()).await;
+    }
+    Ok(child)
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenUrlRequest {
+    url: String,
+}
+
+/// Whether `url` is under one of the allowed prefixes. A prefix without a
+/// path only matches its whole host, so `https://github.com` doesn't allow
+/// `https://github.com.example`.
+fn url_allowed(prefixes: &[String], url: &str) -> bool {
+    if !(url.starts_with("https://") || url.starts_with("http://")) {
+        return false;
+    }
+    prefixes.iter().any(|prefix| {
+        let has_path = prefix
+            .split_once("://")
+            .is_some_and(|(_, rest)| rest.contains('/'));
+        url.strip_prefix(prefix.as_str())
+            .is_some_and(|rest| has_path || rest.is_empty() || rest.starts_with(['/', '?', '#']))
+    })
+}
+
+async fn open_url(
+    State(prefixes): State<Arc<Vec<String>>>,
+    Json(request): Json<OpenUrlRequest>,
+) -> StatusCode {
+    if !url_allowed(&prefixes, &request.url) {
+        warn!(url = %request.url, "Rejected URL outside bridge.open_urls");
+        return StatusCode::FORBIDDEN;
+    }
+
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    info!(url = %request.url, "Opening URL");
+    let status = Command::new(opener)
+        .arg(&request.url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+        .await;
+    match status {
+        Ok(status) if status.success() => StatusCode::NO_CONTENT,
+        _ => {
+            warn!(url = %request.url, opener, "Failed to open URL");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+struct Artifacts {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+/// The upload path as a relative path of plain names, or `None` if it could
+/// leave the artifacts dir.
+fn artifact_path(path: &str) -> Option<PathBuf> {
+    let path = std::path::Path::new(path);
+    let plain = path
+        .components()
+        .all(|c| matches!(c, Component::Normal(name) if !name.as_encoded_bytes().contains(&b'\\')));
+    (plain && path.components().next().is_some()).then(|| path.to_path_buf())
+}
+
+async fn upload_artifact(
+    State(artifacts): State<Arc<Artifacts>>,
+    Path(path): Path<String>,
+    body: Body,
+) -> StatusCode {
+    let Some(relative) = artifact_path(&path) else {
+        warn!(path, "Rejected artifact path");
+        return StatusCode::BAD_REQUEST;
+    };
+    let target = artifacts.dir.join(&relative);
+    // Written beside the target and renamed, so a failed upload never leaves
+    // a truncated artifact
+    let partial = target.with_extension("contenant-upload");
+
+    let status = match receive(body, &partial, artifacts.max_bytes).await {
+        Ok(Some(bytes)) => match tokio::fs::rename(&partial, &target).await {
+            Ok(()) => {
+                info!(path = %target.display(), bytes, "Received artifact");
+                return StatusCode::CREATED;
+            }
+            Err(err) => {
+                warn!(%err, "Failed to save artifact");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        },
+        Ok(None) => {
+            warn!(path, max_bytes = artifacts.max_bytes, "Artifact too large");
+            StatusCode::PAYLOAD_TOO_LARGE
+        }
+        Err(err) => {
+            warn!(%err, "Failed to receive artifact");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    };
+    let _ = tokio::fs::remove_file(&partial).await;
+    status
+}
+
+/// Stream `body` into `path`, returning the size, or `None` once it exceeds
+/// `max_bytes`.
+async fn receive(body: Body, path: &std::path::Path, max_bytes: u64) -> Result<Option<u64>> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let mut file = tokio::fs::File::create(path).await?;
+    let mut stream = body.into_data_stream();
+    let mut bytes = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        bytes += chunk.len() as u64;
+        if bytes > max_bytes {
+            return Ok(None);
+        }
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+    Ok(Some(bytes))
+}
+
+async fn clipboard_read() -> Response {
+    let Some(clipboard) = Clipboard::detect() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    match clipboard.read().await {
+        Ok(text) => text.into_response(),
+        Err(err) => {
+            warn!(%err, "Failed to read the clipboard");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn clipboard_write(text: String) -> StatusCode {
+    let Some(clipboard) = Clipboard::detect() else {
+        return StatusCode::SERVICE_UNAVAILABLE;
+    };
+    match clipboard.write(&text).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(err) => {
+            warn!(%err, "Failed to write the clipboard");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
 async fn trigger(
-    State(triggers): State<Arc<HashMap<String, String>>>,
+    State(triggers): State<Arc<Triggers>>,
     Path(name): Path<String>,
+    body: Option<Json<TriggerRequest>>,
 ) -> (StatusCode, Json<TriggerResponse>) {
-    let Some(cmd) = triggers.get(&name) else {
-        return (StatusCode::BAD_REQUEST, Json(TriggerResponse::default()));
+    let checked = match check_request(&triggers, &name, body) {
+        Ok(checked) => checked,
+        Err(status) => return (status, Json(TriggerResponse::default())),
     };
 
-    info!(trigger = %name, command = %cmd, "Executing trigger");
+    if !confirmed(checked.trigger, &name, &checked.request).await {
+        return (StatusCode::FORBIDDEN, Json(TriggerResponse::default()));
+    }
+    triggers.invoked(&name);
 
-    let Ok(output) = Command::new("sh")
-        .arg("-c")
-        .arg(cmd)
-        .stdin(Stdio::null())
-        .output()
-        .await
+    let child = match spawn_trigger(checked.trigger, &name, checked.request).await {
+        Ok(child) => child,
+        Err(_) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(TriggerResponse::default()),
+            );
+        }
+    };
+    let pid = child.id();
+    let Some(output) = with_timeout(checked.trigger.timeout_secs, child.wait_with_output()).await
     else {
+        warn!(trigger = %name, "Trigger timed out");
+        kill_group(pid);
+        return (
+            StatusCode::REQUEST_TIMEOUT,
+            Json(TriggerResponse::default()),
+        );
+    };
+    let Ok(output) = output else {
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(TriggerResponse::default()),
@@ -67,3 +1156,442 @@ async fn trigger(
         }),
     )
 }
+
+/// Like `trigger`, but streams output lines as `stdout` and `stderr`
+/// server-sent events as they're written, ending with an `exit` event
+/// carrying `{"exit_code": ...}`, plus `"timed_out": true` if the command
+/// was killed for running too long. Closing the connection kills the
+/// command.
+async fn trigger_stream(
+    State(triggers): State<Arc<Triggers>>,
+    Path(name): Path<String>,
+    body: Option<Json<TriggerRequest>>,
+) -> Response {
+    let Checked {
+        trigger,
+        request,
+        _slot: slot,
+    } = match check_request(&triggers, &name, body) {
+        Ok(checked) => checked,
+        Err(status) => return status.into_response(),
+    };
+    if !confirmed(trigger, &name, &request).await {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    triggers.invoked(&name);
+    let Ok(mut child) = spawn_trigger(trigger, &name, request).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    let (tx, rx) = mpsc::channel(64);
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let pid = child.id();
+    let timeout_secs = trigger.timeout_secs;
+    tokio::spawn(async move {
+        // Held until the command exits, not just until the response starts
+        let _slot = slot;
+        let run = async {
+            tokio::join!(
+                forward_lines(stdout, "stdout", &tx),
+                forward_lines(stderr, "stderr", &tx),
+            );
+            child.wait().await.ok().and_then(|status| status.code())
+        };
+        let data = match with_timeout(timeout_secs, run).await {
+            Some(exit_code) => serde_json::json!({ "exit_code": exit_code }),
+            None => {
+                warn!(trigger = %name, "Trigger timed out");
+                kill_group(pid);
+                serde_json::json!({ "exit_code": null, "timed_out": true })
+            }
+        };
+        let _ = tx
+            .send(Event::default().event("exit").data(data.to_string()))
+            .await;
+    });
+
+    let events = futures_util::stream::unfold(rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        Some((Ok::<_, Infallible>(event), rx))
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Send each line of `pipe` as an `event` until it closes or the client
+/// goes away.
+async fn forward_lines(
+    pipe: Option<impl AsyncRead + Unpin>,
+    event: &'static str,
+    tx: &mpsc::Sender<Event>,
+) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let mut lines = BufReader::new(pipe).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        if tx
+            .send(Event::default().event(event).data(line))
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggers() -> State<Arc<Triggers>> {
+        let trigger = |command: &str, allow_args| Trigger {
+            command: TriggerCommand::Shell(command.to_string()),
+            allow_args,
+            ..Default::default()
+        };
+        State(Arc::new(Triggers::new(HashMap::from([
+            (
+                "echo".to_string(),
+                trigger(r#"printf '%s|%s|%s|' "$1" "$2" "$GREETING"; cat"#, true),
+            ),
+            (
+                "fixed".to_string(),
+                Trigger {
+                    description: Some("Print a fixed line".to_string()),
+                    ..trigger("echo fixed", false)
+                },
+            ),
+            (
+                "slow".to_string(),
+                Trigger {
+                    command: TriggerCommand::Shell("sleep 5 & wait".to_string()),
+                    timeout_secs: Some(1),
+                    max_concurrent: Some(1),
+                    ..Default::default()
+                },
+            ),
+        ]))))
+    }
+
+    #[test]
+    fn urls_must_match_an_allowed_prefix() {
+        let prefixes = vec![
+            "https://github.com".to_string(),
+            "https://preview.example.com/app-".to_string(),
+        ];
+        assert!(url_allowed(&prefixes, "https://github.com"));
+        assert!(url_allowed(
+            &prefixes,
+            "https://github.com/owner/repo/pull/1"
+        ));
+        assert!(url_allowed(&prefixes, "https://preview.example.com/app-42"));
+        assert!(!url_allowed(&prefixes, "https://github.com.example/"));
+        assert!(!url_allowed(&prefixes, "https://gitlab.com/"));
+        assert!(!url_allowed(&["file:".to_string()], "file:///etc/passwd"));
+    }
+
+    #[test]
+    fn artifact_paths_stay_inside_the_dir() {
+        assert_eq!(
+            artifact_path("coverage/index.html"),
+            Some(PathBuf::from("coverage/index.html"))
+        );
+        assert_eq!(artifact_path("../escape"), None);
+        assert_eq!(artifact_path("a/../../escape"), None);
+        assert_eq!(artifact_path("/etc/passwd"), None);
+        assert_eq!(artifact_path("a\\..\\b"), None);
+        assert_eq!(artifact_path(""), None);
+    }
+
+    #[tokio::test]
+    async fn uploads_artifacts_up_to_the_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let artifacts = State(Arc::new(Artifacts {
+            dir: dir.path().to_path_buf(),
+            max_bytes: 8,
+        }));
+
+        let status = upload_artifact(
+            artifacts.clone(),
+            Path("out/app.bin".to_string()),
+            Body::from("binary"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("out/app.bin")).unwrap(),
+            "binary"
+        );
+
+        let status = upload_artifact(
+            artifacts,
+            Path("big.bin".to_string()),
+            Body::from("too large for the cap"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!dir.path().join("big.bin").exists());
+        assert!(!dir.path().join("big.contenant-upload").exists());
+    }
+
+    #[test]
+    fn tokens_are_valid_while_issued() {
+        let dir = tempfile::tempdir().unwrap();
+        let token = BridgeToken::issue(dir.path(), "1234abcd-app").unwrap();
+        assert_eq!(token.value.len(), 64);
+        assert_eq!(
+            token_project(dir.path(), &token.value).as_deref(),
+            Some("1234abcd-app")
+        );
+        assert_eq!(token_project(dir.path(), "guess"), None);
+
+        let value = token.value.clone();
+        drop(token);
+        assert_eq!(token_project(dir.path(), &value), None);
+    }
+
+    #[test]
+    fn tokens_are_limited_to_their_project() {
+        assert!(token_allows("1234abcd-app", "/triggers/build"));
+        assert!(token_allows(
+            "1234abcd-app",
+            "/projects/1234abcd-app/triggers/build"
+        ));
+        assert!(!token_allows(
+            "1234abcd-app",
+            "/projects/5678ef90-other/triggers/build"
+        ));
+        assert!(token_allows(
+            "1234abcd-my app",
+            "/projects/1234abcd-my%20app/triggers/build"
+        ));
+    }
+
+    #[tokio::test]
+    async fn routes_triggers_by_registered_project() {
+        let projects = State(Arc::new(Projects::default()));
+        let triggers = HashMap::from([(
+            "build".to_string(),
+            Trigger {
+                command: TriggerCommand::Shell("echo app".to_string()),
+                ..Default::default()
+            },
+        )]);
+        let status = accept_registration(
+            projects.clone(),
+            Path("1234abcd-app".to_string()),
+            Json(Registration {
+                triggers,
+                watch: None,
+                github_repos: vec![],
+                aws_profile: None,
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let path = |id: &str| Path((id.to_string(), "build".to_string()));
+        let (status, Json(response)) =
+            project_trigger(projects.clone(), path("1234abcd-app"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.stdout.as_deref(), Some("app\n"));
+
+        let (status, _) = project_trigger(projects, path("5678ef90-other"), None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn git_credentials_are_only_for_registered_repos() {
+        let projects = State(Arc::new(Projects::default()));
+        accept_registration(
+            projects.clone(),
+            Path("1234abcd-app".to_string()),
+            Json(Registration {
+                triggers: HashMap::new(),
+                watch: None,
+                github_repos: vec!["kejadlen/contenant".to_string()],
+                aws_profile: None,
+            }),
+        )
+        .await;
+
+        let request = |id: &str, repo: &str| {
+            git_credential(
+                projects.clone(),
+                Path(id.to_string()),
+                format!("protocol=https\nhost=github.com\npath={}.git\n", repo),
+            )
+        };
+        let status = |response: Response| response.status();
+        assert_eq!(
+            status(request("1234abcd-app", "kejadlen/other").await),
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(request("5678ef90-other", "kejadlen/contenant").await),
+            StatusCode::FORBIDDEN
+        );
+        let response = git_credential(
+            projects.clone(),
+            Path("1234abcd-app".to_string()),
+            "protocol=https\nhost=github.com\n".to_string(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn long_polls_pushed_events() {
+        let projects = State(Arc::new(Projects::default()));
+        let query = |since, timeout| {
+            Query(EventsQuery {
+                since,
+                timeout: Some(timeout),
+            })
+        };
+        let path = || Path("1234abcd-app".to_string());
+        let status = push_event(
+            projects.clone(),
+            path(),
+            Json(HostEventKind::Message {
+                message: "CI finished".to_string(),
+            }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+
+        let Json(response) = project_events(projects.clone(), path(), query(Some(0), 0)).await;
+        assert_eq!(response.next, 1);
+        assert_eq!(response.events.len(), 1);
+
+        // Without `since` only new events count; after a restart all do
+        let Json(response) = project_events(projects.clone(), path(), query(None, 0)).await;
+        assert_eq!((response.next, response.events.len()), (1, 0));
+        let Json(response) = project_events(projects.clone(), path(), query(Some(7), 0)).await;
+        assert_eq!((response.next, response.events.len()), (1, 1));
+
+        let Json(response) = project_events(
+            projects,
+            Path("5678ef90-other".to_string()),
+            query(Some(0), 0),
+        )
+        .await;
+        assert!(response.events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn passes_args_env_and_stdin() {
+        let request = TriggerRequest {
+            args: vec!["a b".to_string(), "c".to_string()],
+            env: HashMap::from([("GREETING".to_string(), "hi".to_string())]),
+            stdin: Some("input".to_string()),
+        };
+        let (status, Json(response)) =
+            trigger(triggers(), Path("echo".to_string()), Some(Json(request))).await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.stdout.as_deref(), Some("a b|c|hi|input"));
+    }
+
+    #[tokio::test]
+    async fn streams_output_then_exit_code() {
+        let triggers = State(Arc::new(Triggers::new(HashMap::from([(
+            "build".to_string(),
+            Trigger {
+                command: TriggerCommand::Shell(
+                    "echo compiling; echo warning >&2; exit 3".to_string(),
+                ),
+                ..Default::default()
+            },
+        )]))));
+        let response = trigger_stream(triggers, Path("build".to_string()), None).await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.contains("event: stdout\ndata: compiling\n"));
+        assert!(body.contains("event: stderr\ndata: warning\n"));
+        assert!(body.ends_with("event: exit\ndata: {\"exit_code\":3}\n\n"));
+    }
+
+    #[tokio::test]
+    async fn rejects_args_unless_allowed() {
+        let request = TriggerRequest {
+            args: vec!["x".to_string()],
+            ..Default::default()
+        };
+        let (status, _) = trigger(triggers(), Path("fixed".to_string()), Some(Json(request))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (status, _) = trigger(triggers(), Path("fixed".to_string()), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = TriggerRequest {
+            env: HashMap::from([("LD_PRELOAD".to_string(), "/tmp/x.so".to_string())]),
+            ..Default::default()
+        };
+        let (status, _) = trigger(triggers(), Path("echo".to_string()), Some(Json(request))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn limits_concurrency_and_kills_on_timeout() {
+        let triggers = triggers();
+        let held = check_request(&triggers, "slow", None).unwrap();
+        let (status, _) = trigger(triggers.clone(), Path("slow".to_string()), None).await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        drop(held);
+
+        let started = std::time::Instant::now();
+        let (status, _) = trigger(triggers, Path("slow".to_string()), None).await;
+        assert_eq!(status, StatusCode::REQUEST_TIMEOUT);
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn lists_triggers_by_name() {
+        let Json(infos) = list_triggers(triggers()).await;
+        let names: Vec<_> = infos.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["echo", "fixed", "slow"]);
+        assert!(infos[0].allow_args);
+        assert_eq!(infos[1].description.as_deref(), Some("Print a fixed line"));
+    }
+
+    #[tokio::test]
+    async fn execs_argv_in_cwd_with_env() {
+        let dir = tempfile::tempdir().unwrap();
+        let triggers = State(Arc::new(Triggers::new(HashMap::from([(
+            "show".to_string(),
+            Trigger {
+                command: TriggerCommand::Exec(vec![
+                    "sh".to_string(),
+                    "-c".to_string(),
+                    r#"printf '%s|%s|%s' "$(pwd)" "$MODE" "$1""#.to_string(),
+                    "show".to_string(),
+                ]),
+                cwd: Some(dir.path().to_string_lossy().into_owned()),
+                env: HashMap::from([("MODE".to_string(), "config".to_string())]),
+                allow_args: true,
+                ..Default::default()
+            },
+        )]))));
+        let request = TriggerRequest {
+            args: vec!["$HOME; x".to_string()],
+            env: HashMap::from([("MODE".to_string(), "request".to_string())]),
+            ..Default::default()
+        };
+        let (status, Json(response)) =
+            trigger(triggers, Path("show".to_string()), Some(Json(request))).await;
+
+        assert_eq!(status, StatusCode::OK);
+        let cwd = dir.path().canonicalize().unwrap();
+        assert_eq!(
+            response.stdout.unwrap(),
+            format!("{}|config|$HOME; x", cwd.display())
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Bundles of the agent's persistent state, for `contenant state export` and
+//! `contenant state import`, so a new machine doesn't need to log in and set
+//! everything up again.
+//!
+//! A bundle is a gzipped tarball, written by the system `tar`, of these parts
+//! of the state directory: Claude's home (auth and settings), the SSH
+//! `known_hosts`, and contenant's per-project state, including Claude's
+//! conversations, without workspace snapshots. Projects are keyed by
+//! path, so their state is only picked up again where the path is the same.
+
+use std::fs;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+/// What a bundle holds, relative to the state directory.
+pub const PARTS: [&str; 3] = ["claude", "ssh/known_hosts", "projects"];
+
+/// Archive the parts of `state_home` that exist into `archive`, returning
+/// which were included. The archive holds Claude's credentials, so only the
+/// owner can read it.
+pub fn export(state_home: &Path, archive: &Path) -> Result<Vec<&'static str>> {
+    let parts: Vec<_> = PARTS
+        .into_iter()
+        .filter(|part| state_home.join(part).exists())
+        .collect();
+    if parts.is_empty() {
+        bail!("No state in {} to export", state_home.display());
+    }
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(archive)
+        .wrap_err_with(|| format!("Couldn't write {}", archive.display()))?;
+    // `mode` only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    let status = Command::new("tar")
+        .arg("-czf")
+        .arg("-")
+        .arg("-C")
+        .arg(state_home)
+        .arg(format!(
+            "--exclude=projects/*/{}",
+            crate::snapshot::SNAPSHOTS_DIR
+        ))
+        .args(&parts)
+        .stdout(Stdio::from(file))
+        .status()
+        .wrap_err("Couldn't run tar")?;
+    if !status.success() {
+        let _ = fs::remove_file(archive);
+        bail!("tar exited with {}", status);
+    }
+    Ok(parts)
+}
+
+/// Extract `archive` into `state_home`, returning how many entries it had.
+/// Refuses to replace existing Claude state unless `force` is set, and
+/// bundles with anything outside [`PARTS`].
+pub fn import(state_home: &Path, archive: &Path, force: bool) -> Result<usize> {
+    let output = Command::new("tar")
+        .arg("-tzf")
+        .arg(archive)
+        .output()
+        .wrap_err("Couldn't run tar")?;
+    if !output.status.success() {
+        bail!("Couldn't read {}", archive.display());
+    }
+    let entries: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+    if let Some(entry) = entries.iter().find(|entry| !is_part(entry)) {
+        bail!(
+            "{} isn't a state bundle: it has {}",
+            archive.display(),
+            entry
+        );
+    }
+
+    let claude = state_home.join("claude");
+    if !force && fs::read_dir(&claude).is_ok_and(|mut dir| dir.next().is_some()) {
+        bail!(
+            "{} already has Claude state; pass --force to replace it",
+            state_home.display()
+        );
+    }
+
+    fs::create_dir_all(state_home)?;
+    let status = Command::new("tar")
+        .arg("-xzf")
+        .arg(archive)
+        .arg("-C")
+        .arg(state_home)
+        .status()
+        .wrap_err("Couldn't run tar")?;
+    if !status.success() {
+        bail!("tar exited with {}", status);
+    }
+    Ok(entries.len())
+}
+
+/// Whether a tar entry lies inside one of [`PARTS`].
+fn is_part(entry: &str) -> bool {
+    let entry = entry.strip_prefix("./").unwrap_or(entry);
+    let path = Path::new(entry);
+    !path
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+        && PARTS.iter().any(|part| path.starts_with(part))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_state_without_snapshots() {
+        let old = tempfile::tempdir().unwrap();
+        let new = tempfile::tempdir().unwrap();
+        let bundle = tempfile::tempdir().unwrap();
+        let archive = bundle.path().join("state.tar.gz");
+        let write = |root: &Path, path: &str, contents: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        write(old.path(), "claude/.credentials.json", "token");
+        write(old.path(), "ssh/known_hosts", "github.com ssh-ed25519 AAAA");
+        write(old.path(), "projects/0123abcd-app/path", "/src/app");
+        write(old.path(), "projects/0123abcd-app/snapshots/1-a.tar.gz", "");
+        write(old.path(), "env-files/abc", "SECRET=1");
+
+        assert_eq!(export(old.path(), &archive).unwrap(), PARTS);
+        let mode = fs::metadata(&archive).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        import(new.path(), &archive, false).unwrap();
+
+        let read = |path: &str| fs::read_to_string(new.path().join(path)).ok();
+        assert_eq!(read("claude/.credentials.json").as_deref(), Some("token"));
+        assert!(read("ssh/known_hosts").is_some());
+        assert_eq!(
+            read("projects/0123abcd-app/path").as_deref(),
+            Some("/src/app")
+        );
+        assert!(!new.path().join("projects/0123abcd-app/snapshots").exists());
+        assert!(!new.path().join("env-files").exists());
+
+        // Now there's Claude state to overwrite
+        assert!(import(new.path(), &archive, false).is_err());
+        import(new.path(), &archive, true).unwrap();
+    }
+
+    #[test]
+    fn only_accepts_entries_inside_the_bundled_parts() {
+        assert!(is_part("claude/settings.json"));
+        assert!(is_part("./projects/0123abcd-app/logs/1-a/output.log"));
+        assert!(!is_part("ssh/id_ed25519"));
+        assert!(!is_part("claude/../../.bashrc"));
+        assert!(!is_part("/etc/passwd"));
+    }
+}
@@ -0,0 +1,398 @@
+//! What a session changed in the workspace, for `contenant diff`.
+//!
+//! When a session starts, a [`Baseline`] of the workspace is saved in its log
+//! directory. In a git repo that's a tree object holding every file git
+//! doesn't ignore, written through a scratch index so the real one and the
+//! stash are untouched; elsewhere it's a manifest of content hashes, which
+//! can list what changed but not show how.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::host_events::IGNORED_DIRS;
+
+/// File in a session's log directory holding its baseline.
+pub const BASELINE_FILE: &str = "workspace.json";
+
+/// The workspace as a session found it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Baseline {
+    Git {
+        tree: String,
+    },
+    /// SHA-256 of each file, by path relative to the workspace.
+    Files {
+        files: BTreeMap<String, String>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Added => write!(f, "A"),
+            Status::Modified => write!(f, "M"),
+            Status::Deleted => write!(f, "D"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub status: Status,
+    pub path: String,
+}
+
+impl Baseline {
+    /// Capture `workspace` as it is now. `scratch` is a directory for the
+    /// temporary git index.
+    pub fn capture(workspace: &Path, scratch: &Path) -> Result<Self> {
+        if is_git_work_tree(workspace) {
+            Ok(Self::Git {
+                tree: git_tree(workspace, scratch)?,
+            })
+        } else {
+            Ok(Self::Files {
+                files: hash_files(workspace),
+            })
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path)
+            .wrap_err_with(|| format!("No workspace baseline at {}", path.display()))?;
+        Ok(serde_json::from_str(&text)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Files added, modified, or deleted since the baseline, sorted by path.
+    pub fn changes(&self, workspace: &Path, scratch: &Path) -> Result<Vec<Change>> {
+        match self {
+            Self::Git { tree } => {
+                let now = git_tree(workspace, scratch)?;
+                let output = git(workspace)
+                    .args(["diff", "--name-status", "--no-renames", "-z", tree, &now])
+                    .output()?;
+                if !output.status.success() {
+                    bail!(
+                        "git diff failed: {}",
+                        String::from_utf8_lossy(&output.stderr)
+                    );
+                }
+                Ok(parse_name_status(&String::from_utf8_lossy(&output.stdout)))
+            }
+            Self::Files { files } => Ok(compare(files, &hash_files(workspace))),
+        }
+    }
+
+    /// Run `git diff` between the baseline and the workspace now, with
+    /// `args` like `--stat`, writing to stdout.
+    pub fn git_diff(&self, workspace: &Path, scratch: &Path, args: &[&str]) -> Result<()> {
+        let status = self.diff_command(workspace, scratch, args)?.status()?;
+        if !status.success() {
+            bail!("git diff exited with {}", status);
+        }
+        Ok(())
+    }
+
+    /// The `git diff` between the baseline and the workspace now, or `None`
+    /// if nothing changed.
+    pub fn patch(&self, workspace: &Path, scratch: &Path) -> Result<Option<String>> {
+        let output = self.diff_command(workspace, scratch, &[])?.output()?;
+        if !output.status.success() {
+            bail!(
+                "git diff failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        let patch = String::from_utf8_lossy(&output.stdout).into_owned();
+        Ok((!patch.is_empty()).then_some(patch))
+    }
+
+    fn diff_command(&self, workspace: &Path, scratch: &Path, args: &[&str]) -> Result<Command> {
+        let Self::Git { tree } = self else {
+            bail!(
+                "The workspace wasn't a git repo when the session started, so only the list of changed files is available"
+            );
+        };
+        let now = git_tree(workspace, scratch)?;
+        let mut cmd = git(workspace);
+        cmd.arg("diff").args(args).args([tree.as_str(), &now]);
+        Ok(cmd)
+    }
+}
+
+fn git(workspace: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(workspace);
+    cmd
+}
+
+pub(crate) fn is_git_work_tree(workspace: &Path) -> bool {
+    git(workspace)
+        .args(["rev-parse", "--is-inside-work-tree"])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// A tree object of every file in `workspace` git doesn't ignore, staged into
+/// a scratch copy of the index.
+fn git_tree(workspace: &Path, scratch: &Path) -> Result<String> {
+    let index = scratch.join(format!("index-{}", crate::bridge::random_hex()?));
+    let real_index = git(workspace)
+        .args(["rev-parse", "--path-format=absolute", "--git-path", "index"])
+        .output()?;
+    let real_index = String::from_utf8_lossy(&real_index.stdout)
+        .trim()
+        .to_string();
+    // Starting from the real index lets git skip rehashing unchanged files
+    let _ = fs::copy(&real_index, &index);
+
+    let result = (|| {
+        let status = git(workspace)
+            .env("GIT_INDEX_FILE", &index)
+            .args(["add", "--all", "--", "."])
+            .stdout(Stdio::null())
+            .status()?;
+        if !status.success() {
+            bail!("git add exited with {}", status);
+        }
+        let output = git(workspace)
+            .env("GIT_INDEX_FILE", &index)
+            .arg("write-tree")
+            .output()?;
+        if !output.status.success() {
+            bail!("git write-tree failed");
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    })();
+    let _ = fs::remove_file(&index);
+    result
+}
+
+fn parse_name_status(output: &str) -> Vec<Change> {
+    let mut fields = output.split('\0');
+    let mut changes = vec![];
+    while let (Some(status), Some(path)) = (fields.next(), fields.next()) {
+        let status = match status {
+            "A" => Status::Added,
+            "D" => Status::Deleted,
+            _ => Status::Modified,
+        };
+        changes.push(Change {
+            status,
+            path: path.to_string(),
+        });
+    }
+    changes
+}
+
+/// Paths, relative to `workspace`, of the files a baseline covers: those git
+/// doesn't ignore in a git work tree, otherwise everything outside
+/// [`IGNORED_DIRS`].
+pub(crate) fn workspace_files(workspace: &Path) -> Result<Vec<String>> {
+    if !is_git_work_tree(workspace) {
+        return Ok(walk(workspace));
+    }
+    let output = git(workspace)
+        .args([
+            "ls-files",
+            "-z",
+            "--cached",
+            "--others",
+            "--exclude-standard",
+        ])
+        .output()?;
+    if !output.status.success() {
+        bail!("git ls-files failed");
+    }
+    let mut files: Vec<_> = String::from_utf8_lossy(&output.stdout)
+        .split('\0')
+        .filter(|path| !path.is_empty())
+        // Deleted but still in the index
+        .filter(|path| workspace.join(path).symlink_metadata().is_ok())
+        .map(str::to_string)
+        .collect();
+    files.dedup();
+    Ok(files)
+}
+
+fn hash_files(workspace: &Path) -> BTreeMap<String, String> {
+    walk(workspace)
+        .into_iter()
+        .filter_map(|path| {
+            let contents = fs::read(workspace.join(&path)).ok()?;
+            Some((path, format!("{:x}", Sha256::digest(&contents))))
+        })
+        .collect()
+}
+
+/// Regular files under `workspace` outside [`IGNORED_DIRS`].
+fn walk(workspace: &Path) -> Vec<String> {
+    let mut files = vec![];
+    let mut pending = vec![workspace.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if !IGNORED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                    pending.push(path);
+                }
+            } else if file_type.is_file()
+                && let Ok(relative) = path.strip_prefix(workspace)
+            {
+                files.push(relative.to_string_lossy().into_owned());
+            }
+        }
+    }
+    files
+}
+
+fn compare(before: &BTreeMap<String, String>, after: &BTreeMap<String, String>) -> Vec<Change> {
+    let mut changes: Vec<_> = after
+        .iter()
+        .filter_map(|(path, hash)| {
+            let status = match before.get(path) {
+                None => Status::Added,
+                Some(old) if old != hash => Status::Modified,
+                Some(_) => return None,
+            };
+            Some(Change {
+                status,
+                path: path.clone(),
+            })
+        })
+        .chain(
+            before
+                .keys()
+                .filter(|path| !after.contains_key(*path))
+                .map(|path| Change {
+                    status: Status::Deleted,
+                    path: path.clone(),
+                }),
+        )
+        .collect();
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(status: Status, path: &str) -> Change {
+        Change {
+            status,
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn lists_changes_without_git() {
+        let workspace = tempfile::tempdir().unwrap();
+        let scratch = tempfile::tempdir().unwrap();
+        fs::write(workspace.path().join("kept"), "same").unwrap();
+        fs::write(workspace.path().join("edited"), "before").unwrap();
+        fs::write(workspace.path().join("removed"), "gone soon").unwrap();
+        fs::create_dir(workspace.path().join("node_modules")).unwrap();
+
+        let baseline = Baseline::capture(workspace.path(), scratch.path()).unwrap();
+        assert!(matches!(baseline, Baseline::Files { .. }));
+        fs::write(workspace.path().join("edited"), "after").unwrap();
+        fs::remove_file(workspace.path().join("removed")).unwrap();
+        fs::create_dir(workspace.path().join("src")).unwrap();
+        fs::write(workspace.path().join("src/new"), "").unwrap();
+        fs::write(workspace.path().join("node_modules/dep"), "").unwrap();
+
+        assert_eq!(
+            baseline.changes(workspace.path(), scratch.path()).unwrap(),
+            [
+                change(Status::Modified, "edited"),
+                change(Status::Deleted, "removed"),
+                change(Status::Added, "src/new"),
+            ]
+        );
+        assert!(
+            baseline
+                .git_diff(workspace.path(), scratch.path(), &[])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn lists_changes_in_a_git_repo_including_untracked_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        let scratch = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = git(workspace.path())
+                .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+                .args(args)
+                .stdout(Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "--quiet"]);
+        fs::write(workspace.path().join(".gitignore"), "build/\n").unwrap();
+        fs::write(workspace.path().join("committed"), "v1").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", "init"]);
+        fs::write(workspace.path().join("untracked"), "local").unwrap();
+
+        let baseline = Baseline::capture(workspace.path(), scratch.path()).unwrap();
+        fs::write(workspace.path().join("committed"), "v2").unwrap();
+        fs::remove_file(workspace.path().join("untracked")).unwrap();
+        fs::create_dir(workspace.path().join("build")).unwrap();
+        fs::write(workspace.path().join("build/out"), "").unwrap();
+        fs::write(workspace.path().join("added"), "").unwrap();
+        // Commits the agent makes don't hide its changes
+        git(&["add", "committed"]);
+        git(&["commit", "--quiet", "-m", "agent"]);
+
+        assert_eq!(
+            baseline.changes(workspace.path(), scratch.path()).unwrap(),
+            [
+                change(Status::Added, "added"),
+                change(Status::Modified, "committed"),
+                change(Status::Deleted, "untracked"),
+            ]
+        );
+        let patch = baseline
+            .patch(workspace.path(), scratch.path())
+            .unwrap()
+            .unwrap();
+        assert!(patch.contains("diff --git a/added b/added"), "{patch}");
+        assert!(!patch.contains("build/out"), "{patch}");
+        // The real index only has what was staged above
+        let output = super::git(workspace.path())
+            .args(["diff", "--cached", "--name-only"])
+            .output()
+            .unwrap();
+        assert!(output.stdout.is_empty());
+    }
+}
@@ -0,0 +1,110 @@
+//! Host clipboard access for the bridge's `/clipboard` endpoints, through
+//! whichever clipboard tool the host has: `pbcopy`/`pbpaste` on macOS,
+//! `wl-copy`/`wl-paste` under Wayland, or `xclip` under X11.
+
+use std::process::Stdio;
+
+use color_eyre::eyre::{Result, bail};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::on_path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Clipboard {
+    Pasteboard,
+    Wayland,
+    X11,
+}
+
+impl Clipboard {
+    /// The clipboard of the current session, if a tool for it is installed.
+    pub fn detect() -> Option<Self> {
+        select(
+            cfg!(target_os = "macos"),
+            std::env::var_os("WAYLAND_DISPLAY").is_some(),
+            on_path,
+        )
+    }
+
+    fn copy_command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Self::Pasteboard => ("pbcopy", &[]),
+            Self::Wayland => ("wl-copy", &[]),
+            Self::X11 => ("xclip", &["-selection", "clipboard", "-in"]),
+        }
+    }
+
+    fn paste_command(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Self::Pasteboard => ("pbpaste", &[]),
+            Self::Wayland => ("wl-paste", &["--no-newline"]),
+            Self::X11 => ("xclip", &["-selection", "clipboard", "-out"]),
+        }
+    }
+
+    pub async fn read(self) -> Result<String> {
+        let (program, args) = self.paste_command();
+        let output = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+            .await?;
+        if !output.status.success() {
+            bail!(
+                "{} failed: {}",
+                program,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    pub async fn write(self, text: &str) -> Result<()> {
+        let (program, args) = self.copy_command();
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes()).await?;
+        }
+        let status = child.wait().await?;
+        if !status.success() {
+            bail!("{} exited with {}", program, status);
+        }
+        Ok(())
+    }
+}
+
+fn select(macos: bool, wayland: bool, installed: impl Fn(&str) -> bool) -> Option<Clipboard> {
+    if macos {
+        return installed("pbcopy").then_some(Clipboard::Pasteboard);
+    }
+    // XWayland sessions can fall back to xclip
+    if wayland && installed("wl-copy") && installed("wl-paste") {
+        Some(Clipboard::Wayland)
+    } else if installed("xclip") {
+        Some(Clipboard::X11)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_tool_for_session() {
+        let all = |_: &str| true;
+        let xclip_only = |program: &str| program == "xclip";
+
+        assert_eq!(select(true, false, all), Some(Clipboard::Pasteboard));
+        assert_eq!(select(false, true, all), Some(Clipboard::Wayland));
+        assert_eq!(select(false, true, xclip_only), Some(Clipboard::X11));
+        assert_eq!(select(false, false, all), Some(Clipboard::X11));
+        assert_eq!(select(false, true, |_| false), None);
+    }
+}
@@ -1,34 +1,674 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, WrapErr, bail};
 use dirs::home_dir;
-use serde::Deserialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use shellexpand::tilde_with_context;
 
+use crate::devcontainer;
+use crate::ranges::RangeSource;
+use crate::remote_config;
+use crate::schema::{self, Problem};
+
 pub const DEFAULT_BRIDGE_PORT: u16 = 19432;
 
+pub const DEFAULT_PROXY_PORT: u16 = 19433;
+
+pub const DEFAULT_IP_CACHE_TTL_SECS: u64 = 3600;
+
+pub const DEFAULT_ALLOWED_DOMAINS: &[&str] = &["api.github.com", "github.com", "api.anthropic.com"];
+
 pub const CONTAINER_HOME: &str = "/home/claude";
 
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Config {
+    /// Files merged beneath this one, relative to its directory.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Refuse to run with unknown keys in any config file.
+    #[serde(default)]
+    pub strict: Option<bool>,
+    /// Git URL of a team config repo layered beneath user config; only read
+    /// from system and user config.
+    #[serde(default)]
+    pub remote_config: Option<String>,
+    /// Mount the host's Docker socket, handing the agent its daemon.
+    #[serde(default)]
+    pub allow_docker: Option<bool>,
+    /// Refuse `allow_docker` and mounts of the Docker socket; only read from
+    /// system and team config.
+    #[serde(default)]
+    pub forbid_docker: Option<bool>,
     #[serde(default)]
     pub claude: ClaudeConfig,
     #[serde(default)]
+    pub aws: AwsConfig,
+    #[serde(default)]
+    pub ssh_agent: SshAgentConfig,
+    /// Extra mounts; merged across layers.
+    #[serde(default)]
     pub mounts: Vec<Mount>,
+    /// Container environment; `~` expands to the container home, and
+    /// `op://` values are read with the 1Password CLI like `secrets`.
     #[serde(default)]
-    pub env: HashMap<String, String>,
+    pub env: HashMap<String, EnvValue>,
+    /// Host env vars forwarded into the container with their values at run
+    /// time; merged across layers.
+    #[serde(default)]
+    pub env_passthrough: Vec<String>,
+    /// Dotenv files, relative to the config dir, beneath `env`; merged across
+    /// layers.
+    #[serde(default)]
+    pub env_files: Vec<String>,
+    /// Container env vars or files read on the host at run time.
+    #[serde(default)]
+    pub secrets: HashMap<String, Secret>,
+    /// Look for credentials in the workspace and mounts before running.
+    #[serde(default)]
+    pub secret_scan: Option<SecretScan>,
     #[serde(default)]
     pub bridge: BridgeConfig,
+    /// Domains the container may reach; replaces lower layers' lists.
+    /// `*.example.com` allows every subdomain and needs the DNS proxy.
+    pub allowed_domains: Option<Vec<String>>,
+    /// Providers whose published IP ranges are allowed; replaces lower
+    /// layers' lists.
+    pub ip_ranges: Option<Vec<RangeSource>>,
+    /// Named domain lists added to `allowed_domains`; merged across layers.
+    #[serde(default)]
+    pub presets: Vec<Preset>,
+    /// Domains from `run --allow-domain`, added to `allowed_domains` rather
+    /// than replacing it. Not read from config files.
+    #[serde(skip)]
+    pub extra_allowed_domains: Vec<String>,
+    /// Domains, IPs, or CIDRs blocked even when allowed; merged across layers.
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+    /// Filter DNS inside the container, allowing traffic to whatever the
+    /// allowed domains resolve to at runtime.
+    pub dns_proxy: Option<bool>,
+    /// Container ports to publish on the host (`[host_ip:][host_port:]port`);
+    /// merged across layers.
+    #[serde(default)]
+    pub ports: Vec<String>,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub hardening: HardeningConfig,
+    #[serde(default)]
+    pub engine: EngineConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub workspace: WorkspaceConfig,
+    #[serde(default)]
+    pub jj: JjConfig,
+    #[serde(default)]
+    pub git: GitConfig,
+    #[serde(default)]
+    pub image: ImageConfig,
+}
+
+/// How prebuilt images are checked before they run.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct ImageConfig {
+    /// Published base image, like `ghcr.io/kejadlen/contenant:0.1.0`, to
+    /// pull instead of building `contenant:base` locally.
+    #[serde(default, rename = "ref")]
+    pub reference: Option<String>,
+    /// Platform, like `linux/amd64`, to build and run images for instead of
+    /// the host's.
+    #[serde(default)]
+    #[schemars(pattern(r"^[a-z0-9]+/[a-z0-9]+(/[a-z0-9]+)?$"))]
+    pub platform: Option<String>,
+    /// cosign public key images must be signed with; relative paths resolve
+    /// from the config dir.
+    #[serde(default)]
+    pub cosign_key: Option<String>,
+    /// Predicate type, like `slsaprovenance`, of an attestation images must
+    /// also carry.
+    #[serde(default)]
+    pub attestation: Option<String>,
+}
+
+/// What to do about credentials found in paths mounted into the container.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretScan {
+    /// Don't look.
+    #[default]
+    Off,
+    /// List them and run anyway.
+    Warn,
+    /// List them and refuse to run.
+    Block,
+}
+
+/// Git settings set in the container instead of mounting `~/.gitconfig`.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct GitConfig {
+    /// `user.name` for the agent's commits.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// `user.email` for the agent's commits.
+    #[serde(default)]
+    pub email: Option<String>,
+    /// `user.signingKey`, as git takes it.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+    /// `safe.directory` entries; merged across layers.
+    #[serde(default)]
+    pub safe_directories: Vec<String>,
+}
+
+/// Settings for projects that are jj repos.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct JjConfig {
+    /// Allow the hosts of the repo's git remotes, for `jj git push`.
+    #[serde(default)]
+    pub push: Option<bool>,
+}
+
+/// How the project is put in front of the agent.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    #[serde(default)]
+    pub mode: Option<WorkspaceMode>,
+    /// Subdirectory of the project mounted at `/workspace` instead of the
+    /// whole of it, like `services/api` in a monorepo.
+    #[serde(default)]
+    pub path: Option<String>,
+    /// Directory in `/workspace` the agent starts in.
+    #[serde(default)]
+    pub workdir: Option<String>,
+    /// Paths in the workspace hidden from the container behind empty
+    /// volumes, like `node_modules`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Paths in the workspace the agent can write to in `read-only` mode.
+    #[serde(default)]
+    pub writable: Vec<String>,
+    /// Run the agent with the host user's uid and gid, so files it creates
+    /// are owned by the host user. Defaults to on for Linux hosts.
+    #[serde(default)]
+    pub host_user: Option<bool>,
+}
+
+/// How the project reaches `/workspace`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum WorkspaceMode {
+    /// Bind-mounted, so the agent's changes land as it makes them.
+    #[default]
+    Bind,
+    /// Copied into the container at start, and its changes copied back
+    /// when the agent exits successfully.
+    Copy,
+    /// Bind-mounted read-only, apart from the `writable` paths.
+    ReadOnly,
+    /// Copied into the container at start, and kept in sync with the
+    /// project by Mutagen.
+    Sync,
+}
+
+/// How the container reaches the network.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct NetworkConfig {
+    #[serde(default)]
+    pub mode: Option<NetworkMode>,
+    /// Host port for the egress proxy in `proxy` mode.
+    #[serde(default)]
+    pub proxy_port: Option<u16>,
+    /// Ports the egress proxy lets the container reach; 80 and 443 unless
+    /// set. Replaces lower layers' lists.
+    #[serde(default)]
+    pub proxy_allowed_ports: Option<Vec<u16>>,
+    /// How long resolved domains and IP ranges are reused between runs.
+    #[serde(default)]
+    pub ip_cache_ttl_secs: Option<u64>,
+    /// DNS servers for resolving allowed domains instead of the system
+    /// resolver.
+    #[serde(default)]
+    pub resolvers: Option<Vec<String>>,
+    /// DNS servers for specific domains and their subdomains.
+    #[serde(default)]
+    pub domain_resolvers: HashMap<String, Vec<String>>,
+    /// Attach containers to a dedicated Docker network so they can reach
+    /// sidecar containers on it by name.
+    #[serde(default)]
+    pub shared: Option<bool>,
+    /// Name shared by projects on the same network; the project by default.
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Corporate HTTP or SOCKS proxy (`http://proxy.corp:3128`) that egress
+    /// and range fetches go through.
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// Hosts the container reaches without the proxy.
+    #[serde(default)]
+    pub no_proxy: Option<Vec<String>>,
+    /// Allow private (RFC 1918), link-local, and mDNS addresses, for
+    /// services on the local network.
+    #[serde(default)]
+    pub allow_local: Option<bool>,
+    /// Cap on the container's upload rate, in tc's units like `10mbit`.
+    #[serde(default)]
+    #[schemars(pattern(r"^[0-9]+(\.[0-9]+)?([kmgt]?bit|[kmgt]?bps)$"))]
+    pub max_egress: Option<String>,
+    /// Cap on the container's download rate, in tc's units like `50mbit`.
+    #[serde(default)]
+    #[schemars(pattern(r"^[0-9]+(\.[0-9]+)?([kmgt]?bit|[kmgt]?bps)$"))]
+    pub max_ingress: Option<String>,
+}
+
+/// Domains a toolchain's package manager needs, allowed via `presets`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Node,
+    Rust,
+    Python,
+    Go,
+    Ruby,
+}
+
+impl Preset {
+    pub fn domains(self) -> &'static [&'static str] {
+        match self {
+            Preset::Node => &["registry.npmjs.org", "nodejs.org", "registry.yarnpkg.com"],
+            Preset::Rust => &[
+                "crates.io",
+                "static.crates.io",
+                "index.crates.io",
+                "static.rust-lang.org",
+            ],
+            Preset::Python => &["pypi.org", "files.pythonhosted.org"],
+            Preset::Go => &[
+                "proxy.golang.org",
+                "sum.golang.org",
+                "storage.googleapis.com",
+            ],
+            Preset::Ruby => &["rubygems.org", "index.rubygems.org"],
+        }
+    }
+}
+
+/// How egress to `allowed_domains` is enforced.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NetworkMode {
+    /// Firewall rules for the domains' resolved IPs.
+    #[default]
+    Firewall,
+    /// All traffic goes through a host proxy that filters by hostname.
+    Proxy,
+}
+
+/// The container engine contenant drives.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct EngineConfig {
+    #[serde(default)]
+    pub backend: Option<EngineBackend>,
+    /// Settings for the `kubernetes` backend.
+    #[serde(default)]
+    pub kubernetes: Option<KubernetesConfig>,
+    /// Start a stopped Colima/Lima VM hosting the Docker daemon.
+    #[serde(default)]
+    pub autostart_vm: Option<bool>,
+}
+
+impl std::fmt::Display for EngineBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineBackend::Docker => write!(f, "docker"),
+            EngineBackend::DockerApi => write!(f, "docker-api"),
+            EngineBackend::Kubernetes => write!(f, "kubernetes"),
+            EngineBackend::AppleContainer => write!(f, "apple-container"),
+        }
+    }
+}
+
+/// Where and how the Kubernetes backend runs pods.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct KubernetesConfig {
+    /// kubectl context (defaults to the current context).
+    #[serde(default)]
+    pub context: Option<String>,
+    /// Namespace pods run in (defaults to the context's).
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Registry prefix images are pushed to so the cluster can pull them.
+    #[serde(default)]
+    pub registry: Option<String>,
+    /// How long to wait for a pod to start.
+    #[serde(default = "default_startup_timeout_secs")]
+    pub startup_timeout_secs: u64,
+}
+
+fn default_startup_timeout_secs() -> u64 {
+    300
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        Self {
+            context: None,
+            namespace: None,
+            registry: None,
+            startup_timeout_secs: default_startup_timeout_secs(),
+        }
+    }
+}
+
+/// Container backend used to build images and run the agent.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EngineBackend {
+    /// The `docker` CLI.
+    Docker,
+    /// The Docker Engine API over the daemon socket (`docker-api` feature).
+    DockerApi,
+    /// Pods in a Kubernetes cluster, via `kubectl`.
+    Kubernetes,
+    /// Apple's `container` CLI (macOS 15+).
+    AppleContainer,
+}
+
+/// Reductions of the container's privileges beyond the network firewall.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct HardeningConfig {
+    /// Drop every capability except those the entrypoint needs.
+    #[serde(default)]
+    pub drop_capabilities: Option<bool>,
+    /// Keep processes from gaining privileges, as through setuid binaries.
+    #[serde(default)]
+    pub no_new_privileges: Option<bool>,
+    /// Seccomp profile JSON; relative paths resolve from the config dir.
+    #[serde(default)]
+    pub seccomp: Option<String>,
+    /// Mount the root filesystem read-only.
+    #[serde(default)]
+    pub read_only_root: Option<bool>,
+    /// Paths given a tmpfs when the root filesystem is read-only.
+    #[serde(default)]
+    pub writable_paths: Vec<String>,
+}
+
+/// Mandatory access control applied to the container.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// AppArmor profile name (`contenant-default` is bundled).
+    #[serde(default)]
+    pub apparmor: Option<String>,
+    /// SELinux label options, e.g. `type:container_t` or `disable`.
+    #[serde(default)]
+    pub selinux_label: Option<String>,
+    /// Relabel bind mounts so SELinux lets the container use them.
+    #[serde(default)]
+    pub selinux_relabel: Option<SelinuxRelabel>,
+    /// Caps on processes and open files.
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+/// Caps on what the container's processes can use up, so a fork bomb or a
+/// descriptor leak in a build can't wedge the daemon or the host.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Most processes and threads in the container (`--pids-limit`).
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub pids: Option<u64>,
+    /// Most open files per process (`--ulimit nofile`).
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub nofile: Option<u64>,
+    /// Most processes per user (`--ulimit nproc`).
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub nproc: Option<u64>,
+}
+
+impl LimitsConfig {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelinuxRelabel {
+    /// Content may be shared between containers (`:z`).
+    Shared,
+    /// Content is private to this container (`:Z`).
+    Private,
+}
+
+/// The host service the container reaches for triggers, the clipboard,
+/// and credentials.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct BridgeConfig {
+    /// Host port the bridge listens on.
     #[serde(default = "default_bridge_port")]
     pub port: u16,
+    /// Named host commands the container can run through the bridge.
+    #[serde(default)]
+    pub triggers: HashMap<String, Trigger>,
+    /// Serve on a unix socket mounted into the container instead of a TCP
+    /// port.
+    #[serde(default)]
+    pub socket: Option<bool>,
+    /// Expose the host clipboard at `/clipboard`.
     #[serde(default)]
-    pub triggers: HashMap<String, String>,
+    pub clipboard: Option<bool>,
+    /// URL prefixes the container may open in the host browser.
+    #[serde(default)]
+    pub open_urls: Vec<String>,
+    /// Host directory the container may upload files into.
+    #[serde(default)]
+    pub artifacts_dir: Option<String>,
+    /// Largest upload accepted into `artifacts_dir`, in megabytes.
+    #[serde(default)]
+    pub artifacts_max_mb: Option<u64>,
+    /// URLs to POST trigger and session events to.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+    /// Signs webhook bodies in `X-Contenant-Signature`.
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    /// Report changes in the project directory to its sessions.
+    #[serde(default)]
+    pub watch: Option<bool>,
+    /// Address to listen on instead of 127.0.0.1.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Serve HTTPS with a certificate from a private CA.
+    #[serde(default)]
+    pub tls: Option<bool>,
+    /// Require a client certificate minted for each run. Implies `tls`.
+    #[serde(default)]
+    pub client_certs: Option<bool>,
+    /// Extra host names or IPs for the server certificate.
+    #[serde(default)]
+    pub tls_names: Vec<String>,
+    /// Have git in the container get github.com credentials from the bridge.
+    #[serde(default)]
+    pub github_tokens: Option<bool>,
+    /// GitHub App the bridge mints repo-scoped tokens with, instead of
+    /// handing out `gh auth token`.
+    #[serde(default)]
+    pub github_app: Option<GithubApp>,
+}
+
+/// A GitHub App installed on the repos sessions push to.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[schemars(deny_unknown_fields)]
+pub struct GithubApp {
+    /// The App's numeric ID.
+    #[schemars(range(min = 1))]
+    pub app_id: u64,
+    /// PEM private key, relative to the config file.
+    pub private_key: String,
+    /// Permissions to limit tokens to, e.g. `contents: write`, instead of
+    /// everything the installation has.
+    #[serde(default)]
+    pub permissions: BTreeMap<String, String>,
+}
+
+/// A host command the container can run through the bridge, written as
+/// the command string or as a map with options.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(try_from = "TriggerSpec")]
+pub struct Trigger {
+    pub command: TriggerCommand,
+    /// Shown to the container when it lists triggers.
+    pub description: Option<String>,
+    /// Directory to run in, instead of the bridge's.
+    pub cwd: Option<String>,
+    /// Set for every run, taking precedence over the request's env.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Accept `args`, `env`, and `stdin` in the request body.
+    pub allow_args: bool,
+    /// Kill the command's process group after this long.
+    pub timeout_secs: Option<u64>,
+    /// Reject requests while this many runs are in flight.
+    pub max_concurrent: Option<usize>,
+    /// Ask on the host before each run.
+    pub confirm: bool,
+}
+
+/// How a trigger runs: a script for `sh -c`, or a program and its arguments
+/// executed directly, with request args appended.
+#[derive(Clone, Debug, JsonSchema, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum TriggerCommand {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+impl Default for TriggerCommand {
+    fn default() -> Self {
+        Self::Shell(String::new())
+    }
+}
+
+impl std::fmt::Display for TriggerCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shell(script) => write!(f, "{}", script),
+            Self::Exec(argv) => write!(f, "{:?}", argv),
+        }
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(deny_unknown_fields)]
+enum TriggerSpec {
+    Command(String),
+    Full {
+        /// A script, or a program and its arguments.
+        command: CommandSpec,
+        /// Run through `sh -c`. Defaults to true for a string command and
+        /// false for a list.
+        #[serde(default)]
+        shell: Option<bool>,
+        /// Shown to the container when it lists triggers.
+        #[serde(default)]
+        description: Option<String>,
+        /// Directory to run in, instead of the bridge's.
+        #[serde(default)]
+        cwd: Option<String>,
+        /// Set for every run, taking precedence over the request's env.
+        #[serde(default)]
+        env: HashMap<String, String>,
+        /// Accept `args`, `env`, and `stdin` in the request body.
+        #[serde(default)]
+        allow_args: bool,
+        /// Kill the command's process group after this long.
+        #[serde(default)]
+        timeout_secs: Option<u64>,
+        /// Reject requests while this many runs are in flight.
+        #[serde(default)]
+        max_concurrent: Option<usize>,
+        /// Ask on the host before each run.
+        #[serde(default)]
+        confirm: bool,
+    },
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum CommandSpec {
+    String(String),
+    List(Vec<String>),
+}
+
+impl TryFrom<TriggerSpec> for Trigger {
+    type Error = String;
+
+    fn try_from(spec: TriggerSpec) -> Result<Self, Self::Error> {
+        match spec {
+            TriggerSpec::Command(command) => Ok(Self {
+                command: TriggerCommand::Shell(command),
+                ..Default::default()
+            }),
+            TriggerSpec::Full {
+                command,
+                shell,
+                description,
+                cwd,
+                env,
+                allow_args,
+                timeout_secs,
+                max_concurrent,
+                confirm,
+            } => {
+                let command = match (command, shell) {
+                    (CommandSpec::String(script), None | Some(true)) => {
+                        TriggerCommand::Shell(script)
+                    }
+                    // Without a shell there's no quoting, just words
+                    (CommandSpec::String(line), Some(false)) => {
+                        TriggerCommand::Exec(line.split_whitespace().map(String::from).collect())
+                    }
+                    (CommandSpec::List(argv), None | Some(false)) => TriggerCommand::Exec(argv),
+                    (CommandSpec::List(words), Some(true)) => {
+                        TriggerCommand::Shell(words.join(" "))
+                    }
+                };
+                if matches!(&command, TriggerCommand::Exec(argv) if argv.is_empty()) {
+                    return Err("trigger command can't be empty".to_string());
+                }
+                Ok(Self {
+                    command,
+                    description,
+                    cwd,
+                    env,
+                    allow_args,
+                    timeout_secs,
+                    max_concurrent,
+                    confirm,
+                })
+            }
+        }
+    }
 }
 
 fn default_bridge_port() -> u16 {
@@ -40,132 +680,803 @@ impl Default for BridgeConfig {
         Self {
             port: DEFAULT_BRIDGE_PORT,
             triggers: HashMap::new(),
+            socket: None,
+            clipboard: None,
+            open_urls: vec![],
+            artifacts_dir: None,
+            artifacts_max_mb: None,
+            webhooks: vec![],
+            webhook_secret: None,
+            watch: None,
+            address: None,
+            tls: None,
+            client_certs: None,
+            tls_names: vec![],
+            github_tokens: None,
+            github_app: None,
         }
     }
 }
 
-#[derive(Debug, Default, Deserialize)]
+impl BridgeConfig {
+    /// Whether the bridge serves HTTPS. A unix socket takes precedence, since
+    /// it never leaves the machine.
+    pub fn uses_tls(&self) -> bool {
+        !self.socket.unwrap_or(false)
+            && (self.tls.unwrap_or(false) || self.client_certs.unwrap_or(false))
+    }
+}
+
+/// Claude Code in the container, and the login it uses.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ClaudeConfig {
+    /// Claude Code version to install; last layer wins.
     #[serde(default)]
     pub version: Option<String>,
+    /// How the macOS Keychain's Claude login is shared with the container.
+    #[serde(default)]
+    pub keychain: Option<KeychainSync>,
+    /// Where the host keeps the login; found automatically if unset.
+    #[serde(default)]
+    pub credential_store: Option<CredentialStore>,
+}
+
+/// AWS credentials handed to the container.
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct AwsConfig {
+    /// Profile whose short-lived credentials the container gets.
+    #[serde(default)]
+    pub profile: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+/// The host's SSH agent, forwarded for pushing and commit signing.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct SshAgentConfig {
+    /// Forward the first agent found on the host.
+    #[serde(default)]
+    pub forward: Option<bool>,
+    /// Agent socket to forward instead; implies `forward`.
+    #[serde(default)]
+    pub socket: Option<String>,
+    /// Sign git commits and tags with the agent's key.
+    #[serde(default)]
+    pub sign_commits: Option<bool>,
+    /// Public key to sign with, instead of the agent's first.
+    #[serde(default)]
+    pub signing_key: Option<String>,
+}
+
+/// Where the host keeps Claude's credentials.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CredentialStore {
+    /// The macOS Keychain.
+    Keychain,
+    /// libsecret's `secret-tool`.
+    SecretService,
+    Pass,
+    Gopass,
+}
+
+/// How the Keychain's Claude credentials reach the container.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeychainSync {
+    /// Left alone; log in inside the container.
+    Off,
+    /// Copied into the container's Claude state before every run.
+    #[default]
+    Read,
+    /// Copied in, and a token the container refreshed copied back after.
+    Sync,
+}
+
+/// A bind mount, tmpfs, or volume added to the container.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Mount {
+    /// Host path, or the volume's name for `type: volume`. Unused by tmpfs
+    /// mounts.
+    #[serde(default)]
     pub source: String,
+    /// Container path, where `~` is the container home; defaults to the
+    /// source. Required for tmpfs and volume mounts.
     pub target: Option<String>,
+    /// Mount read-only; on by default.
     #[serde(default = "default_readonly")]
     pub readonly: bool,
+    #[serde(default, rename = "type")]
+    pub kind: MountType,
+    /// Size limit of a tmpfs mount, like `512m`.
+    #[serde(default)]
+    pub size: Option<String>,
+    /// Permissions of a tmpfs mount, written in octal like `1777`.
+    #[serde(default)]
+    pub mode: Option<u32>,
+    /// Only mount on matching hosts.
+    #[serde(default)]
+    pub when: When,
+    /// Create a bind mount's source directory if it doesn't exist, rather
+    /// than refusing to start.
+    #[serde(default)]
+    pub create_missing: bool,
+    /// How closely a bind mount's host and container views are kept in
+    /// step, which trades speed for freshness on Docker Desktop.
+    #[serde(default)]
+    pub consistency: Option<Consistency>,
+    /// Whether mounts made under a bind mount's source on one side appear
+    /// on the other.
+    #[serde(default)]
+    pub propagation: Option<Propagation>,
 }
 
-fn default_readonly() -> bool {
-    true
+/// Docker's bind mount consistency modes.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Consistency {
+    /// The host and container always agree.
+    Consistent,
+    /// The host's view is authoritative; the container may lag behind it.
+    Cached,
+    /// The container's view is authoritative; the host may lag behind it.
+    Delegated,
 }
 
-impl Mount {
-    /// Format as a Docker volume mount string.
-    ///
-    /// Relative source paths are resolved from `config_dir`.
-    pub fn to_docker_volume(&self, config_dir: &Path) -> String {
-        let host_home = || home_dir().map(|p| p.to_string_lossy().into_owned());
-        let container_home = || Some(CONTAINER_HOME.to_string());
-
-        let source = tilde_with_context(&self.source, host_home);
-        let target_str = self.target.as_deref().unwrap_or(&self.source);
-        let target = tilde_with_context(target_str, container_home);
-
-        let source_path = Path::new(source.as_ref());
-        let source = if source_path.is_relative() {
-            config_dir.join(source_path).to_string_lossy().into_owned()
-        } else {
-            source.into_owned()
-        };
+/// Docker's bind propagation modes.
+#[derive(Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Propagation {
+    Private,
+    Rprivate,
+    Shared,
+    Rshared,
+    Slave,
+    Rslave,
+}
 
-        let suffix = if self.readonly { ":ro" } else { "" };
-        format!("{}:{}{}", source, target, suffix)
+impl Consistency {
+    fn as_str(self) -> &'static str {
+        match self {
+            Consistency::Consistent => "consistent",
+            Consistency::Cached => "cached",
+            Consistency::Delegated => "delegated",
+        }
     }
 }
 
-impl Config {
-    fn load_file(path: &Path) -> Result<Self> {
-        let contents = fs::read_to_string(path)?;
-        let config = serde_yaml_ng::from_str(&contents)?;
-        Ok(config)
+impl Propagation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Propagation::Private => "private",
+            Propagation::Rprivate => "rprivate",
+            Propagation::Shared => "shared",
+            Propagation::Rshared => "rshared",
+            Propagation::Slave => "slave",
+            Propagation::Rslave => "rslave",
+        }
     }
 }
 
-/// Source of a configuration layer, ordered by precedence (lowest first).
-#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
-pub enum ConfigSource {
-    /// Built-in defaults (lowest precedence).
-    Default,
-    /// User-level config (~/.config/contenant/config.yml).
-    User,
-    /// Project-level config (.contenant/config.yml in the project root).
-    Project,
+/// What a mount puts at its target.
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MountType {
+    /// A host path.
+    #[default]
+    Bind,
+    /// An empty in-memory filesystem, for scratch space or to mask what's
+    /// below it.
+    Tmpfs,
+    /// A named volume the engine manages, kept between runs.
+    Volume,
 }
 
-impl std::fmt::Display for ConfigSource {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+/// An `env` value, optionally set only on some hosts.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(untagged)]
+pub enum EnvValue {
+    Plain(String),
+    Conditional(EnvVariant),
+    /// The first variant that matches wins.
+    Variants(Vec<EnvVariant>),
+}
+
+/// An `env` value set only on hosts matching `when`.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct EnvVariant {
+    pub value: String,
+    /// Only set on matching hosts.
+    #[serde(default)]
+    pub when: When,
+}
+
+impl EnvValue {
+    /// The value on `host`, if any applies.
+    pub fn resolve(&self, host: &Host) -> Option<&str> {
         match self {
-            ConfigSource::Default => write!(f, "default"),
-            ConfigSource::User => write!(f, "user"),
-            ConfigSource::Project => write!(f, "project"),
+            EnvValue::Plain(value) => Some(value),
+            EnvValue::Conditional(variant) => {
+                variant.when.matches(host).then_some(variant.value.as_str())
+            }
+            EnvValue::Variants(variants) => variants
+                .iter()
+                .find(|v| v.when.matches(host))
+                .map(|v| v.value.as_str()),
         }
     }
 }
 
-/// A single configuration layer with its source.
-#[derive(Debug)]
-pub struct ConfigLayer {
-    pub source: ConfigSource,
-    pub data: Config,
-    /// Directory used to resolve relative mount source paths in this layer.
-    pub config_dir: PathBuf,
+impl From<&str> for EnvValue {
+    fn from(value: &str) -> Self {
+        EnvValue::Plain(value.to_string())
+    }
 }
 
-/// Layered configuration that preserves all layers and resolves values on read.
-///
-/// Layers are stored in order of precedence (lowest first). Accessors walk
-/// layers from highest to lowest precedence, taking the first value found
-/// (for scalars/overrides) or accumulating across all layers (for additive
-/// fields like mounts).
-#[derive(Debug, Default)]
-pub struct StackedConfig {
-    layers: Vec<ConfigLayer>,
+/// A `secrets` entry: a reference to the value on the host, and whether
+/// the container gets it as a file under `/run/secrets` instead of an env
+/// var.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq)]
+#[serde(from = "SecretSpec")]
+pub struct Secret {
+    pub from: String,
+    pub file: bool,
+}
+
+/// Secret as written in config: a bare reference or a table.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+#[schemars(deny_unknown_fields)]
+enum SecretSpec {
+    Reference(String),
+    Detailed {
+        /// `env:NAME`, `keychain:SERVICE`, `op://...`, or `cmd:COMMAND`.
+        from: String,
+        /// Write it to `/run/secrets/NAME` instead of an env var.
+        #[serde(default)]
+        file: bool,
+    },
+}
+
+impl From<SecretSpec> for Secret {
+    fn from(spec: SecretSpec) -> Self {
+        match spec {
+            SecretSpec::Reference(from) => Secret { from, file: false },
+            SecretSpec::Detailed { from, file } => Secret { from, file },
+        }
+    }
+}
+
+/// Conditions on the host for a `mounts` or `env` entry; every one given
+/// has to match.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, PartialEq)]
+#[schemars(deny_unknown_fields)]
+pub struct When {
+    /// `macos` or `linux`, as in Rust's `std::env::consts::OS`.
+    #[serde(default)]
+    pub os: Option<String>,
+    /// `aarch64` or `x86_64`, as in Rust's `std::env::consts::ARCH`.
+    #[serde(default)]
+    pub arch: Option<String>,
+    /// The host name, ignoring case; a trailing `*` matches any suffix.
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+impl When {
+    pub fn matches(&self, host: &Host) -> bool {
+        let hostname = |pattern: &str| {
+            let pattern = pattern.to_lowercase();
+            let hostname = host.hostname.to_lowercase();
+            match pattern.strip_suffix('*') {
+                Some(prefix) => hostname.starts_with(prefix),
+                None => hostname == pattern,
+            }
+        };
+        self.os.as_ref().is_none_or(|os| *os == host.os)
+            && self.arch.as_ref().is_none_or(|arch| *arch == host.arch)
+            && self.hostname.as_deref().is_none_or(hostname)
+    }
+}
+
+/// What `when:` conditions are checked against.
+#[derive(Clone, Debug)]
+pub struct Host {
+    pub os: String,
+    pub arch: String,
+    pub hostname: String,
+}
+
+impl Host {
+    pub fn current() -> Self {
+        let mut buf = [0u8; 256];
+        // SAFETY: gethostname writes at most buf.len() bytes into buf
+        let ok = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } == 0;
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        Self {
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            hostname: if ok {
+                String::from_utf8_lossy(&buf[..len]).into_owned()
+            } else {
+                String::new()
+            },
+        }
+    }
+}
+
+impl Default for Host {
+    fn default() -> Self {
+        Self::current()
+    }
+}
+
+fn default_readonly() -> bool {
+    true
+}
+
+impl Mount {
+    /// Format as a Docker volume mount string.
+    ///
+    /// Relative source paths are resolved from `config_dir`.
+    pub fn to_docker_volume(&self, config_dir: &Path) -> String {
+        let container_home = || Some(CONTAINER_HOME.to_string());
+        let target_str = self.target.as_deref().unwrap_or(&self.source);
+        let target = tilde_with_context(target_str, container_home);
+
+        format!(
+            "{}:{}{}",
+            self.host_source(config_dir).display(),
+            target,
+            self.bind_options(self.readonly)
+        )
+    }
+
+    /// The `:opts` suffix of a bind mount string, if it has any options.
+    fn bind_options(&self, readonly: bool) -> String {
+        let options: Vec<_> = readonly
+            .then_some("ro")
+            .into_iter()
+            .chain(self.consistency.map(Consistency::as_str))
+            .chain(self.propagation.map(Propagation::as_str))
+            .collect();
+        if options.is_empty() {
+            String::new()
+        } else {
+            format!(":{}", options.join(","))
+        }
+    }
+
+    /// A bind mount's source on the host, with `~` expanded and relative
+    /// paths resolved from `config_dir`.
+    pub fn host_source(&self, config_dir: &Path) -> PathBuf {
+        let host_home = || home_dir().map(|p| p.to_string_lossy().into_owned());
+        let source = tilde_with_context(&self.source, host_home);
+        config_dir.join(source.as_ref())
+    }
+
+    /// The tmpfs a `type: tmpfs` mount describes. Its target is required and
+    /// must be absolute once `~` expands to the container home.
+    pub fn to_tmpfs(&self) -> Result<crate::TmpfsMount> {
+        let target = self.container_target("tmpfs")?;
+        if let Some(mode) = self.mode
+            && mode.to_string().chars().any(|c| c > '7')
+        {
+            bail!("tmpfs mount mode {} isn't octal", mode);
+        }
+        Ok(crate::TmpfsMount {
+            target,
+            size: self.size.clone(),
+            mode: self.mode,
+        })
+    }
+
+    /// The volume a `type: volume` mount describes, named
+    /// `contenant-<project id>-<source>` so projects don't share one by
+    /// accident. Its target is required, as for tmpfs mounts.
+    pub fn to_volume(&self, project_id: &str) -> Result<crate::VolumeMount> {
+        if self.source.is_empty() {
+            bail!("volume mounts need a source naming the volume");
+        }
+        let name: String = format!("contenant-{}-{}", project_id, self.source)
+            .to_ascii_lowercase()
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | '0'..='9' | '_' | '.' | '-' => c,
+                _ => '-',
+            })
+            .collect();
+        Ok(crate::VolumeMount {
+            name,
+            target: self.container_target("volume")?,
+            readonly: self.readonly,
+        })
+    }
+
+    /// `target` with `~` expanded, which must be absolute.
+    fn container_target(&self, kind: &str) -> Result<String> {
+        let Some(target) = &self.target else {
+            bail!("{} mounts need a target", kind);
+        };
+        if self.consistency.is_some() || self.propagation.is_some() {
+            bail!(
+                "{} mounts don't take consistency or propagation, which are for bind mounts",
+                kind
+            );
+        }
+        let target = tilde_with_context(target, || Some(CONTAINER_HOME.to_string()));
+        if !target.starts_with('/') {
+            bail!("{} mount target {} isn't an absolute path", kind, target);
+        }
+        Ok(target.into_owned())
+    }
+
+    /// Whether `source` has `*`, `?`, or `**` in it.
+    pub fn is_glob(&self) -> bool {
+        self.source.contains(['*', '?'])
+    }
+
+    /// Docker volume mount strings for this mount. A glob `source` expands
+    /// on the host into a read-only mount per matching file, under `target`
+    /// (by default, the part of `source` before the first wildcard) at the
+    /// file's path below that part.
+    pub fn to_docker_volumes(&self, config_dir: &Path) -> Vec<String> {
+        if !self.is_glob() {
+            return vec![self.to_docker_volume(config_dir)];
+        }
+
+        let (base, pattern) = split_glob(&self.source);
+        let base_dir = resolve_path(config_dir, &base);
+        let target = tilde_with_context(self.target.as_deref().unwrap_or(&base), || {
+            Some(CONTAINER_HOME.to_string())
+        });
+        let target = target.trim_end_matches('/');
+        let pattern: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+
+        let mut files = vec![];
+        walk_files(Path::new(&base_dir), &mut vec![], &mut files);
+        files.sort();
+        files
+            .into_iter()
+            .filter(|path| {
+                let components: Vec<&str> = path.iter().map(String::as_str).collect();
+                glob_matches(&pattern, &components)
+            })
+            .map(|path| {
+                let path = path.join("/");
+                format!(
+                    "{}/{}:{}/{}{}",
+                    base_dir.trim_end_matches('/'),
+                    path,
+                    target,
+                    path,
+                    self.bind_options(true)
+                )
+            })
+            .collect()
+    }
+}
+
+/// A glob source split into the directory before its first wildcard and
+/// the pattern below it.
+fn split_glob(source: &str) -> (String, &str) {
+    let wildcard = source.find(['*', '?']).unwrap_or(source.len());
+    let split = source[..wildcard].rfind('/').map_or(0, |i| i + 1);
+    let base = match &source[..split] {
+        "" => ".".to_string(),
+        base => base.to_string(),
+    };
+    (base, &source[split..])
+}
+
+/// Collect the files below `dir` as component lists relative to it. Hidden
+/// entries are skipped, and symlinked directories aren't followed.
+fn walk_files(dir: &Path, prefix: &mut Vec<String>, files: &mut Vec<Vec<String>>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name.starts_with('.') {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        prefix.push(name);
+        if file_type.is_dir() {
+            walk_files(&entry.path(), prefix, files);
+        } else if entry.path().is_file() {
+            files.push(prefix.clone());
+        }
+        prefix.pop();
+    }
+}
+
+/// Whether path components match glob components, where `**` matches any
+/// number of directories.
+pub(crate) fn glob_matches(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(&"**"), _) => {
+            glob_matches(&pattern[1..], path)
+                || (!path.is_empty() && glob_matches(pattern, &path[1..]))
+        }
+        (Some(component), Some(name)) => {
+            wildcard_matches(component.as_bytes(), name.as_bytes())
+                && glob_matches(&pattern[1..], &path[1..])
+        }
+        _ => false,
+    }
+}
+
+/// Whether a file name matches a pattern of literal bytes, `*`, and `?`.
+fn wildcard_matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            wildcard_matches(&pattern[1..], name)
+                || (!name.is_empty() && wildcard_matches(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => wildcard_matches(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) => p == n && wildcard_matches(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+impl Config {
+    fn load_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let config = serde_yaml_ng::from_str(&contents)?;
+        Ok(config)
+    }
+}
+
+/// `path` with `~` expanded, relative to the config dir of the layer that
+/// set it.
+pub(crate) fn resolve_path(config_dir: &Path, path: &str) -> String {
+    let path = tilde_with_context(path, || {
+        home_dir().map(|p| p.to_string_lossy().into_owned())
+    });
+    config_dir
+        .join(path.as_ref())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Append `path` to `files` after its includes, depth first. `stack` holds
+/// the files being included, to catch cycles; a file included twice is only
+/// loaded the first time.
+fn with_includes(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    seen: &mut HashSet<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let canonical = fs::canonicalize(path)
+        .wrap_err_with(|| format!("Couldn't read config {}", path.display()))?;
+    if stack.contains(&canonical) {
+        let cycle: Vec<_> = stack
+            .iter()
+            .chain([&canonical])
+            .map(|p| p.display().to_string())
+            .collect();
+        bail!("Config includes form a cycle: {}", cycle.join(" -> "));
+    }
+    if !seen.insert(canonical.clone()) {
+        return Ok(());
+    }
+
+    // A file that doesn't parse has no includes here; loading reports it
+    let includes = Config::load_file(path)
+        .map(|config| config.include)
+        .unwrap_or_default();
+    let dir = path.parent().unwrap();
+    stack.push(canonical);
+    for include in includes {
+        let include = tilde_with_context(&include, || {
+            home_dir().map(|p| p.to_string_lossy().into_owned())
+        });
+        // Resolved so `..` doesn't end up in mount sources
+        let include_path = dir.join(include.as_ref());
+        let include_path = fs::canonicalize(&include_path).wrap_err_with(|| {
+            format!(
+                "Couldn't read config {}, included from {}",
+                include_path.display(),
+                path.display()
+            )
+        })?;
+        with_includes(&include_path, stack, seen, files)?;
+    }
+    stack.pop();
+    files.push(path.to_path_buf());
+    Ok(())
+}
+
+/// Directories an admin can put a system-wide `config.yml` in, lowest
+/// precedence first.
+fn system_config_dirs() -> Vec<PathBuf> {
+    let mut dirs = vec![PathBuf::from("/etc/contenant")];
+    if cfg!(target_os = "macos") {
+        dirs.push(PathBuf::from("/Library/Application Support/contenant"));
+    }
+    dirs
+}
+
+/// Source of a configuration layer, ordered by precedence (lowest first).
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfigSource {
+    /// Built-in defaults (lowest precedence).
+    Default,
+    /// System-wide config an admin provisions (/etc/contenant/config.yml).
+    System,
+    /// Team config synced from the `remote_config` git repo.
+    Remote,
+    /// User-level config (~/.config/contenant/config.yml).
+    User,
+    /// Project-level config (.contenant/config.yml in the project root).
+    Project,
+    /// `--set` overrides on the command line (highest precedence).
+    Cli,
+}
+
+impl std::fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::System => write!(f, "system"),
+            ConfigSource::Remote => write!(f, "remote"),
+            ConfigSource::User => write!(f, "user"),
+            ConfigSource::Project => write!(f, "project"),
+            ConfigSource::Cli => write!(f, "cli"),
+        }
+    }
+}
+
+/// A single configuration layer with its source.
+#[derive(Debug)]
+pub struct ConfigLayer {
+    pub source: ConfigSource,
+    pub data: Config,
+    /// Directory used to resolve relative mount source paths in this layer.
+    pub config_dir: PathBuf,
+}
+
+/// Layered configuration that preserves all layers and resolves values on read.
+///
+/// Layers are stored in order of precedence (lowest first). Accessors walk
+/// layers from highest to lowest precedence, taking the first value found
+/// (for scalars/overrides) or accumulating across all layers (for additive
+/// fields like mounts).
+#[derive(Debug, Default)]
+pub struct StackedConfig {
+    layers: Vec<ConfigLayer>,
+    host: Host,
+    /// Keys in the loaded files that contenant doesn't know.
+    unknown_keys: Vec<(ConfigSource, Problem)>,
 }
 
 impl StackedConfig {
     /// Load all configuration layers.
     ///
     /// If `project_dir` is provided, a project-level layer is loaded from
-    /// `<project_dir>/.contenant/config.yml` when that file exists.
+    /// `<project_dir>/.contenant/config.yml` when that file exists, or else
+    /// imported from the project's `devcontainer.json`.
     pub fn load(xdg_dirs: &xdg::BaseDirectories, project_dir: Option<&Path>) -> Result<Self> {
         let mut config = Self::with_defaults();
+        for (source, path) in Self::files(xdg_dirs, project_dir)? {
+            let config_dir = path.parent().unwrap().to_path_buf();
+            let data = Config::load_file(&path)?;
+            config.add_layer(source, data, config_dir);
 
-        if let Some(config_path) = xdg_dirs.find_config_file("config.yml") {
-            let config_dir = config_path.parent().unwrap().to_path_buf();
-            let data = Config::load_file(&config_path)?;
-            config.add_layer(ConfigSource::User, data, config_dir);
+            let text = fs::read_to_string(&path)?;
+            for problem in schema::unknown_keys_in(&path, &text) {
+                config.unknown_keys.push((source, problem));
+            }
+        }
+
+        // Without a project config, a devcontainer.json stands in for it
+        if let Some(project_dir) = project_dir
+            && !project_dir.join(".contenant/config.yml").exists()
+            && let Some(path) = devcontainer::find(project_dir)
+        {
+            match devcontainer::load(&path).and_then(|dc| devcontainer::config(project_dir, &dc)) {
+                Ok(data) => {
+                    let config_dir = path.parent().unwrap().to_path_buf();
+                    config.add_layer(ConfigSource::Project, data, config_dir);
+                }
+                Err(err) => eprintln!("contenant: ignoring {}: {:#}", path.display(), err),
+            }
         }
+        Ok(config)
+    }
+
+    /// The config files for each layer, lowest precedence first. A file's
+    /// includes come right before it, in the same layer.
+    pub fn files(
+        xdg_dirs: &xdg::BaseDirectories,
+        project_dir: Option<&Path>,
+    ) -> Result<Vec<(ConfigSource, PathBuf)>> {
+        Self::files_from(&system_config_dirs(), xdg_dirs, project_dir)
+    }
 
+    fn files_from(
+        system_dirs: &[PathBuf],
+        xdg_dirs: &xdg::BaseDirectories,
+        project_dir: Option<&Path>,
+    ) -> Result<Vec<(ConfigSource, PathBuf)>> {
+        let mut roots = vec![];
+        for dir in system_dirs {
+            let config_path = dir.join("config.yml");
+            if config_path.exists() {
+                roots.push((ConfigSource::System, config_path));
+            }
+        }
+        if let Some(config_path) = xdg_dirs.find_config_file("config.yml") {
+            roots.push((ConfigSource::User, config_path));
+        }
         if let Some(project_dir) = project_dir {
             let project_config_path = project_dir.join(".contenant/config.yml");
             if project_config_path.exists() {
-                let config_dir = project_config_path.parent().unwrap().to_path_buf();
-                let data = Config::load_file(&project_config_path)?;
-                config.add_layer(ConfigSource::Project, data, config_dir);
+                roots.push((ConfigSource::Project, project_config_path));
             }
         }
 
-        Ok(config)
+        let mut files = vec![];
+        let mut seen = HashSet::new();
+        for (source, path) in roots {
+            let mut included = vec![];
+            with_includes(&path, &mut vec![], &mut seen, &mut included)?;
+            files.extend(included.into_iter().map(|path| (source, path)));
+        }
+
+        // The remote repo's config goes beneath the user config that may
+        // have named it
+        let url = files
+            .iter()
+            .rev()
+            .filter(|(source, _)| *source <= ConfigSource::User)
+            .find_map(|(_, path)| Config::load_file(path).ok()?.remote_config);
+        if let Some(url) = url {
+            let config_path = remote_config::checkout_dir(xdg_dirs, &url)?.join("config.yml");
+            if config_path.exists() {
+                let mut included = vec![];
+                with_includes(&config_path, &mut vec![], &mut seen, &mut included)?;
+                let index = files.partition_point(|(source, _)| *source < ConfigSource::User);
+                files.splice(
+                    index..index,
+                    included
+                        .into_iter()
+                        .map(|path| (ConfigSource::Remote, path)),
+                );
+            } else {
+                eprintln!(
+                    "contenant: {} isn't synced yet; run `contenant config sync`",
+                    url
+                );
+            }
+        }
+        Ok(files)
     }
 
     /// Create a stack seeded with the built-in default layer.
     pub fn with_defaults() -> Self {
         let mut config = Self::default();
+        let defaults = Config {
+            allowed_domains: Some(
+                DEFAULT_ALLOWED_DOMAINS
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            ),
+            ..Default::default()
+        };
         // Default layer has no meaningful config dir; use root as placeholder.
-        config.add_layer(ConfigSource::Default, Config::default(), PathBuf::from("/"));
+        config.add_layer(ConfigSource::Default, defaults, PathBuf::from("/"));
         config
     }
 
@@ -187,291 +1498,1602 @@ impl StackedConfig {
         &self.layers
     }
 
-    /// Last layer to set `claude.version` wins.
-    pub fn claude_version(&self) -> Option<&str> {
+    /// The `remote_config` URL from the last system or user layer to set it.
+    pub fn remote_config(&self) -> Option<&str> {
         self.layers
             .iter()
             .rev()
-            .find_map(|l| l.data.claude.version.as_deref())
+            .filter(|l| matches!(l.source, ConfigSource::System | ConfigSource::User))
+            .find_map(|l| l.data.remote_config.as_deref())
     }
 
-    /// Mounts from all layers, lowest precedence first.
-    ///
-    /// Each mount is paired with the config directory of its layer, used to
-    /// resolve relative source paths.
-    pub fn mounts(&self) -> impl Iterator<Item = (&Mount, &Path)> {
-        self.layers.iter().flat_map(|l| {
-            l.data
-                .mounts
-                .iter()
-                .map(move |m| (m, l.config_dir.as_path()))
-        })
+    /// Last layer to set `allow_docker` wins; off by default.
+    pub fn allow_docker(&self) -> bool {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.allow_docker)
+            .unwrap_or(false)
     }
 
-    /// Env vars merged across layers; higher precedence overrides.
-    pub fn env(&self) -> HashMap<String, String> {
-        let mut env = HashMap::new();
-        for layer in &self.layers {
-            env.extend(layer.data.env.iter().map(|(k, v)| (k.clone(), v.clone())));
-        }
-        env
+    /// The layer forbidding the Docker socket, if the last system or team
+    /// layer to set `forbid_docker` does. Users can't override it.
+    pub fn docker_forbidden_by(&self) -> Option<ConfigSource> {
+        self.layers
+            .iter()
+            .rev()
+            .filter(|l| matches!(l.source, ConfigSource::System | ConfigSource::Remote))
+            .find_map(|l| l.data.forbid_docker.map(|forbid| (forbid, l.source)))
+            .and_then(|(forbid, source)| forbid.then_some(source))
     }
 
-    /// Bridge config merged across layers: last non-default port wins,
-    /// triggers are merged with higher precedence overriding.
-    pub fn bridge(&self) -> BridgeConfig {
-        let port = self
-            .layers
+    /// Last layer to set `strict` wins; off by default.
+    pub fn strict(&self) -> bool {
+        self.layers
             .iter()
             .rev()
-            .find(|l| l.data.bridge.port != DEFAULT_BRIDGE_PORT)
-            .map_or(DEFAULT_BRIDGE_PORT, |l| l.data.bridge.port);
+            .find_map(|l| l.data.strict)
+            .unwrap_or(false)
+    }
 
-        let mut triggers = HashMap::new();
-        for layer in &self.layers {
-            triggers.extend(
-                layer
-                    .data
-                    .bridge
-                    .triggers
-                    .iter()
-                    .map(|(k, v)| (k.clone(), v.clone())),
+    /// Report keys in the config files that contenant doesn't know, with
+    /// their layers: as warnings, or with `strict` on, as an error.
+    pub fn check_unknown_keys(&self) -> Result<()> {
+        let keys: Vec<_> = self
+            .unknown_keys
+            .iter()
+            .map(|(source, problem)| format!("  {} ({} config)", problem, source))
+            .collect();
+        if keys.is_empty() {
+            return Ok(());
+        }
+        if self.strict() {
+            bail!(
+                "Unknown config keys, which strict mode rejects:\n{}",
+                keys.join("\n")
             );
         }
+        eprintln!("contenant: ignoring unknown config keys:");
+        for key in keys {
+            eprintln!("{}", key);
+        }
+        Ok(())
+    }
 
-        BridgeConfig { port, triggers }
+    /// The host `when:` conditions are checked against.
+    pub fn host(&self) -> &Host {
+        &self.host
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Last layer to set `claude.version` wins.
+    pub fn claude_version(&self) -> Option<&str> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.claude.version.as_deref())
+    }
 
-    #[test]
-    fn mount_absolute_paths() {
-        let mount = Mount {
-            source: "/host/path".to_string(),
-            target: Some("/container/path".to_string()),
-            readonly: false,
-        };
-        assert_eq!(
-            mount.to_docker_volume(Path::new("/config")),
-            "/host/path:/container/path"
-        );
+    /// Last layer to set `claude.keychain` wins.
+    pub fn claude_keychain(&self) -> KeychainSync {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.claude.keychain)
+            .unwrap_or_default()
     }
 
-    #[test]
-    fn mount_target_defaults_to_source() {
-        let mount = Mount {
-            source: "/shared/path".to_string(),
-            target: None,
-            readonly: false,
-        };
-        assert_eq!(
-            mount.to_docker_volume(Path::new("/config")),
-            "/shared/path:/shared/path"
-        );
+    /// Last layer to set `claude.credential_store` wins; `None` to find one.
+    pub fn credential_store(&self) -> Option<CredentialStore> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.claude.credential_store)
     }
 
-    #[test]
-    fn mount_tilde_in_target_expands_to_container_home() {
-        let mount = Mount {
+    /// Last layer to set `aws.profile` wins; empty turns it off.
+    pub fn aws_profile(&self) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.aws.profile.clone())
+            .filter(|profile| !profile.is_empty())
+    }
+
+    /// Each `ssh_agent` field from the last layer to set it, with `socket`
+    /// relative to that layer's config dir.
+    pub fn ssh_agent(&self) -> SshAgentConfig {
+        let last = |get: fn(&SshAgentConfig) -> Option<bool>| {
+            self.layers
+                .iter()
+                .rev()
+                .find_map(|l| get(&l.data.ssh_agent))
+        };
+        SshAgentConfig {
+            forward: last(|c| c.forward),
+            socket: self.layers.iter().rev().find_map(|l| {
+                let socket = l.data.ssh_agent.socket.as_deref()?;
+                Some(resolve_path(&l.config_dir, socket))
+            }),
+            sign_commits: last(|c| c.sign_commits),
+            signing_key: self
+                .layers
+                .iter()
+                .rev()
+                .find_map(|l| l.data.ssh_agent.signing_key.clone()),
+        }
+    }
+
+    /// Last layer to set `engine.backend` wins; `None` if no layer does.
+    pub fn engine_backend(&self) -> Option<EngineBackend> {
+        self.layers.iter().rev().find_map(|l| l.data.engine.backend)
+    }
+
+    /// Last layer to set `engine.autostart_vm` wins; off by default.
+    pub fn autostart_vm(&self) -> bool {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.engine.autostart_vm)
+            .unwrap_or(false)
+    }
+
+    /// Last layer to set `network.mode` wins; `firewall` by default.
+    pub fn network_mode(&self) -> NetworkMode {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.mode)
+            .unwrap_or_default()
+    }
+
+    /// Last layer to set `workspace.mode` wins.
+    pub fn workspace_mode(&self) -> WorkspaceMode {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.workspace.mode)
+            .unwrap_or_default()
+    }
+
+    /// Last layer to set `workspace.path` wins.
+    pub fn workspace_path(&self) -> Option<String> {
+        self.workspace_subdir(|workspace| &workspace.path)
+    }
+
+    /// Last layer to set `workspace.workdir` wins.
+    pub fn workspace_workdir(&self) -> Option<String> {
+        self.workspace_subdir(|workspace| &workspace.workdir)
+    }
+
+    fn workspace_subdir(
+        &self,
+        field: impl Fn(&WorkspaceConfig) -> &Option<String>,
+    ) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| field(&l.data.workspace).clone())
+            .map(|path| path.trim_end_matches('/').to_string())
+            .filter(|path| !path.is_empty() && path != ".")
+    }
+
+    /// Each `git` scalar from the last layer to set it, and
+    /// `safe_directories` accumulated without duplicates.
+    pub fn git(&self) -> GitConfig {
+        let last = |get: fn(&GitConfig) -> &Option<String>| {
+            self.layers
+                .iter()
+                .rev()
+                .find_map(|l| get(&l.data.git).clone())
+        };
+        let mut safe_directories: Vec<String> = vec![];
+        for layer in &self.layers {
+            for dir in &layer.data.git.safe_directories {
+                if !safe_directories.contains(dir) {
+                    safe_directories.push(dir.clone());
+                }
+            }
+        }
+        GitConfig {
+            name: last(|c| &c.name),
+            email: last(|c| &c.email),
+            signing_key: last(|c| &c.signing_key),
+            safe_directories,
+        }
+    }
+
+    /// Each `image` field from the last layer to set it, with the key's path
+    /// resolved against that layer's config dir.
+    pub fn image(&self) -> ImageConfig {
+        let layers = || self.layers.iter().rev();
+        ImageConfig {
+            reference: layers().find_map(|l| l.data.image.reference.clone()),
+            platform: layers().find_map(|l| l.data.image.platform.clone()),
+            cosign_key: layers().find_map(|l| {
+                let path = l.data.image.cosign_key.as_deref()?;
+                Some(resolve_path(&l.config_dir, path))
+            }),
+            attestation: layers().find_map(|l| l.data.image.attestation.clone()),
+        }
+    }
+
+    /// Last layer to set `jj.push` wins.
+    pub fn jj_push(&self) -> bool {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.jj.push)
+            .unwrap_or(false)
+    }
+
+    /// Last layer to set `workspace.host_user` wins. On by default on Linux,
+    /// where bind mounts keep the container's uids; elsewhere the VM
+    /// translates ownership already.
+    pub fn workspace_host_user(&self) -> bool {
+        self.workspace_host_user_setting()
+            .unwrap_or(cfg!(target_os = "linux"))
+    }
+
+    /// `workspace.host_user` as set, without the default.
+    pub fn workspace_host_user_setting(&self) -> Option<bool> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.workspace.host_user)
+    }
+
+    /// `workspace.exclude` accumulated from all layers, lowest precedence
+    /// first.
+    pub fn workspace_exclude(&self) -> Vec<String> {
+        self.workspace_paths(|workspace| &workspace.exclude)
+    }
+
+    /// `workspace.writable` accumulated from all layers, lowest precedence
+    /// first.
+    pub fn workspace_writable(&self) -> Vec<String> {
+        self.workspace_paths(|workspace| &workspace.writable)
+    }
+
+    fn workspace_paths(&self, field: impl Fn(&WorkspaceConfig) -> &Vec<String>) -> Vec<String> {
+        let mut paths: Vec<String> = vec![];
+        for path in self.layers.iter().flat_map(|l| field(&l.data.workspace)) {
+            let path = path.trim_end_matches('/').to_string();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// Last layer to set `network.proxy_port` wins.
+    pub fn proxy_port(&self) -> u16 {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.proxy_port)
+            .unwrap_or(DEFAULT_PROXY_PORT)
+    }
+
+    /// Last layer to set `network.proxy_allowed_ports` wins.
+    pub fn proxy_allowed_ports(&self) -> Vec<u16> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.proxy_allowed_ports.clone())
+            .unwrap_or_else(|| crate::proxy::DEFAULT_ALLOWED_PORTS.to_vec())
+    }
+
+    /// Last layer to set `network.shared` wins; off by default.
+    pub fn shared_network(&self) -> bool {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.shared)
+            .unwrap_or(false)
+    }
+
+    /// Last layer to set `network.group` wins.
+    pub fn network_group(&self) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.group.clone())
+    }
+
+    /// Last layer to set `network.allow_local` wins; off by default.
+    pub fn allow_local(&self) -> bool {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.allow_local)
+            .unwrap_or(false)
+    }
+
+    /// Last layer to set `network.max_egress` wins.
+    pub fn max_egress(&self) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.max_egress.clone())
+    }
+
+    /// Last layer to set `network.max_ingress` wins.
+    pub fn max_ingress(&self) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.max_ingress.clone())
+    }
+
+    /// Last layer to set `network.proxy` wins.
+    pub fn upstream_proxy(&self) -> Option<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.proxy.clone())
+    }
+
+    /// Last layer to set `network.no_proxy` wins.
+    pub fn no_proxy(&self) -> Vec<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.no_proxy.clone())
+            .unwrap_or_default()
+    }
+
+    /// Last layer to set `network.ip_cache_ttl_secs` wins.
+    pub fn ip_cache_ttl_secs(&self) -> u64 {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.ip_cache_ttl_secs)
+            .unwrap_or(DEFAULT_IP_CACHE_TTL_SECS)
+    }
+
+    /// Last layer to set `network.resolvers` wins.
+    pub fn resolvers(&self) -> Vec<String> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.network.resolvers.clone())
+            .unwrap_or_default()
+    }
+
+    /// Per-domain resolvers merged across layers; higher precedence overrides.
+    pub fn domain_resolvers(&self) -> HashMap<String, Vec<String>> {
+        let mut resolvers = HashMap::new();
+        for layer in &self.layers {
+            resolvers.extend(layer.data.network.domain_resolvers.clone());
+        }
+        resolvers
+    }
+
+    /// Last layer to set `engine.kubernetes` wins.
+    pub fn kubernetes(&self) -> KubernetesConfig {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.engine.kubernetes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Last layer to set `allowed_domains` wins, plus the domains of
+    /// presets and `--allow-domain` from every layer.
+    pub fn allowed_domains(&self) -> Vec<String> {
+        let mut domains = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.allowed_domains.clone())
+            .unwrap_or_default();
+        let presets = self.layers.iter().flat_map(|l| &l.data.presets);
+        let extra = self
+            .layers
+            .iter()
+            .flat_map(|l| &l.data.extra_allowed_domains);
+        for domain in presets
+            .flat_map(|p| p.domains().iter().copied())
+            .chain(extra.map(String::as_str))
+        {
+            if !domains.iter().any(|d| d == domain) {
+                domains.push(domain.to_string());
+            }
+        }
+        domains
+    }
+
+    /// Last layer to set `ip_ranges` wins.
+    pub fn ip_ranges(&self) -> Vec<RangeSource> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.ip_ranges.clone())
+            .unwrap_or_default()
+    }
+
+    /// Denied domains accumulated from all layers, lowest precedence first.
+    pub fn denied_domains(&self) -> Vec<String> {
+        let mut denied: Vec<String> = vec![];
+        for domain in self.layers.iter().flat_map(|l| &l.data.denied_domains) {
+            if !denied.contains(domain) {
+                denied.push(domain.clone());
+            }
+        }
+        denied
+    }
+
+    /// Published ports accumulated from all layers, lowest precedence first.
+    pub fn ports(&self) -> Vec<String> {
+        let mut ports: Vec<String> = vec![];
+        for port in self.layers.iter().flat_map(|l| &l.data.ports) {
+            if !ports.contains(port) {
+                ports.push(port.clone());
+            }
+        }
+        ports
+    }
+
+    /// Last layer to set `secret_scan` wins.
+    pub fn secret_scan(&self) -> SecretScan {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.secret_scan)
+            .unwrap_or_default()
+    }
+
+    /// Last layer to set `dns_proxy` wins; when unset, the proxy is on only
+    /// if some allowed domain is a wildcard.
+    pub fn dns_proxy(&self) -> bool {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.dns_proxy)
+            .unwrap_or_else(|| self.allowed_domains().iter().any(|d| d.contains('*')))
+    }
+
+    /// Security options; each field is taken from the last layer to set it.
+    pub fn security(&self) -> SecurityConfig {
+        let layers = || self.layers.iter().rev().map(|l| &l.data.security);
+        SecurityConfig {
+            apparmor: layers().find_map(|s| s.apparmor.clone()),
+            selinux_label: layers().find_map(|s| s.selinux_label.clone()),
+            selinux_relabel: layers().find_map(|s| s.selinux_relabel),
+            limits: LimitsConfig {
+                pids: layers().find_map(|s| s.limits.pids),
+                nofile: layers().find_map(|s| s.limits.nofile),
+                nproc: layers().find_map(|s| s.limits.nproc),
+            },
+        }
+    }
+
+    /// Hardening options: scalars from the last layer to set them, writable
+    /// paths accumulated across layers. The seccomp path is resolved against
+    /// the config dir of the layer that set it.
+    pub fn hardening(&self) -> HardeningConfig {
+        let layers = || self.layers.iter().rev();
+        let seccomp = layers().find_map(|l| {
+            let path = l.data.hardening.seccomp.as_deref()?;
+            Some(resolve_path(&l.config_dir, path))
+        });
+
+        let mut writable_paths: Vec<String> = vec![];
+        for layer in &self.layers {
+            for path in &layer.data.hardening.writable_paths {
+                if !writable_paths.contains(path) {
+                    writable_paths.push(path.clone());
+                }
+            }
+        }
+
+        HardeningConfig {
+            drop_capabilities: layers().find_map(|l| l.data.hardening.drop_capabilities),
+            no_new_privileges: layers().find_map(|l| l.data.hardening.no_new_privileges),
+            seccomp,
+            read_only_root: layers().find_map(|l| l.data.hardening.read_only_root),
+            writable_paths,
+        }
+    }
+
+    /// Mounts from all layers whose `when` matches the host, lowest
+    /// precedence first.
+    ///
+    /// Each mount is paired with the config directory of its layer, used to
+    /// resolve relative source paths.
+    pub fn mounts(&self) -> impl Iterator<Item = (&Mount, &Path)> {
+        self.layers.iter().flat_map(|l| {
+            l.data
+                .mounts
+                .iter()
+                .filter(|m| m.when.matches(&self.host))
+                .map(move |m| (m, l.config_dir.as_path()))
+        })
+    }
+
+    /// Env vars merged across layers; higher precedence overrides. Entries
+    /// whose `when` doesn't match the host are skipped.
+    pub fn env(&self) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        for layer in &self.layers {
+            env.extend(
+                layer
+                    .data
+                    .env
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.clone(), v.resolve(&self.host)?.to_string()))),
+            );
+        }
+        env
+    }
+
+    /// Host env var names to forward, accumulated from all layers.
+    pub fn env_passthrough(&self) -> Vec<String> {
+        let mut names: Vec<String> = vec![];
+        for name in self.layers.iter().flat_map(|l| &l.data.env_passthrough) {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        names
+    }
+
+    /// Dotenv files from all layers, each resolved from its layer's config
+    /// dir, lowest precedence first.
+    pub fn env_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = vec![];
+        for layer in &self.layers {
+            for file in &layer.data.env_files {
+                let file = PathBuf::from(resolve_path(&layer.config_dir, file));
+                if !files.contains(&file) {
+                    files.push(file);
+                }
+            }
+        }
+        files
+    }
+
+    /// Secrets merged across layers; higher precedence overrides.
+    pub fn secrets(&self) -> BTreeMap<String, Secret> {
+        let mut secrets = BTreeMap::new();
+        for layer in &self.layers {
+            secrets.extend(layer.data.secrets.clone());
+        }
+        secrets
+    }
+
+    /// Bridge config merged across layers: last non-default port and last
+    /// `socket`, `clipboard`, artifacts, and webhook secret settings win,
+    /// triggers are merged with higher precedence overriding, and
+    /// `open_urls` and `webhooks` accumulate. The
+    /// artifacts dir and trigger `cwd`s are resolved against the config dir
+    /// of the layer that set them.
+    pub fn bridge(&self) -> BridgeConfig {
+        let port = self
+            .layers
+            .iter()
+            .rev()
+            .find(|l| l.data.bridge.port != DEFAULT_BRIDGE_PORT)
+            .map_or(DEFAULT_BRIDGE_PORT, |l| l.data.bridge.port);
+
+        let mut triggers = HashMap::new();
+        for layer in &self.layers {
+            triggers.extend(layer.data.bridge.triggers.iter().map(|(k, v)| {
+                let mut trigger = v.clone();
+                // Relative to the config dir of the layer that defined it
+                trigger.cwd = v
+                    .cwd
+                    .as_deref()
+                    .map(|cwd| resolve_path(&layer.config_dir, cwd));
+                (k.clone(), trigger)
+            }));
+        }
+
+        let socket = self.layers.iter().rev().find_map(|l| l.data.bridge.socket);
+        let clipboard = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.bridge.clipboard);
+
+        let mut open_urls: Vec<String> = vec![];
+        let mut webhooks: Vec<String> = vec![];
+        let mut tls_names: Vec<String> = vec![];
+        for layer in &self.layers {
+            for prefix in &layer.data.bridge.open_urls {
+                if !open_urls.contains(prefix) {
+                    open_urls.push(prefix.clone());
+                }
+            }
+            for url in &layer.data.bridge.webhooks {
+                if !webhooks.contains(url) {
+                    webhooks.push(url.clone());
+                }
+            }
+            for name in &layer.data.bridge.tls_names {
+                if !tls_names.contains(name) {
+                    tls_names.push(name.clone());
+                }
+            }
+        }
+        let webhook_secret = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.bridge.webhook_secret.clone());
+
+        let artifacts_dir = self.layers.iter().rev().find_map(|l| {
+            let path = l.data.bridge.artifacts_dir.as_deref()?;
+            Some(resolve_path(&l.config_dir, path))
+        });
+        let artifacts_max_mb = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.bridge.artifacts_max_mb);
+        let watch = self.layers.iter().rev().find_map(|l| l.data.bridge.watch);
+        let address = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.bridge.address.clone());
+        let tls = self.layers.iter().rev().find_map(|l| l.data.bridge.tls);
+        let client_certs = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.bridge.client_certs);
+        let github_tokens = self
+            .layers
+            .iter()
+            .rev()
+            .find_map(|l| l.data.bridge.github_tokens);
+        let github_app = self.layers.iter().rev().find_map(|l| {
+            let app = l.data.bridge.github_app.as_ref()?;
+            Some(GithubApp {
+                private_key: resolve_path(&l.config_dir, &app.private_key),
+                ..app.clone()
+            })
+        });
+
+        BridgeConfig {
+            port,
+            triggers,
+            socket,
+            clipboard,
+            open_urls,
+            artifacts_dir,
+            artifacts_max_mb,
+            webhooks,
+            webhook_secret,
+            watch,
+            address,
+            tls,
+            client_certs,
+            tls_names,
+            github_tokens,
+            github_app,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_absolute_paths() {
+        let mount = Mount {
+            source: "/host/path".to_string(),
+            target: Some("/container/path".to_string()),
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        assert_eq!(
+            mount.to_docker_volume(Path::new("/config")),
+            "/host/path:/container/path"
+        );
+    }
+
+    #[test]
+    fn mount_target_defaults_to_source() {
+        let mount = Mount {
+            source: "/shared/path".to_string(),
+            target: None,
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        assert_eq!(
+            mount.to_docker_volume(Path::new("/config")),
+            "/shared/path:/shared/path"
+        );
+    }
+
+    #[test]
+    fn mount_tilde_in_target_expands_to_container_home() {
+        let mount = Mount {
             source: "/host/path".to_string(),
             target: Some("~/.config".to_string()),
             readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        assert_eq!(
+            mount.to_docker_volume(Path::new("/config")),
+            "/host/path:/home/claude/.config"
+        );
+    }
+
+    #[test]
+    fn mount_tilde_target_defaults_to_source_with_container_home() {
+        let mount = Mount {
+            source: "~/.ssh".to_string(),
+            target: None,
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        let result = mount.to_docker_volume(Path::new("/config"));
+        assert!(result.ends_with(":/home/claude/.ssh"));
+    }
+
+    #[test]
+    fn mount_relative_source_resolved_from_config_dir() {
+        let mount = Mount {
+            source: "relative/path".to_string(),
+            target: Some("/container/path".to_string()),
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
         };
         assert_eq!(
             mount.to_docker_volume(Path::new("/config")),
-            "/host/path:/home/claude/.config"
+            "/config/relative/path:/container/path"
+        );
+    }
+
+    #[test]
+    fn mount_readonly() {
+        let mount = Mount {
+            source: "/host/path".to_string(),
+            target: Some("/container/path".to_string()),
+            readonly: true,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        assert_eq!(
+            mount.to_docker_volume(Path::new("/config")),
+            "/host/path:/container/path:ro"
+        );
+    }
+
+    #[test]
+    fn mount_consistency_and_propagation() {
+        let mount: Mount = serde_yaml_ng::from_str(
+            "source: /host/path\ntarget: /container/path\nconsistency: delegated\n\
+             propagation: rslave\n",
+        )
+        .unwrap();
+        assert_eq!(
+            mount.to_docker_volume(Path::new("/config")),
+            "/host/path:/container/path:ro,delegated,rslave"
+        );
+
+        let mount: Mount = serde_yaml_ng::from_str(
+            "source: /host/path\ntarget: /container/path\nreadonly: false\nconsistency: cached\n",
+        )
+        .unwrap();
+        assert_eq!(
+            mount.to_docker_volume(Path::new("/config")),
+            "/host/path:/container/path:cached"
+        );
+
+        let mount: Mount =
+            serde_yaml_ng::from_str("type: tmpfs\ntarget: /tmp/x\npropagation: shared\n").unwrap();
+        assert!(mount.to_tmpfs().is_err());
+        assert!(serde_yaml_ng::from_str::<Mount>("source: /a\nconsistency: eventual\n").is_err());
+    }
+
+    #[test]
+    fn tmpfs_mounts_need_an_absolute_target_and_octal_mode() {
+        let mount = |yaml: &str| serde_yaml_ng::from_str::<Mount>(yaml).unwrap();
+
+        let tmpfs = mount("type: tmpfs\ntarget: /tmp/build\nsize: 1g\nmode: 1777")
+            .to_tmpfs()
+            .unwrap();
+        assert_eq!(tmpfs.target, "/tmp/build");
+        assert_eq!(tmpfs.size.as_deref(), Some("1g"));
+        assert_eq!(tmpfs.mode, Some(1777));
+        assert_eq!(mount("source: data").kind, MountType::Bind);
+
+        assert!(mount("type: tmpfs").to_tmpfs().is_err());
+        assert!(mount("type: tmpfs\ntarget: build").to_tmpfs().is_err());
+        assert!(
+            mount("type: tmpfs\ntarget: /x\nmode: 1787")
+                .to_tmpfs()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn volume_mounts_are_named_by_project() {
+        let mount = |yaml: &str| serde_yaml_ng::from_str::<Mount>(yaml).unwrap();
+
+        let volume = mount("type: volume\nsource: Cargo Cache\ntarget: ~/.cargo/registry")
+            .to_volume("0123abcd-app")
+            .unwrap();
+        assert_eq!(volume.name, "contenant-0123abcd-app-cargo-cache");
+        assert_eq!(volume.target, "/home/claude/.cargo/registry");
+        assert!(volume.readonly);
+
+        assert!(mount("type: volume\ntarget: /x").to_volume("id").is_err());
+        assert!(mount("type: volume\nsource: x").to_volume("id").is_err());
+    }
+
+    #[test]
+    fn glob_mounts_expand_to_read_only_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes");
+        fs::create_dir_all(notes.join("daily/2024")).unwrap();
+        fs::create_dir_all(notes.join(".git")).unwrap();
+        for file in [
+            "todo.md",
+            "ideas.md",
+            "image.png",
+            "daily/mon.md",
+            "daily/2024/jan.md",
+            ".git/HEAD.md",
+        ] {
+            fs::write(notes.join(file), "").unwrap();
+        }
+        let root = dir.path().display();
+
+        let mount = Mount {
+            source: "notes/*.md".to_string(),
+            target: Some("~/notes".to_string()),
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        assert!(mount.is_glob());
+        assert_eq!(
+            mount.to_docker_volumes(dir.path()),
+            [
+                format!("{}/notes/ideas.md:/home/claude/notes/ideas.md:ro", root),
+                format!("{}/notes/todo.md:/home/claude/notes/todo.md:ro", root),
+            ]
+        );
+
+        let mount = Mount {
+            source: format!("{}/notes/**/*.md", root),
+            target: None,
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        let volumes = mount.to_docker_volumes(Path::new("/config"));
+        assert_eq!(volumes.len(), 4);
+        assert!(volumes.contains(&format!(
+            "{0}/notes/daily/2024/jan.md:{0}/notes/daily/2024/jan.md:ro",
+            root
+        )));
+
+        let mount = Mount {
+            source: "notes/daily/**".to_string(),
+            target: Some("/daily".to_string()),
+            readonly: false,
+            kind: MountType::Bind,
+            size: None,
+            mode: None,
+            when: When::default(),
+            create_missing: false,
+            consistency: None,
+            propagation: None,
+        };
+        assert_eq!(
+            mount.to_docker_volumes(dir.path()),
+            [
+                format!("{}/notes/daily/2024/jan.md:/daily/2024/jan.md:ro", root),
+                format!("{}/notes/daily/mon.md:/daily/mon.md:ro", root),
+            ]
+        );
+    }
+
+    #[test]
+    fn wildcards_match_within_a_component() {
+        assert!(wildcard_matches(b"*.md", b"notes.md"));
+        assert!(wildcard_matches(b"n?tes*", b"notes.md"));
+        assert!(!wildcard_matches(b"*.md", b"notes.txt"));
+        assert!(glob_matches(&["**", "*.md"], &["a.md"]));
+        assert!(glob_matches(&["a", "**"], &["a", "b", "c"]));
+        assert!(!glob_matches(&["*"], &["a", "b"]));
+        assert_eq!(split_glob("~/notes/*.md"), ("~/notes/".to_string(), "*.md"));
+        assert_eq!(split_glob("*.md"), (".".to_string(), "*.md"));
+    }
+
+    #[test]
+    fn bridge_config_defaults() {
+        let config: BridgeConfig = serde_yaml_ng::from_str("{}").unwrap();
+        assert_eq!(config.port, 19432);
+        assert!(config.triggers.is_empty());
+    }
+
+    #[test]
+    fn bridge_config_custom_port() {
+        let config: BridgeConfig = serde_yaml_ng::from_str("port: 8080").unwrap();
+        assert_eq!(config.port, 8080);
+    }
+
+    #[test]
+    fn bridge_config_with_triggers() {
+        let yaml = r#"
+triggers:
+  open-editor: "code ."
+  notify: "notify-send 'Done'"
+"#;
+        let config: BridgeConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        assert_eq!(config.triggers.len(), 2);
+        assert_eq!(
+            config
+                .triggers
+                .get("open-editor")
+                .map(|t| t.command.to_string())
+                .as_deref(),
+            Some("code .")
+        );
+        assert_eq!(
+            config
+                .triggers
+                .get("notify")
+                .map(|t| t.command.to_string())
+                .as_deref(),
+            Some("notify-send 'Done'")
+        );
+        assert!(!config.triggers["open-editor"].allow_args);
+    }
+
+    #[test]
+    fn bridge_trigger_with_args() {
+        let yaml = r#"
+triggers:
+  open-file:
+    command: 'code "$1"'
+    allow_args: true
+  deploy:
+    command: make deploy
+    description: Deploy to staging
+    timeout_secs: 600
+    max_concurrent: 1
+    confirm: true
+"#;
+        let config: BridgeConfig = serde_yaml_ng::from_str(yaml).unwrap();
+        assert_eq!(
+            config.triggers["open-file"],
+            Trigger {
+                command: TriggerCommand::Shell(r#"code "$1""#.to_string()),
+                allow_args: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            config.triggers["deploy"],
+            Trigger {
+                command: TriggerCommand::Shell("make deploy".to_string()),
+                description: Some("Deploy to staging".to_string()),
+                allow_args: false,
+                timeout_secs: Some(600),
+                max_concurrent: Some(1),
+                confirm: true,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn bridge_trigger_argv_cwd_and_env() {
+        let yaml = r#"
+triggers:
+  build:
+    command: [make, build]
+    cwd: src/proj
+    env:
+      PROFILE: release
+  words:
+    command: cargo test --all
+    shell: false
+  script:
+    command: [make, build, "&&", make, test]
+    shell: true
+  empty:
+    command: []
+"#;
+        let parsed: Result<BridgeConfig, _> = serde_yaml_ng::from_str(yaml);
+        assert!(parsed.is_err());
+
+        let yaml = yaml.replace("  empty:\n    command: []\n", "");
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(&format!("bridge:\n{}", yaml.replace('\n', "\n  "))).unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        let triggers = config.bridge().triggers;
+
+        let build = &triggers["build"];
+        assert_eq!(
+            build.command,
+            TriggerCommand::Exec(vec!["make".to_string(), "build".to_string()])
+        );
+        assert_eq!(build.cwd.as_deref(), Some("/user-config/src/proj"));
+        assert_eq!(build.env["PROFILE"], "release");
+        assert_eq!(
+            triggers["words"].command,
+            TriggerCommand::Exec(vec![
+                "cargo".to_string(),
+                "test".to_string(),
+                "--all".to_string()
+            ])
+        );
+        assert_eq!(
+            triggers["script"].command,
+            TriggerCommand::Shell("make build && make test".to_string())
+        );
+
+        // Survives registration with the bridge
+        for trigger in triggers.values() {
+            let json = serde_json::to_string(trigger).unwrap();
+            assert_eq!(&serde_json::from_str::<Trigger>(&json).unwrap(), trigger);
+        }
+    }
+
+    #[test]
+    fn bridge_socket_last_layer_wins() {
+        let mut config = StackedConfig::with_defaults();
+        assert_eq!(config.bridge().socket, None);
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("bridge:\n  socket: true").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("bridge:\n  port: 9000").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+        assert_eq!(config.bridge().socket, Some(true));
+        assert_eq!(config.bridge().port, 9000);
+        assert_eq!(config.bridge().clipboard, None);
+        assert!(config.bridge().open_urls.is_empty());
+        assert_eq!(config.bridge().artifacts_dir, None);
+        assert_eq!(config.bridge().watch, None);
+        assert!(!config.bridge().uses_tls());
+    }
+
+    #[test]
+    fn bridge_client_certs_imply_tls() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                "bridge:\n  client_certs: true\n  address: 0.0.0.0\n  tls_names: [devbox]",
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("bridge:\n  tls_names: [devbox, devbox.lan]").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+        let bridge = config.bridge();
+        assert!(bridge.uses_tls());
+        assert_eq!(bridge.address.as_deref(), Some("0.0.0.0"));
+        assert_eq!(bridge.tls_names, ["devbox", "devbox.lan"]);
+
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("bridge:\n  socket: true").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+        assert!(!config.bridge().uses_tls());
+    }
+
+    #[test]
+    fn config_with_bridge_section() {
+        let yaml = r#"
+bridge:
+  port: 9000
+  triggers:
+    test: "echo test"
+"#;
+        let config: Config = serde_yaml_ng::from_str(yaml).unwrap();
+        assert_eq!(config.bridge.port, 9000);
+        assert_eq!(
+            config
+                .bridge
+                .triggers
+                .get("test")
+                .map(|t| t.command.to_string())
+                .as_deref(),
+            Some("echo test")
+        );
+    }
+
+    #[test]
+    fn stacked_config_defaults() {
+        let config = StackedConfig::with_defaults();
+        assert_eq!(config.claude_version(), None);
+        assert_eq!(config.mounts().count(), 0);
+        assert!(config.env().is_empty());
+        assert_eq!(config.bridge().port, DEFAULT_BRIDGE_PORT);
+        assert!(config.bridge().triggers.is_empty());
+    }
+
+    #[test]
+    fn stacked_config_single_layer() {
+        let mut config = StackedConfig::with_defaults();
+        let layer: Config = serde_yaml_ng::from_str(
+            r#"
+claude:
+  version: "1.0"
+mounts:
+  - source: /host/a
+    target: /container/a
+env:
+  FOO: bar
+bridge:
+  port: 9000
+  triggers:
+    test: "echo test"
+"#,
+        )
+        .unwrap();
+        config.add_layer(ConfigSource::User, layer, PathBuf::from("/user-config"));
+
+        assert_eq!(config.claude_version(), Some("1.0"));
+        assert_eq!(config.mounts().count(), 1);
+        assert_eq!(config.env().get("FOO").unwrap(), "bar");
+        assert_eq!(config.bridge().port, 9000);
+        assert_eq!(
+            config
+                .bridge()
+                .triggers
+                .get("test")
+                .map(|t| t.command.to_string())
+                .as_deref(),
+            Some("echo test")
+        );
+    }
+
+    #[test]
+    fn stacked_config_preserves_layers() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                r#"
+env:
+  FOO: from-user
+mounts:
+  - source: /user/mount
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+
+        assert_eq!(config.layers().len(), 2);
+        assert_eq!(config.layers()[0].source, ConfigSource::Default);
+        assert_eq!(config.layers()[1].source, ConfigSource::User);
+        assert_eq!(
+            config.layers()[1].data.env.get("FOO"),
+            Some(&"from-user".into())
+        );
+    }
+
+    #[test]
+    fn stacked_config_mounts_carry_config_dir() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                r#"
+mounts:
+  - source: relative/path
+    target: /container/a
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
         );
+
+        let mounts: Vec<_> = config.mounts().collect();
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].0.source, "relative/path");
+        assert_eq!(mounts[0].1, Path::new("/user-config"));
     }
 
     #[test]
-    fn mount_tilde_target_defaults_to_source_with_container_home() {
-        let mount = Mount {
-            source: "~/.ssh".to_string(),
-            target: None,
-            readonly: false,
-        };
-        let result = mount.to_docker_volume(Path::new("/config"));
-        assert!(result.ends_with(":/home/claude/.ssh"));
+    fn project_layer_overrides_user() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                r#"
+claude:
+  version: "user-version"
+env:
+  SHARED: from-user
+  USER_ONLY: present
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str(
+                r#"
+claude:
+  version: "project-version"
+env:
+  SHARED: from-project
+  PROJECT_ONLY: present
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+
+        // Scalars: project wins
+        assert_eq!(config.claude_version(), Some("project-version"));
+
+        // Env: merged, project overrides shared keys
+        let env = config.env();
+        assert_eq!(env.get("SHARED").unwrap(), "from-project");
+        assert_eq!(env.get("USER_ONLY").unwrap(), "present");
+        assert_eq!(env.get("PROJECT_ONLY").unwrap(), "present");
     }
 
     #[test]
-    fn mount_relative_source_resolved_from_config_dir() {
-        let mount = Mount {
-            source: "relative/path".to_string(),
-            target: Some("/container/path".to_string()),
-            readonly: false,
-        };
-        assert_eq!(
-            mount.to_docker_volume(Path::new("/config")),
-            "/config/relative/path:/container/path"
+    fn project_layer_mounts_accumulate() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                r#"
+mounts:
+  - source: /user/mount
+    target: /container/user
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str(
+                r#"
+mounts:
+  - source: data
+    target: /container/data
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/project/.contenant"),
         );
+
+        let mounts: Vec<_> = config.mounts().collect();
+        assert_eq!(mounts.len(), 2);
+        // User mount first (lower precedence)
+        assert_eq!(mounts[0].0.source, "/user/mount");
+        assert_eq!(mounts[0].1, Path::new("/user-config"));
+        // Project mount second (higher precedence), with project config dir
+        assert_eq!(mounts[1].0.source, "data");
+        assert_eq!(mounts[1].1, Path::new("/project/.contenant"));
     }
 
     #[test]
-    fn mount_readonly() {
-        let mount = Mount {
-            source: "/host/path".to_string(),
-            target: Some("/container/path".to_string()),
-            readonly: true,
-        };
+    fn project_layer_bridge_overrides() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                r#"
+bridge:
+  port: 9000
+  artifacts_dir: artifacts
+  open_urls: ["https://github.com/"]
+  triggers:
+    user-trigger: "echo user"
+    shared: "echo from-user"
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str(
+                r#"
+bridge:
+  open_urls: ["https://github.com/", "https://localhost:3000"]
+  triggers:
+    project-trigger: "echo project"
+    shared: "echo from-project"
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+
+        let bridge = config.bridge();
+        // Port: user set 9000, project didn't override
+        assert_eq!(bridge.port, 9000);
+        // Triggers: merged, project wins on shared key
         assert_eq!(
-            mount.to_docker_volume(Path::new("/config")),
-            "/host/path:/container/path:ro"
+            bridge.triggers["user-trigger"].command.to_string(),
+            "echo user"
+        );
+        assert_eq!(
+            bridge.triggers["project-trigger"].command.to_string(),
+            "echo project"
+        );
+        assert_eq!(
+            bridge.triggers["shared"].command.to_string(),
+            "echo from-project"
+        );
+        // Artifacts dir: relative to the config dir that set it
+        assert_eq!(
+            bridge.artifacts_dir.as_deref(),
+            Some("/user-config/artifacts")
+        );
+        // Open URLs: accumulated and deduped
+        assert_eq!(
+            bridge.open_urls,
+            ["https://github.com/", "https://localhost:3000"]
         );
     }
 
     #[test]
-    fn bridge_config_defaults() {
-        let config: BridgeConfig = serde_yaml_ng::from_str("{}").unwrap();
-        assert_eq!(config.port, 19432);
-        assert!(config.triggers.is_empty());
+    fn allowed_domains_default() {
+        let config = StackedConfig::with_defaults();
+        assert_eq!(config.allowed_domains(), DEFAULT_ALLOWED_DOMAINS);
     }
 
     #[test]
-    fn bridge_config_custom_port() {
-        let config: BridgeConfig = serde_yaml_ng::from_str("port: 8080").unwrap();
-        assert_eq!(config.port, 8080);
+    fn allowed_domains_replaced_by_higher_layer() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("allowed_domains: [registry.npmjs.org]").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("env: {}").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+
+        assert_eq!(config.allowed_domains(), vec!["registry.npmjs.org"]);
     }
 
     #[test]
-    fn bridge_config_with_triggers() {
-        let yaml = r#"
-triggers:
-  open-editor: "code ."
-  notify: "notify-send 'Done'"
-"#;
-        let config: BridgeConfig = serde_yaml_ng::from_str(yaml).unwrap();
-        assert_eq!(config.triggers.len(), 2);
-        assert_eq!(
-            config.triggers.get("open-editor"),
-            Some(&"code .".to_string())
+    fn presets_extend_allowed_domains() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("presets: [node]").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("allowed_domains: [github.com, nodejs.org]\npresets: [python]")
+                .unwrap(),
+            PathBuf::from("/project/.contenant"),
         );
+
         assert_eq!(
-            config.triggers.get("notify"),
-            Some(&"notify-send 'Done'".to_string())
+            config.allowed_domains(),
+            [
+                "github.com",
+                "nodejs.org",
+                "registry.npmjs.org",
+                "registry.yarnpkg.com",
+                "pypi.org",
+                "files.pythonhosted.org",
+            ]
         );
+        assert!(serde_yaml_ng::from_str::<Config>("presets: [cobol]").is_err());
+
+        let cli = Config {
+            extra_allowed_domains: vec!["example.com".to_string()],
+            ..Default::default()
+        };
+        config.add_layer(ConfigSource::Cli, cli, PathBuf::from("/cwd"));
+        assert_eq!(config.allowed_domains().last().unwrap(), "example.com");
+        assert!(config.allowed_domains().contains(&"github.com".to_string()));
     }
 
     #[test]
-    fn config_with_bridge_section() {
-        let yaml = r#"
-bridge:
-  port: 9000
-  triggers:
-    test: "echo test"
-"#;
-        let config: Config = serde_yaml_ng::from_str(yaml).unwrap();
-        assert_eq!(config.bridge.port, 9000);
+    fn ip_ranges_parse() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                r#"
+ip_ranges:
+  - provider: cloudflare
+  - provider: aws
+    services: [S3]
+"#,
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+
         assert_eq!(
-            config.bridge.triggers.get("test"),
-            Some(&"echo test".to_string())
+            config.ip_ranges(),
+            [
+                RangeSource::Cloudflare,
+                RangeSource::Aws {
+                    services: vec!["S3".to_string()],
+                    regions: vec![],
+                },
+            ]
         );
     }
 
     #[test]
-    fn stacked_config_defaults() {
-        let config = StackedConfig::with_defaults();
-        assert_eq!(config.claude_version(), None);
-        assert_eq!(config.mounts().count(), 0);
-        assert!(config.env().is_empty());
-        assert_eq!(config.bridge().port, DEFAULT_BRIDGE_PORT);
-        assert!(config.bridge().triggers.is_empty());
+    fn ports_accumulate() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("ports: [\"3000\"]").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("ports: [\"8080:5173\", \"3000\"]").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+
+        assert_eq!(config.ports(), ["3000", "8080:5173"]);
     }
 
     #[test]
-    fn stacked_config_single_layer() {
+    fn denied_domains_accumulate() {
         let mut config = StackedConfig::with_defaults();
-        let layer: Config = serde_yaml_ng::from_str(
-            r#"
-claude:
-  version: "1.0"
-mounts:
-  - source: /host/a
-    target: /container/a
-env:
-  FOO: bar
-bridge:
-  port: 9000
-  triggers:
-    test: "echo test"
-"#,
-        )
-        .unwrap();
-        config.add_layer(ConfigSource::User, layer, PathBuf::from("/user-config"));
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("denied_domains: [gist.github.com]").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("denied_domains: [uploads.github.com, gist.github.com]")
+                .unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
 
-        assert_eq!(config.claude_version(), Some("1.0"));
-        assert_eq!(config.mounts().count(), 1);
-        assert_eq!(config.env().get("FOO").unwrap(), "bar");
-        assert_eq!(config.bridge().port, 9000);
         assert_eq!(
-            config.bridge().triggers.get("test"),
-            Some(&"echo test".to_string())
+            config.denied_domains(),
+            ["gist.github.com", "uploads.github.com"]
+        );
+    }
+
+    #[test]
+    fn workspace_exclude_accumulates() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("workspace:\n  exclude: [node_modules]").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("workspace:\n  exclude: [target/, node_modules]").unwrap(),
+            PathBuf::from("/project/.contenant"),
         );
+
+        assert_eq!(config.workspace_exclude(), ["node_modules", "target"]);
+        assert!(config.workspace_writable().is_empty());
     }
 
     #[test]
-    fn stacked_config_preserves_layers() {
+    fn workspace_path_and_workdir_take_the_last_layer() {
         let mut config = StackedConfig::with_defaults();
+        assert_eq!(config.workspace_path(), None);
         config.add_layer(
             ConfigSource::User,
-            serde_yaml_ng::from_str(
-                r#"
-env:
-  FOO: from-user
-mounts:
-  - source: /user/mount
-"#,
-            )
-            .unwrap(),
+            serde_yaml_ng::from_str("workspace:\n  path: services/api/\n  workdir: src").unwrap(),
             PathBuf::from("/user-config"),
         );
-
-        assert_eq!(config.layers().len(), 2);
-        assert_eq!(config.layers()[0].source, ConfigSource::Default);
-        assert_eq!(config.layers()[1].source, ConfigSource::User);
-        assert_eq!(
-            config.layers()[1].data.env.get("FOO"),
-            Some(&"from-user".to_string())
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("workspace:\n  workdir: .").unwrap(),
+            PathBuf::from("/project/.contenant"),
         );
+
+        assert_eq!(config.workspace_path().as_deref(), Some("services/api"));
+        assert_eq!(config.workspace_workdir(), None);
     }
 
     #[test]
-    fn stacked_config_mounts_carry_config_dir() {
+    fn dns_proxy_follows_wildcards_unless_set() {
         let mut config = StackedConfig::with_defaults();
+        assert!(!config.dns_proxy());
+
         config.add_layer(
             ConfigSource::User,
-            serde_yaml_ng::from_str(
-                r#"
-mounts:
-  - source: relative/path
-    target: /container/a
-"#,
-            )
-            .unwrap(),
+            serde_yaml_ng::from_str("allowed_domains: ['*.githubusercontent.com']").unwrap(),
             PathBuf::from("/user-config"),
         );
+        assert!(config.dns_proxy());
 
-        let mounts: Vec<_> = config.mounts().collect();
-        assert_eq!(mounts.len(), 1);
-        assert_eq!(mounts[0].0.source, "relative/path");
-        assert_eq!(mounts[0].1, Path::new("/user-config"));
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("dns_proxy: false").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+        assert!(!config.dns_proxy());
     }
 
     #[test]
-    fn project_layer_overrides_user() {
+    fn security_fields_resolve_independently() {
         let mut config = StackedConfig::with_defaults();
         config.add_layer(
             ConfigSource::User,
             serde_yaml_ng::from_str(
                 r#"
-claude:
-  version: "user-version"
-env:
-  SHARED: from-user
-  USER_ONLY: present
+security:
+  apparmor: contenant-default
+  selinux_relabel: shared
+  limits:
+    pids: 512
+    nofile: 1024
 "#,
             )
             .unwrap(),
@@ -481,37 +3103,42 @@ env:
             ConfigSource::Project,
             serde_yaml_ng::from_str(
                 r#"
-claude:
-  version: "project-version"
-env:
-  SHARED: from-project
-  PROJECT_ONLY: present
+security:
+  selinux_relabel: private
+  limits:
+    nofile: 4096
 "#,
             )
             .unwrap(),
             PathBuf::from("/project/.contenant"),
         );
 
-        // Scalars: project wins
-        assert_eq!(config.claude_version(), Some("project-version"));
-
-        // Env: merged, project overrides shared keys
-        let env = config.env();
-        assert_eq!(env.get("SHARED").unwrap(), "from-project");
-        assert_eq!(env.get("USER_ONLY").unwrap(), "present");
-        assert_eq!(env.get("PROJECT_ONLY").unwrap(), "present");
+        let security = config.security();
+        assert_eq!(security.apparmor.as_deref(), Some("contenant-default"));
+        assert_eq!(security.selinux_label, None);
+        assert_eq!(security.selinux_relabel, Some(SelinuxRelabel::Private));
+        assert_eq!(
+            security.limits,
+            LimitsConfig {
+                pids: Some(512),
+                nofile: Some(4096),
+                nproc: None,
+            }
+        );
     }
 
     #[test]
-    fn project_layer_mounts_accumulate() {
+    fn hardening_merges_layers() {
         let mut config = StackedConfig::with_defaults();
         config.add_layer(
             ConfigSource::User,
             serde_yaml_ng::from_str(
                 r#"
-mounts:
-  - source: /user/mount
-    target: /container/user
+hardening:
+  drop_capabilities: true
+  read_only_root: true
+  seccomp: seccomp.json
+  writable_paths: [/home/claude/.cache]
 "#,
             )
             .unwrap(),
@@ -521,38 +3148,82 @@ mounts:
             ConfigSource::Project,
             serde_yaml_ng::from_str(
                 r#"
-mounts:
-  - source: data
-    target: /container/data
+hardening:
+  read_only_root: false
+  writable_paths: [/workspace-cache]
 "#,
             )
             .unwrap(),
             PathBuf::from("/project/.contenant"),
         );
 
-        let mounts: Vec<_> = config.mounts().collect();
-        assert_eq!(mounts.len(), 2);
-        // User mount first (lower precedence)
-        assert_eq!(mounts[0].0.source, "/user/mount");
-        assert_eq!(mounts[0].1, Path::new("/user-config"));
-        // Project mount second (higher precedence), with project config dir
-        assert_eq!(mounts[1].0.source, "data");
-        assert_eq!(mounts[1].1, Path::new("/project/.contenant"));
+        let hardening = config.hardening();
+        assert_eq!(hardening.drop_capabilities, Some(true));
+        assert_eq!(hardening.read_only_root, Some(false));
+        assert_eq!(hardening.no_new_privileges, None);
+        assert_eq!(
+            hardening.seccomp.as_deref(),
+            Some("/user-config/seccomp.json")
+        );
+        assert_eq!(
+            hardening.writable_paths,
+            vec!["/home/claude/.cache", "/workspace-cache"]
+        );
     }
 
     #[test]
-    fn project_layer_bridge_overrides() {
+    fn engine_backend_unset_by_default() {
+        let mut config = StackedConfig::with_defaults();
+        assert_eq!(config.engine_backend(), None);
+
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("engine:\n  backend: docker-api\n").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        assert_eq!(config.engine_backend(), Some(EngineBackend::DockerApi));
+    }
+
+    #[test]
+    fn network_mode_defaults_to_firewall() {
+        let mut config = StackedConfig::with_defaults();
+        assert_eq!(config.network_mode(), NetworkMode::Firewall);
+        assert_eq!(config.proxy_port(), DEFAULT_PROXY_PORT);
+        assert_eq!(config.ip_cache_ttl_secs(), DEFAULT_IP_CACHE_TTL_SECS);
+        assert!(!config.shared_network());
+        assert_eq!(config.network_group(), None);
+        assert_eq!(config.upstream_proxy(), None);
+        assert!(config.no_proxy().is_empty());
+        assert!(!config.allow_local());
+
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str(
+                "network:\n  mode: proxy\n  proxy_port: 8888\n  shared: true\n  group: web\n  \
+                 proxy: http://proxy.corp:3128\n  no_proxy: [.corp]\n  allow_local: true\n",
+            )
+            .unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+        assert_eq!(config.network_mode(), NetworkMode::Proxy);
+        assert_eq!(config.proxy_port(), 8888);
+        assert!(config.shared_network());
+        assert_eq!(config.network_group().as_deref(), Some("web"));
+        assert_eq!(
+            config.upstream_proxy().as_deref(),
+            Some("http://proxy.corp:3128")
+        );
+        assert_eq!(config.no_proxy(), [".corp"]);
+        assert!(config.allow_local());
+    }
+
+    #[test]
+    fn domain_resolvers_merge_per_domain() {
         let mut config = StackedConfig::with_defaults();
         config.add_layer(
             ConfigSource::User,
             serde_yaml_ng::from_str(
-                r#"
-bridge:
-  port: 9000
-  triggers:
-    user-trigger: "echo user"
-    shared: "echo from-user"
-"#,
+                "network:\n  resolvers: [1.1.1.1]\n  domain_resolvers:\n    corp.example.com: [10.1.2.3]\n    lab.example.com: [10.0.0.53]\n",
             )
             .unwrap(),
             PathBuf::from("/user-config"),
@@ -560,27 +3231,33 @@ bridge:
         config.add_layer(
             ConfigSource::Project,
             serde_yaml_ng::from_str(
-                r#"
-bridge:
-  triggers:
-    project-trigger: "echo project"
-    shared: "echo from-project"
-"#,
+                "network:\n  domain_resolvers:\n    corp.example.com: [10.4.5.6]\n",
             )
             .unwrap(),
             PathBuf::from("/project/.contenant"),
         );
 
-        let bridge = config.bridge();
-        // Port: user set 9000, project didn't override
-        assert_eq!(bridge.port, 9000);
-        // Triggers: merged, project wins on shared key
-        assert_eq!(bridge.triggers.get("user-trigger").unwrap(), "echo user");
-        assert_eq!(
-            bridge.triggers.get("project-trigger").unwrap(),
-            "echo project"
-        );
-        assert_eq!(bridge.triggers.get("shared").unwrap(), "echo from-project");
+        assert_eq!(config.resolvers(), ["1.1.1.1"]);
+        let domain_resolvers = config.domain_resolvers();
+        assert_eq!(domain_resolvers["corp.example.com"], ["10.4.5.6"]);
+        assert_eq!(domain_resolvers["lab.example.com"], ["10.0.0.53"]);
+    }
+
+    #[test]
+    fn kubernetes_config_defaults() {
+        let config: Config = serde_yaml_ng::from_str(
+            r#"
+engine:
+  backend: kubernetes
+  kubernetes:
+    namespace: agents
+"#,
+        )
+        .unwrap();
+        let kubernetes = config.engine.kubernetes.unwrap();
+        assert_eq!(config.engine.backend, Some(EngineBackend::Kubernetes));
+        assert_eq!(kubernetes.namespace.as_deref(), Some("agents"));
+        assert_eq!(kubernetes.startup_timeout_secs, 300);
     }
 
     #[test]
@@ -608,6 +3285,263 @@ bridge:
         assert_eq!(config.env().get("FROM_PROJECT").unwrap(), "hello");
     }
 
+    #[test]
+    fn includes_merge_beneath_the_including_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("shared");
+        let contenant_dir = dir.path().join("app/.contenant");
+        fs::create_dir_all(&shared).unwrap();
+        fs::create_dir_all(&contenant_dir).unwrap();
+        fs::write(
+            shared.join("base.yml"),
+            "include: [domains.yml]\nmounts:\n  - source: scripts\nenv:\n  A: base\n  B: base\n",
+        )
+        .unwrap();
+        fs::write(
+            shared.join("domains.yml"),
+            "allowed_domains: [example.com]\n",
+        )
+        .unwrap();
+        fs::write(
+            contenant_dir.join("config.yml"),
+            "include: [../../shared/base.yml, ../../shared/domains.yml]\nenv:\n  B: project\n",
+        )
+        .unwrap();
+
+        let xdg = xdg::BaseDirectories::with_prefix("contenant-test-nonexistent");
+        let config = StackedConfig::load(&xdg, Some(&dir.path().join("app"))).unwrap();
+
+        // default, domains, base, project; domains.yml only once
+        assert_eq!(config.layers().len(), 4);
+        assert!(
+            config.layers()[1..]
+                .iter()
+                .all(|l| l.source == ConfigSource::Project)
+        );
+        assert_eq!(config.env()["A"], "base");
+        assert_eq!(config.env()["B"], "project");
+        assert_eq!(config.allowed_domains(), ["example.com"]);
+        let (mount, mount_dir) = config.mounts().next().unwrap();
+        assert_eq!(
+            mount.to_docker_volume(mount_dir),
+            format!(
+                "{}:scripts:ro",
+                fs::canonicalize(&shared).unwrap().join("scripts").display()
+            )
+        );
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let contenant_dir = dir.path().join(".contenant");
+        fs::create_dir_all(&contenant_dir).unwrap();
+        fs::write(
+            contenant_dir.join("config.yml"),
+            "mounts:\n  - soruce: ~/.ssh\n    source: ~/.ssh\n",
+        )
+        .unwrap();
+
+        let xdg = xdg::BaseDirectories::with_prefix("contenant-test-nonexistent");
+        let mut config = StackedConfig::load(&xdg, Some(dir.path())).unwrap();
+        // Only warned about until strict is on
+        config.check_unknown_keys().unwrap();
+        assert_eq!(config.unknown_keys.len(), 1);
+
+        config.add_layer(
+            ConfigSource::Cli,
+            serde_yaml_ng::from_str("strict: true").unwrap(),
+            PathBuf::from("/"),
+        );
+        let err = config.check_unknown_keys().unwrap_err().to_string();
+        assert!(err.contains("config.yml:2: unknown key `mounts[0].soruce` (project config)"));
+    }
+
+    #[test]
+    fn when_conditions_pick_mounts_and_env() {
+        let mut config = StackedConfig::with_defaults();
+        config.host = Host {
+            os: "linux".to_string(),
+            arch: "x86_64".to_string(),
+            hostname: "Work-Laptop".to_string(),
+        };
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str(
+                "mounts:\n\
+                 - source: /op/agent.sock\n  when: {os: macos}\n\
+                 - source: /keyring/ssh\n  when: {os: linux, hostname: work-*}\n\
+                 - source: /arm\n  when: {arch: aarch64}\n\
+                 env:\n\
+                 \x20 EDITOR: vim\n\
+                 \x20 PAGER: {value: less, when: {os: macos}}\n\
+                 \x20 SSH_AUTH_SOCK:\n\
+                 \x20   - {value: /op/agent.sock, when: {os: macos}}\n\
+                 \x20   - {value: /keyring/ssh, when: {hostname: work-laptop}}\n",
+            )
+            .unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("env:\n  EDITOR: {value: nano, when: {os: macos}}\n").unwrap(),
+            PathBuf::from("/project"),
+        );
+
+        let mounts: Vec<_> = config.mounts().map(|(m, _)| m.source.as_str()).collect();
+        assert_eq!(mounts, ["/keyring/ssh"]);
+        let env = config.env();
+        // The project's EDITOR doesn't apply here, so the user's does
+        assert_eq!(env["EDITOR"], "vim");
+        assert_eq!(env["SSH_AUTH_SOCK"], "/keyring/ssh");
+        assert!(!env.contains_key("PAGER"));
+    }
+
+    #[test]
+    fn system_config_sits_beneath_user_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let system = dir.path().join("etc/contenant");
+        let app = dir.path().join("app");
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(app.join(".contenant")).unwrap();
+        fs::write(
+            system.join("config.yml"),
+            "denied_domains: [pastebin.com]
+",
+        )
+        .unwrap();
+        fs::write(
+            app.join(".contenant/config.yml"),
+            "ports: ['3000']
+",
+        )
+        .unwrap();
+
+        let xdg = xdg::BaseDirectories::with_prefix("contenant-test-nonexistent");
+        let missing = dir.path().join("Library/Application Support/contenant");
+        let files =
+            StackedConfig::files_from(&[system.clone(), missing], &xdg, Some(&app)).unwrap();
+        let sources: Vec<_> = files.iter().map(|(source, _)| *source).collect();
+        assert_eq!(sources, [ConfigSource::System, ConfigSource::Project]);
+        assert_eq!(files[0].1, system.join("config.yml"));
+
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            serde_yaml_ng::from_str("network:\n  proxy_port: 8080\n").unwrap(),
+            PathBuf::from("/user-config"),
+        );
+        config.add_layer(
+            ConfigSource::System,
+            serde_yaml_ng::from_str("network:\n  proxy_port: 9090\n").unwrap(),
+            system,
+        );
+        let sources: Vec<_> = config.layers().iter().map(|l| l.source).collect();
+        assert_eq!(
+            sources,
+            [
+                ConfigSource::Default,
+                ConfigSource::System,
+                ConfigSource::User
+            ]
+        );
+        assert_eq!(config.proxy_port(), 8080);
+    }
+
+    #[test]
+    fn only_system_and_team_config_can_forbid_docker() {
+        let mut config = StackedConfig::with_defaults();
+        assert!(!config.allow_docker());
+        config.add_layer(
+            ConfigSource::Project,
+            serde_yaml_ng::from_str("allow_docker: true\nforbid_docker: false").unwrap(),
+            PathBuf::from("/project/.contenant"),
+        );
+        assert!(config.allow_docker());
+        assert_eq!(config.docker_forbidden_by(), None);
+
+        config.add_layer(
+            ConfigSource::Remote,
+            serde_yaml_ng::from_str("forbid_docker: true").unwrap(),
+            PathBuf::from("/remote-config"),
+        );
+        assert_eq!(config.docker_forbidden_by(), Some(ConfigSource::Remote));
+    }
+
+    #[test]
+    fn remote_config_sits_between_system_and_user_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let xdg = crate::testing::isolated_app_dirs(dir.path());
+        let system = dir.path().join("etc/contenant");
+        let user = xdg.get_config_home().unwrap();
+        fs::create_dir_all(&system).unwrap();
+        fs::create_dir_all(&user).unwrap();
+        fs::write(system.join("config.yml"), "ports: ['22']\n").unwrap();
+        fs::write(
+            user.join("config.yml"),
+            "remote_config: git@github.com:org/policy.git\n",
+        )
+        .unwrap();
+
+        // Not synced yet, so there's no remote layer
+        let files = StackedConfig::files_from(std::slice::from_ref(&system), &xdg, None).unwrap();
+        let sources: Vec<_> = files.iter().map(|(source, _)| *source).collect();
+        assert_eq!(sources, [ConfigSource::System, ConfigSource::User]);
+
+        let checkout = remote_config::checkout_dir(&xdg, "git@github.com:org/policy.git").unwrap();
+        fs::create_dir_all(&checkout).unwrap();
+        fs::write(
+            checkout.join("config.yml"),
+            "include: [domains.yml]\nremote_config: https://example.com/other.git\n",
+        )
+        .unwrap();
+        fs::write(
+            checkout.join("domains.yml"),
+            "denied_domains: [pastebin.com]\n",
+        )
+        .unwrap();
+
+        let files = StackedConfig::files_from(&[system], &xdg, None).unwrap();
+        let sources: Vec<_> = files.iter().map(|(source, _)| *source).collect();
+        assert_eq!(
+            sources,
+            [
+                ConfigSource::System,
+                ConfigSource::Remote,
+                ConfigSource::Remote,
+                ConfigSource::User
+            ]
+        );
+        assert_eq!(files[1].1, checkout.join("domains.yml"));
+
+        let mut config = StackedConfig::with_defaults();
+        for (source, path) in files {
+            let data = Config::load_file(&path).unwrap();
+            config.add_layer(source, data, path.parent().unwrap().to_path_buf());
+        }
+        assert_eq!(config.denied_domains(), ["pastebin.com"]);
+        // The remote repo can't point somewhere else
+        assert_eq!(
+            config.remote_config(),
+            Some("git@github.com:org/policy.git")
+        );
+    }
+
+    #[test]
+    fn include_cycles_are_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let contenant_dir = dir.path().join(".contenant");
+        fs::create_dir_all(&contenant_dir).unwrap();
+        fs::write(contenant_dir.join("config.yml"), "include: [a.yml]\n").unwrap();
+        fs::write(contenant_dir.join("a.yml"), "include: [b.yml]\n").unwrap();
+        fs::write(contenant_dir.join("b.yml"), "include: [a.yml]\n").unwrap();
+
+        let xdg = xdg::BaseDirectories::with_prefix("contenant-test-nonexistent");
+        let err = StackedConfig::load(&xdg, Some(dir.path())).unwrap_err();
+        assert!(format!("{:?}", err).contains("a.yml -> "));
+        assert!(format!("{:?}", err).contains("cycle"));
+    }
+
     #[test]
     fn load_without_project_dir() {
         let xdg = xdg::BaseDirectories::with_prefix("contenant-test-nonexistent");
@@ -0,0 +1,412 @@
+//! Reading and writing single keys in a config file, for `contenant config
+//! get` and `set`.
+//!
+//! serde would drop comments and reorder keys, so `set` edits the text
+//! instead: it replaces the value on the key's line or adds the key at the
+//! end of its parent mapping. That only works for block-style YAML, which is
+//! what the README shows; anything else is left to `contenant config edit`.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use serde_yaml_ng::Value as Yaml;
+
+use crate::config::{Config, EnvValue, Mount};
+use crate::schema;
+
+/// The user config file, or the project's with `project`. It may not
+/// exist yet.
+pub fn config_path(
+    xdg_dirs: &xdg::BaseDirectories,
+    project_dir: &Path,
+    project: bool,
+) -> Result<PathBuf> {
+    if project {
+        return Ok(project_dir.join(".contenant/config.yml"));
+    }
+    match xdg_dirs.find_config_file("config.yml") {
+        Some(path) => Ok(path),
+        None => Ok(xdg_dirs.place_config_file("config.yml")?),
+    }
+}
+
+/// The value at the dotted `key` in `text`, as YAML, if it's set.
+pub fn get(text: &str, key: &str) -> Result<Option<String>> {
+    let mut value: Yaml = serde_yaml_ng::from_str(text)?;
+    for segment in key.split('.') {
+        match value.get(segment) {
+            Some(child) => value = child.clone(),
+            None => return Ok(None),
+        }
+    }
+    if value.is_null() {
+        return Ok(None);
+    }
+    Ok(Some(
+        serde_yaml_ng::to_string(&value)?.trim_end().to_string(),
+    ))
+}
+
+/// `text` with the dotted `key` set to `value`, which is parsed as YAML so
+/// `9000` is a number and `true` a boolean. Comments and the order of
+/// everything else are kept.
+pub fn set(text: &str, key: &str, value: &str) -> Result<String> {
+    let value = render_value(value)?;
+    let segments: Vec<&str> = key.split('.').collect();
+    if segments.iter().any(|s| s.is_empty()) {
+        bail!("Invalid key `{}`", key);
+    }
+    let mut lines: Vec<String> = text.lines().map(String::from).collect();
+
+    // The lines of the mapping being searched, and its parent's indent
+    let (mut start, mut end) = (0, lines.len());
+    let mut parent_indent: Option<usize> = None;
+    for (i, segment) in segments.iter().enumerate() {
+        let indent = (start..end)
+            .find(|&n| is_content(&lines[n]))
+            .map(|n| indent_of(&lines[n]));
+        let entries: Vec<usize> = (start..end)
+            .filter(|&n| {
+                is_content(&lines[n]) && Some(indent_of(&lines[n])) == indent && !is_item(&lines[n])
+            })
+            .collect();
+        let found = entries
+            .iter()
+            .copied()
+            .find(|&n| key_of(&lines[n]) == Some(segment));
+        let prefix = segments[..=i].join(".");
+
+        let Some(n) = found else {
+            // Add the rest of the key after the mapping's last entry
+            let indent = indent.unwrap_or(parent_indent.map_or(0, |i| i + 2));
+            let at = entries
+                .last()
+                .map_or(end, |&n| block_end(&lines, n, end) + 1);
+            let at = if entries.is_empty() && parent_indent.is_none() {
+                lines.len()
+            } else {
+                at
+            };
+            let new_lines = segments[i..].iter().enumerate().map(|(depth, segment)| {
+                let pad = " ".repeat(indent + depth * 2);
+                if i + depth == segments.len() - 1 {
+                    format!("{}{}: {}", pad, segment, value)
+                } else {
+                    format!("{}{}:", pad, segment)
+                }
+            });
+            lines.splice(at..at, new_lines.collect::<Vec<_>>());
+            return Ok(lines.join("\n") + "\n");
+        };
+
+        let line = &lines[n];
+        let key_end = line.find(':').unwrap() + 1;
+        let (current, comment) = split_comment(&line[key_end..]);
+        let current = current.trim();
+        if i == segments.len() - 1 {
+            if current.is_empty() || current.starts_with(['|', '>']) {
+                bail!(
+                    "`{}` has a nested value; use `contenant config edit` to change it",
+                    prefix
+                );
+            }
+            lines[n] = format!("{} {}{}", &line[..key_end], value, comment);
+            return Ok(lines.join("\n") + "\n");
+        }
+        if !current.is_empty() {
+            bail!(
+                "`{}` isn't a block mapping; use `contenant config edit` to change it",
+                prefix
+            );
+        }
+        parent_indent = indent;
+        end = block_end(&lines, n, end) + 1;
+        start = n + 1;
+    }
+    unreachable!("every segment either matches or is added")
+}
+
+/// A config layer from `--set key=value` overrides, later ones winning.
+pub fn overrides(sets: &[String]) -> Result<Config> {
+    let mut text = String::new();
+    for set_arg in sets {
+        let Some((key, value)) = set_arg.split_once('=') else {
+            bail!("--set {} isn't key=value", set_arg);
+        };
+        text = set(&text, key.trim(), value).wrap_err_with(|| format!("--set {}", set_arg))?;
+    }
+    // Lines in the generated text would only confuse
+    let problems = schema::validate(Path::new("--set"), &text);
+    if let Some(problem) = problems.first() {
+        bail!("--set: {}", problem.message);
+    }
+    Ok(serde_yaml_ng::from_str(&text)?)
+}
+
+/// Add `run`'s one-off flags to an overrides layer: `--env KEY=VALUE` (or
+/// `KEY`, passing the host's value through), `--mount
+/// SOURCE[:TARGET][:ro|:rw]`, and `--allow-domain`.
+pub fn add_run_flags(
+    config: &mut Config,
+    env: &[String],
+    mounts: &[String],
+    allow_domains: &[String],
+) -> Result<()> {
+    for var in env {
+        match var.split_once('=') {
+            Some((key, value)) => {
+                config
+                    .env
+                    .insert(key.to_string(), EnvValue::Plain(value.to_string()));
+            }
+            None => config.env_passthrough.push(var.clone()),
+        }
+    }
+    for spec in mounts {
+        config.mounts.push(mount_flag(spec)?);
+    }
+    config
+        .extra_allowed_domains
+        .extend(allow_domains.iter().cloned());
+    Ok(())
+}
+
+/// A `--mount` flag: read-only unless it ends in `:rw`, like a config mount.
+fn mount_flag(spec: &str) -> Result<Mount> {
+    let mut parts: Vec<&str> = spec.split(':').collect();
+    let readonly = match parts.last() {
+        Some(&"rw") => false,
+        Some(&"ro") => true,
+        _ => {
+            parts.push("ro");
+            true
+        }
+    };
+    parts.pop();
+    let (source, target) = match parts[..] {
+        [source] => (source, None),
+        [source, target] => (source, Some(target.to_string())),
+        _ => bail!("--mount {} isn't SOURCE[:TARGET][:ro|:rw]", spec),
+    };
+    if source.is_empty() {
+        bail!("--mount {} has no source", spec);
+    }
+    Ok(Mount {
+        source: source.to_string(),
+        target,
+        readonly,
+        kind: Default::default(),
+        size: None,
+        mode: None,
+        when: Default::default(),
+        create_missing: false,
+        consistency: None,
+        propagation: None,
+    })
+}
+
+/// `value` as it should appear after `key: `: scalars in YAML's quoting,
+/// flow collections as given.
+fn render_value(value: &str) -> Result<String> {
+    let parsed: Yaml = serde_yaml_ng::from_str(value)
+        .wrap_err_with(|| format!("`{}` isn't a YAML value", value))?;
+    match parsed {
+        Yaml::Sequence(_) | Yaml::Mapping(_) | Yaml::Tagged(_) => {
+            let value = value.trim();
+            if value.contains('\n') || !value.starts_with(['[', '{']) {
+                bail!(
+                    "Only single-line values can be set; write lists as `[a, b]` or use \
+                     `contenant config edit`"
+                );
+            }
+            Ok(value.to_string())
+        }
+        scalar => Ok(serde_yaml_ng::to_string(&scalar)?.trim_end().to_string()),
+    }
+}
+
+/// Whether the line holds YAML rather than a comment or nothing.
+fn is_content(line: &str) -> bool {
+    let content = line.trim_start();
+    !content.is_empty() && !content.starts_with('#') && content != "---"
+}
+
+fn is_item(line: &str) -> bool {
+    let content = line.trim_start();
+    content == "-" || content.starts_with("- ")
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// The key of a `key: value` line, unquoted.
+fn key_of(line: &str) -> Option<&str> {
+    let content = line.trim_start();
+    for quote in ['"', '\''] {
+        if let Some(rest) = content.strip_prefix(quote) {
+            let (key, rest) = rest.split_once(quote)?;
+            return rest.starts_with(':').then_some(key);
+        }
+    }
+    let (key, rest) = content.split_once(':')?;
+    (rest.is_empty() || rest.starts_with([' ', '\t'])).then_some(key)
+}
+
+/// The last content line of the entry starting at line `n`, before `end`.
+fn block_end(lines: &[String], n: usize, end: usize) -> usize {
+    let indent = indent_of(&lines[n]);
+    let mut last = n;
+    for (i, line) in lines.iter().enumerate().take(end).skip(n + 1) {
+        if !is_content(line) {
+            continue;
+        }
+        // A sequence may sit at its key's indent
+        if indent_of(line) < indent || (indent_of(line) == indent && !is_item(line)) {
+            break;
+        }
+        last = i;
+    }
+    last
+}
+
+/// A value and the comment after it, with the space before the comment.
+fn split_comment(rest: &str) -> (&str, &str) {
+    let mut quote = None;
+    let mut previous = ' ';
+    for (i, c) in rest.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '#') if previous.is_whitespace() => {
+                let value = rest[..i].trim_end();
+                return (value, &rest[value.len()..]);
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    (rest, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+# Shared settings
+mounts:
+  - source: ~/.gitconfig
+
+bridge:
+  port: 19500 # picked to avoid the default
+  triggers:
+    test: cargo test
+network:
+  mode: proxy
+";
+
+    #[test]
+    fn set_replaces_values_and_keeps_comments() {
+        let text = set(CONFIG, "bridge.port", "9000").unwrap();
+        assert!(text.contains("  port: 9000 # picked to avoid the default\n"));
+        assert!(text.starts_with("# Shared settings\n"));
+
+        let text = set(CONFIG, "bridge.triggers.test", "cargo nextest run").unwrap();
+        assert!(text.contains("    test: cargo nextest run\n"));
+        assert_eq!(get(&text, "bridge.port").unwrap().unwrap(), "19500");
+    }
+
+    #[test]
+    fn set_adds_missing_keys_under_their_parent() {
+        let text = set(CONFIG, "bridge.clipboard", "true").unwrap();
+        assert!(text.contains("    test: cargo test\n  clipboard: true\nnetwork:\n"));
+
+        let text = set(CONFIG, "engine.kubernetes.namespace", "dev").unwrap();
+        assert!(text.ends_with("engine:\n  kubernetes:\n    namespace: dev\n"));
+
+        let text = set(CONFIG, "bridge.triggers.lint", "cargo clippy").unwrap();
+        assert!(text.contains("    test: cargo test\n    lint: cargo clippy\n"));
+
+        let text = set(CONFIG, "dns_proxy", "false").unwrap();
+        assert!(text.ends_with("  mode: proxy\ndns_proxy: false\n"));
+
+        let text = set("", "claude.version", "'1.0'").unwrap();
+        assert_eq!(text, "claude:\n  version: '1.0'\n");
+    }
+
+    #[test]
+    fn set_quotes_strings_and_refuses_nested_values() {
+        let text = set(CONFIG, "network.proxy", "'#proxy'").unwrap();
+        assert!(text.contains("  proxy: '#proxy'\n"));
+        let text = set(CONFIG, "network.no_proxy", "[localhost, internal]").unwrap();
+        assert!(text.contains("  no_proxy: [localhost, internal]\n"));
+
+        assert!(set(CONFIG, "bridge.triggers", "x").is_err());
+        assert!(set(CONFIG, "bridge.port.x", "1").is_err());
+        assert!(set(CONFIG, "bridge..port", "1").is_err());
+    }
+
+    #[test]
+    fn overrides_build_a_layer() {
+        let config = overrides(&[
+            "bridge.port=9001".to_string(),
+            "env.RUST_LOG=debug".to_string(),
+            "env.RUST_LOG=trace".to_string(),
+            "env.QUERY=a=b".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(config.bridge.port, 9001);
+        assert_eq!(config.env["RUST_LOG"], "trace".into());
+        assert_eq!(config.env["QUERY"], "a=b".into());
+
+        assert!(overrides(&["bridge.port".to_string()]).is_err());
+        let err = overrides(&["bridge.prot=1".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), "--set: unknown key `bridge.prot`");
+        assert!(overrides(&["network.mode=bogus".to_string()]).is_err());
+    }
+
+    #[test]
+    fn run_flags_add_to_the_layer() {
+        let mut config = overrides(&["env.A=a".to_string()]).unwrap();
+        add_run_flags(
+            &mut config,
+            &["B=x=y".to_string(), "SSH_AUTH_SOCK".to_string()],
+            &["./data".to_string(), "~/cache:/cache:rw".to_string()],
+            &["example.com".to_string()],
+        )
+        .unwrap();
+        assert_eq!(config.env["A"], "a".into());
+        assert_eq!(config.env["B"], "x=y".into());
+        assert_eq!(config.env_passthrough, ["SSH_AUTH_SOCK"]);
+        assert_eq!(
+            (
+                config.mounts[0].target.as_deref(),
+                config.mounts[0].readonly
+            ),
+            (None, true)
+        );
+        assert_eq!(
+            (
+                config.mounts[1].target.as_deref(),
+                config.mounts[1].readonly
+            ),
+            (Some("/cache"), false)
+        );
+        assert_eq!(config.extra_allowed_domains, ["example.com"]);
+
+        let mut config = Config::default();
+        assert!(add_run_flags(&mut config, &[], &["a:b:c:ro".to_string()], &[]).is_err());
+        assert!(add_run_flags(&mut config, &[], &[":/x".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn get_reads_values() {
+        assert_eq!(get(CONFIG, "network.mode").unwrap().unwrap(), "proxy");
+        assert_eq!(
+            get(CONFIG, "mounts").unwrap().unwrap(),
+            "- source: ~/.gitconfig"
+        );
+        assert_eq!(get(CONFIG, "network.group").unwrap(), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! Checking prebuilt images' cosign signatures before running them.
+//!
+//! [`verify`] runs `cosign verify` against the public key in
+//! `image.cosign_key`, and with `image.attestation` also
+//! `cosign verify-attestation` for a predicate of that type, so an image
+//! pushed by someone without the signing key never runs. Tags can move, so
+//! the attestation, and whatever uses the image afterwards, goes by the digest
+//! whose signature was verified. `contenant verify-image` does the same on
+//! demand.
+
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr, bail, eyre};
+
+/// The `cosign` invocation checking `reference`'s signature.
+pub fn signature_command(reference: &str, key: &Path) -> Command {
+    let mut verify = Command::new("cosign");
+    verify.arg("verify").arg("--key").arg(key).arg(reference);
+    verify
+}
+
+/// The `cosign` invocation checking `reference`'s attestation of type
+/// `attestation`.
+pub fn attestation_command(reference: &str, key: &Path, attestation: &str) -> Command {
+    let mut verify = Command::new("cosign");
+    verify
+        .arg("verify-attestation")
+        .arg("--key")
+        .arg(key)
+        .args(["--type", attestation])
+        .arg(reference);
+    verify
+}
+
+/// Fail unless `reference` is signed, and attested if `attestation` is
+/// given, with the private half of `key`, returning the digest that was.
+pub fn verify(reference: &str, key: &Path, attestation: Option<&str>) -> Result<String> {
+    if !key.exists() {
+        bail!("image.cosign_key {} doesn't exist", key.display());
+    }
+    let output = run(signature_command(reference, key), reference, key)?;
+    let digest = signed_digest(&output.stdout).ok_or_else(|| {
+        eyre!(
+            "cosign didn't say which digest of {} it verified",
+            reference
+        )
+    })?;
+    if let Some(kind) = attestation {
+        let pinned = pinned(reference, &digest);
+        run(attestation_command(&pinned, key, kind), &pinned, key)?;
+    }
+    Ok(digest)
+}
+
+fn run(mut command: Command, reference: &str, key: &Path) -> Result<Output> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .wrap_err("Couldn't run cosign; install it to verify images")?;
+    if !output.status.success() {
+        bail!(
+            "{} failed verification against {}: {}",
+            reference,
+            key.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output)
+}
+
+/// The manifest digest `cosign verify` printed for the signatures it
+/// checked, if they all agree on one.
+fn signed_digest(stdout: &[u8]) -> Option<String> {
+    let signatures: Vec<serde_json::Value> = serde_json::from_slice(stdout).ok()?;
+    let mut digests = signatures.iter().map(|signature| {
+        signature["critical"]["image"]["docker-manifest-digest"]
+            .as_str()
+            .filter(|digest| digest.starts_with("sha256:"))
+    });
+    let digest = digests.next()??;
+    digests
+        .all(|other| other == Some(digest))
+        .then(|| digest.to_string())
+}
+
+/// `reference` pinned to `digest`, in place of any tag or digest it had.
+pub fn pinned(reference: &str, digest: &str) -> String {
+    let name = reference
+        .split_once('@')
+        .map_or(reference, |(name, _)| name);
+    // A colon before the last slash is a registry's port
+    let name = match name.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => name,
+    };
+    format!("{}@{}", name, digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_the_signature_then_the_attestation() {
+        let key = Path::new("/keys/cosign.pub");
+        let args = |command: &Command| -> Vec<String> {
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect()
+        };
+
+        assert_eq!(
+            args(&signature_command("ghcr.io/kejadlen/contenant:1.0", key)),
+            [
+                "verify",
+                "--key",
+                "/keys/cosign.pub",
+                "ghcr.io/kejadlen/contenant:1.0"
+            ]
+        );
+        assert_eq!(
+            args(&attestation_command(
+                "ghcr.io/kejadlen/contenant@sha256:abc",
+                key,
+                "slsaprovenance",
+            )),
+            [
+                "verify-attestation",
+                "--key",
+                "/keys/cosign.pub",
+                "--type",
+                "slsaprovenance",
+                "ghcr.io/kejadlen/contenant@sha256:abc"
+            ]
+        );
+
+        assert!(verify("ghcr.io/kejadlen/contenant:1.0", key, None).is_err());
+    }
+
+    #[test]
+    fn pins_what_was_verified_by_digest() {
+        let signature = |digest: &str| {
+            format!(
+                r#"{{"critical":{{"image":{{"docker-manifest-digest":"{}"}}}}}}"#,
+                digest
+            )
+        };
+        let output = format!("[{},{}]", signature("sha256:abc"), signature("sha256:abc"));
+        assert_eq!(
+            signed_digest(output.as_bytes()).as_deref(),
+            Some("sha256:abc")
+        );
+        let output = format!("[{},{}]", signature("sha256:abc"), signature("sha256:def"));
+        assert_eq!(signed_digest(output.as_bytes()), None);
+        assert_eq!(signed_digest(b"[]"), None);
+        assert_eq!(signed_digest(signature("abc").as_bytes()), None);
+
+        for reference in [
+            "ghcr.io/kejadlen/contenant:1.0",
+            "ghcr.io/kejadlen/contenant",
+            "ghcr.io/kejadlen/contenant:1.0@sha256:old",
+        ] {
+            assert_eq!(
+                pinned(reference, "sha256:abc"),
+                "ghcr.io/kejadlen/contenant@sha256:abc"
+            );
+        }
+        assert_eq!(
+            pinned("localhost:5000/contenant:1.0", "sha256:abc"),
+            "localhost:5000/contenant@sha256:abc"
+        );
+        assert_eq!(
+            pinned("localhost:5000/contenant", "sha256:abc"),
+            "localhost:5000/contenant@sha256:abc"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Claude's login, shared between the host and the container.
+//!
+//! On macOS, Claude Code keeps its OAuth credentials in the Keychain, where
+//! the container can't reach them, so [`sync_in`] copies them into
+//! [`FILE`] in the mounted Claude state before every run. Either side
+//! refreshes the access token when it expires, and a refresh can invalidate
+//! the other side's refresh token, so the copy that expires later wins:
+//! [`sync_in`] leaves the file alone when the container has refreshed it
+//! since, and with `claude.keychain: sync`, [`write_back`] hands the
+//! container's refreshed copy back to the Keychain after the run.
+//!
+//! Linux desktops get the same from a [`Store`] backed by the Secret
+//! Service or a `pass`-style password store.
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+use crate::config::CredentialStore;
+
+/// The credentials file, in the Claude state directory.
+pub const FILE: &str = ".credentials.json";
+
+/// The Keychain item Claude Code stores its credentials in.
+pub const KEYCHAIN_SERVICE: &str = "Claude Code-credentials";
+
+/// When `credentials`' access token expires, in milliseconds since the
+/// epoch.
+pub fn expires_at(credentials: &str) -> Option<u64> {
+    let value: serde_json::Value = serde_json::from_str(credentials).ok()?;
+    value["claudeAiOauth"]["expiresAt"].as_u64()
+}
+
+fn has_refresh_token(credentials: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(credentials)
+        .is_ok_and(|value| value["claudeAiOauth"]["refreshToken"].is_string())
+}
+
+/// Whether `candidate` expires later than `current`, so should replace it.
+fn is_newer(candidate: &str, current: Option<&str>) -> bool {
+    let Some(current) = current else {
+        return true;
+    };
+    candidate != current && expires_at(candidate) > expires_at(current)
+}
+
+/// Copy `keychain`'s credentials into `state_dir` unless the file there
+/// expires later, and warn if what the agent gets has expired.
+pub fn sync_in(state_dir: &Path, keychain: Option<&str>) -> Result<()> {
+    let path = state_dir.join(FILE);
+    let mut current = fs::read_to_string(&path).ok();
+    if let Some(keychain) = keychain
+        && is_newer(keychain, current.as_deref())
+    {
+        write_private(&path, keychain)?;
+        current = Some(keychain.to_string());
+    }
+
+    let Some(current) = current else {
+        return Ok(());
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    if expires_at(&current).is_some_and(|expires| expires <= now) {
+        if has_refresh_token(&current) {
+            eprintln!("contenant: Claude's access token has expired; the agent will refresh it");
+        } else {
+            eprintln!(
+                "contenant: Claude's login has expired; run /login in the container to log in again"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The credentials in `state_dir`, if they expire later than `keychain`'s
+/// and so should be written back.
+pub fn write_back(state_dir: &Path, keychain: Option<&str>) -> Option<String> {
+    let file = fs::read_to_string(state_dir.join(FILE)).ok()?;
+    (expires_at(&file).is_some() && is_newer(&file, keychain)).then_some(file)
+}
+
+/// Where the host keeps Claude's login.
+pub trait Store {
+    /// What it is, for messages.
+    fn name(&self) -> &'static str;
+    /// The credentials, if it has any.
+    fn read(&self) -> Result<Option<String>>;
+    /// Replace the credentials.
+    fn write(&self, credentials: &str) -> Result<()>;
+}
+
+/// The macOS Keychain, where Claude Code logs in.
+pub struct Keychain;
+
+impl Store for Keychain {
+    fn name(&self) -> &'static str {
+        "the Keychain"
+    }
+
+    fn read(&self) -> Result<Option<String>> {
+        read(Command::new("security").args(["find-generic-password", "-w", "-s", KEYCHAIN_SERVICE]))
+    }
+
+    /// The command goes through `security -i`'s stdin, hex-encoded, to keep
+    /// the tokens off its command line.
+    fn write(&self, credentials: &str) -> Result<()> {
+        let hex: String = credentials.bytes().map(|b| format!("{:02x}", b)).collect();
+        let command = format!(
+            "add-generic-password -U -a \"{}\" -s \"{}\" -X {}\n",
+            account().replace('"', ""),
+            KEYCHAIN_SERVICE,
+            hex
+        );
+        write(Command::new("security").arg("-i"), &command)
+    }
+}
+
+/// The Secret Service (GNOME Keyring, KWallet) through libsecret's
+/// `secret-tool`, under the same service name as the Keychain item.
+pub struct SecretService;
+
+impl Store for SecretService {
+    fn name(&self) -> &'static str {
+        "the Secret Service"
+    }
+
+    fn read(&self) -> Result<Option<String>> {
+        read(Command::new("secret-tool").args(["lookup", "service", KEYCHAIN_SERVICE]))
+    }
+
+    fn write(&self, credentials: &str) -> Result<()> {
+        write(
+            Command::new("secret-tool")
+                .args(["store", "--label=Claude Code credentials"])
+                .args(["service", KEYCHAIN_SERVICE, "account"])
+                .arg(account()),
+            credentials,
+        )
+    }
+}
+
+/// A `pass` or `gopass` password store, in [`PASS_ENTRY`].
+pub struct Pass {
+    pub program: &'static str,
+}
+
+/// The password store entry [`Pass`] uses.
+pub const PASS_ENTRY: &str = "claude-code/credentials";
+
+impl Store for Pass {
+    fn name(&self) -> &'static str {
+        self.program
+    }
+
+    fn read(&self) -> Result<Option<String>> {
+        read(Command::new(self.program).args(["show", PASS_ENTRY]))
+    }
+
+    fn write(&self, credentials: &str) -> Result<()> {
+        write(
+            Command::new(self.program).args(["insert", "--multiline", "--force", PASS_ENTRY]),
+            credentials,
+        )
+    }
+}
+
+/// The store `configured` names, or else the first available: the Keychain
+/// on macOS, then `secret-tool`, `gopass`, and `pass`.
+pub fn store(configured: Option<CredentialStore>) -> Option<Box<dyn Store>> {
+    let store = configured.or_else(|| {
+        if cfg!(target_os = "macos") {
+            Some(CredentialStore::Keychain)
+        } else if crate::on_path("secret-tool") {
+            Some(CredentialStore::SecretService)
+        } else if crate::on_path("gopass") {
+            Some(CredentialStore::Gopass)
+        } else if crate::on_path("pass") {
+            Some(CredentialStore::Pass)
+        } else {
+            None
+        }
+    })?;
+    Some(match store {
+        CredentialStore::Keychain => Box::new(Keychain),
+        CredentialStore::SecretService => Box::new(SecretService),
+        CredentialStore::Pass => Box::new(Pass { program: "pass" }),
+        CredentialStore::Gopass => Box::new(Pass { program: "gopass" }),
+    })
+}
+
+fn account() -> String {
+    std::env::var("USER").unwrap_or_else(|_| "claude".to_string())
+}
+
+/// The command's stdout, or `None` if it fails, which is how each store
+/// reports a missing entry. Its stdin is the terminal's, so it can prompt
+/// to unlock.
+fn read(command: &mut Command) -> Result<Option<String>> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stderr(Stdio::null())
+        .output()
+        .wrap_err_with(|| format!("Couldn't run {}", program))?;
+    if !output.status.success() {
+        return Ok(None);
+    }
+    let credentials = String::from_utf8(output.stdout)
+        .wrap_err_with(|| format!("{} printed something other than UTF-8", program))?;
+    Ok(Some(credentials.trim_end().to_string()))
+}
+
+/// Run the command with `input` on its stdin.
+fn write(command: &mut Command, input: &str) -> Result<()> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .wrap_err_with(|| format!("Couldn't run {}", program))?;
+    child.stdin.take().unwrap().write_all(input.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("{} exited with {}", program, status);
+    }
+    Ok(())
+}
+
+/// Write a file only its owner can read.
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .wrap_err_with(|| format!("Couldn't write {}", path.display()))?;
+    file.write_all(contents.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    fn credentials(expires_at: u64) -> String {
+        format!(
+            r#"{{"claudeAiOauth":{{"accessToken":"a{0}","refreshToken":"r{0}","expiresAt":{0}}}}}"#,
+            expires_at
+        )
+    }
+
+    #[test]
+    fn the_copy_that_expires_later_wins() {
+        let state = tempfile::tempdir().unwrap();
+        let path = state.path().join(FILE);
+
+        sync_in(state.path(), Some(&credentials(2000))).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), credentials(2000));
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+        assert_eq!(write_back(state.path(), Some(&credentials(2000))), None);
+
+        // The container refreshed the token
+        fs::write(&path, credentials(3000)).unwrap();
+        sync_in(state.path(), Some(&credentials(2000))).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), credentials(3000));
+        assert_eq!(
+            write_back(state.path(), Some(&credentials(2000))),
+            Some(credentials(3000))
+        );
+
+        // The host did
+        sync_in(state.path(), Some(&credentials(4000))).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), credentials(4000));
+        assert_eq!(expires_at(&credentials(4000)), Some(4000));
+        assert_eq!(expires_at("{}"), None);
+    }
+
+    #[test]
+    fn stores_take_credentials_on_stdin_and_report_missing_entries_as_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = dir.path().join("entry");
+        write(
+            Command::new("sh").arg("-c").arg("cat > \"$0\"").arg(&entry),
+            &credentials(1000),
+        )
+        .unwrap();
+        assert_eq!(
+            read(Command::new("cat").arg(&entry)).unwrap(),
+            Some(credentials(1000))
+        );
+        assert_eq!(
+            read(Command::new("cat").arg(dir.path().join("missing"))).unwrap(),
+            None
+        );
+        assert!(write(Command::new("false").arg("x"), "").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! What detached sessions leave behind for their containers: the bridge
+//! token, and the per-run directories of certificates, AWS config, and
+//! secrets. They're recorded by session id so they can be removed once the
+//! container is gone, rather than staying valid for good.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+
+/// Where records are kept, under the state directory.
+pub const DIR: &str = "detached";
+
+/// Record `paths` as `session`'s.
+pub fn record(dir: &Path, session: &str, paths: &[PathBuf]) -> Result<()> {
+    let contents: String = paths
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect();
+    let record = dir.join(session);
+    fs::write(&record, contents).wrap_err_with(|| format!("Couldn't write {}", record.display()))
+}
+
+/// Sessions with records.
+pub fn sessions(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut sessions: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect();
+    sessions.sort();
+    sessions
+}
+
+/// Remove what sessions other than those `running` left behind, along with
+/// their records, returning which sessions they were.
+pub fn revoke(dir: &Path, running: &HashSet<String>) -> Result<Vec<String>> {
+    let mut revoked = vec![];
+    for session in sessions(dir) {
+        if running.contains(&session) {
+            continue;
+        }
+        let record = dir.join(&session);
+        let paths = fs::read_to_string(&record)
+            .wrap_err_with(|| format!("Couldn't read {}", record.display()))?;
+        for path in paths.lines().map(Path::new) {
+            // Already gone is as good as removed
+            let _ = if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            };
+        }
+        fs::remove_file(&record)?;
+        revoked.push(session);
+    }
+    Ok(revoked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revokes_only_what_stopped_sessions_left() {
+        let dir = tempfile::tempdir().unwrap();
+        let records = dir.path().join(DIR);
+        fs::create_dir(&records).unwrap();
+        let token = dir.path().join("token");
+        let certs = dir.path().join("certs");
+        fs::write(&token, "").unwrap();
+        fs::create_dir(&certs).unwrap();
+        fs::write(certs.join("client.key"), "").unwrap();
+        let running = dir.path().join("running-token");
+        fs::write(&running, "").unwrap();
+
+        record(&records, "1-stopped", &[token.clone(), certs.clone()]).unwrap();
+        record(&records, "2-running", std::slice::from_ref(&running)).unwrap();
+        assert_eq!(sessions(&records), ["1-stopped", "2-running"]);
+
+        let revoked = revoke(&records, &HashSet::from(["2-running".to_string()])).unwrap();
+        assert_eq!(revoked, ["1-stopped"]);
+        assert!(!token.exists() && !certs.exists());
+        assert!(running.exists());
+        assert_eq!(sessions(&records), ["2-running"]);
+    }
+}
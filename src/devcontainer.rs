@@ -0,0 +1,434 @@
+//! Importing a `devcontainer.json`, for `contenant init --from-devcontainer`
+//! and for projects without a `.contenant/config.yml`.
+//!
+//! `containerEnv`, `forwardPorts`, and bind `mounts` map onto config keys.
+//! The image and features don't: contenant's images build on
+//! `contenant:base`, so they become notes in a Dockerfile to fill in.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+use serde::Deserialize;
+use serde_json::Value;
+use serde_yaml_ng::{Mapping, Value as Yaml};
+
+use crate::config::{CONTAINER_HOME, Config};
+
+/// Where the dev container spec looks for the file, in order.
+const PATHS: &[&str] = &[".devcontainer/devcontainer.json", ".devcontainer.json"];
+
+/// The parts of a `devcontainer.json` contenant understands.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DevContainer {
+    pub image: Option<String>,
+    pub build: Option<Build>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Value>,
+    #[serde(default)]
+    pub mounts: Vec<MountSpec>,
+    #[serde(default)]
+    pub container_env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub forward_ports: Vec<Value>,
+    pub remote_user: Option<String>,
+    pub container_user: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Build {
+    pub dockerfile: Option<String>,
+}
+
+/// A mount, as a Docker `--mount` string or an object.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum MountSpec {
+    String(String),
+    Object {
+        source: Option<String>,
+        target: String,
+        #[serde(rename = "type")]
+        kind: Option<String>,
+    },
+}
+
+/// A `devcontainer.json` as contenant config, with what couldn't be carried
+/// over.
+#[derive(Debug)]
+pub struct Import {
+    /// `.contenant/config.yml` contents.
+    pub config: String,
+    /// `.contenant/Dockerfile` contents, when there's an image or features.
+    pub dockerfile: Option<String>,
+    /// Settings left out, one per line.
+    pub skipped: Vec<String>,
+}
+
+/// The project's `devcontainer.json`, if it has one.
+pub fn find(project_dir: &Path) -> Option<PathBuf> {
+    PATHS
+        .iter()
+        .map(|path| project_dir.join(path))
+        .find(|path| path.exists())
+}
+
+/// Read a `devcontainer.json`, which may have comments and trailing commas.
+pub fn load(path: &Path) -> Result<DevContainer> {
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&strip_jsonc(&text))
+        .wrap_err_with(|| format!("Couldn't parse {}", path.display()))
+}
+
+/// The config layer a project without `.contenant/config.yml` gets from its
+/// `devcontainer.json`.
+pub fn config(project_dir: &Path, devcontainer: &DevContainer) -> Result<Config> {
+    let workspace = project_dir.to_string_lossy();
+    let import = import(devcontainer, &workspace)?;
+    Ok(serde_yaml_ng::from_str(&import.config)?)
+}
+
+/// Translate `devcontainer`. `workspace` replaces `${localWorkspaceFolder}`
+/// in mount sources: `..` for a config written to `.contenant/`.
+pub fn import(devcontainer: &DevContainer, workspace: &str) -> Result<Import> {
+    let mut config = Mapping::new();
+    let mut skipped = vec![];
+
+    let mut env = Mapping::new();
+    let mut secrets = Mapping::new();
+    for (name, value) in &devcontainer.container_env {
+        // A host variable passed through is a secret read at run time
+        if let Some(var) = local_env(value) {
+            secrets.insert(name.as_str().into(), format!("env:{}", var).into());
+        } else if value.contains("${") {
+            skipped.push(format!("containerEnv.{}: {}", name, value));
+        } else {
+            env.insert(name.as_str().into(), value.as_str().into());
+        }
+    }
+
+    let mut ports = vec![];
+    for port in &devcontainer.forward_ports {
+        match port {
+            Value::Number(port) => ports.push(Yaml::from(port.to_string())),
+            Value::String(port) => match port.strip_prefix("localhost:") {
+                Some(port) => ports.push(port.into()),
+                None => skipped.push(format!("forwardPorts: {}", port)),
+            },
+            _ => skipped.push(format!("forwardPorts: {}", port)),
+        }
+    }
+
+    let homes: Vec<String> = [&devcontainer.remote_user, &devcontainer.container_user]
+        .into_iter()
+        .flatten()
+        .map(|user| match user.as_str() {
+            "root" => "/root".to_string(),
+            user => format!("/home/{}", user),
+        })
+        .collect();
+    let mut mounts = vec![];
+    for spec in &devcontainer.mounts {
+        match mount(spec, workspace, &homes) {
+            Some(mount) => mounts.push(Yaml::Mapping(mount)),
+            None => skipped.push(format!("mounts: {}", spec)),
+        }
+    }
+
+    if !mounts.is_empty() {
+        config.insert("mounts".into(), Yaml::Sequence(mounts));
+    }
+    if !env.is_empty() {
+        config.insert("env".into(), Yaml::Mapping(env));
+    }
+    if !secrets.is_empty() {
+        config.insert("secrets".into(), Yaml::Mapping(secrets));
+    }
+    if !ports.is_empty() {
+        config.insert("ports".into(), Yaml::Sequence(ports));
+    }
+    let config = if config.is_empty() {
+        String::new()
+    } else {
+        serde_yaml_ng::to_string(&config)?
+    };
+
+    Ok(Import {
+        config: format!("# Imported from devcontainer.json\n{}", config),
+        dockerfile: dockerfile(devcontainer),
+        skipped,
+    })
+}
+
+/// A Dockerfile noting the image and features to install by hand.
+fn dockerfile(devcontainer: &DevContainer) -> Option<String> {
+    let build = devcontainer
+        .build
+        .as_ref()
+        .and_then(|b| b.dockerfile.as_deref());
+    if devcontainer.image.is_none() && build.is_none() && devcontainer.features.is_empty() {
+        return None;
+    }
+
+    let mut lines = vec![
+        "# Imported from devcontainer.json. contenant images build on".to_string(),
+        "# contenant:base, so install what the project needs here.".to_string(),
+    ];
+    if let Some(image) = &devcontainer.image {
+        lines.push(format!("#   image: {}", image));
+    }
+    if let Some(build) = build {
+        lines.push(format!("#   build.dockerfile: {}", build));
+    }
+    for (feature, options) in &devcontainer.features {
+        let empty = options.as_object().is_some_and(|o| o.is_empty());
+        if empty {
+            lines.push(format!("#   feature: {}", feature));
+        } else {
+            lines.push(format!("#   feature: {} {}", feature, options));
+        }
+    }
+    lines.push(String::new());
+    lines.push("FROM contenant:user".to_string());
+    Some(lines.join("\n") + "\n")
+}
+
+/// A bind mount as a config `mounts` entry. Volumes and sources that use
+/// variables contenant can't fill in are left out.
+fn mount(spec: &MountSpec, workspace: &str, homes: &[String]) -> Option<Mapping> {
+    let (source, target, kind, readonly) = match spec {
+        MountSpec::String(spec) => {
+            let mut fields = BTreeMap::new();
+            let mut readonly = false;
+            for field in spec.split(',') {
+                match field.split_once('=') {
+                    Some((key, value)) => {
+                        fields.insert(key.trim(), value.trim());
+                    }
+                    None if matches!(field.trim(), "readonly" | "ro") => readonly = true,
+                    None => {}
+                }
+            }
+            let source = fields.get("source").or(fields.get("src"))?;
+            let target = fields
+                .get("target")
+                .or(fields.get("dst"))
+                .or(fields.get("destination"))?;
+            let kind = fields.get("type").copied().unwrap_or("volume");
+            readonly |= fields
+                .get("readonly")
+                .or(fields.get("ro"))
+                .is_some_and(|v| *v != "false");
+            (source.to_string(), target.to_string(), kind, readonly)
+        }
+        MountSpec::Object {
+            source,
+            target,
+            kind,
+        } => (
+            source.clone()?,
+            target.clone(),
+            kind.as_deref().unwrap_or("volume"),
+            false,
+        ),
+    };
+    if kind != "bind" {
+        return None;
+    }
+
+    let source = source
+        .replace("${localWorkspaceFolder}", workspace)
+        .replace("${localEnv:HOME}", "~")
+        .replace("${localEnv:USERPROFILE}", "~");
+    let mut target = target.replace("${containerWorkspaceFolder}", "/workspace");
+    for home in homes {
+        if let Some(rest) = target.strip_prefix(home.as_str())
+            && (rest.is_empty() || rest.starts_with('/'))
+        {
+            target = format!("{}{}", CONTAINER_HOME, rest);
+        }
+    }
+    if source.contains("${") || target.contains("${") {
+        return None;
+    }
+
+    let mut mount = Mapping::new();
+    mount.insert("source".into(), source.into());
+    mount.insert("target".into(), target.into());
+    mount.insert("readonly".into(), readonly.into());
+    Some(mount)
+}
+
+/// The host variable a `${localEnv:VAR}` value passes through.
+fn local_env(value: &str) -> Option<&str> {
+    let var = value.strip_prefix("${localEnv:")?.strip_suffix('}')?;
+    (!var.contains([':', '$', '{'])).then_some(var)
+}
+
+impl std::fmt::Display for MountSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MountSpec::String(spec) => write!(f, "{}", spec),
+            MountSpec::Object {
+                source,
+                target,
+                kind,
+            } => write!(
+                f,
+                "source={},target={},type={}",
+                source.as_deref().unwrap_or(""),
+                target,
+                kind.as_deref().unwrap_or("volume")
+            ),
+        }
+    }
+}
+
+/// JSON from JSON with comments: comments dropped, trailing commas removed.
+fn strip_jsonc(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => while chars.next_if(|&c| c != '\n').is_some() {},
+            ('/', Some('*')) => {
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            (']' | '}', _) => {
+                let trimmed = out.trim_end().len();
+                if out[..trimmed].ends_with(',') {
+                    out.truncate(trimmed - 1);
+                }
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVCONTAINER: &str = r#"{
+  // Rust toolchain
+  "image": "mcr.microsoft.com/devcontainers/rust:1",
+  "features": {
+    "ghcr.io/devcontainers/features/node:1": { "version": "20" },
+    "ghcr.io/devcontainers/features/github-cli:1": {},
+  },
+  /* Caches and credentials */
+  "mounts": [
+    "source=${localEnv:HOME}/.cargo/registry,target=/home/vscode/.cargo/registry,type=bind",
+    "source=${localWorkspaceFolder}/data,target=/data,type=bind,readonly",
+    "source=node_modules,target=${containerWorkspaceFolder}/node_modules,type=volume",
+    { "source": "/var/run/docker.sock", "target": "/var/run/docker.sock", "type": "bind" },
+  ],
+  "containerEnv": {
+    "RUST_LOG": "debug",
+    "GH_TOKEN": "${localEnv:GH_TOKEN}",
+    "PATH": "${containerEnv:PATH}:/opt/bin",
+    "URL": "http://example.com/a//b",
+  },
+  "forwardPorts": [3000, "localhost:8080", "db:5432"],
+  "remoteUser": "vscode",
+}"#;
+
+    #[test]
+    fn imports_env_ports_and_bind_mounts() {
+        let devcontainer: DevContainer = serde_json::from_str(&strip_jsonc(DEVCONTAINER)).unwrap();
+        let import = import(&devcontainer, "..").unwrap();
+        let config: Config = serde_yaml_ng::from_str(&import.config).unwrap();
+
+        let mounts: Vec<_> = config
+            .mounts
+            .iter()
+            .map(|m| (m.source.as_str(), m.target.as_deref().unwrap(), m.readonly))
+            .collect();
+        assert_eq!(
+            mounts,
+            [
+                ("~/.cargo/registry", "/home/claude/.cargo/registry", false),
+                ("../data", "/data", true),
+                ("/var/run/docker.sock", "/var/run/docker.sock", false),
+            ]
+        );
+        assert_eq!(config.env["RUST_LOG"], "debug".into());
+        assert_eq!(config.env["URL"], "http://example.com/a//b".into());
+        assert_eq!(config.secrets["GH_TOKEN"].from, "env:GH_TOKEN");
+        assert_eq!(config.ports, ["3000", "8080"]);
+        assert_eq!(
+            import.skipped,
+            [
+                "containerEnv.PATH: ${containerEnv:PATH}:/opt/bin",
+                "forwardPorts: db:5432",
+                "mounts: source=node_modules,target=${containerWorkspaceFolder}/node_modules,type=volume",
+            ]
+        );
+    }
+
+    #[test]
+    fn notes_image_and_features_in_a_dockerfile() {
+        let devcontainer: DevContainer = serde_json::from_str(&strip_jsonc(DEVCONTAINER)).unwrap();
+        let dockerfile = dockerfile(&devcontainer).unwrap();
+        assert!(dockerfile.contains("#   image: mcr.microsoft.com/devcontainers/rust:1\n"));
+        assert!(dockerfile.contains("#   feature: ghcr.io/devcontainers/features/github-cli:1\n"));
+        assert!(
+            dockerfile.contains(
+                "#   feature: ghcr.io/devcontainers/features/node:1 {\"version\":\"20\"}\n"
+            )
+        );
+        assert!(dockerfile.ends_with("\nFROM contenant:user\n"));
+
+        assert!(dockerfile_for("{}").is_none());
+    }
+
+    fn dockerfile_for(json: &str) -> Option<String> {
+        dockerfile(&serde_json::from_str(json).unwrap())
+    }
+
+    #[test]
+    fn projects_without_config_fall_back_to_devcontainer() {
+        let project = tempfile::tempdir().unwrap();
+        fs::create_dir(project.path().join(".devcontainer")).unwrap();
+        fs::write(
+            project.path().join(".devcontainer/devcontainer.json"),
+            r#"{ "containerEnv": { "FOO": "bar" } }"#,
+        )
+        .unwrap();
+
+        let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant-test-nonexistent");
+        let config = crate::StackedConfig::load(&xdg_dirs, Some(project.path())).unwrap();
+        assert_eq!(config.env()["FOO"], "bar");
+
+        // A contenant config takes its place
+        fs::create_dir(project.path().join(".contenant")).unwrap();
+        fs::write(project.path().join(".contenant/config.yml"), "env: {}\n").unwrap();
+        let config = crate::StackedConfig::load(&xdg_dirs, Some(project.path())).unwrap();
+        assert!(!config.env().contains_key("FOO"));
+    }
+}
@@ -0,0 +1,216 @@
+//! Host-side resolution of allowed domains, through the system resolver or
+//! configured DNS servers (`network.resolvers`), with per-domain overrides
+//! for names that only resolve via a specific server, such as on a VPN.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Result, bail, eyre};
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+#[derive(Debug, Default)]
+pub struct Resolver {
+    /// Servers for every domain; empty means the system resolver.
+    servers: Vec<IpAddr>,
+    /// Servers for a domain and its subdomains, overriding `servers`.
+    overrides: HashMap<String, Vec<IpAddr>>,
+}
+
+impl Resolver {
+    pub fn new(servers: &[String], overrides: &HashMap<String, Vec<String>>) -> Result<Self> {
+        let parse = |servers: &[String]| -> Result<Vec<IpAddr>> {
+            servers
+                .iter()
+                .map(|s| s.parse().map_err(|_| eyre!("Invalid DNS server {}", s)))
+                .collect()
+        };
+        Ok(Self {
+            servers: parse(servers)?,
+            overrides: overrides
+                .iter()
+                .map(|(domain, servers)| Ok((domain.to_ascii_lowercase(), parse(servers)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// Servers for `name`: the most specific override, else the defaults.
+    fn servers_for(&self, name: &str) -> &[IpAddr] {
+        let name = name.to_ascii_lowercase();
+        self.overrides
+            .iter()
+            .filter(|(domain, _)| name == **domain || name.ends_with(&format!(".{}", domain)))
+            .max_by_key(|(domain, _)| domain.len())
+            .map_or(&self.servers, |(_, servers)| servers)
+    }
+
+    pub fn lookup(&self, name: &str) -> Result<Vec<IpAddr>> {
+        let servers = self.servers_for(name);
+        if servers.is_empty() {
+            return Ok((name, 443).to_socket_addrs()?.map(|a| a.ip()).collect());
+        }
+
+        let mut last_err = None;
+        for server in servers {
+            let result = [TYPE_A, TYPE_AAAA]
+                .iter()
+                .map(|&qtype| query(*server, name, qtype))
+                .collect::<Result<Vec<_>>>();
+            match result {
+                Ok(answers) => return Ok(answers.concat()),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| eyre!("No DNS servers for {}", name)))
+    }
+}
+
+fn query(server: IpAddr, name: &str, qtype: u16) -> Result<Vec<IpAddr>> {
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.subsec_nanos()) as u16;
+    let request = encode_query(id, name, qtype);
+
+    let bind: SocketAddr = match server {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.connect((server, 53))?;
+    socket.send(&request)?;
+
+    let mut buf = [0; 4096];
+    loop {
+        let n = socket.recv(&mut buf)?;
+        if n >= 2 && u16::from_be_bytes([buf[0], buf[1]]) == id {
+            return decode_answers(&buf[..n]);
+        }
+    }
+}
+
+fn encode_query(id: u16, name: &str, qtype: u16) -> Vec<u8> {
+    let mut packet = vec![];
+    packet.extend(id.to_be_bytes());
+    packet.extend([0x01, 0x00]); // recursion desired
+    packet.extend([0, 1, 0, 0, 0, 0, 0, 0]); // one question
+    for label in name.trim_end_matches('.').split('.') {
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend(qtype.to_be_bytes());
+    packet.extend([0, 1]); // class IN
+    packet
+}
+
+fn decode_answers(packet: &[u8]) -> Result<Vec<IpAddr>> {
+    let field = |at: usize| -> Result<u16> {
+        let bytes = packet
+            .get(at..at + 2)
+            .ok_or_else(|| eyre!("Truncated DNS response"))?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+
+    let rcode = field(2)? & 0x0f;
+    // NXDOMAIN is an empty answer, not a failure of the server
+    if rcode == 3 {
+        return Ok(vec![]);
+    }
+    if rcode != 0 {
+        bail!("DNS server returned rcode {}", rcode);
+    }
+
+    let questions = field(4)?;
+    let answers = field(6)?;
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(packet, at)? + 4;
+    }
+
+    let mut ips = vec![];
+    for _ in 0..answers {
+        at = skip_name(packet, at)?;
+        let (rtype, len) = (field(at)?, field(at + 8)? as usize);
+        let data = packet
+            .get(at + 10..at + 10 + len)
+            .ok_or_else(|| eyre!("Truncated DNS response"))?;
+        match (rtype, len) {
+            (TYPE_A, 4) => ips.push(IpAddr::from(<[u8; 4]>::try_from(data)?)),
+            (TYPE_AAAA, 16) => ips.push(IpAddr::from(<[u8; 16]>::try_from(data)?)),
+            _ => {}
+        }
+        at += 10 + len;
+    }
+    Ok(ips)
+}
+
+/// Offset just past the (possibly compressed) name starting at `at`.
+fn skip_name(packet: &[u8], mut at: usize) -> Result<usize> {
+    loop {
+        let len = *packet
+            .get(at)
+            .ok_or_else(|| eyre!("Truncated DNS response"))?;
+        match len {
+            0 => return Ok(at + 1),
+            // Compression pointer ends the name
+            l if l & 0xc0 == 0xc0 => return Ok(at + 2),
+            l => at += 1 + l as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_pick_most_specific_domain() {
+        let overrides = HashMap::from([
+            ("corp.example.com".to_string(), vec!["10.1.2.3".to_string()]),
+            (
+                "eu.corp.example.com".to_string(),
+                vec!["10.9.9.9".to_string()],
+            ),
+        ]);
+        let resolver = Resolver::new(&["1.1.1.1".to_string()], &overrides).unwrap();
+
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(
+            resolver.servers_for("git.corp.example.com"),
+            [ip("10.1.2.3")]
+        );
+        assert_eq!(
+            resolver.servers_for("x.eu.corp.example.com"),
+            [ip("10.9.9.9")]
+        );
+        assert_eq!(resolver.servers_for("github.com"), [ip("1.1.1.1")]);
+        assert!(Resolver::new(&["not-an-ip".to_string()], &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn decodes_compressed_answers() {
+        let mut response = encode_query(7, "github.com", TYPE_A);
+        response[2] = 0x81; // response, recursion desired
+        response[3] = 0x80; // recursion available, rcode 0
+        response[7] = 2; // two answers
+        for ip in [[140, 82, 112, 3], [140, 82, 112, 4]] {
+            response.extend([0xc0, 12]); // pointer to the question name
+            response.extend(TYPE_A.to_be_bytes());
+            response.extend([0, 1, 0, 0, 0, 60, 0, 4]);
+            response.extend(ip);
+        }
+
+        assert_eq!(
+            decode_answers(&response).unwrap(),
+            [
+                "140.82.112.3".parse::<IpAddr>().unwrap(),
+                "140.82.112.4".parse().unwrap()
+            ]
+        );
+    }
+}
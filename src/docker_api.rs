@@ -0,0 +1,748 @@
+//! Docker Engine API backend.
+//!
+//! Talks to the daemon through `bollard` instead of shelling out to the
+//! `docker` CLI, so failures come back as structured errors and build output
+//! can be captured by the caller.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+
+use bollard::container::LogOutput;
+use bollard::errors::Error as BollardError;
+use bollard::models::{
+    ContainerCreateBody, HostConfig, Mount, MountTypeEnum, NetworkCreateRequest, PortBinding,
+    ResourcesUlimits, VolumeCreateRequest,
+};
+use bollard::query_parameters::{
+    AttachContainerOptions, BuildImageOptions, CreateContainerOptions, CreateImageOptions,
+    ListContainersOptions, ListImagesOptions, RemoveContainerOptions, RemoveImageOptions,
+    ResizeContainerTTYOptions, StopContainerOptions, TagImageOptions,
+};
+use bollard::{Docker, body_full};
+use color_eyre::eyre::{Result, bail};
+use futures_util::{Stream, StreamExt};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::runtime::Runtime;
+use tracing::info;
+
+use crate::{Backend, BuildOptions, PROJECT_LABEL, RunSpec, SESSION_LABEL, images, ps, queue};
+
+/// Error reported by the Docker daemon.
+#[derive(Debug)]
+pub enum DockerApiError {
+    /// Non-success HTTP response.
+    Status { status: u16, message: String },
+    /// Error reported in the build output stream.
+    Build { message: String },
+}
+
+impl std::fmt::Display for DockerApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DockerApiError::Status { status, message } => {
+                write!(f, "Docker API returned {}: {}", status, message)
+            }
+            DockerApiError::Build { message } => write!(f, "Docker build failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for DockerApiError {}
+
+/// Report the daemon's error responses as `DockerApiError`s.
+fn api_error(err: BollardError) -> color_eyre::Report {
+    match err {
+        BollardError::DockerResponseServerError {
+            status_code,
+            message,
+        } => DockerApiError::Status {
+            status: status_code,
+            message,
+        }
+        .into(),
+        err => err.into(),
+    }
+}
+
+fn is_not_found(err: &BollardError) -> bool {
+    matches!(
+        err,
+        BollardError::DockerResponseServerError {
+            status_code: 404,
+            ..
+        }
+    )
+}
+
+type OutputSink = Box<dyn Fn(&str)>;
+type Output = Pin<Box<dyn Stream<Item = Result<LogOutput, BollardError>> + Send>>;
+type Input = Pin<Box<dyn AsyncWrite + Send>>;
+
+pub struct DockerApi {
+    docker: Docker,
+    runtime: Runtime,
+    build_output: Option<OutputSink>,
+}
+
+impl DockerApi {
+    /// Connect to the daemon in `DOCKER_HOST` (`unix://` or `tcp://`) or the
+    /// default socket.
+    pub fn new() -> Result<Self> {
+        let runtime = Runtime::new()?;
+        let docker = {
+            let _guard = runtime.enter();
+            Docker::connect_with_defaults()?
+        };
+        Ok(Self {
+            docker,
+            runtime,
+            build_output: None,
+        })
+    }
+
+    /// Send build output to `sink` instead of stderr.
+    pub fn on_build_output(mut self, sink: impl Fn(&str) + 'static) -> Self {
+        self.build_output = Some(Box::new(sink));
+        self
+    }
+
+    /// Create the network `name` unless it already exists.
+    async fn ensure_network(&self, name: &str) -> Result<()> {
+        match self.docker.inspect_network(name, None).await {
+            Err(err) if is_not_found(&err) => {}
+            inspected => return inspected.map(|_| ()).map_err(api_error),
+        }
+
+        info!(name, "Creating network");
+        let request = NetworkCreateRequest {
+            name: name.to_string(),
+            labels: Some(HashMap::from([("contenant".to_string(), "1".to_string())])),
+            ..Default::default()
+        };
+        self.docker
+            .create_network(request)
+            .await
+            .map_err(api_error)?;
+        Ok(())
+    }
+
+    /// Create the volume `name` unless it already exists, labeled with its
+    /// project.
+    async fn ensure_volume(&self, name: &str, project: Option<&String>) -> Result<()> {
+        match self.docker.inspect_volume(name).await {
+            Err(err) if is_not_found(&err) => {}
+            inspected => return inspected.map(|_| ()).map_err(api_error),
+        }
+
+        info!(name, "Creating volume");
+        let mut labels = HashMap::from([("contenant".to_string(), "1".to_string())]);
+        if let Some(project) = project {
+            labels.insert(crate::PROJECT_LABEL.to_string(), project.clone());
+        }
+        let request = VolumeCreateRequest {
+            name: Some(name.to_string()),
+            labels: Some(labels),
+            ..Default::default()
+        };
+        self.docker
+            .create_volume(request)
+            .await
+            .map_err(api_error)?;
+        Ok(())
+    }
+
+    /// Open an attach stream to `id`. With `detach_keys`, the daemon ends
+    /// the stream when they're typed.
+    async fn open_attach(&self, id: &str, detach_keys: Option<&str>) -> Result<(Input, Output)> {
+        let options = AttachContainerOptions {
+            detach_keys: detach_keys.map(str::to_string),
+            stream: true,
+            stdin: true,
+            stdout: true,
+            stderr: true,
+            ..Default::default()
+        };
+        let attached = self
+            .docker
+            .attach_container(id, Some(options))
+            .await
+            .map_err(api_error)?;
+        Ok((attached.input, attached.output))
+    }
+
+    /// Match the container's TTY to the terminal's size.
+    async fn resize(&self, id: &str, (rows, cols): (u16, u16)) {
+        let options = ResizeContainerTTYOptions {
+            h: rows.into(),
+            w: cols.into(),
+        };
+        let _ = self.docker.resize_container_tty(id, options).await;
+    }
+
+    /// Copy `output` to stdout and stderr until it ends, keeping a TTY's size
+    /// in step with the terminal's.
+    async fn copy_output(&self, id: &str, mut output: Output, tty: bool) -> Result<()> {
+        let mut size = None;
+        let mut ticks = tokio::time::interval(Duration::from_millis(250));
+        loop {
+            tokio::select! {
+                chunk = output.next() => {
+                    let Some(chunk) = chunk else {
+                        return Ok(());
+                    };
+                    match chunk.map_err(api_error)? {
+                        LogOutput::StdErr { message } => {
+                            io::stderr().write_all(&message)?;
+                        }
+                        other => {
+                            let mut stdout = io::stdout();
+                            stdout.write_all(&other.into_bytes())?;
+                            stdout.flush()?;
+                        }
+                    }
+                }
+                _ = ticks.tick(), if tty => {
+                    let current = terminal::size();
+                    if current != size
+                        && let Some(current) = current
+                    {
+                        self.resize(id, current).await;
+                    }
+                    size = current;
+                }
+            }
+        }
+    }
+
+    /// The exit code of `id` once it stops.
+    async fn wait(&self, id: &str) -> Result<i32> {
+        match self.docker.wait_container(id, None).next().await {
+            Some(Ok(waited)) => Ok(waited.status_code as i32),
+            // Non-zero exits come back as errors
+            Some(Err(BollardError::DockerContainerWaitError { code, .. })) => Ok(code as i32),
+            Some(Err(err)) => Err(api_error(err)),
+            None => bail!("Docker didn't report how {} exited", id),
+        }
+    }
+
+    async fn remove_container(&self, id: &str) {
+        let options = RemoveContainerOptions {
+            force: true,
+            v: true,
+            ..Default::default()
+        };
+        let _ = self.docker.remove_container(id, Some(options)).await;
+    }
+
+    async fn run_container(&self, id: &str, tty: bool) -> Result<i32> {
+        let (input, output) = self.open_attach(id, None).await?;
+        self.docker
+            .start_container(id, None)
+            .await
+            .map_err(api_error)?;
+
+        let _raw = match tty {
+            true => Some(terminal::RawMode::enable()?),
+            false => None,
+        };
+        self.forward_stdin(input, true);
+        self.copy_output(id, output, tty).await?;
+
+        self.wait(id).await
+    }
+
+    /// Forward stdin to `input` on a background thread; it ends with the
+    /// process, or at end of input, closing `input` if `close` is set.
+    fn forward_stdin(&self, mut input: Input, close: bool) {
+        let runtime = self.runtime.handle().clone();
+        std::thread::spawn(move || {
+            let mut buf = [0; 8192];
+            loop {
+                let read = match io::stdin().read(&mut buf) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => read,
+                };
+                let written = runtime.block_on(async {
+                    input.write_all(&buf[..read]).await?;
+                    input.flush().await
+                });
+                if written.is_err() {
+                    return;
+                }
+            }
+            if close {
+                let _ = runtime.block_on(input.shutdown());
+            }
+        });
+    }
+
+    /// List the containers with `label`.
+    async fn containers(&self, label: &str) -> Result<Vec<bollard::models::ContainerSummary>> {
+        let options = ListContainersOptions {
+            filters: Some(HashMap::from([(
+                "label".to_string(),
+                vec![label.to_string()],
+            )])),
+            ..Default::default()
+        };
+        self.docker
+            .list_containers(Some(options))
+            .await
+            .map_err(api_error)
+    }
+}
+
+impl Backend for DockerApi {
+    fn build(&self, tag: &str, context: &Path, options: &BuildOptions) -> Result<()> {
+        info!(tag, "Building image");
+
+        let archive = tar_directory(context)?;
+        let labels: HashMap<_, _> = std::iter::once(images::IMAGE_LABEL)
+            .chain(options.labels.iter().map(String::as_str))
+            .filter_map(|label| label.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let build_options = BuildImageOptions {
+            t: Some(tag.to_string()),
+            rm: true,
+            nocache: options.no_cache,
+            pull: options.pull.then(|| "1".to_string()),
+            labels: Some(labels),
+            platform: options.platform.clone().unwrap_or_default(),
+            ..Default::default()
+        };
+
+        let mut log = match &options.log {
+            Some(log) => Some(fs::OpenOptions::new().create(true).append(true).open(log)?),
+            None => None,
+        };
+        self.runtime.block_on(async {
+            let mut messages =
+                self.docker
+                    .build_image(build_options, None, Some(body_full(archive.into())));
+            while let Some(message) = messages.next().await {
+                let output = match message {
+                    Ok(message) => message.stream,
+                    Err(BollardError::DockerStreamError { error }) => {
+                        if let Some(log) = &mut log {
+                            writeln!(log, "{}", error)?;
+                        }
+                        return Err(DockerApiError::Build { message: error }.into());
+                    }
+                    Err(err) => return Err(api_error(err)),
+                };
+                if let Some(output) = output {
+                    if let Some(log) = &mut log {
+                        log.write_all(output.as_bytes())?;
+                    }
+                    match &self.build_output {
+                        Some(sink) => sink(&output),
+                        None => eprint!("{}", output),
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn tag(&self, source: &str, target: &str) -> Result<()> {
+        info!(source, target, "Tagging image");
+
+        let (repo, tag) = target.rsplit_once(':').unwrap_or((target, "latest"));
+        let options = TagImageOptions {
+            repo: Some(repo.to_string()),
+            tag: Some(tag.to_string()),
+        };
+        self.runtime
+            .block_on(self.docker.tag_image(source, Some(options)))
+            .map_err(api_error)
+    }
+
+    fn run(&self, spec: &RunSpec) -> Result<i32> {
+        // Detached containers always get a TTY, for `docker attach` later
+        let tty = spec.detach || io::stdin().is_terminal();
+
+        let mut security_opts = vec![];
+        for opt in spec.security_opts() {
+            // The API takes the seccomp profile itself rather than a path
+            match opt.strip_prefix("seccomp=") {
+                Some(path) if path != "unconfined" => {
+                    security_opts.push(format!("seccomp={}", fs::read_to_string(path)?));
+                }
+                _ => security_opts.push(opt),
+            }
+        }
+        let tmpfs: HashMap<_, _> = spec
+            .tmpfs()
+            .into_iter()
+            .map(|(p, options)| (p, options.to_string()))
+            .chain(
+                spec.tmpfs_mounts
+                    .iter()
+                    .map(|t| (t.target.clone(), t.options())),
+            )
+            .collect();
+        let volumes: Vec<_> = spec
+            .volumes
+            .iter()
+            .map(|v| Mount {
+                typ: Some(MountTypeEnum::VOLUME),
+                source: Some(v.name.clone()),
+                target: Some(v.target.clone()),
+                read_only: Some(v.readonly),
+                ..Default::default()
+            })
+            .collect();
+        let env: Vec<_> = spec
+            .env
+            .iter()
+            .chain(spec.env_file.iter().flat_map(|f| &f.vars))
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+
+        let ulimits: Vec<_> = spec
+            .ulimits()
+            .into_iter()
+            .map(|(name, value)| ResourcesUlimits {
+                name: Some(name.to_string()),
+                soft: Some(value as i64),
+                hard: Some(value as i64),
+            })
+            .collect();
+
+        let mut exposed = vec![];
+        let mut bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
+        for port in &spec.ports {
+            exposed.push(port.container_target());
+            bindings
+                .entry(port.container_target())
+                .or_default()
+                .get_or_insert_default()
+                .push(PortBinding {
+                    host_ip: Some(port.host_ip.to_string()),
+                    host_port: Some(port.host_port.to_string()),
+                });
+        }
+
+        let body = ContainerCreateBody {
+            image: Some(spec.image.clone()),
+            // Anonymous volumes, removed with the container
+            volumes: Some(spec.excluded_paths()),
+            labels: Some(spec.labels.clone().into_iter().collect()),
+            exposed_ports: Some(exposed),
+            cmd: Some(spec.args.clone()),
+            env: Some(env),
+            working_dir: Some(spec.container_workdir()),
+            tty: Some(tty),
+            open_stdin: Some(true),
+            stdin_once: Some(!spec.detach),
+            attach_stdin: Some(!spec.detach),
+            attach_stdout: Some(!spec.detach),
+            attach_stderr: Some(!spec.detach),
+            host_config: Some(HostConfig {
+                auto_remove: Some(spec.detach),
+                binds: Some(spec.binds()),
+                extra_hosts: Some(vec!["host.docker.internal:host-gateway".to_string()]),
+                cap_add: Some(spec.cap_add()),
+                cap_drop: Some(spec.cap_drop()),
+                security_opt: Some(security_opts),
+                readonly_rootfs: Some(spec.read_only_root()),
+                pids_limit: spec.security.limits.pids.map(|pids| pids as i64),
+                ulimits: Some(ulimits),
+                tmpfs: Some(tmpfs),
+                mounts: Some(volumes),
+                port_bindings: Some(bindings),
+                network_mode: Some(spec.network.clone().unwrap_or("default".to_string())),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let options = CreateContainerOptions {
+            name: None,
+            platform: spec.platform.clone().unwrap_or_default(),
+        };
+
+        self.runtime.block_on(async {
+            if let Some(network) = &spec.network {
+                self.ensure_network(network).await?;
+            }
+            for volume in &spec.volumes {
+                self.ensure_volume(&volume.name, spec.labels.get(crate::PROJECT_LABEL))
+                    .await?;
+            }
+
+            let id = self
+                .docker
+                .create_container(Some(options), body)
+                .await
+                .map_err(api_error)?
+                .id;
+
+            if spec.detach {
+                let started = self.docker.start_container(&id, None).await;
+                if let Err(err) = started {
+                    self.remove_container(&id).await;
+                    return Err(api_error(err));
+                }
+                return Ok(0);
+            }
+            let result = self.run_container(&id, tty).await;
+            self.remove_container(&id).await;
+            result
+        })
+    }
+
+    fn detaches(&self) -> bool {
+        true
+    }
+
+    fn stop(&self, label: &str, grace_secs: u64) -> Result<usize> {
+        self.runtime.block_on(async {
+            let listed = self.containers(label).await?;
+            for id in listed.iter().filter_map(|c| c.id.as_deref()) {
+                let options = StopContainerOptions {
+                    t: Some(grace_secs as i32),
+                    signal: None,
+                };
+                // Stopping one that exited on its own in the meantime is fine
+                self.docker
+                    .stop_container(id, Some(options))
+                    .await
+                    .map_err(api_error)?;
+            }
+            Ok(listed.len())
+        })
+    }
+
+    fn sessions(&self, label: &str) -> Result<Vec<ps::Session>> {
+        self.runtime.block_on(async {
+            let mut sessions = vec![];
+            for container in self.containers(label).await? {
+                let id = container.id.unwrap_or_default();
+                let mut labels = container.labels.unwrap_or_default();
+                // A container that exited since the listing counts no execs
+                let execs = self
+                    .docker
+                    .inspect_container(&id, None)
+                    .await
+                    .ok()
+                    .and_then(|inspected| inspected.exec_ids)
+                    .map_or(0, |ids| ids.len());
+                sessions.push(ps::Session {
+                    project: labels.remove(PROJECT_LABEL).unwrap_or_default(),
+                    session: labels.remove(SESSION_LABEL),
+                    agent: labels.remove(ps::AGENT_LABEL),
+                    uptime: ps::uptime(&container.status.unwrap_or_default()),
+                    execs,
+                    id,
+                });
+            }
+            Ok(sessions)
+        })
+    }
+
+    fn attach(&self, id: &str, detach_keys: &str) -> Result<i32> {
+        self.runtime.block_on(async {
+            let (input, output) = self.open_attach(id, Some(detach_keys)).await?;
+            let _raw = match io::stdin().is_terminal() {
+                true => Some(terminal::RawMode::enable()?),
+                false => None,
+            };
+            self.forward_stdin(input, false);
+            self.copy_output(id, output, true).await?;
+
+            // Gone if it exited and was removed
+            let inspected = match self.docker.inspect_container(id, None).await {
+                Err(err) if is_not_found(&err) => return Ok(0),
+                inspected => inspected.map_err(api_error)?,
+            };
+            let state = inspected.state.unwrap_or_default();
+            Ok(match state.running.unwrap_or(false) {
+                true => 0,
+                false => state.exit_code.unwrap_or(0) as i32,
+            })
+        })
+    }
+
+    fn images(&self) -> Result<Vec<images::Image>> {
+        self.runtime.block_on(async {
+            let mut found = vec![];
+            for filters in [
+                HashMap::from([("reference", vec!["contenant"])]),
+                HashMap::from([
+                    ("dangling", vec!["true"]),
+                    ("label", vec![images::IMAGE_LABEL]),
+                ]),
+            ] {
+                let options = ListImagesOptions {
+                    filters: Some(
+                        filters
+                            .into_iter()
+                            .map(|(key, values)| {
+                                (
+                                    key.to_string(),
+                                    values.into_iter().map(Into::into).collect(),
+                                )
+                            })
+                            .collect(),
+                    ),
+                    ..Default::default()
+                };
+                let listed = self
+                    .docker
+                    .list_images(Some(options))
+                    .await
+                    .map_err(api_error)?;
+                let now = queue::now();
+                for image in listed {
+                    let tags: Vec<_> = image
+                        .repo_tags
+                        .into_iter()
+                        .filter(|t| t.starts_with("contenant:"))
+                        .collect();
+                    let tags = if tags.is_empty() {
+                        vec![None]
+                    } else {
+                        tags.into_iter().map(Some).collect()
+                    };
+                    for tag in tags {
+                        found.push(images::Image {
+                            id: image.id.clone(),
+                            tag,
+                            size: images::size(image.size.max(0) as u64),
+                            created: images::age(now.saturating_sub(image.created.max(0) as u64)),
+                        });
+                    }
+                }
+            }
+            Ok(found)
+        })
+    }
+
+    fn remove_image(&self, reference: &str) -> Result<()> {
+        self.runtime
+            .block_on(
+                self.docker
+                    .remove_image(reference, None::<RemoveImageOptions>, None),
+            )
+            .map_err(api_error)?;
+        Ok(())
+    }
+
+    fn pull(&self, image: &str, platform: Option<&str>) -> Result<()> {
+        info!(image, "Pulling image");
+
+        let options = CreateImageOptions {
+            from_image: Some(image.to_string()),
+            platform: platform.unwrap_or_default().to_string(),
+            ..Default::default()
+        };
+        self.runtime.block_on(async {
+            let mut progress = self.docker.create_image(Some(options), None, None);
+            // Progress is dropped; only a failure partway through matters
+            while let Some(message) = progress.next().await {
+                match message {
+                    Ok(_) => {}
+                    Err(BollardError::DockerStreamError { error }) => {
+                        bail!("Pulling {} failed: {}", image, error)
+                    }
+                    Err(err) => return Err(api_error(err)),
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn image_label(&self, image: &str, key: &str) -> Result<Option<String>> {
+        let inspected = match self.runtime.block_on(self.docker.inspect_image(image)) {
+            Err(err) if is_not_found(&err) => return Ok(None),
+            inspected => inspected.map_err(api_error)?,
+        };
+        Ok(inspected
+            .config
+            .and_then(|config| config.labels)
+            .and_then(|mut labels| labels.remove(key)))
+    }
+}
+
+/// Archive a build context directory, keeping symlinks as links.
+fn tar_directory(dir: &Path) -> Result<Vec<u8>> {
+    let mut archive = tar::Builder::new(vec![]);
+    archive.follow_symlinks(false);
+    archive.append_dir_all(".", dir)?;
+    Ok(archive.into_inner()?)
+}
+
+mod terminal {
+    use std::io;
+
+    /// Puts the terminal into raw mode until dropped.
+    pub struct RawMode;
+
+    impl RawMode {
+        pub fn enable() -> io::Result<Self> {
+            crossterm::terminal::enable_raw_mode()?;
+            Ok(Self)
+        }
+    }
+
+    impl Drop for RawMode {
+        fn drop(&mut self) {
+            let _ = crossterm::terminal::disable_raw_mode();
+        }
+    }
+
+    /// Terminal size as (rows, columns).
+    pub fn size() -> Option<(u16, u16)> {
+        crossterm::terminal::size()
+            .ok()
+            .map(|(cols, rows)| (rows, cols))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_build_contexts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("Dockerfile"), "FROM scratch\n").unwrap();
+        fs::create_dir(dir.path().join("bin")).unwrap();
+        std::os::unix::fs::symlink("../Dockerfile", dir.path().join("bin/link")).unwrap();
+
+        let archive = tar_directory(dir.path()).unwrap();
+        let mut entries: Vec<_> = tar::Archive::new(&archive[..])
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let link = entry.link_name().unwrap().map(|l| l.into_owned());
+                (entry.path().unwrap().into_owned(), link)
+            })
+            .collect();
+        entries.sort();
+        assert!(entries.contains(&("Dockerfile".into(), None)));
+        assert!(entries.contains(&("bin/link".into(), Some("../Dockerfile".into()))));
+    }
+
+    #[test]
+    fn daemon_errors_keep_their_status() {
+        let err = api_error(BollardError::DockerResponseServerError {
+            status_code: 409,
+            message: "conflict".to_string(),
+        });
+        assert!(matches!(
+            err.downcast_ref::<DockerApiError>(),
+            Some(DockerApiError::Status { status: 409, .. })
+        ));
+        assert!(is_not_found(&BollardError::DockerResponseServerError {
+            status_code: 404,
+            message: String::new(),
+        }));
+    }
+}
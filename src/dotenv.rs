@@ -0,0 +1,186 @@
+//! `env_files`: dotenv files read on the host and handed to the engine as a
+//! Docker-format env file, so their values stay off command lines.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr, bail, eyre};
+
+/// Variables from dotenv files, later files overriding earlier ones. Files
+/// that don't exist are skipped, so `.env.local` can be optional.
+pub fn load(paths: &[PathBuf]) -> Result<BTreeMap<String, String>> {
+    let mut vars = BTreeMap::new();
+    for path in paths {
+        if !path.exists() {
+            continue;
+        }
+        let text = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Couldn't read {}", path.display()))?;
+        let parsed = parse(&text).wrap_err_with(|| format!("Couldn't parse {}", path.display()))?;
+        vars.extend(parsed);
+    }
+    Ok(vars)
+}
+
+/// Parse dotenv syntax: `KEY=value` lines with an optional `export`, `#`
+/// comments, and single- (literal) or double-quoted (with `\n`, `\"`, and
+/// `\\` escapes) values.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>> {
+    let mut vars = vec![];
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((key, value)) = line.split_once('=') else {
+            bail!("line {}: expected KEY=value", n + 1);
+        };
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            bail!("line {}: invalid name `{}`", n + 1, key);
+        }
+        let value = value.trim_start();
+        let value = if let Some(rest) = value.strip_prefix('\'') {
+            let Some((value, _)) = rest.split_once('\'') else {
+                bail!("line {}: unterminated quote", n + 1);
+            };
+            value.to_string()
+        } else if let Some(rest) = value.strip_prefix('"') {
+            unescape(rest).ok_or_else(|| eyre!("line {}: unterminated quote", n + 1))?
+        } else {
+            // An unquoted value ends at a comment
+            let end = value.find(" #").unwrap_or(value.len());
+            value[..end].trim_end().to_string()
+        };
+        vars.push((key.to_string(), value));
+    }
+    Ok(vars)
+}
+
+/// A double-quoted value up to its closing quote, unescaped.
+fn unescape(rest: &str) -> Option<String> {
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                't' => value.push('\t'),
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// A Docker-format env file and the variables in it.
+#[derive(Clone, Debug)]
+pub struct EnvFile {
+    pub path: PathBuf,
+    pub vars: BTreeMap<String, String>,
+}
+
+/// The env file for one run, readable only by the user. Removed when
+/// dropped.
+pub struct RunEnvFile {
+    pub file: EnvFile,
+}
+
+impl RunEnvFile {
+    pub fn write(path: &Path, vars: BTreeMap<String, String>) -> Result<Self> {
+        let mut contents = String::new();
+        for (key, value) in &vars {
+            // Docker's format has no quoting, so each value is one line
+            if value.contains('\n') {
+                bail!(
+                    "{} from env_files has a newline, which --env-file can't hold",
+                    key
+                );
+            }
+            contents.push_str(&format!("{}={}\n", key, value));
+        }
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(path)?;
+        std::io::Write::write_all(&mut file, contents.as_bytes())?;
+        Ok(Self {
+            file: EnvFile {
+                path: path.to_path_buf(),
+                vars,
+            },
+        })
+    }
+}
+
+impl Drop for RunEnvFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.file.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dotenv_syntax() {
+        let vars = parse(
+            "# comment\n\
+             \n\
+             PLAIN=value # trailing\n\
+             export EXPORTED=1\n\
+             SINGLE='a #literal \\n'\n\
+             DOUBLE=\"line\\nnext \\\"quoted\\\"\"\n\
+             EMPTY=\n\
+             URL=http://example.com/#anchor\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            [
+                ("PLAIN", "value"),
+                ("EXPORTED", "1"),
+                ("SINGLE", "a #literal \\n"),
+                ("DOUBLE", "line\nnext \"quoted\""),
+                ("EMPTY", ""),
+                ("URL", "http://example.com/#anchor"),
+            ]
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+        );
+
+        assert!(parse("NOEQUALS\n").is_err());
+        assert!(parse("A='open\n").is_err());
+    }
+
+    #[test]
+    fn later_files_win_and_missing_ones_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(".env"), "A=1\nB=1\n").unwrap();
+        fs::write(dir.path().join(".env.local"), "B=2\n").unwrap();
+        let vars = load(&[
+            dir.path().join(".env"),
+            dir.path().join(".env.missing"),
+            dir.path().join(".env.local"),
+        ])
+        .unwrap();
+        assert_eq!(vars["A"], "1");
+        assert_eq!(vars["B"], "2");
+
+        let path = dir.path().join("run/env");
+        let written = RunEnvFile::write(&path, vars).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "A=1\nB=2\n");
+        drop(written);
+        assert!(!path.exists());
+
+        let vars = BTreeMap::from([("A".to_string(), "x\ny".to_string())]);
+        assert!(RunEnvFile::write(&dir.path().join("multi"), vars).is_err());
+    }
+}
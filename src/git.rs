@@ -0,0 +1,80 @@
+//! Git config for the container, passed in the environment.
+//!
+//! Settings contenant adds for git, like the `git:` identity, credential
+//! helpers, and commit signing, go through `GIT_CONFIG_COUNT` and
+//! `GIT_CONFIG_KEY_n`/`VALUE_n`. Git reads them on top of every config
+//! file, so they apply without mounting or touching the user's.
+
+use crate::config::GitConfig;
+
+/// The identity and `safe.directory` entries `git` sets.
+pub fn identity_config(git: &GitConfig) -> Vec<(String, String)> {
+    let mut entries = vec![];
+    let scalars = [
+        ("user.name", &git.name),
+        ("user.email", &git.email),
+        ("user.signingKey", &git.signing_key),
+    ];
+    for (key, value) in scalars {
+        if let Some(value) = value {
+            entries.push((key.to_string(), value.clone()));
+        }
+    }
+    for dir in &git.safe_directories {
+        entries.push(("safe.directory".to_string(), dir.clone()));
+    }
+    entries
+}
+
+/// `entries`, in order, as `GIT_CONFIG_*` variables.
+pub fn config_env(entries: &[(String, String)]) -> Vec<(String, String)> {
+    let mut env = vec![("GIT_CONFIG_COUNT".to_string(), entries.len().to_string())];
+    for (i, (key, value)) in entries.iter().enumerate() {
+        env.push((format!("GIT_CONFIG_KEY_{}", i), key.clone()));
+        env.push((format!("GIT_CONFIG_VALUE_{}", i), value.clone()));
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_only_whats_configured() {
+        let git = GitConfig {
+            name: Some("Agent".to_string()),
+            safe_directories: vec!["/workspace".to_string(), "*".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            identity_config(&git),
+            [
+                ("user.name", "Agent"),
+                ("safe.directory", "/workspace"),
+                ("safe.directory", "*"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert!(identity_config(&GitConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn numbers_entries_in_order() {
+        let entries = [
+            ("credential.helper".to_string(), String::new()),
+            ("credential.helper".to_string(), "store".to_string()),
+        ];
+        assert_eq!(
+            config_env(&entries),
+            [
+                ("GIT_CONFIG_COUNT", "2"),
+                ("GIT_CONFIG_KEY_0", "credential.helper"),
+                ("GIT_CONFIG_VALUE_0", ""),
+                ("GIT_CONFIG_KEY_1", "credential.helper"),
+                ("GIT_CONFIG_VALUE_1", "store"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+    }
+}
@@ -0,0 +1,353 @@
+//! Short-lived GitHub tokens for git in the container.
+//!
+//! With `bridge.github_tokens`, `contenant run` registers the GitHub repos
+//! the workspace's remotes point at, and points git in the container at
+//! [`CREDENTIAL_HELPER`] for github.com. The helper asks the bridge, which
+//! refuses repos the project didn't register and answers with a token for
+//! the one repo: an installation token from `bridge.github_app`, limited to
+//! that repo and the app's permissions and expiring within the hour, or
+//! else whatever `gh auth token` prints. Either way nothing long-lived is
+//! mounted into the container.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Result, WrapErr, bail, eyre};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::config::GithubApp;
+
+/// The git credential helper baked into the image.
+pub const CREDENTIAL_HELPER: &str = "/usr/local/bin/contenant-git-credential";
+
+/// Installation tokens last an hour; hand the same one out for most of it.
+const TOKEN_REUSE: Duration = Duration::from_secs(50 * 60);
+
+const API: &str = "https://api.github.com";
+
+/// The `owner/repo` a github.com remote URL points at, lowercased since
+/// GitHub ignores case.
+pub fn repo_slug(url: &str) -> Option<String> {
+    let rest = ["https://", "http://", "ssh://", "git://"]
+        .iter()
+        .find_map(|scheme| url.strip_prefix(scheme))
+        .unwrap_or(url);
+    let rest = match rest.split_once('@') {
+        Some((user, host)) if !user.contains('/') => host,
+        _ => rest,
+    };
+    let path = rest
+        .strip_prefix("github.com/")
+        .or_else(|| rest.strip_prefix("github.com:"))?
+        .trim_end_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    let (owner, repo) = path.split_once('/')?;
+    (!owner.is_empty() && !repo.is_empty() && !repo.contains('/'))
+        .then(|| format!("{}/{}", owner, repo).to_lowercase())
+}
+
+/// The GitHub repos `workspace`'s git remotes point at.
+pub fn remote_repos(workspace: &Path) -> Vec<String> {
+    let Ok(output) = std::process::Command::new("git")
+        .arg("-C")
+        .arg(workspace)
+        .args(["remote", "-v"])
+        .stderr(Stdio::null())
+        .output()
+    else {
+        return vec![];
+    };
+    let mut repos: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .filter_map(repo_slug)
+        .collect();
+    repos.sort();
+    repos.dedup();
+    repos
+}
+
+/// Git config sending github.com credential requests to
+/// [`CREDENTIAL_HELPER`] alone, with the repo's path so the bridge knows
+/// which one to scope the token to.
+pub fn git_config() -> Vec<(String, String)> {
+    [
+        // An empty helper clears any configured before it, like `store`,
+        // that would keep the token around
+        ("credential.https://github.com.helper", ""),
+        ("credential.https://github.com.helper", CREDENTIAL_HELPER),
+        ("credential.https://github.com.useHttpPath", "true"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+/// The repo a git credential request is for, if it's an HTTPS one for
+/// github.com that says which.
+pub fn requested_repo(request: &str) -> Option<String> {
+    let fields: HashMap<&str, &str> = request
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    if fields.get("protocol") != Some(&"https") || fields.get("host") != Some(&"github.com") {
+        return None;
+    }
+    repo_slug(&format!("github.com/{}", fields.get("path")?))
+}
+
+/// Mints tokens on the bridge, reusing each repo's until it nears expiry.
+#[derive(Default)]
+pub struct Tokens {
+    app: Option<GithubApp>,
+    cache: Mutex<HashMap<String, (String, Instant)>>,
+}
+
+impl Tokens {
+    pub fn new(app: Option<GithubApp>) -> Self {
+        Self {
+            app,
+            cache: Mutex::default(),
+        }
+    }
+
+    /// A token that can push to and pull from `repo`.
+    pub async fn token(&self, repo: &str) -> Result<String> {
+        let Some(app) = &self.app else {
+            let token = gh_token().await?;
+            crate::redact::register(&token);
+            return Ok(token);
+        };
+        if let Some((token, minted)) = self.cache.lock().unwrap().get(repo)
+            && minted.elapsed() < TOKEN_REUSE
+        {
+            return Ok(token.clone());
+        }
+        let token = installation_token(app, repo).await?;
+        crate::redact::register(&token);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(repo.to_string(), (token.clone(), Instant::now()));
+        Ok(token)
+    }
+}
+
+/// The GitHub CLI's token. It's as powerful as the host's login, but it
+/// stays on the host and is only handed out for registered repos.
+async fn gh_token() -> Result<String> {
+    let output = Command::new("gh")
+        .args(["auth", "token"])
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .wrap_err("Couldn't run gh; install the GitHub CLI or set bridge.github_app")?;
+    if !output.status.success() {
+        bail!("gh auth token failed; run gh auth login on the host");
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+/// A token from the app's installation on `repo`, for that repo alone.
+async fn installation_token(app: &GithubApp, repo: &str) -> Result<String> {
+    let jwt = app_jwt(app).await?;
+    let installation = api(&jwt, "GET", &format!("/repos/{}/installation", repo), None)
+        .await
+        .wrap_err_with(|| format!("The GitHub App isn't installed on {}", repo))?;
+    let id = installation["id"]
+        .as_u64()
+        .ok_or_else(|| eyre!("GitHub didn't say which installation covers {}", repo))?;
+    let name = repo.split_once('/').map_or(repo, |(_, name)| name);
+    let mut body = serde_json::json!({ "repositories": [name] });
+    if !app.permissions.is_empty() {
+        body["permissions"] = serde_json::to_value(&app.permissions)?;
+    }
+    let response = api(
+        &jwt,
+        "POST",
+        &format!("/app/installations/{}/access_tokens", id),
+        Some(&body),
+    )
+    .await
+    .wrap_err_with(|| format!("Couldn't mint a token for {}", repo))?;
+    response["token"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("GitHub's response had no token"))
+}
+
+/// A JWT identifying the app, signed with its private key by `openssl`.
+async fn app_jwt(app: &GithubApp) -> Result<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // Backdated for clock drift, and well inside GitHub's ten minute limit
+    let claims = serde_json::json!({
+        "iat": now - 60,
+        "exp": now + 540,
+        "iss": app.app_id.to_string(),
+    });
+    let signing_input = format!(
+        "{}.{}",
+        base64url(br#"{"alg":"RS256","typ":"JWT"}"#),
+        base64url(claims.to_string().as_bytes())
+    );
+    let mut child = Command::new("openssl")
+        .args(["dgst", "-sha256", "-sign"])
+        .arg(&app.private_key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .wrap_err("Couldn't run openssl to sign the GitHub App's JWT")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(signing_input.as_bytes()).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!(
+            "Couldn't sign with {}: {}",
+            app.private_key,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(format!("{}.{}", signing_input, base64url(&output.stdout)))
+}
+
+/// Call the GitHub API with curl, the JWT going through stdin to keep it off
+/// the command line.
+async fn api(
+    jwt: &str,
+    method: &str,
+    path: &str,
+    body: Option<&serde_json::Value>,
+) -> Result<serde_json::Value> {
+    let mut curl = Command::new("curl");
+    curl.args(["-fsS", "--max-time", "10", "-X", method, "-H", "@-"])
+        .args(["-H", "Accept: application/vnd.github+json"])
+        .args(["-H", "X-GitHub-Api-Version: 2022-11-28"]);
+    if let Some(body) = body {
+        curl.arg("--data-binary").arg(body.to_string());
+    }
+    let mut child = curl
+        .arg(format!("{}{}", API, path))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(format!("Authorization: Bearer {}\n", jwt).as_bytes())
+            .await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Unpadded URL-safe base64, as JWTs use.
+fn base64url(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_repo_in_github_remotes() {
+        for url in [
+            "https://github.com/kejadlen/contenant.git",
+            "https://user@github.com/kejadlen/contenant",
+            "git@github.com:kejadlen/contenant.git",
+            "ssh://git@github.com/Kejadlen/Contenant/",
+        ] {
+            assert_eq!(
+                repo_slug(url).as_deref(),
+                Some("kejadlen/contenant"),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(repo_slug("https://gitlab.com/kejadlen/contenant"), None);
+        assert_eq!(repo_slug("https://github.com/kejadlen"), None);
+        assert_eq!(repo_slug("https://github.com/a/b/c"), None);
+
+        assert_eq!(
+            requested_repo("protocol=https\nhost=github.com\npath=kejadlen/contenant.git\n")
+                .as_deref(),
+            Some("kejadlen/contenant")
+        );
+        assert_eq!(requested_repo("protocol=https\nhost=github.com\n"), None);
+        assert_eq!(
+            requested_repo("protocol=https\nhost=example.com\npath=a/b\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn reads_github_remotes_from_the_workspace() {
+        let workspace = tempfile::tempdir().unwrap();
+        assert_eq!(remote_repos(workspace.path()), Vec::<String>::new());
+
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .arg("-C")
+                .arg(workspace.path())
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+        git(&["init", "--quiet"]);
+        git(&[
+            "remote",
+            "add",
+            "origin",
+            "git@github.com:kejadlen/contenant.git",
+        ]);
+        git(&[
+            "remote",
+            "add",
+            "fork",
+            "https://github.com/someone/contenant",
+        ]);
+        git(&[
+            "remote",
+            "add",
+            "mirror",
+            "https://gitlab.com/kejadlen/contenant",
+        ]);
+        assert_eq!(
+            remote_repos(workspace.path()),
+            vec!["kejadlen/contenant", "someone/contenant"]
+        );
+    }
+
+    #[test]
+    fn encodes_base64url_without_padding() {
+        assert_eq!(base64url(b""), "");
+        assert_eq!(base64url(b"f"), "Zg");
+        assert_eq!(base64url(b"fo"), "Zm8");
+        assert_eq!(base64url(b"foo"), "Zm9v");
+        assert_eq!(base64url(&[0xfb, 0xff]), "-_8");
+    }
+}
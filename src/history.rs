@@ -0,0 +1,180 @@
+//! Every run across projects, for `contenant history`.
+//!
+//! Runs are appended as JSON lines to [`HISTORY_FILE`] in the state
+//! directory when they finish, or when they start for detached runs, whose
+//! end contenant doesn't see.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// File in the state directory holding the history.
+pub const HISTORY_FILE: &str = "history.jsonl";
+
+/// One `contenant run`.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Entry {
+    /// Session id, the name of its log directory.
+    pub session: Option<String>,
+    pub project: String,
+    pub path: PathBuf,
+    pub agent: String,
+    /// Arguments passed through to the agent.
+    pub args: Vec<String>,
+    /// Tag the session ran, like `contenant:user`.
+    pub image: String,
+    /// The tagged image's id at the time, if the engine reported it.
+    pub image_id: Option<String>,
+    pub started_at: u64,
+    /// Missing for detached runs.
+    pub finished_at: Option<u64>,
+    /// Missing for detached runs.
+    pub exit_code: Option<i32>,
+}
+
+/// Which entries `contenant history` shows.
+#[derive(Clone, Debug, Default)]
+pub struct Filter {
+    /// Only runs in this project directory.
+    pub path: Option<PathBuf>,
+    /// Only runs started at or after this unix time.
+    pub since: Option<u64>,
+    /// Only runs that exited non-zero.
+    pub failed: bool,
+    /// Only the newest this many.
+    pub limit: Option<usize>,
+}
+
+impl Filter {
+    fn matches(&self, entry: &Entry) -> bool {
+        self.path.as_ref().is_none_or(|path| *path == entry.path)
+            && self.since.is_none_or(|since| entry.started_at >= since)
+            && (!self.failed || entry.exit_code.is_some_and(|code| code != 0))
+    }
+}
+
+pub fn record(history_file: &Path, entry: &Entry) -> Result<()> {
+    if let Some(parent) = history_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(history_file)?;
+    writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    Ok(())
+}
+
+/// Entries matching `filter`, oldest first. Lines that don't parse are
+/// skipped.
+pub fn load(history_file: &Path, filter: &Filter) -> Result<Vec<Entry>> {
+    let Ok(text) = fs::read_to_string(history_file) else {
+        return Ok(vec![]);
+    };
+    let mut entries: Vec<Entry> = text
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    entries.sort_by_key(|entry| entry.started_at);
+    if let Some(limit) = filter.limit {
+        entries.drain(..entries.len().saturating_sub(limit));
+    }
+    Ok(entries)
+}
+
+/// Seconds in a duration like `30m`, `12h`, or `7d`.
+pub fn parse_duration(text: &str) -> Result<u64> {
+    let unit = match text.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3_600,
+        Some('d') => 86_400,
+        Some('w') => 604_800,
+        _ => bail!("{} isn't a duration like 30m, 12h, or 7d", text),
+    };
+    let Ok(n) = text[..text.len() - 1].parse::<u64>() else {
+        bail!("{} isn't a duration like 30m, 12h, or 7d", text);
+    };
+    Ok(n * unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, started_at: u64, exit_code: Option<i32>) -> Entry {
+        Entry {
+            session: None,
+            project: "0123abcd-app".to_string(),
+            path: PathBuf::from(path),
+            agent: "claude".to_string(),
+            args: vec![],
+            image: "contenant:user".to_string(),
+            image_id: None,
+            started_at,
+            finished_at: exit_code.map(|_| started_at + 60),
+            exit_code,
+        }
+    }
+
+    #[test]
+    fn filters_recorded_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join(HISTORY_FILE);
+        assert!(load(&file, &Filter::default()).unwrap().is_empty());
+
+        record(&file, &entry("/src/app", 300, Some(0))).unwrap();
+        record(&file, &entry("/src/api", 100, Some(1))).unwrap();
+        record(&file, &entry("/src/app", 200, None)).unwrap();
+        fs::write(&file, fs::read_to_string(&file).unwrap() + "not json\n").unwrap();
+
+        let started = |filter: Filter| -> Vec<u64> {
+            load(&file, &filter)
+                .unwrap()
+                .iter()
+                .map(|e| e.started_at)
+                .collect()
+        };
+        assert_eq!(started(Filter::default()), [100, 200, 300]);
+        assert_eq!(
+            started(Filter {
+                path: Some(PathBuf::from("/src/app")),
+                ..Default::default()
+            }),
+            [200, 300]
+        );
+        assert_eq!(
+            started(Filter {
+                since: Some(200),
+                ..Default::default()
+            }),
+            [200, 300]
+        );
+        assert_eq!(
+            started(Filter {
+                failed: true,
+                ..Default::default()
+            }),
+            [100]
+        );
+        assert_eq!(
+            started(Filter {
+                limit: Some(2),
+                ..Default::default()
+            }),
+            [200, 300]
+        );
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30m").unwrap(), 1_800);
+        assert_eq!(parse_duration("7d").unwrap(), 604_800);
+        assert!(parse_duration("7").is_err());
+        assert!(parse_duration("d").is_err());
+    }
+}
@@ -0,0 +1,242 @@
+//! Events from the host for a project's sessions, which the container
+//! long-polls through the bridge: files changed in the project directory
+//! (with `bridge.watch`) and messages pushed with `contenant event`.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+/// Events kept for sessions that fall behind.
+const CAPACITY: usize = 1000;
+
+/// How often the project directory is rescanned.
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Scans stop after this many files, to bound the cost on huge trees.
+const WATCH_MAX_FILES: usize = 50_000;
+
+/// Directories too noisy or large to report changes in.
+pub(crate) const IGNORED_DIRS: &[&str] = &[".git", "target", "node_modules", ".contenant"];
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostEventKind {
+    /// Created or modified; the path is relative to the project directory.
+    FileChanged {
+        path: String,
+    },
+    FileRemoved {
+        path: String,
+    },
+    Message {
+        message: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct HostEvent {
+    pub id: u64,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub kind: HostEventKind,
+}
+
+/// A project's recent events, numbered from 1.
+#[derive(Default)]
+pub struct EventLog {
+    inner: Mutex<Inner>,
+    pushed: Notify,
+}
+
+#[derive(Default)]
+struct Inner {
+    last_id: u64,
+    events: VecDeque<HostEvent>,
+}
+
+impl EventLog {
+    pub fn push(&self, kind: HostEventKind) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_id += 1;
+        let event = HostEvent {
+            id: inner.last_id,
+            timestamp: crate::queue::now(),
+            kind,
+        };
+        if inner.events.len() == CAPACITY {
+            inner.events.pop_front();
+        }
+        inner.events.push_back(event);
+        drop(inner);
+        self.pushed.notify_waiters();
+    }
+
+    pub fn last_id(&self) -> u64 {
+        self.inner.lock().unwrap().last_id
+    }
+
+    fn since(&self, since: u64) -> Vec<HostEvent> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .events
+            .iter()
+            .filter(|e| e.id > since)
+            .cloned()
+            .collect()
+    }
+
+    /// Events after `since`, waiting up to `timeout` for one if there are
+    /// none yet.
+    pub async fn wait_since(&self, since: u64, timeout: Duration) -> Vec<HostEvent> {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            // Registered before checking, so a push in between isn't missed
+            let pushed = self.pushed.notified();
+            tokio::pin!(pushed);
+            pushed.as_mut().enable();
+
+            let events = self.since(since);
+            if !events.is_empty() {
+                return events;
+            }
+            if tokio::time::timeout_at(deadline, pushed).await.is_err() {
+                return vec![];
+            }
+        }
+    }
+}
+
+/// Push file events for `dir` into `log`, rescanning it forever.
+pub async fn watch(dir: PathBuf, log: Arc<EventLog>) {
+    let mut before = scan(&dir);
+    loop {
+        tokio::time::sleep(WATCH_INTERVAL).await;
+        let after = {
+            let dir = dir.clone();
+            tokio::task::spawn_blocking(move || scan(&dir))
+                .await
+                .unwrap_or_default()
+        };
+        for kind in changes(&before, &after) {
+            log.push(kind);
+        }
+        before = after;
+    }
+}
+
+/// Modification times of the files under `dir`, by relative path.
+fn scan(dir: &Path) -> HashMap<String, SystemTime> {
+    let mut files = HashMap::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&current) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = entry.path();
+            if file_type.is_dir() {
+                if !IGNORED_DIRS.contains(&entry.file_name().to_string_lossy().as_ref()) {
+                    pending.push(path);
+                }
+            } else if file_type.is_file() {
+                let modified = entry.metadata().and_then(|m| m.modified());
+                if let (Ok(relative), Ok(modified)) = (path.strip_prefix(dir), modified) {
+                    files.insert(relative.to_string_lossy().into_owned(), modified);
+                }
+                if files.len() >= WATCH_MAX_FILES {
+                    return files;
+                }
+            }
+        }
+    }
+    files
+}
+
+fn changes(
+    before: &HashMap<String, SystemTime>,
+    after: &HashMap<String, SystemTime>,
+) -> Vec<HostEventKind> {
+    let mut changes: Vec<_> = after
+        .iter()
+        .filter(|(path, modified)| before.get(*path) != Some(modified))
+        .map(|(path, _)| HostEventKind::FileChanged { path: path.clone() })
+        .chain(
+            before
+                .keys()
+                .filter(|path| !after.contains_key(*path))
+                .map(|path| HostEventKind::FileRemoved { path: path.clone() }),
+        )
+        .collect();
+    changes.sort_by_key(|kind| format!("{:?}", kind));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_events_after_since() {
+        let log = Arc::new(EventLog::default());
+        log.push(HostEventKind::Message {
+            message: "CI finished".to_string(),
+        });
+        assert_eq!(log.wait_since(0, Duration::ZERO).await.len(), 1);
+        assert!(
+            log.wait_since(1, Duration::from_millis(10))
+                .await
+                .is_empty()
+        );
+
+        let pusher = log.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            pusher.push(HostEventKind::FileChanged {
+                path: "src/foo.rs".to_string(),
+            });
+        });
+        let events = log.wait_since(1, Duration::from_secs(5)).await;
+        assert_eq!(events[0].id, 2);
+        assert_eq!(
+            events[0].kind,
+            HostEventKind::FileChanged {
+                path: "src/foo.rs".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn diffs_scans_into_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::create_dir_all(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join("src/foo.rs"), "").unwrap();
+        std::fs::write(dir.path().join("old.txt"), "").unwrap();
+        std::fs::write(dir.path().join(".git/index"), "").unwrap();
+        let before = scan(dir.path());
+        assert_eq!(before.len(), 2);
+
+        std::fs::remove_file(dir.path().join("old.txt")).unwrap();
+        std::fs::write(dir.path().join("new.txt"), "").unwrap();
+        let after = scan(dir.path());
+
+        assert_eq!(
+            changes(&before, &after),
+            [
+                HostEventKind::FileChanged {
+                    path: "new.txt".to_string()
+                },
+                HostEventKind::FileRemoved {
+                    path: "old.txt".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! `.contenantignore`: paths in the workspace the agent shouldn't see.
+//!
+//! The file uses gitignore syntax: blank lines and `#` comments are skipped,
+//! `!` re-includes, a trailing `/` matches only directories, and a pattern
+//! with a `/` anywhere but the end is anchored to the project root while
+//! one without matches at any depth. `*`, `?`, and `**` work as in mount
+//! globs. As in git, nothing inside an ignored directory can be
+//! re-included.
+
+use std::fs;
+use std::path::Path;
+
+use color_eyre::eyre::{Result, WrapErr};
+
+use crate::config::glob_matches;
+
+/// The file, in the project root.
+pub const IGNORE_FILE: &str = ".contenantignore";
+
+/// Paths the ignore file matches, relative to the workspace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Ignored {
+    pub dirs: Vec<String>,
+    pub files: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Rule {
+    pattern: Vec<String>,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let dir_only = line.ends_with('/');
+        let line = line.trim_end_matches('/');
+        let anchored = line.contains('/');
+        let mut pattern: Vec<String> = vec![];
+        if !anchored {
+            pattern.push("**".to_string());
+        }
+        pattern.extend(
+            line.split('/')
+                .filter(|c| !c.is_empty())
+                .map(str::to_string),
+        );
+        (pattern.iter().any(|c| c != "**")).then_some(Self {
+            pattern,
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, path: &[&str], is_dir: bool) -> bool {
+        let pattern: Vec<&str> = self.pattern.iter().map(String::as_str).collect();
+        (is_dir || !self.dir_only) && glob_matches(&pattern, path)
+    }
+}
+
+fn parse(text: &str) -> Vec<Rule> {
+    text.lines().filter_map(Rule::parse).collect()
+}
+
+/// What `workspace`'s ignore file matches, or nothing if it has none.
+pub fn ignored(workspace: &Path) -> Result<Ignored> {
+    let Ok(text) = fs::read_to_string(workspace.join(IGNORE_FILE)) else {
+        return Ok(Ignored::default());
+    };
+    let rules = parse(&text);
+    let mut ignored = Ignored::default();
+    let mut pending = vec![vec![]];
+    while let Some(dir) = pending.pop() {
+        let path = dir
+            .iter()
+            .fold(workspace.to_path_buf(), |path, c| path.join(c));
+        let entries =
+            fs::read_dir(&path).wrap_err_with(|| format!("Couldn't read {}", path.display()))?;
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Symlinks are masked like files rather than followed
+            let is_dir = entry.file_type()?.is_dir();
+            let mut components = dir.clone();
+            components.push(name);
+            let path: Vec<&str> = components.iter().map(String::as_str).collect();
+            let matched = rules
+                .iter()
+                .rev()
+                .find(|rule| rule.matches(&path, is_dir))
+                .is_some_and(|rule| !rule.negated);
+            match (matched, is_dir) {
+                (true, true) => ignored.dirs.push(components.join("/")),
+                (true, false) => ignored.files.push(components.join("/")),
+                (false, true) if components != [".git"] => pending.push(components),
+                (false, _) => {}
+            }
+        }
+    }
+    ignored.dirs.sort();
+    ignored.files.sort();
+    Ok(ignored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_gitignore_patterns() {
+        let rules = parse("# secrets\n\nsecrets/\n/build\nconfig/*.key\n!keep.env\n\\#literal\n");
+        let matched = |path: &str, is_dir: bool| {
+            let path: Vec<&str> = path.split('/').collect();
+            rules
+                .iter()
+                .rev()
+                .find(|rule| rule.matches(&path, is_dir))
+                .is_some_and(|rule| !rule.negated)
+        };
+        assert!(matched("secrets", true));
+        assert!(matched("deploy/secrets", true));
+        assert!(!matched("secrets", false));
+        assert!(matched("build", true));
+        assert!(!matched("src/build", true));
+        assert!(matched("config/prod.key", false));
+        assert!(!matched("other/config/prod.key", false));
+        assert!(matched("#literal", false));
+        assert!(!matched("keep.env", false));
+    }
+
+    #[test]
+    fn finds_ignored_paths_without_descending_into_them() {
+        let workspace = tempfile::tempdir().unwrap();
+        let write = |path: &str| {
+            let path = workspace.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        };
+        assert_eq!(ignored(workspace.path()).unwrap(), Ignored::default());
+
+        write(IGNORE_FILE);
+        fs::write(
+            workspace.path().join(IGNORE_FILE),
+            ".env.*\n!.env.example\nsecrets/\n",
+        )
+        .unwrap();
+        write(".env.production");
+        write(".env.example");
+        write("secrets/api.key");
+        write("services/api/.env.local");
+        write("src/main.rs");
+
+        assert_eq!(
+            ignored(workspace.path()).unwrap(),
+            Ignored {
+                dirs: vec!["secrets".to_string()],
+                files: vec![
+                    ".env.production".to_string(),
+                    "services/api/.env.local".to_string()
+                ],
+            }
+        );
+    }
+}
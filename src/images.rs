@@ -0,0 +1,196 @@
+//! Images contenant built, for `contenant images`: which project each
+//! belongs to, and which `images prune` can delete.
+//!
+//! Every build is labeled [`IMAGE_LABEL`], so a build that a newer one has
+//! replaced is still recognizable once its tag has moved on.
+
+use std::path::PathBuf;
+
+use serde::Serialize;
+
+use crate::ProjectState;
+
+/// Label (`key=value`) on every image contenant builds.
+pub const IMAGE_LABEL: &str = "contenant=1";
+
+/// Label on `contenant:base` holding the digest of its build context, which
+/// an `image.ref` image must match.
+pub const BASE_HASH_LABEL: &str = "contenant.base-hash";
+
+/// Where the digest last verified for each `image.ref` is kept, under the
+/// state directory, by the reference percent-encoded.
+pub const VERIFIED_DIR: &str = "verified-images";
+
+/// An image in the engine's store. Untagged (superseded) images have no tag.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Image {
+    pub id: String,
+    pub tag: Option<String>,
+    pub size: String,
+    /// How long ago it was built, like `3 days ago`.
+    pub created: String,
+}
+
+/// Who an image belongs to.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Owner {
+    /// `contenant:base` and `contenant:user`, which every project builds on.
+    Shared,
+    /// A project image, with the project's path if its state records one.
+    Project { id: String, path: Option<PathBuf> },
+    /// An untagged image a newer build replaced.
+    Superseded,
+}
+
+impl Owner {
+    pub fn of(image: &Image, states: &[ProjectState]) -> Self {
+        let Some(tag) = &image.tag else {
+            return Self::Superseded;
+        };
+        match tag.strip_prefix("contenant:") {
+            Some("base" | "user") => Self::Shared,
+            Some(id) => Self::Project {
+                id: id.to_string(),
+                path: states
+                    .iter()
+                    .find(|state| state.id == id)
+                    .and_then(|state| state.path.clone()),
+            },
+            None => Self::Superseded,
+        }
+    }
+
+    /// Whether `images prune` removes it: superseded images, and project
+    /// images whose project is gone or unknown.
+    pub fn is_prunable(&self) -> bool {
+        match self {
+            Self::Shared => false,
+            Self::Project { path, .. } => !path.as_ref().is_some_and(|p| p.exists()),
+            Self::Superseded => true,
+        }
+    }
+}
+
+impl std::fmt::Display for Owner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Shared => write!(f, "(shared)"),
+            Self::Project {
+                path: Some(path), ..
+            } => write!(f, "{}", path.display()),
+            Self::Project { path: None, .. } => write!(f, "(unknown project)"),
+            Self::Superseded => write!(f, "(superseded)"),
+        }
+    }
+}
+
+/// Parse `docker images` lines formatted as
+/// `{{.ID}}\t{{.Repository}}:{{.Tag}}\t{{.Size}}\t{{.CreatedSince}}`.
+pub fn parse_docker_images(output: &str) -> Vec<Image> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?.to_string();
+            let tag = fields.next()?;
+            Some(Image {
+                id,
+                tag: (!tag.contains("<none>")).then(|| tag.to_string()),
+                size: fields.next()?.to_string(),
+                created: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// `secs` as a rough age, like `docker images` shows.
+pub fn age(secs: u64) -> String {
+    let (n, unit) = match secs {
+        0..120 => return "just now".to_string(),
+        120..7_200 => (secs / 60, "minutes"),
+        7_200..172_800 => (secs / 3_600, "hours"),
+        _ => (secs / 86_400, "days"),
+    };
+    format!("{} {} ago", n, unit)
+}
+
+/// `bytes` in the decimal units `docker images` uses.
+pub fn size(bytes: u64) -> String {
+    let bytes = bytes as f64;
+    match bytes {
+        b if b >= 1e9 => format!("{:.2}GB", b / 1e9),
+        b if b >= 1e6 => format!("{:.1}MB", b / 1e6),
+        b => format!("{:.1}kB", b / 1e3),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(tag: Option<&str>) -> Image {
+        Image {
+            id: "sha256:abc".to_string(),
+            tag: tag.map(str::to_string),
+            size: "1GB".to_string(),
+            created: "just now".to_string(),
+        }
+    }
+
+    #[test]
+    fn owners_and_what_prune_removes() {
+        let project = tempfile::tempdir().unwrap();
+        let states = [
+            ProjectState {
+                id: "0123abcd-app".to_string(),
+                dir: PathBuf::from("/state/projects/0123abcd-app"),
+                path: Some(project.path().to_path_buf()),
+            },
+            ProjectState {
+                id: "4567cdef-gone".to_string(),
+                dir: PathBuf::from("/state/projects/4567cdef-gone"),
+                path: Some(PathBuf::from("/nonexistent/gone")),
+            },
+        ];
+
+        let owner = |tag| Owner::of(&image(tag), &states);
+        assert_eq!(owner(Some("contenant:base")), Owner::Shared);
+        assert!(!owner(Some("contenant:user")).is_prunable());
+        assert_eq!(
+            owner(Some("contenant:0123abcd-app")).to_string(),
+            project.path().display().to_string()
+        );
+        assert!(!owner(Some("contenant:0123abcd-app")).is_prunable());
+        assert!(owner(Some("contenant:4567cdef-gone")).is_prunable());
+        assert!(owner(Some("contenant:89abcdef-unknown")).is_prunable());
+        assert_eq!(owner(None), Owner::Superseded);
+        assert!(owner(None).is_prunable());
+
+        assert_eq!(
+            serde_json::to_value(owner(Some("contenant:4567cdef-gone"))).unwrap(),
+            serde_json::json!({"kind": "project", "id": "4567cdef-gone", "path": "/nonexistent/gone"})
+        );
+    }
+
+    #[test]
+    fn parses_docker_images_output() {
+        let images = parse_docker_images(
+            "sha256:1\tcontenant:base\t1.2GB\t2 days ago\n\
+             sha256:2\t<none>:<none>\t1.1GB\t3 weeks ago\n",
+        );
+        assert_eq!(images[0].tag.as_deref(), Some("contenant:base"));
+        assert_eq!(images[0].size, "1.2GB");
+        assert_eq!(images[1].tag, None);
+        assert_eq!(images[1].created, "3 weeks ago");
+    }
+
+    #[test]
+    fn formats_sizes_and_ages() {
+        assert_eq!(size(1_234_000_000), "1.23GB");
+        assert_eq!(size(56_700_000), "56.7MB");
+        assert_eq!(age(30), "just now");
+        assert_eq!(age(3 * 3_600), "3 hours ago");
+        assert_eq!(age(3 * 86_400), "3 days ago");
+    }
+}
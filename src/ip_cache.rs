@@ -0,0 +1,134 @@
+//! Cache of resolved domains and published IP ranges, so runs don't wait
+//! on DNS and range APIs every time and still start when briefly offline.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::queue::now;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Entry {
+    fetched_at: u64,
+    cidrs: Vec<String>,
+}
+
+pub struct IpCache {
+    /// JSON file backing the cache; `None` keeps it in memory only.
+    path: Option<PathBuf>,
+    ttl_secs: u64,
+    /// Ignore fresh entries and fetch everything again.
+    refresh: bool,
+    entries: HashMap<String, Entry>,
+}
+
+impl IpCache {
+    /// Load the cache from `path`, starting empty if it's missing or corrupt.
+    pub fn load(path: Option<PathBuf>, ttl_secs: u64, refresh: bool) -> Self {
+        let entries = path
+            .as_ref()
+            .and_then(|p| fs::read(p).ok())
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            ttl_secs,
+            refresh,
+            entries,
+        }
+    }
+
+    /// Cached CIDRs for `key` while fresh, otherwise the result of `fetch`.
+    /// If fetching fails, stale CIDRs are returned with a warning.
+    pub fn get_or_fetch(
+        &mut self,
+        key: &str,
+        fetch: impl FnOnce() -> Result<Vec<String>>,
+    ) -> Result<Vec<String>> {
+        let cached = self.entries.get(key);
+        if let Some(entry) = cached
+            && !self.refresh
+            && now().saturating_sub(entry.fetched_at) < self.ttl_secs
+        {
+            return Ok(entry.cidrs.clone());
+        }
+
+        match fetch() {
+            Ok(cidrs) => {
+                let entry = Entry {
+                    fetched_at: now(),
+                    cidrs: cidrs.clone(),
+                };
+                self.entries.insert(key.to_string(), entry);
+                Ok(cidrs)
+            }
+            Err(err) => match cached {
+                Some(entry) => {
+                    let age = now().saturating_sub(entry.fetched_at);
+                    warn!(key, %err, age_secs = age, "Using stale cached IPs");
+                    Ok(entry.cidrs.clone())
+                }
+                None => Err(err),
+            },
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            fs::write(path, serde_json::to_vec(&self.entries)?)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::eyre;
+
+    #[test]
+    fn fresh_entries_skip_fetching() {
+        let mut cache = IpCache::load(None, 60, false);
+        let first = cache.get_or_fetch("dns:a", || Ok(vec!["1.1.1.1/32".to_string()]));
+        assert_eq!(first.unwrap(), ["1.1.1.1/32"]);
+
+        let second = cache.get_or_fetch("dns:a", || panic!("should be cached"));
+        assert_eq!(second.unwrap(), ["1.1.1.1/32"]);
+    }
+
+    #[test]
+    fn stale_entries_cover_fetch_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ips.json");
+
+        let mut cache = IpCache::load(Some(path.clone()), 0, false);
+        cache
+            .get_or_fetch("dns:a", || Ok(vec!["1.1.1.1/32".to_string()]))
+            .unwrap();
+        cache.save().unwrap();
+
+        // TTL of zero: always stale, so a refetch is attempted
+        let mut cache = IpCache::load(Some(path), 0, false);
+        let cidrs = cache.get_or_fetch("dns:a", || Err(eyre!("offline")));
+        assert_eq!(cidrs.unwrap(), ["1.1.1.1/32"]);
+        assert!(
+            cache
+                .get_or_fetch("dns:b", || Err(eyre!("offline")))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn refresh_ignores_fresh_entries() {
+        let mut cache = IpCache::load(None, 60, true);
+        cache
+            .get_or_fetch("dns:a", || Ok(vec!["1.1.1.1/32".to_string()]))
+            .unwrap();
+        let cidrs = cache.get_or_fetch("dns:a", || Ok(vec!["2.2.2.2/32".to_string()]));
+        assert_eq!(cidrs.unwrap(), ["2.2.2.2/32"]);
+    }
+}
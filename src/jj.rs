@@ -0,0 +1,281 @@
+//! [Jujutsu](https://jj-vcs.github.io/jj/) repos as workspaces.
+//!
+//! A jj repo keeps its op store and commits under `.jj/repo`, with a git
+//! store either inside it or, when colocated, in the workspace's `.git`.
+//! Both are in the workspace, except in a secondary workspace made by `jj
+//! workspace add`, whose `.jj/repo` is a file naming the main one's repo
+//! directory. [`detect`] finds that, and the git store it uses, for
+//! [`Repo::mounts`] to mount where jj in the container will look.
+//!
+//! The user's jj config is mounted too, along with the public key commits
+//! are signed with when `signing.backend` is `ssh`, since its path is
+//! usually under a home directory the container doesn't share.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr};
+use tracing::warn;
+
+use crate::config::CONTAINER_HOME;
+use crate::worktree::normalize;
+
+/// A jj repo, and anything it needs outside the workspace.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Repo {
+    /// Whether git and jj share the workspace's `.git`.
+    pub colocated: bool,
+    /// Directories outside the workspace, on the host and in the container.
+    pub external: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Repo {
+    /// The directories outside the workspace, writable, since every jj
+    /// command records an operation.
+    pub fn mounts(&self) -> Vec<String> {
+        self.external
+            .iter()
+            .map(|(host, container)| format!("{}:{}", host.display(), container.display()))
+            .collect()
+    }
+}
+
+/// The jj repo `workspace` is, if it's one.
+pub fn detect(workspace: &Path) -> Result<Option<Repo>> {
+    let dot_jj = workspace.join(".jj");
+    let Ok(metadata) = fs::metadata(dot_jj.join("repo")) else {
+        return Ok(None);
+    };
+    let container_dot_jj = Path::new("/workspace/.jj");
+    let mut repo = Repo::default();
+
+    // A secondary workspace's repo directory is wherever the file says
+    let (repo_dir, container_repo_dir) = if metadata.is_file() {
+        let target = fs::read_to_string(dot_jj.join("repo"))?;
+        let target = Path::new(target.trim());
+        let host = fs::canonicalize(dot_jj.join(target))
+            .wrap_err("Couldn't find the jj workspace's repo directory")?;
+        let container = normalize(&container_dot_jj.join(target));
+        repo.external.push((host.clone(), container.clone()));
+        (host, container)
+    } else {
+        (dot_jj.join("repo"), container_dot_jj.join("repo"))
+    };
+
+    if let Ok(git_target) = fs::read_to_string(repo_dir.join("store/git_target")) {
+        let git_target = Path::new(git_target.trim());
+        let host = fs::canonicalize(repo_dir.join("store").join(git_target))
+            .wrap_err("Couldn't find the jj repo's git store")?;
+        repo.colocated = fs::canonicalize(workspace.join(".git")).is_ok_and(|git| git == host);
+        let canonical_repo_dir = fs::canonicalize(&repo_dir)?;
+        let in_workspace = fs::canonicalize(workspace).is_ok_and(|ws| host.starts_with(ws));
+        if !in_workspace && !host.starts_with(&canonical_repo_dir) {
+            let container = normalize(&container_repo_dir.join("store").join(git_target));
+            repo.external.push((host, container));
+        }
+    }
+    Ok(Some(repo))
+}
+
+/// Mounts for the user's jj config and, when commits are signed with SSH,
+/// the key: the config read-only at the container's XDG location, and the
+/// key where its path leads in the container.
+pub fn config_mounts(workspace: &Path) -> Vec<String> {
+    let Some(home) = dirs::home_dir() else {
+        return vec![];
+    };
+    let mut mounts = vec![];
+    let container_config = format!("{}/.config/jj", CONTAINER_HOME);
+    match std::env::var_os("JJ_CONFIG").map(PathBuf::from) {
+        Some(path) if path.is_dir() => {
+            mounts.push(format!("{}:{}:ro", path.display(), container_config));
+        }
+        Some(path) if path.is_file() => {
+            mounts.push(format!(
+                "{}:{}/config.toml:ro",
+                path.display(),
+                container_config
+            ));
+        }
+        Some(_) => {}
+        None => {
+            let dirs = [dirs::config_dir(), Some(home.join(".config"))];
+            if let Some(dir) = dirs
+                .into_iter()
+                .flatten()
+                .map(|dir| dir.join("jj"))
+                .find(|dir| dir.is_dir())
+            {
+                mounts.push(format!("{}:{}:ro", dir.display(), container_config));
+            }
+            let legacy = home.join(".jjconfig.toml");
+            if legacy.is_file() {
+                mounts.push(format!(
+                    "{}:{}/.jjconfig.toml:ro",
+                    legacy.display(),
+                    CONTAINER_HOME
+                ));
+            }
+        }
+    }
+
+    match config_get(workspace, "signing.backend").as_deref() {
+        Some("ssh") => {
+            if let Some(key) = config_get(workspace, "signing.key")
+                && let Some(mount) = signing_key_mount(&key, &home)
+            {
+                mounts.push(mount);
+            }
+        }
+        Some("gpg" | "gpgsm") => {
+            warn!("jj signs commits with GPG, which the container can't use; they'll fail to sign")
+        }
+        _ => {}
+    }
+    mounts
+}
+
+/// The mount for an SSH signing key given as a path, so the same setting
+/// finds it in the container. Keys given inline need nothing.
+fn signing_key_mount(key: &str, home: &Path) -> Option<String> {
+    if key.starts_with("ssh-") || key.starts_with("ecdsa-") || key.starts_with("sk-") {
+        return None;
+    }
+    let (host, container) = match key.strip_prefix("~/") {
+        Some(rest) => (home.join(rest), format!("{}/{}", CONTAINER_HOME, rest)),
+        None => (PathBuf::from(key), key.to_string()),
+    };
+    host.is_file()
+        .then(|| format!("{}:{}:ro", host.display(), container))
+}
+
+/// Hosts of the repo's git remotes, for `jj.push`.
+pub fn remote_hosts(workspace: &Path) -> Vec<String> {
+    let Some(remotes) = jj(workspace, &["git", "remote", "list"]) else {
+        return vec![];
+    };
+    let mut hosts = vec![];
+    for url in remotes
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+    {
+        if let Some(host) = url_host(url)
+            && !hosts.contains(&host)
+        {
+            hosts.push(host);
+        }
+    }
+    hosts
+}
+
+/// The host in a git URL, like `https://github.com/org/repo.git`,
+/// `ssh://git@host:2222/repo`, or `git@github.com:org/repo.git`.
+fn url_host(url: &str) -> Option<String> {
+    let rest = match url.split_once("://") {
+        Some((_, rest)) => rest,
+        // scp-like syntax needs a colon before any slash
+        None => match (url.find(':'), url.find('/')) {
+            (Some(colon), slash) if slash.is_none_or(|slash| colon < slash) => url,
+            _ => return None,
+        },
+    };
+    let authority = rest.split(['/', ':']).next()?;
+    let host = authority.rsplit('@').next()?;
+    (!host.is_empty()).then(|| host.to_string())
+}
+
+fn config_get(workspace: &Path, name: &str) -> Option<String> {
+    jj(workspace, &["config", "get", name])
+}
+
+/// Output of `jj` run in `workspace`, if it's installed and succeeds.
+fn jj(workspace: &Path, args: &[&str]) -> Option<String> {
+    if !crate::on_path("jj") {
+        return None;
+    }
+    let output = Command::new("jj")
+        .arg("--ignore-working-copy")
+        .arg("-R")
+        .arg(workspace)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_a_secondary_workspaces_repo() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        let main = root.join("main");
+        fs::create_dir_all(main.join(".jj/repo/store")).unwrap();
+        fs::create_dir_all(main.join(".git")).unwrap();
+        fs::write(main.join(".jj/repo/store/git_target"), "../../../.git").unwrap();
+        let second = root.join("second");
+        fs::create_dir_all(second.join(".jj")).unwrap();
+        fs::write(second.join(".jj/repo"), "../../main/.jj/repo").unwrap();
+
+        let repo = detect(&main).unwrap().unwrap();
+        assert_eq!(
+            repo,
+            Repo {
+                colocated: true,
+                external: vec![]
+            }
+        );
+
+        let repo = detect(&second).unwrap().unwrap();
+        assert!(!repo.colocated);
+        assert_eq!(
+            repo.mounts(),
+            [
+                format!("{}:/main/.jj/repo", main.join(".jj/repo").display()),
+                format!("{}:/main/.git", main.join(".git").display()),
+            ]
+        );
+        assert_eq!(detect(root.as_path()).unwrap(), None);
+    }
+
+    #[test]
+    fn mounts_ssh_signing_keys_where_the_config_expects_them() {
+        let home = tempfile::tempdir().unwrap();
+        fs::create_dir(home.path().join(".ssh")).unwrap();
+        fs::write(home.path().join(".ssh/id_ed25519.pub"), "ssh-ed25519 AAAA").unwrap();
+
+        assert_eq!(
+            signing_key_mount("~/.ssh/id_ed25519.pub", home.path()),
+            Some(format!(
+                "{}/.ssh/id_ed25519.pub:/home/claude/.ssh/id_ed25519.pub:ro",
+                home.path().display()
+            ))
+        );
+        assert_eq!(signing_key_mount("ssh-ed25519 AAAA", home.path()), None);
+        assert_eq!(signing_key_mount("~/.ssh/missing.pub", home.path()), None);
+    }
+
+    #[test]
+    fn parses_remote_hosts() {
+        assert_eq!(
+            url_host("https://github.com/org/repo.git").as_deref(),
+            Some("github.com")
+        );
+        assert_eq!(
+            url_host("ssh://git@git.example.com:2222/repo").as_deref(),
+            Some("git.example.com")
+        );
+        assert_eq!(
+            url_host("git@gitlab.com:org/repo.git").as_deref(),
+            Some("gitlab.com")
+        );
+        assert_eq!(url_host("../local/repo"), None);
+    }
+}
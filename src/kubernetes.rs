@@ -0,0 +1,517 @@
+//! Kubernetes backend: runs the agent as a pod via `kubectl`.
+//!
+//! Images are built locally with Docker and pushed to `registry` (if set) so
+//! the cluster can pull them. Host bind mounts can't follow the agent into a
+//! cluster, so the workspace is copied into the pod before the agent starts
+//! and other mounts are skipped. Egress is restricted twice: by the
+//! entrypoint firewall inside the pod and by a NetworkPolicy generated from
+//! the same allowed IP list.
+
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail, eyre};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+
+use crate::config::KubernetesConfig;
+use crate::images::Image;
+use crate::{Backend, BuildOptions, Docker, RunSpec, cidr_contains, parse_cidr};
+
+const AGENT_CONTAINER: &str = "agent";
+const UPLOAD_CONTAINER: &str = "upload";
+const READY_FILE: &str = "/workspace/.contenant-ready";
+
+pub struct Kubernetes {
+    config: KubernetesConfig,
+}
+
+impl Kubernetes {
+    pub fn new(config: KubernetesConfig) -> Self {
+        Self { config }
+    }
+
+    fn kubectl(&self) -> Command {
+        let mut cmd = Command::new("kubectl");
+        if let Some(context) = &self.config.context {
+            cmd.args(["--context", context]);
+        }
+        if let Some(namespace) = &self.config.namespace {
+            cmd.args(["--namespace", namespace]);
+        }
+        cmd
+    }
+
+    fn kubectl_output(&self, args: &[&str]) -> Result<String> {
+        let output = self.kubectl().args(args).output()?;
+        if !output.status.success() {
+            bail!(
+                "kubectl {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn kubectl_status(&self, args: &[&str]) -> Result<()> {
+        if !self.kubectl().args(args).status()?.success() {
+            bail!("kubectl {} failed", args.join(" "));
+        }
+        Ok(())
+    }
+
+    /// Image reference the cluster pulls.
+    fn image_ref(&self, image: &str) -> String {
+        match &self.config.registry {
+            Some(registry) => format!("{}/{}", registry.trim_end_matches('/'), image),
+            None => image.to_string(),
+        }
+    }
+
+    fn push(&self, image: &str) -> Result<()> {
+        if self.config.registry.is_none() {
+            return Ok(());
+        }
+
+        let remote = self.image_ref(image);
+        Docker.tag(image, &remote)?;
+        info!(image = %remote, "Pushing image");
+        if !Command::new("docker")
+            .args(["push", &remote])
+            .status()?
+            .success()
+        {
+            bail!("Docker push failed");
+        }
+        Ok(())
+    }
+
+    fn apply(&self, manifest: &Value) -> Result<()> {
+        let mut child = self
+            .kubectl()
+            .args(["apply", "-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| eyre!("kubectl stdin unavailable"))?
+            .write_all(manifest.to_string().as_bytes())?;
+        if !child.wait()?.success() {
+            bail!("kubectl apply failed");
+        }
+        Ok(())
+    }
+
+    /// Poll a pod field (JSONPath) until it's non-empty.
+    fn wait_for(&self, pod: &str, jsonpath: &str) -> Result<String> {
+        for _ in 0..self.config.startup_timeout_secs {
+            let value =
+                self.kubectl_output(&["get", "pod", pod, "-o", &format!("jsonpath={}", jsonpath)])?;
+            if !value.trim().is_empty() {
+                return Ok(value);
+            }
+            sleep(Duration::from_secs(1));
+        }
+        bail!("Timed out waiting for pod {} ({})", pod, jsonpath)
+    }
+
+    fn run_pod(&self, pod: &str, spec: &RunSpec, tty: bool) -> Result<i32> {
+        self.wait_for(
+            pod,
+            "{.status.initContainerStatuses[0].state.running.startedAt}",
+        )?;
+
+        info!(pod, "Copying workspace into pod");
+        let source = format!("{}/.", spec.workspace.display());
+        let target = format!("{}:/workspace", pod);
+        self.kubectl_status(&["cp", "-c", UPLOAD_CONTAINER, &source, &target])?;
+        self.kubectl_status(&[
+            "exec",
+            pod,
+            "-c",
+            UPLOAD_CONTAINER,
+            "--",
+            "touch",
+            READY_FILE,
+        ])?;
+
+        self.wait_for(
+            pod,
+            "{.status.containerStatuses[0].state.running.startedAt}{.status.containerStatuses[0].state.terminated.exitCode}",
+        )?;
+        let attach_flags = if tty { "-it" } else { "-i" };
+        // Attach exits when the agent does; its status isn't the agent's
+        let _ = self
+            .kubectl()
+            .args(["attach", attach_flags, pod, "-c", AGENT_CONTAINER])
+            .status()?;
+
+        let code = self.wait_for(
+            pod,
+            "{.status.containerStatuses[0].state.terminated.exitCode}",
+        )?;
+        Ok(code.trim().parse()?)
+    }
+}
+
+impl Backend for Kubernetes {
+    fn build(&self, image: &str, context: &Path, options: &BuildOptions) -> Result<()> {
+        Docker.build(image, context, options)?;
+        self.push(image)
+    }
+
+    fn tag(&self, source: &str, target: &str) -> Result<()> {
+        Docker.tag(source, target)?;
+        self.push(target)
+    }
+
+    // Images are built with the local Docker; copies pushed to the registry
+    // are left alone
+    fn images(&self) -> Result<Vec<Image>> {
+        Docker.images()
+    }
+
+    fn remove_image(&self, reference: &str) -> Result<()> {
+        Docker.remove_image(reference)
+    }
+
+    fn run(&self, spec: &RunSpec) -> Result<i32> {
+        if !spec.mounts.is_empty() {
+            warn!(
+                count = spec.mounts.len(),
+                "Host mounts aren't available in Kubernetes pods; skipping them"
+            );
+        }
+        if !spec.tmpfs_mounts.is_empty() {
+            warn!(
+                count = spec.tmpfs_mounts.len(),
+                "tmpfs mounts aren't supported by the Kubernetes backend; skipping them"
+            );
+        }
+        if !spec.volumes.is_empty() {
+            warn!(
+                count = spec.volumes.len(),
+                "Named volumes aren't supported by the Kubernetes backend; skipping them"
+            );
+        }
+
+        if !spec.ports.is_empty() {
+            warn!(
+                count = spec.ports.len(),
+                "Pods don't publish ports on the host; use kubectl port-forward instead"
+            );
+        }
+
+        if spec.network.is_some() {
+            warn!(
+                "Shared networks don't apply to Kubernetes; reach sidecars through Services \
+                 in the namespace and allow them in allowed_domains"
+            );
+        }
+
+        if !spec.dns_allowlist.is_empty() {
+            warn!(
+                "The NetworkPolicy only allows addresses resolved before the pod starts; \
+                 wildcard domains are enforced by the in-pod firewall alone"
+            );
+        }
+
+        let tty = std::io::stdin().is_terminal();
+        let pod = format!("contenant-{}-{}", std::process::id(), crate::queue::now());
+        let image = self.image_ref(&spec.image);
+        self.apply(&manifests(&pod, &image, spec, tty))?;
+
+        let result = self.run_pod(&pod, spec, tty);
+
+        for kind in ["pod", "configmap", "networkpolicy"] {
+            let _ = self
+                .kubectl()
+                .args(["delete", kind, &pod, "--ignore-not-found", "--wait=false"])
+                .stdout(Stdio::null())
+                .status();
+        }
+
+        result
+    }
+
+    fn stop(&self, label: &str, grace_secs: u64) -> Result<usize> {
+        let selector = match label.split_once('=') {
+            Some((key, value)) => format!("{}={}", key, label_value(value)),
+            None => label.to_string(),
+        };
+        let pods = self.kubectl_output(&["get", "pods", "-l", &selector, "-o", "name"])?;
+        let pods: Vec<_> = pods.split_whitespace().collect();
+        if pods.is_empty() {
+            return Ok(0);
+        }
+
+        // The session's `run` sees the agent exit and cleans up the rest
+        let grace_period = format!("--grace-period={}", grace_secs);
+        let mut args = vec!["delete", &grace_period, "--wait=false"];
+        args.extend(&pods);
+        self.kubectl_output(&args)?;
+        Ok(pods.len())
+    }
+}
+
+/// `value` with the characters Kubernetes doesn't allow in label values
+/// replaced, cut to its 63-character limit.
+fn label_value(value: &str) -> String {
+    let value: String = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '-',
+        })
+        .take(63)
+        .collect();
+    value
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+/// Pod, allowed-IP ConfigMap, and egress NetworkPolicy for one session.
+fn manifests(name: &str, image: &str, spec: &RunSpec, tty: bool) -> Value {
+    let mut labels = json!({
+        "app.kubernetes.io/managed-by": "contenant",
+        "contenant/session": name,
+    });
+    for (key, value) in &spec.labels {
+        labels[key] = json!(label_value(value));
+    }
+
+    let env: Vec<_> = spec
+        .env
+        .iter()
+        .chain(spec.env_file.iter().flat_map(|f| &f.vars))
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect();
+
+    let workspace_mount = json!({ "name": "workspace", "mountPath": "/workspace" });
+    let mut agent_mounts = vec![
+        workspace_mount.clone(),
+        json!({
+            "name": "allowed-ips",
+            "mountPath": "/etc/contenant/allowed-ips",
+            "subPath": "allowed-ips",
+            "readOnly": true,
+        }),
+    ];
+    let files = [
+        ("denied-ips", true),
+        ("dns-allowlist", !spec.dns_allowlist.is_empty()),
+        ("dns-denylist", !spec.dns_denylist.is_empty()),
+    ];
+    for (file, _) in files.iter().filter(|(_, mounted)| *mounted) {
+        agent_mounts.push(json!({
+            "name": "allowed-ips",
+            "mountPath": format!("/etc/contenant/{}", file),
+            "subPath": file,
+            "readOnly": true,
+        }));
+    }
+    // Schedule onto nodes that can run the image
+    let node_selector = match spec.platform.as_deref().map(|p| p.split('/')) {
+        Some(mut parts) => json!({
+            "kubernetes.io/os": parts.next(),
+            "kubernetes.io/arch": parts.next(),
+        }),
+        None => json!({}),
+    };
+    let pod = json!({
+        "apiVersion": "v1",
+        "kind": "Pod",
+        "metadata": { "name": name, "labels": labels },
+        "spec": {
+            "restartPolicy": "Never",
+            "nodeSelector": node_selector,
+            "initContainers": [{
+                "name": UPLOAD_CONTAINER,
+                "image": "busybox",
+                "command": [
+                    "sh", "-c",
+                    format!("until [ -f {} ]; do sleep 1; done; rm {}", READY_FILE, READY_FILE),
+                ],
+                "volumeMounts": [workspace_mount],
+            }],
+            "containers": [{
+                "name": AGENT_CONTAINER,
+                "image": image,
+                "args": spec.args,
+                "env": env,
+                "workingDir": spec.container_workdir(),
+                "stdin": true,
+                "stdinOnce": true,
+                "tty": tty,
+                "securityContext": {
+                    "capabilities": { "add": spec.cap_add(), "drop": spec.cap_drop() },
+                    "readOnlyRootFilesystem": spec.read_only_root(),
+                    "allowPrivilegeEscalation": spec.hardening.no_new_privileges != Some(true),
+                },
+                "volumeMounts": agent_mounts,
+            }],
+            "volumes": [
+                { "name": "workspace", "emptyDir": {} },
+                { "name": "allowed-ips", "configMap": { "name": name } },
+            ],
+        },
+    });
+
+    let config_map = json!({
+        "apiVersion": "v1",
+        "kind": "ConfigMap",
+        "metadata": { "name": name, "labels": labels },
+        "data": {
+            "allowed-ips": spec.allowed_ips.join("\n") + "\n",
+            "dns-allowlist": spec.dns_allowlist.join("\n") + "\n",
+            "denied-ips": spec.denied_ips.join("\n") + "\n",
+            "dns-denylist": spec.dns_denylist.join("\n") + "\n",
+        },
+    });
+
+    let peers: Vec<_> = spec
+        .allowed_ips
+        .iter()
+        .filter_map(|cidr| egress_peer(cidr, &spec.denied_ips))
+        .collect();
+    let network_policy = json!({
+        "apiVersion": "networking.k8s.io/v1",
+        "kind": "NetworkPolicy",
+        "metadata": { "name": name, "labels": labels },
+        "spec": {
+            "podSelector": { "matchLabels": { "contenant/session": name } },
+            "policyTypes": ["Egress"],
+            "egress": [
+                {
+                    "ports": [
+                        { "protocol": "UDP", "port": 53 },
+                        { "protocol": "TCP", "port": 53 },
+                    ],
+                },
+                { "to": peers },
+            ],
+        },
+    });
+
+    json!({
+        "apiVersion": "v1",
+        "kind": "List",
+        "items": [config_map, network_policy, pod],
+    })
+}
+
+/// An `ipBlock` for an allowed CIDR with denied ranges inside it carved
+/// out, or `None` if a denied range covers it entirely.
+fn egress_peer(cidr: &str, denied: &[String]) -> Option<Value> {
+    let Some(allowed) = parse_cidr(cidr) else {
+        return Some(json!({ "ipBlock": { "cidr": cidr } }));
+    };
+
+    let denied: Vec<_> = denied
+        .iter()
+        .filter_map(|d| Some((d, parse_cidr(d)?)))
+        .collect();
+    if denied
+        .iter()
+        .any(|(_, d)| d.1 <= allowed.1 && cidr_contains(*d, allowed.0))
+    {
+        return None;
+    }
+
+    let except: Vec<_> = denied
+        .iter()
+        .filter(|(_, d)| d.1 > allowed.1 && cidr_contains(allowed, d.0))
+        .map(|(d, _)| d)
+        .collect();
+    if except.is_empty() {
+        Some(json!({ "ipBlock": { "cidr": cidr } }))
+    } else {
+        Some(json!({ "ipBlock": { "cidr": cidr, "except": except } }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_policy_allows_only_resolved_ips() {
+        let spec = RunSpec {
+            allowed_ips: vec!["140.82.112.0/20".to_string(), "1.2.3.4/32".to_string()],
+            ..Default::default()
+        };
+        let manifests = manifests("contenant-test", "contenant:user", &spec, false);
+
+        let policy = &manifests["items"][1];
+        assert_eq!(policy["kind"], "NetworkPolicy");
+        assert_eq!(
+            policy["spec"]["egress"][1]["to"][0]["ipBlock"]["cidr"],
+            "140.82.112.0/20"
+        );
+        assert_eq!(
+            manifests["items"][0]["data"]["allowed-ips"],
+            "140.82.112.0/20\n1.2.3.4/32\n"
+        );
+    }
+
+    #[test]
+    fn network_policy_carves_out_denied_ranges() {
+        let denied = vec!["140.82.112.4/32".to_string(), "1.2.3.4/32".to_string()];
+        assert_eq!(
+            egress_peer("140.82.112.0/20", &denied),
+            Some(
+                json!({ "ipBlock": { "cidr": "140.82.112.0/20", "except": ["140.82.112.4/32"] } })
+            )
+        );
+        assert_eq!(egress_peer("1.2.3.4/32", &denied), None);
+    }
+
+    #[test]
+    fn labels_the_pod() {
+        let spec = RunSpec {
+            labels: [(
+                "contenant.project".to_string(),
+                "0123abcd-My Project!".to_string(),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let manifests = manifests("contenant-test", "contenant:user", &spec, false);
+
+        let labels = &manifests["items"][2]["metadata"]["labels"];
+        assert_eq!(labels["contenant.project"], "0123abcd-My-Project");
+        assert_eq!(labels["contenant/session"], "contenant-test");
+    }
+
+    #[test]
+    fn schedules_onto_nodes_of_the_platform() {
+        let spec = RunSpec {
+            platform: Some("linux/amd64".to_string()),
+            ..Default::default()
+        };
+        let manifests = manifests("contenant-test", "contenant:user", &spec, false);
+
+        assert_eq!(
+            manifests["items"][2]["spec"]["nodeSelector"],
+            json!({ "kubernetes.io/os": "linux", "kubernetes.io/arch": "amd64" })
+        );
+    }
+
+    #[test]
+    fn image_ref_prefixes_registry() {
+        let backend = Kubernetes::new(KubernetesConfig {
+            registry: Some("registry.example.com/team/".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            backend.image_ref("contenant:user"),
+            "registry.example.com/team/contenant:user"
+        );
+    }
+}
@@ -0,0 +1,160 @@
+//! Per-session logs in the project's state dir, for `contenant logs`.
+//!
+//! Backends copy image build output into `build.log` as it streams to the
+//! terminal. The container's output goes to `output.log`, which is mounted
+//! into the container and written by the entrypoint: through `script` when
+//! attached to a terminal, so the agent keeps its TTY, or `tee` otherwise.
+
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+use crate::{bridge, queue};
+
+/// Where the session's `output.log` is mounted in the container.
+pub const CONTAINER_OUTPUT_LOG: &str = "/var/log/contenant-session.log";
+
+pub const OUTPUT_LOG: &str = "output.log";
+pub const BUILD_LOG: &str = "build.log";
+
+/// Sessions kept per project; older ones are removed as new ones start.
+const KEEP_SESSIONS: usize = 20;
+
+/// Create the log directory for a new session under `logs_dir`, with an
+/// empty `output.log` the container's user can write, and remove all but the
+/// most recent sessions.
+pub fn start_session(logs_dir: &Path) -> Result<PathBuf> {
+    // Named so they sort by start time
+    let dir = logs_dir.join(format!("{}-{}", queue::now(), &bridge::random_hex()?[..8]));
+    fs::create_dir_all(&dir)?;
+    let output_log = dir.join(OUTPUT_LOG);
+    fs::write(&output_log, "")?;
+    // The agent runs as a user that doesn't own the file on the host
+    fs::set_permissions(&output_log, fs::Permissions::from_mode(0o666))?;
+
+    let sessions = sessions(logs_dir)?;
+    for old in &sessions[..sessions.len().saturating_sub(KEEP_SESSIONS)] {
+        let _ = fs::remove_dir_all(old);
+    }
+    Ok(dir)
+}
+
+/// Session log directories under `logs_dir`, oldest first.
+pub fn sessions(logs_dir: &Path) -> Result<Vec<PathBuf>> {
+    let Ok(entries) = fs::read_dir(logs_dir) else {
+        return Ok(vec![]);
+    };
+    let mut sessions: Vec<_> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    sessions.sort();
+    Ok(sessions)
+}
+
+/// Run `cmd`, copying its stdout and stderr to `log` as well as to ours.
+pub fn tee(cmd: &mut Command, log: &Path) -> Result<ExitStatus> {
+    let log = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .wrap_err_with(|| format!("Couldn't open {}", log.display()))?;
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let stdout_log = log.try_clone()?;
+    let copy_stdout = std::thread::spawn(move || copy(stdout, std::io::stdout(), stdout_log));
+    copy(stderr, std::io::stderr(), log);
+    let _ = copy_stdout.join();
+
+    Ok(child.wait()?)
+}
+
+/// Copy `from` into both `to` and `log` until it closes. Errors writing
+/// either are ignored, so the command isn't cut off.
+fn copy(mut from: impl Read, mut to: impl Write, mut log: fs::File) {
+    let mut buf = [0; 8192];
+    while let Ok(n) = from.read(&mut buf) {
+        if n == 0 {
+            break;
+        }
+        let _ = to.write_all(&buf[..n]);
+        let _ = to.flush();
+        let _ = log.write_all(&buf[..n]);
+    }
+}
+
+/// Write the file at `path` to stdout. With `follow`, keep writing what's
+/// appended to it until interrupted.
+pub fn print(path: &Path, follow: bool) -> Result<()> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            bail!("No log at {}", path.display())
+        }
+        Err(err) => return Err(err.into()),
+    };
+    let mut stdout = std::io::stdout();
+    std::io::copy(&mut file, &mut stdout)?;
+    stdout.flush()?;
+    if !follow {
+        return Ok(());
+    }
+
+    loop {
+        std::thread::sleep(Duration::from_millis(250));
+        let position = file.stream_position()?;
+        if fs::metadata(path)?.len() < position {
+            // Truncated; start over
+            file.seek(SeekFrom::Start(0))?;
+        }
+        std::io::copy(&mut file, &mut stdout)?;
+        stdout.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_most_recent_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        for n in 0..KEEP_SESSIONS {
+            fs::create_dir_all(dir.path().join(format!("1000000{:03}-old", n))).unwrap();
+        }
+
+        let session = start_session(dir.path()).unwrap();
+        let sessions = sessions(dir.path()).unwrap();
+        assert_eq!(sessions.len(), KEEP_SESSIONS);
+        assert!(!dir.path().join("1000000000-old").exists());
+        assert_eq!(sessions.last(), Some(&session));
+
+        let output_log = session.join(OUTPUT_LOG);
+        let mode = fs::metadata(&output_log).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+    }
+
+    #[test]
+    fn tee_copies_output_to_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join(BUILD_LOG);
+        let status = tee(
+            Command::new("sh").args(["-c", "echo out; echo err >&2; exit 3"]),
+            &log,
+        )
+        .unwrap();
+
+        assert_eq!(status.code(), Some(3));
+        let contents = fs::read_to_string(&log).unwrap();
+        assert!(contents.contains("out\n"));
+        assert!(contents.contains("err\n"));
+    }
+}
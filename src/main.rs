@@ -1,19 +1,39 @@
 use std::ffi::OsStr;
+use std::fs;
+use std::io::IsTerminal;
 use std::path::PathBuf;
 use std::process::Command as ProcessCommand;
 
-use clap::{CommandFactory, Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::engine::{ArgValueCompleter, CompletionCandidate};
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{Result, bail};
+use serde::Serialize;
+use serde_json::json;
 use tracing_subscriber::EnvFilter;
 
-use contenant::{Contenant, StackedConfig, bridge};
+use contenant::usage::Ledger;
+use contenant::vm::Vm;
+use contenant::{
+    Contenant, PrintBackend, RunOptions, StackedConfig, bridge, config_edit, devcontainer, history,
+    logs, provenance, redact, remote_config, schema,
+};
 
 #[derive(Parser)]
 #[command(version, about)]
 struct Cli {
     #[command(subcommand)]
     command: Option<Command>,
+
+    /// Output format for `queue list`, `status`, `ps`, `images`, `history`,
+    /// and `config show`
+    #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Text,
+    Json,
 }
 
 #[derive(Subcommand)]
@@ -23,12 +43,413 @@ enum Command {
         /// Project directory to mount (defaults to current directory)
         path: Option<PathBuf>,
 
+        /// Print the docker commands, mounts, env, and allowed IPs instead of running
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Disable the egress firewall for this run only
+        #[arg(long)]
+        unrestricted: bool,
+
+        /// Skip the confirmation prompt for --unrestricted
+        #[arg(long, requires = "unrestricted")]
+        yes: bool,
+
+        /// Re-resolve allowed domains and refetch IP ranges, ignoring the cache
+        #[arg(long)]
+        refresh_ips: bool,
+
+        /// Publish a container port on the host, e.g. 3000 or 8080:3000
+        #[arg(short, long, value_name = "[HOST_IP:][HOST_PORT:]PORT")]
+        publish: Vec<String>,
+
+        /// Override a config value for this run, e.g. bridge.port=9001
+        #[arg(long = "set", value_name = "KEY=VALUE")]
+        set: Vec<String>,
+
+        /// Set an env var for this run, or pass the host's through with KEY
+        #[arg(short, long, value_name = "KEY[=VALUE]")]
+        env: Vec<String>,
+
+        /// Mount a host path for this run, read-only unless it ends in :rw
+        #[arg(long, value_name = "SOURCE[:TARGET][:ro|:rw]")]
+        mount: Vec<String>,
+
+        /// Allow a domain through the firewall for this run
+        #[arg(long, value_name = "DOMAIN")]
+        allow_domain: Vec<String>,
+
+        /// Start the agent in this directory under /workspace
+        #[arg(long, value_name = "DIR")]
+        workdir: Option<String>,
+
+        /// Build and run images for this platform, e.g. linux/amd64
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Refuse to run if a config file has unknown keys
+        #[arg(long)]
+        strict: bool,
+
+        /// Run an image.ref base image without verifying its signature
+        #[arg(long)]
+        insecure: bool,
+
+        /// Start the agent in the background and print its session id
+        #[arg(short, long)]
+        detach: bool,
+
         /// Arguments to pass through to claude
         #[arg(last = true, add = ArgValueCompleter::new(complete_claude_args))]
         claude_args: Vec<String>,
     },
+    /// Check the firewall by connecting to allowed and blocked destinations
+    NetTest {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+
+        /// Destinations expected to be blocked (defaults to a few public sites)
+        #[arg(long)]
+        blocked: Vec<String>,
+    },
     /// Start the host command bridge server
     Bridge,
+    /// Show the container engine and the state of its VM
+    Status {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Report on past sessions
+    Report {
+        /// Token usage and estimated cost per project
+        #[arg(long)]
+        costs: bool,
+
+        /// Include every project with recorded state
+        #[arg(long)]
+        all: bool,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Rebuild the base, user, and project images
+    Rebuild {
+        /// Rebuild every layer, rerunning the Claude installer and apt
+        #[arg(long)]
+        no_cache: bool,
+
+        /// Pull a newer Debian image to build on, or image.ref again
+        #[arg(long)]
+        pull: bool,
+
+        /// Use an image.ref base image without verifying its signature
+        #[arg(long)]
+        insecure: bool,
+
+        /// Build images for this platform, e.g. linux/amd64
+        #[arg(long)]
+        platform: Option<String>,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Check the project's setup; without flags, run every check
+    Doctor {
+        /// Only score the effective config against a hardening checklist
+        #[arg(long)]
+        security: bool,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Check an image's cosign signature against image.cosign_key
+    VerifyImage {
+        /// Image reference, e.g. ghcr.io/kejadlen/contenant:1.0
+        reference: String,
+
+        /// Public key to verify with instead of image.cosign_key
+        #[arg(long)]
+        key: Option<PathBuf>,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// List every project's running sessions
+    Ps,
+    /// Connect the terminal to a running session
+    Attach {
+        /// Session id from `run --detach` or `ps` (defaults to the project's
+        /// newest session)
+        session: Option<String>,
+
+        /// Keys that detach, leaving the session running
+        #[arg(long, default_value = contenant::DEFAULT_DETACH_KEYS)]
+        detach_keys: String,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Stop the project's running sessions
+    Stop {
+        /// Stop every project's sessions
+        #[arg(long)]
+        all: bool,
+
+        /// Seconds to wait after SIGTERM before killing
+        #[arg(short, long, default_value_t = contenant::DEFAULT_STOP_GRACE_SECS)]
+        time: u64,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// List the images contenant built and the projects they belong to
+    Images {
+        #[command(subcommand)]
+        command: Option<ImagesCommand>,
+    },
+    /// List state left behind by projects that no longer exist
+    Gc {
+        /// Remove it
+        #[arg(long)]
+        yes: bool,
+    },
+    /// List past runs across projects, newest last
+    History {
+        /// Only runs in this project directory
+        #[arg(long)]
+        path: Option<PathBuf>,
+
+        /// Only runs started within this long, e.g. 12h or 7d
+        #[arg(long, value_name = "DURATION")]
+        since: Option<String>,
+
+        /// Only runs that exited non-zero
+        #[arg(long)]
+        failed: bool,
+
+        /// How many runs to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Move Claude's auth, settings, and sessions between machines
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
+    /// Inspect synced workspaces (workspace.mode: sync)
+    Sync {
+        #[command(subcommand)]
+        command: SyncCommand,
+    },
+    /// Show what a session changed in the workspace
+    Diff {
+        /// Session id (defaults to the latest)
+        session: Option<String>,
+
+        /// Summarize changes per file
+        #[arg(long, conflicts_with = "patch")]
+        stat: bool,
+
+        /// Show the changes as a patch
+        #[arg(long)]
+        patch: bool,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Archive the workspace, or list the archives
+    Snapshot {
+        /// List the project's snapshots instead
+        #[arg(long)]
+        list: bool,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Roll the workspace back to a snapshot
+    Restore {
+        /// Snapshot id, from `snapshot` or `snapshot --list`
+        id: String,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Print the output of the project's latest session
+    Logs {
+        /// Keep printing output as it's written
+        #[arg(short, long)]
+        follow: bool,
+
+        /// Print the image build output instead of the container's
+        #[arg(long)]
+        build: bool,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Send a message to the project's sessions through the bridge
+    Event {
+        /// Message for the agent, e.g. "CI finished: 2 tests failed"
+        message: String,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Queue headless tasks and process them sequentially
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommand,
+    },
+    /// Write a project config in .contenant/
+    Init {
+        /// Translate .devcontainer/devcontainer.json into config and a Dockerfile
+        #[arg(long)]
+        from_devcontainer: bool,
+
+        /// Overwrite existing files
+        #[arg(long)]
+        force: bool,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Check, show, or edit the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Check the user and project config strictly, reporting unknown keys and
+    /// invalid values with their lines
+    Validate {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Print a JSON Schema for config.yml, for editor completion and checks
+    Schema,
+    /// Clone or update the `remote_config` repo
+    Sync,
+    /// Print the merged config, with the layer each value came from
+    Show {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Print a key's effective value, or its value in one config file
+    Get {
+        /// Dotted key, like `bridge.port`
+        key: String,
+        /// Read the user config file
+        #[arg(long, conflicts_with = "project")]
+        user: bool,
+        /// Read the project config file
+        #[arg(long)]
+        project: bool,
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Set a key in the user config file, keeping its comments
+    Set {
+        /// Dotted key, like `bridge.port`
+        key: String,
+        /// YAML value, like `9000`, `true`, or `[a, b]`
+        value: String,
+        /// Write the project config file instead
+        #[arg(long)]
+        project: bool,
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Open the user config file in $VISUAL or $EDITOR, then check it
+    Edit {
+        /// Edit the project config file instead
+        #[arg(long)]
+        project: bool,
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ImagesCommand {
+    /// Remove images whose projects no longer exist, and ones newer builds
+    /// replaced
+    Prune,
+}
+
+#[derive(Subcommand)]
+enum SyncCommand {
+    /// Show the project's running syncs and their conflicts
+    Status {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum StateCommand {
+    /// Write Claude's state, known_hosts, and per-project sessions to a
+    /// tarball
+    Export {
+        /// Archive to write, e.g. contenant-state.tar.gz
+        file: PathBuf,
+    },
+    /// Unpack a tarball from `state export`
+    Import {
+        /// Archive to read
+        file: PathBuf,
+
+        /// Replace Claude state that's already here
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueueCommand {
+    /// Add a prompt to the project's queue
+    Add {
+        /// Prompt passed to `claude -p`
+        prompt: String,
+
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// List queued and finished tasks
+    List {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
+    /// Run queued tasks one at a time until the queue is empty
+    Work {
+        /// Project directory (defaults to current directory)
+        #[arg(long)]
+        path: Option<PathBuf>,
+    },
 }
 
 /// Output of `claude --help`, used as fallback when claude is not installed.
@@ -78,36 +499,789 @@ fn parse_help_flags(help: &str, prefix: &str) -> Vec<CompletionCandidate> {
     candidates
 }
 
-fn main() -> Result<std::process::ExitCode> {
+fn project_dir(path: Option<PathBuf>) -> Result<PathBuf> {
+    match path {
+        Some(p) => Ok(p),
+        None => Ok(std::env::current_dir()?),
+    }
+}
+
+/// Warn about an unrestricted run and, unless `yes`, ask for confirmation.
+fn confirm_unrestricted(yes: bool) -> Result<()> {
+    eprintln!("WARNING: --unrestricted disables the egress firewall for this session.");
+    eprintln!("WARNING: The agent will be able to reach any host on the network.");
+    if yes {
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        bail!("Refusing --unrestricted without a terminal to confirm; pass --yes");
+    }
+    eprint!("Continue? [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    if !matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes") {
+        bail!("Aborted");
+    }
+    Ok(())
+}
+
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(report) => {
+            // Error context can quote a command line or a response
+            eprintln!("Error: {}", redact::redact(&format!("{:?}", report)));
+            std::process::ExitCode::FAILURE
+        }
+    }
+}
+
+fn run() -> Result<std::process::ExitCode> {
     color_eyre::install()?;
 
     clap_complete::CompleteEnv::with_factory(Cli::command).complete();
 
+    redact::register_env(std::env::vars());
     tracing_subscriber::fmt()
         .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(redact::Stderr)
         .init();
 
     let cli = Cli::parse();
+    let json = cli.format == Format::Json;
 
     match cli.command.unwrap_or(Command::Run {
         path: None,
+        dry_run: false,
+        unrestricted: false,
+        yes: false,
+        refresh_ips: false,
+        publish: vec![],
+        set: vec![],
+        env: vec![],
+        mount: vec![],
+        allow_domain: vec![],
+        workdir: None,
+        platform: None,
+        strict: false,
+        insecure: false,
+        detach: false,
         claude_args: vec![],
     }) {
-        Command::Run { path, claude_args } => {
-            let project_dir = match path {
-                Some(p) => p,
-                None => std::env::current_dir()?,
+        Command::Run {
+            path,
+            dry_run,
+            unrestricted,
+            yes,
+            refresh_ips,
+            publish,
+            mut set,
+            env,
+            mount,
+            allow_domain,
+            workdir,
+            platform,
+            strict,
+            insecure,
+            detach,
+            claude_args,
+        } => {
+            let project_dir = project_dir(path)?;
+            if strict {
+                set.push("strict=true".to_string());
+            }
+            if let Some(dir) = workdir {
+                set.push(format!("workspace.workdir={}", dir));
+            }
+            if let Some(platform) = platform {
+                set.push(format!("image.platform={}", platform));
+            }
+            let overrides = if set.is_empty()
+                && env.is_empty()
+                && mount.is_empty()
+                && allow_domain.is_empty()
+            {
+                None
+            } else {
+                let mut overrides = if set.is_empty() {
+                    Default::default()
+                } else {
+                    config_edit::overrides(&set)?
+                };
+                config_edit::add_run_flags(&mut overrides, &env, &mount, &allow_domain)?;
+                Some(overrides)
+            };
+            if unrestricted {
+                confirm_unrestricted(yes)?;
+            }
+            let options = RunOptions {
+                unrestricted,
+                refresh_ips,
+                publish,
+                insecure,
+                ..Default::default()
             };
-            let exit_code = Contenant::new(&project_dir)?.run(&claude_args)?;
+            let contenant = if dry_run {
+                let mut contenant = Contenant::with_backend(
+                    Box::new(PrintBackend) as Box<dyn contenant::Backend>,
+                    &project_dir,
+                )?;
+                if let Some(overrides) = overrides {
+                    contenant = contenant.with_overrides(overrides)?;
+                }
+                contenant
+            } else {
+                Contenant::from_config_with(&project_dir, overrides)?
+            };
+            if detach {
+                if let Some(session) = contenant.run_detached(&claude_args, &options)? {
+                    println!("{}", session);
+                    eprintln!("contenant: follow it with `contenant logs --follow`");
+                }
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            let exit_code = contenant.run_with_options(&claude_args, &options)?;
             Ok(std::process::ExitCode::from(exit_code as u8))
         }
+        Command::NetTest { path, blocked } => {
+            let outcomes = Contenant::from_config(&project_dir(path)?)?.net_test(&blocked)?;
+            contenant::net_test::print_matrix(&outcomes);
+            if outcomes.iter().all(|o| o.passed()) {
+                Ok(std::process::ExitCode::SUCCESS)
+            } else {
+                Ok(std::process::ExitCode::FAILURE)
+            }
+        }
         Command::Bridge => {
             let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
             let config = StackedConfig::load(&xdg_dirs, None)?;
             let bridge = config.bridge();
             let rt = tokio::runtime::Runtime::new()?;
-            rt.block_on(bridge::serve(bridge.port, bridge.triggers))?;
+            let tokens_dir = xdg_dirs.create_state_directory(bridge::TOKENS_DIR)?;
+            let key_path = xdg_dirs.place_state_file(bridge::REGISTRATION_KEY_FILE)?;
+            let listen = bridge::Listen::from_config(&bridge, &xdg_dirs)?;
+            rt.block_on(bridge::serve(listen, bridge, tokens_dir, key_path))?;
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Status { path } => {
+            let contenant = Contenant::from_config(&project_dir(path)?)?;
+            if json {
+                let vm = Vm::detect().map(|vm| {
+                    let running = vm.is_running();
+                    json!({ "kind": vm.kind, "name": vm.name, "socket": vm.socket, "running": running })
+                });
+                print_json(&json!({ "engine": contenant.engine(), "vm": vm }))?;
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            println!("engine: {}", contenant.engine());
+            match Vm::detect() {
+                Some(vm) => {
+                    let state = if vm.is_running() {
+                        "running"
+                    } else {
+                        "stopped"
+                    };
+                    println!("vm: {} {} ({})", vm.kind, vm.name, state);
+                    println!("socket: {}", vm.socket.display());
+                }
+                None => println!("vm: none"),
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Report { costs, all, path } => {
+            if !costs {
+                bail!("Nothing to report; pass --costs");
+            }
+
+            let ledgers = if all {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                contenant::project_states(&xdg_dirs)?
+                    .into_iter()
+                    .map(|state| {
+                        let name = state.path.map_or(state.id, |p| p.display().to_string());
+                        (name, Ledger::new(&state.dir.join("usage.jsonl")))
+                    })
+                    .collect()
+            } else {
+                let project_dir = std::fs::canonicalize(project_dir(path)?)?;
+                let ledger = Contenant::from_config(&project_dir)?.usage_ledger()?;
+                vec![(project_dir.display().to_string(), ledger)]
+            };
+
+            println!("PROJECT\tSESSIONS\tINPUT\tOUTPUT\tCACHE WRITE\tCACHE READ\tEST. COST");
+            for (name, ledger) in ledgers {
+                let (sessions, usage) = ledger.totals()?;
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}\t${:.2}",
+                    name,
+                    sessions,
+                    usage.input_tokens,
+                    usage.output_tokens,
+                    usage.cache_creation_input_tokens,
+                    usage.cache_read_input_tokens,
+                    usage.cost_usd
+                );
+            }
+            eprintln!(
+                "Costs are estimates from list prices built into contenant, recorded when each \
+                 session ended; models other than Opus, Sonnet, and Haiku count as $0, and \
+                 plans and discounts aren't reflected."
+            );
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Rebuild {
+            no_cache,
+            pull,
+            insecure,
+            platform,
+            path,
+        } => {
+            let overrides = platform
+                .map(|platform| config_edit::overrides(&[format!("image.platform={}", platform)]))
+                .transpose()?;
+            let image = Contenant::from_config_with(&project_dir(path)?, overrides)?
+                .rebuild(no_cache, pull, insecure)?;
+            println!("Built {}", image);
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        // The security audit is the only check so far, so it runs either way
+        Command::Doctor { security: _, path } => {
+            let contenant = Contenant::from_config(&project_dir(path)?)?;
+            let report = contenant.security_audit()?;
+            let failed = report
+                .checks
+                .iter()
+                .any(|check| check.status == contenant::audit::Status::Fail);
+            if json {
+                print_json(&report)?;
+            } else {
+                println!("Security: {}/100", report.score);
+                let width = report
+                    .checks
+                    .iter()
+                    .map(|c| c.name.len())
+                    .max()
+                    .unwrap_or(0);
+                for check in &report.checks {
+                    println!(
+                        "  {:<4}  {:<width$}  {}",
+                        check.status.to_string(),
+                        check.name,
+                        check.detail
+                    );
+                    if let Some(hint) = &check.hint {
+                        println!("        {:<width$}  {}", "", hint);
+                    }
+                }
+            }
+            Ok(if failed {
+                std::process::ExitCode::FAILURE
+            } else {
+                std::process::ExitCode::SUCCESS
+            })
+        }
+        Command::VerifyImage {
+            reference,
+            key,
+            path,
+        } => {
+            let digest = Contenant::from_config(&project_dir(path)?)?
+                .verify_image(&reference, key.as_deref())?;
+            println!("Verified {} as {}", reference, digest);
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Ps => {
+            let contenant = Contenant::from_config(&std::env::current_dir()?)?;
+            if json {
+                #[derive(Serialize)]
+                struct Row {
+                    #[serde(flatten)]
+                    session: contenant::ps::Session,
+                    path: Option<PathBuf>,
+                }
+                let rows: Vec<_> = contenant
+                    .ps()?
+                    .into_iter()
+                    .map(|(session, path)| Row { session, path })
+                    .collect();
+                print_json(&rows)?;
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            println!("CONTAINER\tSESSION\tPROJECT\tAGENT\tUPTIME\tEXECS");
+            for (session, path) in contenant.ps()? {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    session.id,
+                    session.session.as_deref().unwrap_or("-"),
+                    path.map_or(session.project, |p| p.display().to_string()),
+                    session.agent.as_deref().unwrap_or("-"),
+                    session.uptime,
+                    session.execs
+                );
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Attach {
+            session,
+            detach_keys,
+            path,
+        } => {
+            let exit_code = Contenant::from_config(&project_dir(path)?)?
+                .attach(session.as_deref(), &detach_keys)?;
+            Ok(std::process::ExitCode::from(exit_code as u8))
+        }
+        Command::Stop { all, time, path } => {
+            let stopped = Contenant::from_config(&project_dir(path)?)?.stop(all, time)?;
+            println!(
+                "Stopped {} session{}",
+                stopped,
+                if stopped == 1 { "" } else { "s" }
+            );
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Images { command } => {
+            let contenant = Contenant::from_config(&std::env::current_dir()?)?;
+            match command {
+                None if json => {
+                    #[derive(Serialize)]
+                    struct Row {
+                        #[serde(flatten)]
+                        image: contenant::images::Image,
+                        owner: contenant::images::Owner,
+                    }
+                    let rows: Vec<_> = contenant
+                        .images()?
+                        .into_iter()
+                        .map(|(image, owner)| Row { image, owner })
+                        .collect();
+                    print_json(&rows)?;
+                }
+                None => {
+                    println!("IMAGE\tID\tSIZE\tCREATED\tPROJECT");
+                    for (image, owner) in contenant.images()? {
+                        println!(
+                            "{}\t{}\t{}\t{}\t{}",
+                            image.tag.as_deref().unwrap_or("<none>"),
+                            image.id,
+                            image.size,
+                            image.created,
+                            owner
+                        );
+                    }
+                }
+                Some(ImagesCommand::Prune) => {
+                    for reference in contenant.prune_images()? {
+                        println!("Removed {}", reference);
+                    }
+                }
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::History {
+            path,
+            since,
+            failed,
+            limit,
+        } => {
+            let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+            let Some(state_home) = xdg_dirs.get_state_home() else {
+                bail!("Couldn't find the state directory");
+            };
+            let filter = history::Filter {
+                path: path.map(fs::canonicalize).transpose()?,
+                since: since
+                    .map(|since| history::parse_duration(&since))
+                    .transpose()?
+                    .map(|secs| contenant::queue::now().saturating_sub(secs)),
+                failed,
+                limit: Some(limit),
+            };
+            let entries = history::load(&state_home.join(history::HISTORY_FILE), &filter)?;
+            if json {
+                print_json(&entries)?;
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            println!("STARTED\tDURATION\tEXIT\tPROJECT\tIMAGE\tARGS");
+            let now = contenant::queue::now();
+            for entry in entries {
+                println!(
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    contenant::images::age(now.saturating_sub(entry.started_at)),
+                    entry.finished_at.map_or("-".to_string(), |end| format!(
+                        "{}s",
+                        end.saturating_sub(entry.started_at)
+                    )),
+                    entry
+                        .exit_code
+                        .map_or("detached".to_string(), |code| code.to_string()),
+                    entry.path.display(),
+                    entry.image_id.as_deref().unwrap_or(&entry.image),
+                    entry.args.join(" ")
+                );
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::State { command } => {
+            let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+            let Some(state_home) = xdg_dirs.get_state_home() else {
+                bail!("Couldn't find the state directory");
+            };
+            match command {
+                StateCommand::Export { file } => {
+                    let parts = contenant::bundle::export(&state_home, &file)?;
+                    println!("Exported {} to {}", parts.join(", "), file.display());
+                }
+                StateCommand::Import { file, force } => {
+                    let entries = contenant::bundle::import(&state_home, &file, force)?;
+                    println!("Imported {} entries into {}", entries, state_home.display());
+                }
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Sync {
+            command: SyncCommand::Status { path },
+        } => {
+            let contenant = Contenant::from_config(&project_dir(path)?)?;
+            let sessions = contenant::sync::project_sessions(&contenant.project_id())?;
+            if json {
+                print_json(&sessions)?;
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            if sessions.is_empty() {
+                println!("No running syncs for this project");
+            }
+            for session in sessions {
+                println!(
+                    "{}\t{}\t{} conflict{}",
+                    session.session().unwrap_or(&session.name),
+                    session.status,
+                    session.conflicts.len(),
+                    if session.conflicts.len() == 1 {
+                        ""
+                    } else {
+                        "s"
+                    }
+                );
+                for conflict in &session.conflicts {
+                    println!("  {}", conflict.root);
+                }
+                if let Some(err) = &session.last_error {
+                    println!("  error: {}", err);
+                }
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Gc { yes } => {
+            let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+            if yes {
+                let revoked =
+                    Contenant::from_config(&std::env::current_dir()?)?.revoke_detached()?;
+                if revoked > 0 {
+                    println!(
+                        "Revoked credentials of {} exited detached session{}",
+                        revoked,
+                        if revoked == 1 { "" } else { "s" }
+                    );
+                }
+            }
+            let orphans = contenant::collect_garbage(&xdg_dirs, yes)?;
+            for state in &orphans {
+                let path = state
+                    .path
+                    .as_ref()
+                    .map_or("(unknown project)".to_string(), |p| p.display().to_string());
+                println!("{}\t{}", state.dir.display(), path);
+            }
+            if orphans.is_empty() {
+                println!("No orphaned state");
+            } else if yes {
+                println!(
+                    "Removed {} state director{}",
+                    orphans.len(),
+                    if orphans.len() == 1 { "y" } else { "ies" }
+                );
+            } else {
+                println!("Pass --yes to remove these");
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Diff {
+            session,
+            stat,
+            patch,
+            path,
+        } => {
+            let contenant = Contenant::from_config(&project_dir(path)?)?;
+            let session = session.as_deref();
+            if stat {
+                contenant.workspace_diff(session, &["--stat"])?;
+            } else if patch {
+                contenant.workspace_diff(session, &[])?;
+            } else {
+                for change in contenant.workspace_changes(session)? {
+                    println!("{}\t{}", change.status, change.path);
+                }
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Snapshot { list, path } => {
+            let contenant = Contenant::from_config(&project_dir(path)?)?;
+            if list {
+                for snapshot in contenant.snapshots()? {
+                    println!(
+                        "{}\t{}",
+                        snapshot.id,
+                        contenant::images::size(snapshot.size)
+                    );
+                }
+            } else {
+                println!("{}", contenant.snapshot()?);
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Restore { id, path } => {
+            let (backup, deleted) = Contenant::from_config(&project_dir(path)?)?.restore(&id)?;
+            println!(
+                "Restored {}, deleting {} file{}",
+                id,
+                deleted,
+                if deleted == 1 { "" } else { "s" }
+            );
+            println!("The workspace as it was is snapshot {}", backup);
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Logs {
+            follow,
+            build,
+            path,
+        } => {
+            let contenant = Contenant::from_config(&project_dir(path)?)?;
+            let Some(session) = contenant.session_logs()?.pop() else {
+                bail!("No session logs for this project yet");
+            };
+            let file = if build {
+                logs::BUILD_LOG
+            } else {
+                logs::OUTPUT_LOG
+            };
+            logs::print(&session.join(file), follow)?;
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Event { message, path } => {
+            Contenant::from_config(&project_dir(path)?)?.push_event(&message)?;
             Ok(std::process::ExitCode::SUCCESS)
         }
+        Command::Queue { command } => {
+            match command {
+                QueueCommand::Add { prompt, path } => {
+                    let contenant = Contenant::from_config(&project_dir(path)?)?;
+                    let task = contenant.queue()?.add(&prompt)?;
+                    println!("Queued task {}", task.id);
+                }
+                QueueCommand::List { path } => {
+                    let contenant = Contenant::from_config(&project_dir(path)?)?;
+                    let tasks = contenant.queue()?.tasks()?;
+                    if json {
+                        print_json(&tasks)?;
+                        return Ok(std::process::ExitCode::SUCCESS);
+                    }
+                    for task in tasks {
+                        let exit_code = task.exit_code.map(|c| c.to_string());
+                        println!(
+                            "{}\t{}\t{}\t{}",
+                            task.id,
+                            task.status,
+                            exit_code.as_deref().unwrap_or("-"),
+                            task.prompt
+                        );
+                    }
+                }
+                QueueCommand::Work { path } => {
+                    Contenant::from_config(&project_dir(path)?)?.work_queue()?;
+                }
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Init {
+            from_devcontainer,
+            force,
+            path,
+        } => {
+            if !from_devcontainer {
+                bail!("Nothing to initialize from; pass --from-devcontainer");
+            }
+            let project_dir = project_dir(path)?;
+            let Some(source) = devcontainer::find(&project_dir) else {
+                bail!("No devcontainer.json in {}", project_dir.display());
+            };
+            let import = devcontainer::import(&devcontainer::load(&source)?, "..")?;
+
+            let dir = project_dir.join(".contenant");
+            let files: Vec<_> = [
+                (dir.join("config.yml"), Some(import.config)),
+                (dir.join("Dockerfile"), import.dockerfile),
+            ]
+            .into_iter()
+            .filter_map(|(file, contents)| Some((file, contents?)))
+            .collect();
+            if !force && let Some((file, _)) = files.iter().find(|(file, _)| file.exists()) {
+                bail!(
+                    "{} already exists; pass --force to replace it",
+                    file.display()
+                );
+            }
+            fs::create_dir_all(&dir)?;
+            for (file, contents) in files {
+                fs::write(&file, contents)?;
+                println!("Wrote {}", file.display());
+            }
+            for skipped in import.skipped {
+                eprintln!("Skipped {}", skipped);
+            }
+            Ok(std::process::ExitCode::SUCCESS)
+        }
+        Command::Config { command } => match command {
+            ConfigCommand::Validate { path } => {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                let project_dir = project_dir(path)?;
+                let files = StackedConfig::files(&xdg_dirs, Some(&project_dir))?;
+                if files.is_empty() {
+                    eprintln!("No config files found");
+                }
+                let mut valid = true;
+                for (_, file) in files {
+                    let problems = schema::validate_file(&file)?;
+                    if problems.is_empty() {
+                        println!("{}: ok", file.display());
+                    }
+                    for problem in &problems {
+                        println!("{}", problem);
+                    }
+                    valid &= problems.is_empty();
+                }
+                if valid {
+                    Ok(std::process::ExitCode::SUCCESS)
+                } else {
+                    Ok(std::process::ExitCode::FAILURE)
+                }
+            }
+            ConfigCommand::Show { path } => {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                let config = StackedConfig::load(&xdg_dirs, Some(&project_dir(path)?))?;
+                let settings = provenance::settings(&config);
+                if json {
+                    print_json(&settings)?;
+                } else {
+                    print!("{}", provenance::render(&settings));
+                }
+                Ok(std::process::ExitCode::SUCCESS)
+            }
+            ConfigCommand::Get {
+                key,
+                user,
+                project,
+                path,
+            } => {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                let project_dir = project_dir(path)?;
+                if user || project {
+                    let file = config_edit::config_path(&xdg_dirs, &project_dir, project)?;
+                    let text = fs::read_to_string(&file).unwrap_or_default();
+                    match config_edit::get(&text, &key)? {
+                        Some(value) => println!("{}", value),
+                        None => return Ok(std::process::ExitCode::FAILURE),
+                    }
+                } else {
+                    let config = StackedConfig::load(&xdg_dirs, Some(&project_dir))?;
+                    let prefix = format!("{}.", key);
+                    let settings: Vec<_> = provenance::settings(&config)
+                        .into_iter()
+                        .filter(|s| s.key == key || s.key.starts_with(&prefix))
+                        .collect();
+                    if settings.is_empty() {
+                        return Ok(std::process::ExitCode::FAILURE);
+                    }
+                    print!("{}", provenance::render(&settings));
+                }
+                Ok(std::process::ExitCode::SUCCESS)
+            }
+            ConfigCommand::Set {
+                key,
+                value,
+                project,
+                path,
+            } => {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                let file = config_edit::config_path(&xdg_dirs, &project_dir(path)?, project)?;
+                let text = fs::read_to_string(&file).unwrap_or_default();
+                let text = config_edit::set(&text, &key, &value)?;
+                let problems = schema::validate(&file, &text);
+                if !problems.is_empty() {
+                    for problem in &problems {
+                        eprintln!("{}", problem);
+                    }
+                    bail!("Not saving {}", file.display());
+                }
+                fs::create_dir_all(file.parent().unwrap())?;
+                fs::write(&file, text)?;
+                Ok(std::process::ExitCode::SUCCESS)
+            }
+            ConfigCommand::Edit { project, path } => {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                let file = config_edit::config_path(&xdg_dirs, &project_dir(path)?, project)?;
+                if !file.exists() {
+                    fs::create_dir_all(file.parent().unwrap())?;
+                    fs::write(&file, "")?;
+                }
+                let editor = std::env::var("VISUAL")
+                    .or_else(|_| std::env::var("EDITOR"))
+                    .unwrap_or_else(|_| "vi".to_string());
+                // Through the shell, since $EDITOR may carry arguments
+                let status = ProcessCommand::new("sh")
+                    .arg("-c")
+                    .arg(format!("{} \"$1\"", editor))
+                    .arg("sh")
+                    .arg(&file)
+                    .status()?;
+                if !status.success() {
+                    bail!("{} exited with {}", editor, status);
+                }
+                let problems = schema::validate_file(&file)?;
+                for problem in &problems {
+                    println!("{}", problem);
+                }
+                if problems.is_empty() {
+                    Ok(std::process::ExitCode::SUCCESS)
+                } else {
+                    Ok(std::process::ExitCode::FAILURE)
+                }
+            }
+            ConfigCommand::Sync => {
+                let xdg_dirs = xdg::BaseDirectories::with_prefix("contenant");
+                let config = StackedConfig::load(&xdg_dirs, None)?;
+                let Some(url) = config.remote_config() else {
+                    bail!("No remote_config set in the system or user config");
+                };
+                let dir = remote_config::sync(&xdg_dirs, url)?;
+                println!("{} at {}", url, remote_config::head(&dir)?);
+                if !dir.join("config.yml").exists() {
+                    eprintln!("contenant: {} has no config.yml", url);
+                }
+                Ok(std::process::ExitCode::SUCCESS)
+            }
+            ConfigCommand::Schema => {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&schema::config_schema())?
+                );
+                Ok(std::process::ExitCode::SUCCESS)
+            }
+        },
     }
 }
+
+fn print_json(value: &impl Serialize) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
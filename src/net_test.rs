@@ -0,0 +1,165 @@
+//! `contenant net-test`: connect to allowed and known-blocked destinations
+//! from inside the container, to catch stale IPs, missing ranges, or broken
+//! firewall rules before a real session.
+//!
+//! The entrypoint reads the targets from a mounted file, tries a TCP
+//! connection to port 443 on each as the agent user, and writes
+//! `<target> ok|blocked` lines to the mounted log directory.
+
+use std::collections::HashMap;
+
+use crate::proxy::host_allowed;
+
+/// Results file in the log directory shared with the container.
+pub const RESULTS_FILE: &str = "net-test";
+
+/// Probed as blocked when no `--blocked` destinations are given.
+pub const DEFAULT_BLOCKED: &[&str] = &["example.com", "neverssl.com"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Probe {
+    pub target: String,
+    pub expect_allowed: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Outcome {
+    pub probe: Probe,
+    /// Whether the connection succeeded; `None` if the container reported
+    /// nothing for the target.
+    pub reachable: Option<bool>,
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        self.reachable == Some(self.probe.expect_allowed)
+    }
+}
+
+/// Allowed domains and IPs are expected to connect; denied entries and
+/// `blocked` (or the defaults, unless allowed) are expected not to. CIDRs
+/// are skipped, and wildcards are probed at their base domain.
+pub fn probes(allowed: &[String], denied: &[String], blocked: &[String]) -> Vec<Probe> {
+    let target = |entry: &String| {
+        let entry = entry.strip_prefix("*.").unwrap_or(entry);
+        (!entry.contains('/') && !entry.contains('*')).then(|| entry.to_string())
+    };
+
+    let mut probes: Vec<Probe> = vec![];
+    let mut add = |target: String, expect_allowed| {
+        if !probes.iter().any(|p| p.target == target) {
+            probes.push(Probe {
+                target,
+                expect_allowed,
+            });
+        }
+    };
+    // Denies win over allows, so add them first
+    for entry in denied.iter().filter_map(target) {
+        add(entry, false);
+    }
+    for entry in allowed.iter().filter_map(target) {
+        add(entry, true);
+    }
+    if blocked.is_empty() {
+        for entry in DEFAULT_BLOCKED {
+            if !host_allowed(allowed, entry) {
+                add(entry.to_string(), false);
+            }
+        }
+    } else {
+        for entry in blocked {
+            add(entry.clone(), false);
+        }
+    }
+    probes
+}
+
+/// Match the container's results to the probes, in probe order.
+pub fn outcomes(probes: Vec<Probe>, results: &str) -> Vec<Outcome> {
+    let reachable: HashMap<_, _> = results
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .map(|(target, result)| (target, result.trim() == "ok"))
+        .collect();
+    probes
+        .into_iter()
+        .map(|probe| Outcome {
+            reachable: reachable.get(probe.target.as_str()).copied(),
+            probe,
+        })
+        .collect()
+}
+
+pub fn print_matrix(outcomes: &[Outcome]) {
+    let label = |allowed| if allowed { "allowed" } else { "blocked" };
+    println!("RESULT\tEXPECTED\tACTUAL\tTARGET");
+    for outcome in outcomes {
+        println!(
+            "{}\t{}\t{}\t{}",
+            if outcome.passed() { "pass" } else { "FAIL" },
+            label(outcome.probe.expect_allowed),
+            outcome.reachable.map_or("no result", label),
+            outcome.probe.target
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn probes_allowed_denied_and_default_blocked() {
+        let allowed = strings(&["github.com", "*.npmjs.org", "10.0.0.0/8", "gist.github.com"]);
+        let denied = strings(&["gist.github.com"]);
+        let targets: Vec<_> = probes(&allowed, &denied, &[])
+            .into_iter()
+            .map(|p| (p.target, p.expect_allowed))
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                ("gist.github.com".to_string(), false),
+                ("github.com".to_string(), true),
+                ("npmjs.org".to_string(), true),
+                ("example.com".to_string(), false),
+                ("neverssl.com".to_string(), false),
+            ]
+        );
+
+        // Defaults are dropped when allowed, and replaced by --blocked
+        let allowed = strings(&["example.com"]);
+        assert_eq!(probes(&allowed, &[], &[]).len(), 2);
+        let blocked = strings(&["pastebin.com"]);
+        assert_eq!(probes(&[], &[], &blocked)[0].target, "pastebin.com");
+    }
+
+    #[test]
+    fn outcomes_compare_results_to_expectations() {
+        let probes = vec![
+            Probe {
+                target: "github.com".to_string(),
+                expect_allowed: true,
+            },
+            Probe {
+                target: "example.com".to_string(),
+                expect_allowed: false,
+            },
+            Probe {
+                target: "crates.io".to_string(),
+                expect_allowed: true,
+            },
+        ];
+        let outcomes = outcomes(probes, "github.com ok\nexample.com ok\n");
+
+        assert!(outcomes[0].passed());
+        assert!(!outcomes[1].passed());
+        assert_eq!(outcomes[2].reachable, None);
+        assert!(!outcomes[2].passed());
+    }
+}
@@ -0,0 +1,164 @@
+//! Summaries of connections the firewall blocked during a session.
+//!
+//! The entrypoint logs rejected packets and the agent's DNS queries with
+//! `tcpdump` on NFLOG groups into a directory mounted from the project's
+//! state dir. Blocked addresses are attributed to the names the agent looked
+//! up by resolving those names on the host.
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::Path;
+
+/// Directory the logs are mounted at inside the container.
+pub const CONTAINER_DIR: &str = "/var/log/contenant";
+
+const BLOCKED_LOG: &str = "blocked";
+const DNS_LOG: &str = "dns";
+
+/// A destination the agent tried to reach, by name when it can be
+/// attributed to a DNS lookup and by address otherwise.
+#[derive(Debug, PartialEq)]
+pub struct Blocked {
+    pub destination: String,
+    pub attempts: usize,
+    pub is_domain: bool,
+}
+
+/// Empty the logs before a session.
+pub fn reset(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(BLOCKED_LOG), "")?;
+    fs::write(dir.join(DNS_LOG), "")
+}
+
+/// Blocked destinations from the logs in `dir`, most attempts first.
+pub fn report(dir: &Path) -> Vec<Blocked> {
+    let read = |name| fs::read_to_string(dir.join(name)).unwrap_or_default();
+    summarize(&read(BLOCKED_LOG), &read(DNS_LOG), |name| {
+        (name, 443)
+            .to_socket_addrs()
+            .map(|addrs| addrs.map(|a| a.ip()).collect())
+            .unwrap_or_default()
+    })
+}
+
+fn summarize(
+    blocked_log: &str,
+    dns_log: &str,
+    resolve: impl Fn(&str) -> Vec<IpAddr>,
+) -> Vec<Blocked> {
+    let mut attempts: HashMap<IpAddr, usize> = HashMap::new();
+    for ip in blocked_log.lines().filter_map(parse_blocked) {
+        *attempts.entry(ip).or_default() += 1;
+    }
+
+    let mut queried: Vec<_> = dns_log.lines().filter_map(parse_query).collect();
+    queried.sort();
+    queried.dedup();
+
+    let mut by_destination: HashMap<(String, bool), usize> = HashMap::new();
+    for name in queried {
+        for ip in resolve(&name) {
+            if let Some(count) = attempts.remove(&ip) {
+                *by_destination.entry((name.clone(), true)).or_default() += count;
+            }
+        }
+    }
+    for (ip, count) in attempts {
+        *by_destination.entry((ip.to_string(), false)).or_default() += count;
+    }
+
+    let mut blocked: Vec<_> = by_destination
+        .into_iter()
+        .map(|((destination, is_domain), attempts)| Blocked {
+            destination,
+            attempts,
+            is_domain,
+        })
+        .collect();
+    blocked.sort_by(|a, b| {
+        b.attempts
+            .cmp(&a.attempts)
+            .then(a.destination.cmp(&b.destination))
+    });
+    blocked
+}
+
+/// Destination address of a tcpdump line like
+/// `IP 172.17.0.2.40000 > 104.16.1.34.443: Flags [S], ...`.
+fn parse_blocked(line: &str) -> Option<IpAddr> {
+    let (_, rest) = line.split_once(" > ")?;
+    let target = rest.split_whitespace().next()?.trim_end_matches(':');
+    // Address plus `.port`, unless the protocol has no ports (e.g. ICMP)
+    target
+        .parse()
+        .ok()
+        .or_else(|| target.rsplit_once('.')?.0.parse().ok())
+}
+
+/// Queried name from a tcpdump DNS line like
+/// `IP 172.17.0.2.43210 > 127.0.0.11.53: 4660+ A? registry.npmjs.org. (36)`.
+fn parse_query(line: &str) -> Option<String> {
+    let mut words = line.split_whitespace();
+    words.find(|w| w.ends_with('?'))?;
+    let name = words.next()?.trim_end_matches('.');
+    (!name.is_empty()).then(|| name.to_ascii_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tcpdump_lines() {
+        assert_eq!(
+            parse_blocked("12:00:00 IP 172.17.0.2.40000 > 104.16.1.34.443: Flags [S], seq 1"),
+            Some("104.16.1.34".parse().unwrap())
+        );
+        assert_eq!(
+            parse_blocked("IP6 fd00::2.40000 > 2606:4700::6810:84e5.443: Flags [S]"),
+            Some("2606:4700::6810:84e5".parse().unwrap())
+        );
+        assert_eq!(
+            parse_query(
+                "IP 172.17.0.2.43210 > 127.0.0.11.53: 4660+ AAAA? Registry.npmjs.org. (36)"
+            ),
+            Some("registry.npmjs.org".to_string())
+        );
+    }
+
+    #[test]
+    fn attributes_blocked_ips_to_queried_names() {
+        let blocked = "\
+IP 172.17.0.2.1 > 104.16.1.34.443: Flags [S]
+IP 172.17.0.2.2 > 104.16.1.34.443: Flags [S]
+IP 172.17.0.2.3 > 104.16.2.34.443: Flags [S]
+IP 172.17.0.2.4 > 9.9.9.9.443: Flags [S]
+";
+        let dns = "IP 172.17.0.2.5 > 127.0.0.11.53: 1+ A? registry.npmjs.org. (36)\n";
+        let resolve = |name: &str| match name {
+            "registry.npmjs.org" => vec![
+                "104.16.1.34".parse().unwrap(),
+                "104.16.2.34".parse().unwrap(),
+            ],
+            _ => vec![],
+        };
+
+        assert_eq!(
+            summarize(blocked, dns, resolve),
+            [
+                Blocked {
+                    destination: "registry.npmjs.org".to_string(),
+                    attempts: 3,
+                    is_domain: true,
+                },
+                Blocked {
+                    destination: "9.9.9.9".to_string(),
+                    attempts: 1,
+                    is_domain: false,
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,574 @@
+//! The effective config with the layer each value came from, for
+//! `contenant config show`.
+//!
+//! Each setting follows the same merge rules as the [`StackedConfig`]
+//! accessors: single values come from the last layer to set them, lists
+//! accumulate with the first layer to list an item credited, and map
+//! entries come from the last layer to set each key.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::config::{
+    ConfigLayer, ConfigSource, DEFAULT_BRIDGE_PORT, DEFAULT_IP_CACHE_TTL_SECS, DEFAULT_PROXY_PORT,
+    KeychainSync, MountType, SecretScan, StackedConfig, WorkspaceMode, resolve_path,
+};
+
+/// One effective value: a key in config file syntax, and a list item or
+/// map entry in its own row.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Setting {
+    pub key: String,
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+/// Every value that applies, in config file order. Unset options without a
+/// default are left out.
+pub fn settings(config: &StackedConfig) -> Vec<Setting> {
+    let mut s = Settings {
+        layers: config.layers(),
+        settings: vec![],
+    };
+
+    s.last("strict", |l| l.data.strict, Some(false));
+    if let Some((url, source)) = s.find(|l| match l.source {
+        ConfigSource::System | ConfigSource::User => l.data.remote_config.clone(),
+        _ => None,
+    }) {
+        s.push("remote_config", &url, source);
+    }
+    s.last("allow_docker", |l| l.data.allow_docker, Some(false));
+    if let Some((forbid, source)) = s.find(|l| match l.source {
+        ConfigSource::System | ConfigSource::Remote => l.data.forbid_docker,
+        _ => None,
+    }) {
+        s.push("forbid_docker", &forbid, source);
+    }
+    s.last("claude.version", |l| l.data.claude.version.clone(), None);
+    s.last(
+        "claude.keychain",
+        |l| l.data.claude.keychain,
+        Some(KeychainSync::Read),
+    );
+    s.last(
+        "claude.credential_store",
+        |l| l.data.claude.credential_store,
+        None,
+    );
+    s.last("aws.profile", |l| l.data.aws.profile.clone(), None);
+    s.last("ssh_agent.forward", |l| l.data.ssh_agent.forward, None);
+    s.last(
+        "ssh_agent.socket",
+        |l| {
+            let socket = l.data.ssh_agent.socket.as_deref()?;
+            Some(resolve_path(&l.config_dir, socket))
+        },
+        None,
+    );
+    s.last(
+        "ssh_agent.sign_commits",
+        |l| l.data.ssh_agent.sign_commits,
+        None,
+    );
+    s.last(
+        "ssh_agent.signing_key",
+        |l| l.data.ssh_agent.signing_key.clone(),
+        None,
+    );
+
+    let host = config.host();
+    s.each("mounts", |l| {
+        l.data
+            .mounts
+            .iter()
+            .filter(|m| m.when.matches(host))
+            .flat_map(|m| match m.kind {
+                MountType::Tmpfs => vec![format!("tmpfs:{}", m.target.as_deref().unwrap_or(""))],
+                MountType::Volume => vec![format!(
+                    "volume:{}:{}",
+                    m.source,
+                    m.target.as_deref().unwrap_or("")
+                )],
+                MountType::Bind => m.to_docker_volumes(&l.config_dir),
+            })
+            .collect()
+    });
+    s.entries("env", |l| {
+        l.data
+            .env
+            .iter()
+            .filter_map(|(k, v)| Some((k.clone(), v.resolve(host)?.to_string())))
+            .collect()
+    });
+
+    s.each("env_passthrough", |l| l.data.env_passthrough.clone());
+    s.each("env_files", |l| {
+        l.data
+            .env_files
+            .iter()
+            .map(|file| resolve_path(&l.config_dir, file))
+            .collect()
+    });
+    s.last("secret_scan", |l| l.data.secret_scan, Some(SecretScan::Off));
+    s.entries("secrets", |l| {
+        l.data
+            .secrets
+            .iter()
+            .map(|(name, secret)| {
+                let value = match secret.file {
+                    true => format!("{} (file)", secret.from),
+                    false => secret.from.clone(),
+                };
+                (name.clone(), value)
+            })
+            .collect()
+    });
+
+    // The last layer to list allowed domains, then the presets' domains
+    if let Some((domains, source)) = s.find(|l| l.data.allowed_domains.clone()) {
+        for domain in domains {
+            s.push("allowed_domains", &domain, source);
+        }
+    }
+    s.each("presets", |l| l.data.presets.clone());
+    s.each("allowed_domains", |l| {
+        l.data
+            .presets
+            .iter()
+            .flat_map(|p| p.domains())
+            .map(|d| d.to_string())
+            .chain(l.data.extra_allowed_domains.iter().cloned())
+            .collect()
+    });
+    if let Some((ranges, source)) = s.find(|l| l.data.ip_ranges.clone()) {
+        for range in ranges {
+            s.push("ip_ranges", &range, source);
+        }
+    }
+    s.each("denied_domains", |l| l.data.denied_domains.clone());
+    s.last("dns_proxy", |l| l.data.dns_proxy, Some(config.dns_proxy()));
+    s.each("ports", |l| l.data.ports.clone());
+
+    s.last(
+        "security.apparmor",
+        |l| l.data.security.apparmor.clone(),
+        None,
+    );
+    s.last(
+        "security.selinux_label",
+        |l| l.data.security.selinux_label.clone(),
+        None,
+    );
+    s.last(
+        "security.selinux_relabel",
+        |l| l.data.security.selinux_relabel,
+        None,
+    );
+    s.last(
+        "security.limits.pids",
+        |l| l.data.security.limits.pids,
+        None,
+    );
+    s.last(
+        "security.limits.nofile",
+        |l| l.data.security.limits.nofile,
+        None,
+    );
+    s.last(
+        "security.limits.nproc",
+        |l| l.data.security.limits.nproc,
+        None,
+    );
+
+    s.last(
+        "hardening.drop_capabilities",
+        |l| l.data.hardening.drop_capabilities,
+        None,
+    );
+    s.last(
+        "hardening.no_new_privileges",
+        |l| l.data.hardening.no_new_privileges,
+        None,
+    );
+    s.last(
+        "hardening.seccomp",
+        |l| {
+            let path = l.data.hardening.seccomp.as_deref()?;
+            Some(resolve_path(&l.config_dir, path))
+        },
+        None,
+    );
+    s.last(
+        "hardening.read_only_root",
+        |l| l.data.hardening.read_only_root,
+        None,
+    );
+    s.each("hardening.writable_paths", |l| {
+        l.data.hardening.writable_paths.clone()
+    });
+
+    s.last("engine.backend", |l| l.data.engine.backend, None);
+    s.last(
+        "engine.autostart_vm",
+        |l| l.data.engine.autostart_vm,
+        Some(false),
+    );
+    // Kubernetes settings are taken as a whole from one layer
+    if let Some((kubernetes, source)) = s.find(|l| l.data.engine.kubernetes.clone()) {
+        let fields = [
+            ("context", kubernetes.context.map(Value::from)),
+            ("namespace", kubernetes.namespace.map(Value::from)),
+            ("registry", kubernetes.registry.map(Value::from)),
+            (
+                "startup_timeout_secs",
+                Some(kubernetes.startup_timeout_secs.into()),
+            ),
+        ];
+        for (field, value) in fields {
+            if let Some(value) = value {
+                s.push(&format!("engine.kubernetes.{}", field), &value, source);
+            }
+        }
+    }
+
+    s.last(
+        "network.mode",
+        |l| l.data.network.mode,
+        Some(Default::default()),
+    );
+    s.last(
+        "network.proxy_port",
+        |l| l.data.network.proxy_port,
+        Some(DEFAULT_PROXY_PORT),
+    );
+    s.last(
+        "network.ip_cache_ttl_secs",
+        |l| l.data.network.ip_cache_ttl_secs,
+        Some(DEFAULT_IP_CACHE_TTL_SECS),
+    );
+    s.last(
+        "network.resolvers",
+        |l| l.data.network.resolvers.clone(),
+        None,
+    );
+    s.entries("network.domain_resolvers", |l| {
+        l.data.network.domain_resolvers.clone()
+    });
+    s.last("network.shared", |l| l.data.network.shared, Some(false));
+    s.last("network.group", |l| l.data.network.group.clone(), None);
+    s.last("network.proxy", |l| l.data.network.proxy.clone(), None);
+    s.last(
+        "network.no_proxy",
+        |l| l.data.network.no_proxy.clone(),
+        None,
+    );
+    s.last(
+        "network.allow_local",
+        |l| l.data.network.allow_local,
+        Some(false),
+    );
+    s.last(
+        "network.max_egress",
+        |l| l.data.network.max_egress.clone(),
+        None,
+    );
+    s.last(
+        "network.max_ingress",
+        |l| l.data.network.max_ingress.clone(),
+        None,
+    );
+    s.last(
+        "workspace.mode",
+        |l| l.data.workspace.mode,
+        Some(WorkspaceMode::Bind),
+    );
+    s.last("workspace.path", |l| l.data.workspace.path.clone(), None);
+    s.last(
+        "workspace.workdir",
+        |l| l.data.workspace.workdir.clone(),
+        None,
+    );
+    s.last(
+        "workspace.host_user",
+        |l| l.data.workspace.host_user,
+        Some(cfg!(target_os = "linux")),
+    );
+    s.last("jj.push", |l| l.data.jj.push, Some(false));
+    s.last("git.name", |l| l.data.git.name.clone(), None);
+    s.last("git.email", |l| l.data.git.email.clone(), None);
+    s.last("git.signing_key", |l| l.data.git.signing_key.clone(), None);
+    s.each("git.safe_directories", |l| {
+        l.data.git.safe_directories.clone()
+    });
+    s.last("image.ref", |l| l.data.image.reference.clone(), None);
+    s.last("image.platform", |l| l.data.image.platform.clone(), None);
+    s.last(
+        "image.cosign_key",
+        |l| l.data.image.cosign_key.clone(),
+        None,
+    );
+    s.last(
+        "image.attestation",
+        |l| l.data.image.attestation.clone(),
+        None,
+    );
+    s.each("workspace.exclude", |l| l.data.workspace.exclude.clone());
+    s.each("workspace.writable", |l| l.data.workspace.writable.clone());
+
+    s.last(
+        "bridge.port",
+        |l| Some(l.data.bridge.port).filter(|&port| port != DEFAULT_BRIDGE_PORT),
+        Some(DEFAULT_BRIDGE_PORT),
+    );
+    s.entries("bridge.triggers", |l| {
+        l.data
+            .bridge
+            .triggers
+            .iter()
+            .map(|(name, trigger)| (name.clone(), trigger.command.to_string()))
+            .collect()
+    });
+    s.last("bridge.socket", |l| l.data.bridge.socket, None);
+    s.last("bridge.clipboard", |l| l.data.bridge.clipboard, None);
+    s.each("bridge.open_urls", |l| l.data.bridge.open_urls.clone());
+    s.last(
+        "bridge.artifacts_dir",
+        |l| {
+            let path = l.data.bridge.artifacts_dir.as_deref()?;
+            Some(resolve_path(&l.config_dir, path))
+        },
+        None,
+    );
+    s.last(
+        "bridge.artifacts_max_mb",
+        |l| l.data.bridge.artifacts_max_mb,
+        None,
+    );
+    s.each("bridge.webhooks", |l| l.data.bridge.webhooks.clone());
+    // Only whether it's set, so the output is safe to paste
+    s.last(
+        "bridge.webhook_secret",
+        |l| l.data.bridge.webhook_secret.as_ref().map(|_| "(set)"),
+        None,
+    );
+    s.last("bridge.watch", |l| l.data.bridge.watch, None);
+    s.last("bridge.address", |l| l.data.bridge.address.clone(), None);
+    s.last("bridge.tls", |l| l.data.bridge.tls, None);
+    s.last("bridge.client_certs", |l| l.data.bridge.client_certs, None);
+    s.each("bridge.tls_names", |l| l.data.bridge.tls_names.clone());
+    s.last(
+        "bridge.github_tokens",
+        |l| l.data.bridge.github_tokens,
+        None,
+    );
+    s.last(
+        "bridge.github_app",
+        |l| l.data.bridge.github_app.as_ref().map(|app| app.app_id),
+        None,
+    );
+
+    s.settings
+}
+
+/// Settings as aligned `source  key  value` rows.
+pub fn render(settings: &[Setting]) -> String {
+    let width = settings.iter().map(|s| s.key.len()).max().unwrap_or(0);
+    settings
+        .iter()
+        .map(|s| {
+            format!(
+                "{:<7}  {:<width$}  {}\n",
+                s.source.to_string(),
+                s.key,
+                s.value
+            )
+        })
+        .collect()
+}
+
+struct Settings<'a> {
+    layers: &'a [ConfigLayer],
+    settings: Vec<Setting>,
+}
+
+impl Settings<'_> {
+    fn push(&mut self, key: &str, value: &impl Serialize, source: ConfigSource) {
+        self.settings.push(Setting {
+            key: key.to_string(),
+            value: display(value),
+            source,
+        });
+    }
+
+    /// The value from the last layer to set it, and that layer's source.
+    fn find<T>(&self, get: impl Fn(&ConfigLayer) -> Option<T>) -> Option<(T, ConfigSource)> {
+        self.layers
+            .iter()
+            .rev()
+            .find_map(|l| get(l).map(|value| (value, l.source)))
+    }
+
+    /// A single value, credited to the defaults when no layer sets it.
+    fn last<T: Serialize>(
+        &mut self,
+        key: &str,
+        get: impl Fn(&ConfigLayer) -> Option<T>,
+        default: Option<T>,
+    ) {
+        let found = self
+            .find(get)
+            .or_else(|| default.map(|value| (value, ConfigSource::Default)));
+        if let Some((value, source)) = found {
+            self.push(key, &value, source);
+        }
+    }
+
+    /// List items from every layer, skipping ones already listed.
+    fn each<T: Serialize>(&mut self, key: &str, get: impl Fn(&ConfigLayer) -> Vec<T>) {
+        for layer in self.layers {
+            for item in get(layer) {
+                let value = display(&item);
+                let listed = self
+                    .settings
+                    .iter()
+                    .any(|s| s.key == key && s.value == value);
+                if !listed {
+                    self.settings.push(Setting {
+                        key: key.to_string(),
+                        value,
+                        source: layer.source,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Map entries merged across layers, sorted by key.
+    fn entries<T: Serialize>(
+        &mut self,
+        key: &str,
+        get: impl Fn(&ConfigLayer) -> HashMap<String, T>,
+    ) {
+        let mut entries = BTreeMap::new();
+        for layer in self.layers {
+            for (name, value) in get(layer) {
+                entries.insert(name, (value, layer.source));
+            }
+        }
+        for (name, (value, source)) in entries {
+            self.push(&format!("{}.{}", key, name), &value, source);
+        }
+    }
+}
+
+/// Strings as they are, anything else as compact JSON.
+fn display(value: &impl Serialize) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(s)) => s,
+        Ok(value) => value.to_string(),
+        Err(e) => format!("<{}>", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::config::Config;
+
+    fn layer(yaml: &str) -> Config {
+        serde_yaml_ng::from_str(yaml).unwrap()
+    }
+
+    fn value<'a>(settings: &'a [Setting], key: &str) -> Vec<(&'a str, ConfigSource)> {
+        settings
+            .iter()
+            .filter(|s| s.key == key)
+            .map(|s| (s.value.as_str(), s.source))
+            .collect()
+    }
+
+    #[test]
+    fn credits_each_value_to_its_layer() {
+        let mut config = StackedConfig::with_defaults();
+        config.add_layer(
+            ConfigSource::User,
+            layer(
+                "mounts:\n  - source: ~/.gitconfig\n    target: ~/.gitconfig\n\
+                 env:\n  EDITOR: vim\n  TERM: xterm\n\
+                 network:\n  proxy_port: 8080\n",
+            ),
+            PathBuf::from("/home/me/.config/contenant"),
+        );
+        config.add_layer(
+            ConfigSource::Project,
+            layer(
+                "mounts:\n  - source: data\n    readonly: false\n\
+                 env:\n  EDITOR: nano\n\
+                 presets: [go]\n\
+                 bridge:\n  webhook_secret: hunter2\n",
+            ),
+            PathBuf::from("/work/app/.contenant"),
+        );
+        let settings = settings(&config);
+
+        let mounts = value(&settings, "mounts");
+        assert_eq!(mounts.len(), 2);
+        assert!(mounts[0].0.ends_with(":/home/claude/.gitconfig:ro"));
+        assert_eq!(mounts[0].1, ConfigSource::User);
+        assert_eq!(
+            mounts[1],
+            ("/work/app/.contenant/data:data", ConfigSource::Project)
+        );
+
+        assert_eq!(
+            value(&settings, "env.EDITOR"),
+            [("nano", ConfigSource::Project)]
+        );
+        assert_eq!(
+            value(&settings, "env.TERM"),
+            [("xterm", ConfigSource::User)]
+        );
+        assert_eq!(
+            value(&settings, "network.proxy_port"),
+            [("8080", ConfigSource::User)]
+        );
+        assert_eq!(
+            value(&settings, "network.mode"),
+            [("firewall", ConfigSource::Default)]
+        );
+        assert_eq!(
+            value(&settings, "bridge.webhook_secret"),
+            [("(set)", ConfigSource::Project)]
+        );
+        assert!(value(&settings, "network.group").is_empty());
+
+        let domains = value(&settings, "allowed_domains");
+        assert_eq!(domains[0], ("api.github.com", ConfigSource::Default));
+        assert!(domains.contains(&("proxy.golang.org", ConfigSource::Project)));
+    }
+
+    #[test]
+    fn renders_aligned_rows() {
+        let settings = [
+            Setting {
+                key: "dns_proxy".to_string(),
+                value: "false".to_string(),
+                source: ConfigSource::Default,
+            },
+            Setting {
+                key: "env.EDITOR".to_string(),
+                value: "vim".to_string(),
+                source: ConfigSource::User,
+            },
+        ];
+        assert_eq!(
+            render(&settings),
+            "default  dns_proxy   false\nuser     env.EDITOR  vim\n"
+        );
+    }
+}
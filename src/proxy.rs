@@ -0,0 +1,709 @@
+//! Hostname-filtering egress proxy for `network.mode: proxy`.
+//!
+//! The container reaches the outside world only through this proxy (via
+//! `HTTP_PROXY`/`HTTPS_PROXY`), which checks each destination against the
+//! allowed domain patterns: the `CONNECT` target for HTTPS, also required to
+//! match the TLS SNI the client sends, and the `Host` header for plain HTTP.
+//! Plain HTTP gets one request per connection, so every request's head is
+//! checked, and only the session's allowed ports can be reached.
+//!
+//! One proxy serves every session on the host. Each session's proxy URL
+//! carries a token, sent back as `Proxy-Authorization`, naming a file in the
+//! sessions directory with that session's patterns. Whichever contenant
+//! process binds the port first serves it; the others wait to take over when
+//! it exits.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{debug, info, warn};
+
+use crate::{cidr_contains, parse_cidr};
+
+const MAX_HEAD: usize = 16 * 1024;
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a waiting process tries to take over the port.
+const TAKEOVER_INTERVAL: Duration = Duration::from_secs(1);
+/// Identifies the proxy in its responses, so another process can tell it's
+/// ours holding the port.
+const PROXY_AGENT: &str = "Proxy-Agent: contenant";
+
+/// Ports sessions may reach unless `network.proxy_allowed_ports` says
+/// otherwise.
+pub const DEFAULT_ALLOWED_PORTS: [u16; 2] = [80, 443];
+
+/// Where sessions' patterns are kept, under the state directory.
+pub const SESSIONS_DIR: &str = "proxy-sessions";
+
+/// Ports this process serves or waits to.
+static STARTED: Mutex<Option<HashSet<u16>>> = Mutex::new(None);
+
+/// A session's allowed and denied patterns and the ports it may reach, valid
+/// while this value is alive. Hosts matching `denied` are refused even if
+/// `allowed` matches them.
+pub struct ProxySession {
+    pub token: String,
+    path: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Patterns {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+    ports: Vec<u16>,
+}
+
+impl ProxySession {
+    pub fn issue(
+        sessions_dir: &Path,
+        allowed: Vec<String>,
+        denied: Vec<String>,
+        ports: Vec<u16>,
+    ) -> Result<Self> {
+        let token = crate::bridge::random_hex()?;
+        crate::redact::register(&token);
+        let path = sessions_dir.join(token_hash(&token));
+        let patterns = Patterns {
+            allowed,
+            denied,
+            ports,
+        };
+        fs::write(&path, serde_json::to_vec(&patterns)?)?;
+        Ok(Self { token, path })
+    }
+
+    /// The URL the container reaches the proxy at, as `host:port`.
+    pub fn url(&self, host: &str, port: u16) -> String {
+        format!("http://contenant:{}@{}:{}", self.token, host, port)
+    }
+}
+
+impl Drop for ProxySession {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Make sure `port` is served for sessions in `sessions_dir`: on a
+/// background thread if it's free, and otherwise by the contenant process
+/// holding it, with a thread waiting to take over when that one exits.
+pub fn serve(port: u16, sessions_dir: PathBuf) -> Result<()> {
+    let mut started = STARTED.lock().unwrap();
+    if !started.get_or_insert_default().insert(port) {
+        return Ok(());
+    }
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    let sessions_dir = Arc::new(sessions_dir);
+    match TcpListener::bind(addr) {
+        Ok(listener) => {
+            info!(%addr, "Egress proxy listening");
+            thread::spawn(move || accept(listener, sessions_dir));
+        }
+        Err(err) if err.kind() == io::ErrorKind::AddrInUse => {
+            if !is_contenant_proxy(addr) {
+                started.as_mut().unwrap().remove(&port);
+                bail!(
+                    "Port {} is in use by something other than contenant's proxy; set \
+                     network.proxy_port to another",
+                    port
+                );
+            }
+            debug!(%addr, "Egress proxy served by another contenant process");
+            thread::spawn(move || {
+                loop {
+                    thread::sleep(TAKEOVER_INTERVAL);
+                    if let Ok(listener) = TcpListener::bind(addr) {
+                        info!(%addr, "Egress proxy taken over");
+                        accept(listener, sessions_dir.clone());
+                    }
+                }
+            });
+        }
+        Err(err) => {
+            started.as_mut().unwrap().remove(&port);
+            return Err(err.into());
+        }
+    }
+    Ok(())
+}
+
+fn accept(listener: TcpListener, sessions_dir: Arc<PathBuf>) {
+    for stream in listener.incoming().flatten() {
+        let sessions_dir = sessions_dir.clone();
+        thread::spawn(move || {
+            if let Err(err) = handle(stream, &sessions_dir) {
+                debug!(%err, "Proxy connection ended");
+            }
+        });
+    }
+}
+
+/// Whether what's listening on `addr` answers like this proxy.
+fn is_contenant_proxy(addr: SocketAddr) -> bool {
+    let probe = || -> io::Result<String> {
+        let mut stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        stream.write_all(b"CONNECT contenant.invalid:443 HTTP/1.1\r\n\r\n")?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    };
+    probe().is_ok_and(|response| response.contains(PROXY_AGENT))
+}
+
+/// The patterns of the session whose token `authorization` carries.
+fn session_patterns(sessions_dir: &Path, authorization: Option<&str>) -> Option<Patterns> {
+    let (scheme, credentials) = authorization?.split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+    let credentials = String::from_utf8(base64_decode(credentials.trim())?).ok()?;
+    let (_, token) = credentials.split_once(':')?;
+    let patterns = fs::read(sessions_dir.join(token_hash(token))).ok()?;
+    serde_json::from_slice(&patterns).ok()
+}
+
+/// Standard base64, as in `Basic` credentials.
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bytes = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text.trim_end_matches('=').bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Whether `host` matches one of the patterns, allowed or denied. `*.example.com` matches
+/// `example.com` and every subdomain, like the DNS proxy; IP literals match
+/// IP and CIDR patterns.
+pub fn host_allowed(patterns: &[String], host: &str) -> bool {
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        return patterns
+            .iter()
+            .filter_map(|pattern| parse_cidr(pattern))
+            .any(|range| cidr_contains(range, ip));
+    }
+
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(suffix) => host == suffix || host.ends_with(&format!(".{}", suffix)),
+            None => host == pattern,
+        }
+    })
+}
+
+fn handle(mut client: TcpStream, sessions_dir: &Path) -> io::Result<()> {
+    client.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+    let mut buf = vec![];
+    let head_len = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        if buf.len() > MAX_HEAD || read_more(&mut client, &mut buf)? == 0 {
+            return Ok(());
+        }
+    };
+    let head = String::from_utf8_lossy(&buf[..head_len]).into_owned();
+    let rest = buf.split_off(head_len);
+
+    let Some(request) = Request::parse(&head) else {
+        return respond(&mut client, "400 Bad Request");
+    };
+    let Some(Patterns {
+        allowed,
+        denied,
+        ports,
+    }) = session_patterns(sessions_dir, request.authorization.as_deref())
+    else {
+        return respond(&mut client, "407 Proxy Authentication Required");
+    };
+    if !host_allowed(&allowed, &request.host) || host_allowed(&denied, &request.host) {
+        warn!(host = %request.host, "Blocked egress");
+        return respond(&mut client, "403 Forbidden");
+    }
+    if !ports.contains(&request.port) {
+        warn!(host = %request.host, port = request.port, "Blocked egress to a port not allowed");
+        return respond(&mut client, "403 Forbidden");
+    }
+    // Without a length, the body's end (and the next request's start) can't
+    // be told apart
+    let Some(body_len) = request.body_len else {
+        return respond(&mut client, "411 Length Required");
+    };
+
+    let mut upstream = TcpStream::connect((request.host.as_str(), request.port))?;
+    if request.connect {
+        respond(&mut client, "200 Connection Established")?;
+
+        // The tunnel must carry TLS for the host it was opened to
+        let mut hello = rest;
+        let sni = loop {
+            if let Some(sni) = client_hello_sni(&hello) {
+                break sni;
+            }
+            if hello.len() > MAX_HEAD || read_more(&mut client, &mut hello)? == 0 {
+                return Ok(());
+            }
+        };
+        if !sni.is_some_and(|sni| sni.eq_ignore_ascii_case(&request.host)) {
+            warn!(host = %request.host, "Blocked tunnel with mismatched SNI");
+            return Ok(());
+        }
+        upstream.write_all(&hello)?;
+    } else {
+        // Only this request's body goes upstream; anything the client
+        // pipelined after it is dropped, and it reconnects to send it
+        upstream.write_all(request.forward_head.as_bytes())?;
+        let buffered = rest.len().min(body_len as usize);
+        upstream.write_all(&rest[..buffered])?;
+        client.set_read_timeout(None)?;
+        io::copy(
+            &mut (&client).take(body_len - buffered as u64),
+            &mut upstream,
+        )?;
+        // The upstream closes after responding, as asked
+        io::copy(&mut upstream, &mut client)?;
+        let _ = client.shutdown(Shutdown::Both);
+        return Ok(());
+    }
+
+    client.set_read_timeout(None)?;
+    splice(client, upstream)
+}
+
+fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0; 4096];
+    let n = stream.read(&mut chunk)?;
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+fn respond(client: &mut TcpStream, status: &str) -> io::Result<()> {
+    write!(
+        client,
+        "HTTP/1.1 {}\r\n{}\r\nContent-Length: 0\r\n\r\n",
+        status, PROXY_AGENT
+    )
+}
+
+/// Copy bytes both ways until either side closes.
+fn splice(client: TcpStream, upstream: TcpStream) -> io::Result<()> {
+    let (mut client_read, mut upstream_write) = (client.try_clone()?, upstream.try_clone()?);
+    let uploader = thread::spawn(move || {
+        let _ = io::copy(&mut client_read, &mut upstream_write);
+        let _ = upstream_write.shutdown(Shutdown::Write);
+    });
+
+    let (mut upstream_read, mut client_write) = (upstream, client);
+    let _ = io::copy(&mut upstream_read, &mut client_write);
+    let _ = client_write.shutdown(Shutdown::Write);
+    let _ = uploader.join();
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    connect: bool,
+    host: String,
+    port: u16,
+    /// Head to send upstream for plain HTTP: origin-form target, one
+    /// request per connection so later requests can't switch hosts.
+    forward_head: String,
+    /// Length of a plain-HTTP request's body; `None` if it's sent chunked.
+    body_len: Option<u64>,
+    /// The `Proxy-Authorization` header, which isn't forwarded.
+    authorization: Option<String>,
+}
+
+impl Request {
+    fn parse(head: &str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next()?.split(' ');
+        let (method, target, version) = (
+            request_line.next()?,
+            request_line.next()?,
+            request_line.next()?,
+        );
+
+        let mut headers = vec![];
+        let mut authorization = None;
+        for line in lines.filter(|l| !l.is_empty()) {
+            let (name, value) = line.split_once(':')?;
+            match name.trim().to_ascii_lowercase().as_str() {
+                "proxy-authorization" => authorization = Some(value.trim().to_string()),
+                name => headers.push((name.to_string(), value.trim().to_string(), line)),
+            }
+        }
+
+        if method == "CONNECT" {
+            let (host, port) = split_host_port(target, 443)?;
+            return Some(Self {
+                connect: true,
+                host,
+                port,
+                forward_head: String::new(),
+                body_len: Some(0),
+                authorization,
+            });
+        }
+
+        let url = target.strip_prefix("http://")?;
+        let (authority, path) = match url.find('/') {
+            Some(i) => url.split_at(i),
+            None => (url, "/"),
+        };
+
+        let mut forward_head = format!("{} {} {}\r\n", method, path, version);
+        let mut host_header = None;
+        let mut body_len = Some(0);
+        for (name, value, line) in headers {
+            match name.as_str() {
+                "host" => host_header = Some(value),
+                "content-length" => body_len = body_len.and(Some(value.parse().ok()?)),
+                "transfer-encoding" => body_len = None,
+                "connection" | "proxy-connection" | "keep-alive" => continue,
+                _ => {}
+            }
+            forward_head.push_str(line);
+            forward_head.push_str("\r\n");
+        }
+        forward_head.push_str("Connection: close\r\n\r\n");
+
+        // The Host header decides where virtual-hosted servers route the
+        // request, so it must agree with where we connect
+        let (host, port) = split_host_port(authority, 80)?;
+        if host_header.is_some_and(|h| split_host_port(&h, 80) != Some((host.clone(), port))) {
+            return None;
+        }
+
+        Some(Self {
+            connect: false,
+            host,
+            port,
+            forward_head,
+            body_len,
+            authorization,
+        })
+    }
+}
+
+fn split_host_port(authority: &str, default_port: u16) -> Option<(String, u16)> {
+    match authority.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') || host.ends_with(']') => {
+            Some((host.to_string(), port.parse().ok()?))
+        }
+        _ => Some((authority.to_string(), default_port)),
+    }
+}
+
+/// Server name from a TLS ClientHello: `None` until the record is complete,
+/// `Some(None)` if it's complete but isn't a ClientHello with SNI.
+fn client_hello_sni(data: &[u8]) -> Option<Option<String>> {
+    let record_len = u16::from_be_bytes([*data.get(3)?, *data.get(4)?]) as usize;
+    let record = data.get(5..5 + record_len)?;
+    if data[0] != 0x16 {
+        return Some(None);
+    }
+    Some(parse_client_hello(record))
+}
+
+fn parse_client_hello(record: &[u8]) -> Option<String> {
+    let mut r = Reader(record);
+    if r.u8()? != 1 {
+        return None;
+    }
+    r.skip(3 + 2 + 32)?; // length, version, random
+    let session_id = r.u8()? as usize;
+    r.skip(session_id)?;
+    let ciphers = r.u16()? as usize;
+    r.skip(ciphers)?;
+    let compression = r.u8()? as usize;
+    r.skip(compression)?;
+
+    let extensions_len = r.u16()? as usize;
+    let mut extensions = Reader(r.take(extensions_len)?);
+    while let Some(kind) = extensions.u16() {
+        let body_len = extensions.u16()? as usize;
+        let mut body = Reader(extensions.take(body_len)?);
+        if kind != 0 {
+            continue;
+        }
+        body.u16()?; // server name list length
+        if body.u8()? != 0 {
+            return None;
+        }
+        let name_len = body.u16()? as usize;
+        let name = body.take(name_len)?;
+        return String::from_utf8(name.to_vec()).ok();
+    }
+    None
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if self.0.len() < n {
+            return None;
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Some(head)
+    }
+
+    fn skip(&mut self, n: usize) -> Option<()> {
+        self.take(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let bytes = self.take(2)?;
+        Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_exact_and_wildcard_hosts() {
+        let patterns = vec!["github.com".to_string(), "*.amazonaws.com".to_string()];
+        assert!(host_allowed(&patterns, "github.com"));
+        assert!(host_allowed(&patterns, "GitHub.com."));
+        assert!(host_allowed(&patterns, "s3.us-east-1.amazonaws.com"));
+        assert!(!host_allowed(&patterns, "gist.github.com"));
+        assert!(!host_allowed(&patterns, "evilamazonaws.com"));
+
+        let patterns = vec!["10.0.0.0/8".to_string()];
+        assert!(host_allowed(&patterns, "10.1.2.3"));
+        assert!(!host_allowed(&patterns, "11.0.0.1"));
+    }
+
+    #[test]
+    fn parses_requests() {
+        let connect = Request::parse("CONNECT github.com:443 HTTP/1.1\r\n\r\n").unwrap();
+        assert!(connect.connect);
+        assert_eq!((connect.host.as_str(), connect.port), ("github.com", 443));
+
+        let get = Request::parse(
+            "GET http://example.com:8080/a?b HTTP/1.1\r\nHost: example.com:8080\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!((get.host.as_str(), get.port), ("example.com", 8080));
+        assert_eq!(
+            get.forward_head,
+            "GET /a?b HTTP/1.1\r\nHost: example.com:8080\r\nConnection: close\r\n\r\n"
+        );
+
+        // Host header pointing elsewhere is rejected
+        assert_eq!(
+            Request::parse("GET http://example.com/ HTTP/1.1\r\nHost: evil.com\r\n\r\n"),
+            None
+        );
+
+        // Credentials are kept from upstream
+        let authorized = Request::parse(
+            "GET http://example.com/ HTTP/1.1\r\nProxy-Authorization: Basic eA==\r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(authorized.authorization.as_deref(), Some("Basic eA=="));
+        assert_eq!(
+            authorized.forward_head,
+            "GET / HTTP/1.1\r\nConnection: close\r\n\r\n"
+        );
+
+        // Bodies need a length to find where the next request starts
+        let post = "POST http://example.com/ HTTP/1.1\r\nContent-Length: 5\r\n\r\n";
+        assert_eq!(Request::parse(post).unwrap().body_len, Some(5));
+        let chunked = "POST http://example.com/ HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n";
+        assert_eq!(Request::parse(chunked).unwrap().body_len, None);
+    }
+
+    /// A session allowing `127.0.0.1` on `ports`, and its `Proxy-Authorization`.
+    fn local_session(dir: &Path, ports: Vec<u16>) -> (ProxySession, String) {
+        let session =
+            ProxySession::issue(dir, vec!["127.0.0.1/32".to_string()], vec![], ports).unwrap();
+        let credentials = format!("contenant:{}", session.token);
+        let authorization = format!("Basic {}", base64_encode(credentials.as_bytes()));
+        (session, authorization)
+    }
+
+    /// Send `request` through a proxy connection handled by `handle`,
+    /// returning the response.
+    fn proxy(sessions_dir: &Path, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let sessions_dir = sessions_dir.to_path_buf();
+        let proxy = thread::spawn(move || handle(stream, &sessions_dir));
+        client.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        proxy.join().unwrap().unwrap();
+        response
+    }
+
+    #[test]
+    fn plain_http_sends_one_checked_request_per_connection() {
+        let upstream = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = upstream.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = upstream.accept().unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(500)))
+                .unwrap();
+            let mut received = vec![];
+            let _ = stream.read_to_end(&mut received);
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok")
+                .unwrap();
+            String::from_utf8(received).unwrap()
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let (_session, authorization) = local_session(dir.path(), vec![port]);
+
+        let response = proxy(
+            dir.path(),
+            &format!(
+                "POST http://127.0.0.1:{port}/ HTTP/1.1\r\nHost: 127.0.0.1:{port}\r\n\
+                 Proxy-Authorization: {authorization}\r\nContent-Length: 2\r\n\r\nhi\
+                 GET http://127.0.0.1:{port}/ HTTP/1.1\r\nHost: evil.com\r\n\r\n"
+            ),
+        );
+        assert!(response.ends_with("\r\n\r\nok"), "{response}");
+        let received = server.join().unwrap();
+        assert!(
+            received.ends_with("Connection: close\r\n\r\nhi"),
+            "{received}"
+        );
+        assert!(!received.contains("evil.com"), "{received}");
+    }
+
+    #[test]
+    fn refuses_ports_the_session_doesnt_allow() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_session, authorization) = local_session(dir.path(), vec![80, 443]);
+
+        let response = proxy(
+            dir.path(),
+            &format!(
+                "CONNECT 127.0.0.1:22 HTTP/1.1\r\nProxy-Authorization: {authorization}\r\n\r\n"
+            ),
+        );
+        assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    }
+
+    #[test]
+    fn looks_sessions_up_by_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let session = ProxySession::issue(
+            dir.path(),
+            vec!["*.github.com".to_string()],
+            vec!["evil.github.com".to_string()],
+            DEFAULT_ALLOWED_PORTS.to_vec(),
+        )
+        .unwrap();
+        let credentials = format!("contenant:{}", session.token);
+        let authorization = format!("Basic {}", base64_encode(credentials.as_bytes()));
+        let patterns = session_patterns(dir.path(), Some(&authorization)).unwrap();
+        assert_eq!(patterns.allowed, ["*.github.com"]);
+        assert_eq!(patterns.denied, ["evil.github.com"]);
+
+        let wrong = format!("Basic {}", base64_encode(b"contenant:nope"));
+        assert!(session_patterns(dir.path(), Some(&wrong)).is_none());
+        assert!(session_patterns(dir.path(), None).is_none());
+
+        drop(session);
+        assert!(session_patterns(dir.path(), Some(&authorization)).is_none());
+    }
+
+    #[test]
+    fn decodes_base64() {
+        assert_eq!(base64_decode("").unwrap(), b"");
+        assert_eq!(base64_decode("Zg==").unwrap(), b"f");
+        assert_eq!(base64_decode("Zm9vYg==").unwrap(), b"foob");
+        assert_eq!(base64_decode("Zm9vYmFy").unwrap(), b"foobar");
+        assert!(base64_decode("Zm9v!").is_none());
+    }
+
+    fn base64_encode(bytes: &[u8]) -> String {
+        const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        let mut text = String::new();
+        for chunk in bytes.chunks(3) {
+            let bits = chunk
+                .iter()
+                .enumerate()
+                .fold(0u32, |bits, (i, &b)| bits | (b as u32) << (16 - 8 * i));
+            for i in 0..4 {
+                text.push(if i <= chunk.len() {
+                    ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char
+                } else {
+                    '='
+                });
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn extracts_sni_from_client_hello() {
+        let name = b"api.github.com";
+        let mut sni = vec![0, 0]; // extension type
+        sni.extend((name.len() as u16 + 5).to_be_bytes());
+        sni.extend((name.len() as u16 + 3).to_be_bytes());
+        sni.push(0);
+        sni.extend((name.len() as u16).to_be_bytes());
+        sni.extend(name);
+
+        let mut hello = vec![3, 3];
+        hello.extend([0; 32]); // random
+        hello.push(0); // session id
+        hello.extend([0, 2, 0x13, 0x01]); // one cipher suite
+        hello.extend([1, 0]); // null compression
+        hello.extend((sni.len() as u16).to_be_bytes());
+        hello.extend(&sni);
+
+        let mut handshake = vec![1];
+        handshake.extend(&(hello.len() as u32).to_be_bytes()[1..]);
+        handshake.extend(&hello);
+
+        let mut record = vec![0x16, 3, 1];
+        record.extend((handshake.len() as u16).to_be_bytes());
+        record.extend(&handshake);
+
+        assert_eq!(client_hello_sni(&record[..10]), None);
+        assert_eq!(
+            client_hello_sni(&record),
+            Some(Some("api.github.com".to_string()))
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Running sessions, for `contenant ps`.
+//!
+//! Sessions are found by the labels `run` puts on each container:
+//! [`PROJECT_LABEL`](crate::PROJECT_LABEL),
+//! [`SESSION_LABEL`](crate::SESSION_LABEL), and [`AGENT_LABEL`].
+
+use serde::Serialize;
+
+/// Label naming the agent a session's container runs.
+pub const AGENT_LABEL: &str = "contenant.agent";
+
+/// The agent contenant runs, as recorded in [`AGENT_LABEL`].
+pub const AGENT: &str = "claude";
+
+/// A running session's container.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Session {
+    pub id: String,
+    /// Project id from the container's label.
+    pub project: String,
+    /// Session id, the name of its log directory. Missing for dry runs and
+    /// containers started before the label existed.
+    pub session: Option<String>,
+    /// Missing on containers started before the label existed.
+    pub agent: Option<String>,
+    /// How long it's been running, like `5 minutes`.
+    pub uptime: String,
+    /// Processes exec'd into the container alongside the agent.
+    pub execs: usize,
+}
+
+/// Parse `docker ps` lines formatted as `{{.ID}}\t{{.Label
+/// "contenant.project"}}\t{{.Label "contenant.session"}}\t{{.Label
+/// "contenant.agent"}}\t{{.Status}}`. Exec counts are left at zero.
+pub fn parse_docker_ps(output: &str) -> Vec<Session> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let id = fields.next()?.to_string();
+            let project = fields.next()?.to_string();
+            let session = fields.next()?;
+            let agent = fields.next()?;
+            Some(Session {
+                id,
+                project,
+                session: (!session.is_empty()).then(|| session.to_string()),
+                agent: (!agent.is_empty()).then(|| agent.to_string()),
+                uptime: uptime(fields.next()?),
+                execs: 0,
+            })
+        })
+        .collect()
+}
+
+/// The uptime in a container status like `Up 5 minutes`.
+pub fn uptime(status: &str) -> String {
+    status.strip_prefix("Up ").unwrap_or(status).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_docker_ps_output() {
+        let sessions = parse_docker_ps(
+            "abc123\t0123abcd-app\t1700000000-0a1b2c3d\tclaude\tUp 5 minutes\n\
+             def456\t4567cdef-old\t\t\tUp About an hour (healthy)\n",
+        );
+        assert_eq!(
+            sessions[0],
+            Session {
+                id: "abc123".to_string(),
+                project: "0123abcd-app".to_string(),
+                session: Some("1700000000-0a1b2c3d".to_string()),
+                agent: Some("claude".to_string()),
+                uptime: "5 minutes".to_string(),
+                execs: 0,
+            }
+        );
+        assert_eq!(sessions[1].session, None);
+        assert_eq!(sessions[1].agent, None);
+        assert_eq!(sessions[1].uptime, "About an hour (healthy)");
+    }
+}
@@ -0,0 +1,311 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use color_eyre::eyre::{Result, eyre};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
+
+impl std::fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TaskStatus::Queued => write!(f, "queued"),
+            TaskStatus::Running => write!(f, "running"),
+            TaskStatus::Done => write!(f, "done"),
+            TaskStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A headless task waiting to be (or already) run in the project's container.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Task {
+    pub id: u64,
+    pub prompt: String,
+    pub status: TaskStatus,
+    /// Unix timestamps (seconds).
+    pub queued_at: u64,
+    #[serde(default)]
+    pub started_at: Option<u64>,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Process id of the `queue work` running the task.
+    #[serde(default)]
+    pub worker: Option<u32>,
+    /// Workspace changes made while the task ran.
+    #[serde(default)]
+    pub diff: Option<PathBuf>,
+    /// Claude session transcript written during the task.
+    #[serde(default)]
+    pub transcript: Option<PathBuf>,
+}
+
+/// Per-project task queue stored as JSON lines.
+pub struct Queue {
+    dir: PathBuf,
+}
+
+impl Queue {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    fn tasks_file(&self) -> PathBuf {
+        self.dir.join("tasks.jsonl")
+    }
+
+    /// Directory holding per-task artifacts such as diffs.
+    pub fn results_dir(&self) -> PathBuf {
+        self.dir.join("results")
+    }
+
+    /// All tasks, oldest first.
+    pub fn tasks(&self) -> Result<Vec<Task>> {
+        let path = self.tasks_file();
+        if !path.exists() {
+            return Ok(vec![]);
+        }
+
+        fs::read_to_string(&path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    pub fn add(&self, prompt: &str) -> Result<Task> {
+        self.modify(|tasks| {
+            let id = tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+            let task = Task {
+                id,
+                prompt: prompt.to_string(),
+                status: TaskStatus::Queued,
+                queued_at: now(),
+                started_at: None,
+                finished_at: None,
+                exit_code: None,
+                worker: None,
+                diff: None,
+                transcript: None,
+            };
+            tasks.push(task.clone());
+            Ok(task)
+        })
+    }
+
+    /// The oldest task that hasn't been started yet.
+    pub fn next(&self) -> Result<Option<Task>> {
+        Ok(self
+            .tasks()?
+            .into_iter()
+            .find(|t| t.status == TaskStatus::Queued))
+    }
+
+    /// Mark the oldest task that hasn't been started as run by this process,
+    /// so no other worker takes it too.
+    pub fn claim_next(&self) -> Result<Option<Task>> {
+        self.modify(|tasks| {
+            let Some(task) = tasks.iter_mut().find(|t| t.status == TaskStatus::Queued) else {
+                return Ok(None);
+            };
+            task.status = TaskStatus::Running;
+            task.started_at = Some(now());
+            task.worker = Some(std::process::id());
+            Ok(Some(task.clone()))
+        })
+    }
+
+    /// Put tasks left `Running` by a worker that's no longer alive back in
+    /// the queue, returning their ids.
+    pub fn requeue_stale(&self) -> Result<Vec<u64>> {
+        self.modify(|tasks| {
+            let mut requeued = vec![];
+            for task in tasks {
+                if task.status != TaskStatus::Running || task.worker.is_some_and(alive) {
+                    continue;
+                }
+                task.status = TaskStatus::Queued;
+                task.started_at = None;
+                task.worker = None;
+                requeued.push(task.id);
+            }
+            Ok(requeued)
+        })
+    }
+
+    /// Replace the stored task with the same id.
+    pub fn update(&self, task: &Task) -> Result<()> {
+        self.modify(|tasks| {
+            let stored = tasks
+                .iter_mut()
+                .find(|t| t.id == task.id)
+                .ok_or_else(|| eyre!("no queued task with id {}", task.id))?;
+            *stored = task.clone();
+            Ok(())
+        })
+    }
+
+    /// Change the tasks under an exclusive lock, so concurrent `queue add`s
+    /// and workers don't lose each other's changes. The file is replaced by
+    /// renaming, so readers and crashes never see it half-written.
+    fn modify<T>(&self, change: impl FnOnce(&mut Vec<Task>) -> Result<T>) -> Result<T> {
+        fs::create_dir_all(&self.dir)?;
+        let lock = fs::File::create(self.dir.join("tasks.lock"))?;
+        lock.lock()?;
+
+        let mut tasks = self.tasks()?;
+        let result = change(&mut tasks)?;
+
+        let mut contents = String::new();
+        for task in &tasks {
+            contents.push_str(&serde_json::to_string(task)?);
+            contents.push('\n');
+        }
+        let partial = self.dir.join("tasks.jsonl.partial");
+        let mut file = fs::File::create(&partial)?;
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&partial, self.tasks_file())?;
+
+        Ok(result)
+    }
+}
+
+/// Whether process `pid` exists (signal 0 checks without sending anything).
+fn alive(pid: u32) -> bool {
+    // SAFETY: kill with signal 0 has no effect beyond the existence check
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn add_assigns_sequential_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::new(dir.path());
+
+        assert_eq!(queue.add("first").unwrap().id, 1);
+        assert_eq!(queue.add("second").unwrap().id, 2);
+
+        let tasks = queue.tasks().unwrap();
+        assert_eq!(tasks.len(), 2);
+        assert_eq!(tasks[0].prompt, "first");
+        assert_eq!(tasks[1].status, TaskStatus::Queued);
+    }
+
+    #[test]
+    fn next_skips_started_tasks() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::new(dir.path());
+        let mut first = queue.add("first").unwrap();
+        queue.add("second").unwrap();
+
+        first.status = TaskStatus::Done;
+        first.exit_code = Some(0);
+        queue.update(&first).unwrap();
+
+        let next = queue.next().unwrap().unwrap();
+        assert_eq!(next.prompt, "second");
+        assert_eq!(queue.tasks().unwrap()[0].exit_code, Some(0));
+    }
+
+    #[test]
+    fn requeues_tasks_whose_worker_died() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::new(dir.path());
+        let mut live = queue.add("live").unwrap();
+        let mut dead = queue.add("dead").unwrap();
+        let mut unowned = queue.add("unowned").unwrap();
+
+        live.status = TaskStatus::Running;
+        live.worker = Some(std::process::id());
+        queue.update(&live).unwrap();
+        // A reaped child's pid is free until something reuses it
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        dead.status = TaskStatus::Running;
+        dead.started_at = Some(now());
+        dead.worker = Some(child.id());
+        queue.update(&dead).unwrap();
+        unowned.status = TaskStatus::Running;
+        queue.update(&unowned).unwrap();
+
+        assert_eq!(queue.requeue_stale().unwrap(), [2, 3]);
+        let tasks = queue.tasks().unwrap();
+        assert_eq!(tasks[0].status, TaskStatus::Running);
+        assert_eq!(tasks[1].status, TaskStatus::Queued);
+        assert_eq!(tasks[1].started_at, None);
+        assert_eq!(queue.next().unwrap().unwrap().prompt, "dead");
+    }
+
+    #[test]
+    fn concurrent_adds_and_updates_keep_every_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::new(dir.path());
+        let mut first = queue.add("first").unwrap();
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..50 {
+                    queue.add(&format!("task {i}")).unwrap();
+                }
+            });
+            scope.spawn(|| {
+                for code in 0..50 {
+                    first.exit_code = Some(code);
+                    queue.update(&first).unwrap();
+                }
+            });
+        });
+
+        let tasks = queue.tasks().unwrap();
+        let ids: HashSet<_> = tasks.iter().map(|t| t.id).collect();
+        assert_eq!(tasks.len(), 51);
+        assert_eq!(ids.len(), 51);
+        assert_eq!(tasks[0].exit_code, Some(49));
+    }
+
+    #[test]
+    fn claimed_tasks_go_to_one_worker() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::new(dir.path());
+        queue.add("only").unwrap();
+
+        let claimed = queue.claim_next().unwrap().unwrap();
+        assert_eq!(claimed.status, TaskStatus::Running);
+        assert_eq!(claimed.worker, Some(std::process::id()));
+        assert!(queue.claim_next().unwrap().is_none());
+    }
+
+    #[test]
+    fn empty_queue() {
+        let dir = tempfile::tempdir().unwrap();
+        let queue = Queue::new(&dir.path().join("missing"));
+        assert!(queue.tasks().unwrap().is_empty());
+        assert!(queue.next().unwrap().is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! Published IP ranges for services whose addresses can't be pinned by
+//! resolving a hostname: GitHub, and CDNs like Cloudflare, Fastly, and AWS.
+
+use std::process::Command;
+
+use color_eyre::eyre::{Result, bail};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A source of CIDRs to add to the firewall allowlist.
+pub trait RangeProvider {
+    fn name(&self) -> &str;
+    fn fetch(&self, http: &Http) -> Result<Vec<String>>;
+
+    /// Identifies what `fetch` returns, for caching.
+    fn key(&self) -> String {
+        self.name().to_string()
+    }
+}
+
+/// A provider listed under `ip_ranges` in config.
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+#[serde(tag = "provider", rename_all = "lowercase")]
+#[schemars(deny_unknown_fields)]
+pub enum RangeSource {
+    Github,
+    Cloudflare,
+    Fastly,
+    Aws {
+        /// Only prefixes for these services (e.g. `S3`, `CLOUDFRONT`).
+        #[serde(default)]
+        services: Vec<String>,
+        /// Only prefixes in these regions (e.g. `us-east-1`, `GLOBAL`).
+        #[serde(default)]
+        regions: Vec<String>,
+    },
+}
+
+impl RangeSource {
+    pub fn provider(&self) -> Box<dyn RangeProvider> {
+        match self {
+            RangeSource::Github => Box::new(Github),
+            RangeSource::Cloudflare => Box::new(Cloudflare),
+            RangeSource::Fastly => Box::new(Fastly),
+            RangeSource::Aws { services, regions } => Box::new(Aws {
+                services: services.clone(),
+                regions: regions.clone(),
+            }),
+        }
+    }
+}
+
+/// Providers for the configured sources, plus GitHub when `api.github.com`
+/// is allowed.
+pub fn providers(sources: &[RangeSource], domains: &[String]) -> Vec<Box<dyn RangeProvider>> {
+    let mut sources = sources.to_vec();
+    if domains.iter().any(|d| d == "api.github.com") && !sources.contains(&RangeSource::Github) {
+        sources.insert(0, RangeSource::Github);
+    }
+    sources.iter().map(RangeSource::provider).collect()
+}
+
+/// Fetches range lists with `curl`, through `network.proxy` when set.
+#[derive(Debug, Default)]
+pub struct Http {
+    pub proxy: Option<String>,
+}
+
+impl Http {
+    fn get(&self, url: &str) -> Result<Vec<u8>> {
+        let mut cmd = Command::new("curl");
+        cmd.args(["-fsSL", "--max-time", "10"]);
+        if let Some(proxy) = &self.proxy {
+            cmd.args(["--proxy", proxy]);
+        }
+        let output = cmd.arg(url).output()?;
+        if !output.status.success() {
+            bail!("curl {} exited with {}", url, output.status);
+        }
+        Ok(output.stdout)
+    }
+
+    fn get_json(&self, url: &str) -> Result<Value> {
+        Ok(serde_json::from_slice(&self.get(url)?)?)
+    }
+}
+
+fn strings(value: &Value) -> impl Iterator<Item = String> + '_ {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|v| v.as_str())
+        .map(String::from)
+}
+
+/// GitHub's web, API, and git ranges from `api.github.com/meta`.
+pub struct Github;
+
+impl RangeProvider for Github {
+    fn name(&self) -> &str {
+        "github"
+    }
+
+    fn fetch(&self, http: &Http) -> Result<Vec<String>> {
+        Ok(github_cidrs(&http.get_json("https://api.github.com/meta")?))
+    }
+}
+
+fn github_cidrs(meta: &Value) -> Vec<String> {
+    ["web", "api", "git"]
+        .iter()
+        .flat_map(|key| strings(&meta[key]))
+        .collect()
+}
+
+pub struct Cloudflare;
+
+impl RangeProvider for Cloudflare {
+    fn name(&self) -> &str {
+        "cloudflare"
+    }
+
+    fn fetch(&self, http: &Http) -> Result<Vec<String>> {
+        let mut ranges = vec![];
+        for url in [
+            "https://www.cloudflare.com/ips-v4",
+            "https://www.cloudflare.com/ips-v6",
+        ] {
+            ranges.extend(lines(&String::from_utf8_lossy(&http.get(url)?)));
+        }
+        Ok(ranges)
+    }
+}
+
+fn lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+pub struct Fastly;
+
+impl RangeProvider for Fastly {
+    fn name(&self) -> &str {
+        "fastly"
+    }
+
+    fn fetch(&self, http: &Http) -> Result<Vec<String>> {
+        Ok(fastly_cidrs(
+            &http.get_json("https://api.fastly.com/public-ip-list")?,
+        ))
+    }
+}
+
+fn fastly_cidrs(list: &Value) -> Vec<String> {
+    strings(&list["addresses"])
+        .chain(strings(&list["ipv6_addresses"]))
+        .collect()
+}
+
+/// AWS prefixes from `ip-ranges.json`, optionally narrowed by service and
+/// region.
+pub struct Aws {
+    pub services: Vec<String>,
+    pub regions: Vec<String>,
+}
+
+impl RangeProvider for Aws {
+    fn name(&self) -> &str {
+        "aws"
+    }
+
+    fn key(&self) -> String {
+        format!("aws:{}:{}", self.services.join(","), self.regions.join(","))
+    }
+
+    fn fetch(&self, http: &Http) -> Result<Vec<String>> {
+        Ok(self.cidrs(&http.get_json("https://ip-ranges.amazonaws.com/ip-ranges.json")?))
+    }
+}
+
+impl Aws {
+    fn cidrs(&self, ranges: &Value) -> Vec<String> {
+        let wanted = |filter: &[String], value: &Value| {
+            filter.is_empty()
+                || value
+                    .as_str()
+                    .is_some_and(|v| filter.iter().any(|f| f == v))
+        };
+
+        let mut cidrs = vec![];
+        for (list, key) in [("prefixes", "ip_prefix"), ("ipv6_prefixes", "ipv6_prefix")] {
+            for prefix in ranges[list].as_array().into_iter().flatten() {
+                if wanted(&self.services, &prefix["service"])
+                    && wanted(&self.regions, &prefix["region"])
+                    && let Some(cidr) = prefix[key].as_str()
+                {
+                    cidrs.push(cidr.to_string());
+                }
+            }
+        }
+        cidrs.sort();
+        cidrs.dedup();
+        cidrs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn github_keeps_ipv6_ranges() {
+        let meta = json!({
+            "web": ["140.82.112.0/20", "2a0a:a440::/29"],
+            "api": ["192.30.252.0/22"],
+            "hooks": ["143.55.64.0/20"],
+        });
+        assert_eq!(
+            github_cidrs(&meta),
+            ["140.82.112.0/20", "2a0a:a440::/29", "192.30.252.0/22"]
+        );
+    }
+
+    #[test]
+    fn fastly_and_cloudflare_lists() {
+        let list = json!({ "addresses": ["23.235.32.0/20"], "ipv6_addresses": ["2a04:4e40::/32"] });
+        assert_eq!(fastly_cidrs(&list), ["23.235.32.0/20", "2a04:4e40::/32"]);
+        assert_eq!(
+            lines("173.245.48.0/20\n103.21.244.0/22\n"),
+            ["173.245.48.0/20", "103.21.244.0/22"]
+        );
+    }
+
+    #[test]
+    fn aws_filters_by_service_and_region() {
+        let ranges = json!({
+            "prefixes": [
+                { "ip_prefix": "3.5.0.0/19", "region": "us-east-1", "service": "S3" },
+                { "ip_prefix": "3.5.0.0/19", "region": "us-east-1", "service": "AMAZON" },
+                { "ip_prefix": "52.95.0.0/16", "region": "eu-west-1", "service": "S3" },
+            ],
+            "ipv6_prefixes": [
+                { "ipv6_prefix": "2600:1f18::/33", "region": "us-east-1", "service": "S3" },
+            ],
+        });
+        let aws = Aws {
+            services: vec!["S3".to_string()],
+            regions: vec!["us-east-1".to_string()],
+        };
+        assert_eq!(aws.cidrs(&ranges), ["2600:1f18::/33", "3.5.0.0/19"]);
+
+        let all = Aws {
+            services: vec![],
+            regions: vec![],
+        };
+        assert_eq!(all.cidrs(&ranges).len(), 3);
+    }
+
+    #[test]
+    fn github_added_for_api_github_com() {
+        let names = |sources: &[RangeSource], domains: &[&str]| {
+            let domains: Vec<_> = domains.iter().map(|d| d.to_string()).collect();
+            providers(sources, &domains)
+                .iter()
+                .map(|p| p.name().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&[RangeSource::Cloudflare], &["api.github.com"]),
+            ["github", "cloudflare"]
+        );
+        assert_eq!(
+            names(&[RangeSource::Cloudflare], &["github.com"]),
+            ["cloudflare"]
+        );
+    }
+}
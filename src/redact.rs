@@ -0,0 +1,157 @@
+//! Masking credentials before they're shown or logged.
+//!
+//! Values are [`register`]ed as contenant reads them: secrets as they're
+//! resolved, bridge tokens and keys as they're issued, tokens the bridge
+//! mints, and the values of env vars whose names look sensitive. [`redact`]
+//! replaces each with [`MASK`]. [`Stderr`] applies it to tracing output,
+//! which is also the bridge's log, `main` to error reports, and
+//! [`redact_file`] to session logs once the session ends.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::RwLock;
+
+use color_eyre::eyre::{Result, WrapErr};
+
+pub const MASK: &str = "[redacted]";
+
+/// Shorter values are too likely to turn up by accident, and masking them
+/// would garble the output more than it protects.
+const MIN_LEN: usize = 8;
+
+/// Name fragments of env vars holding credentials.
+const SENSITIVE_NAMES: &[&str] = &[
+    "TOKEN",
+    "SECRET",
+    "PASSWORD",
+    "PASSWD",
+    "PASSPHRASE",
+    "CREDENTIAL",
+    "API_KEY",
+    "APIKEY",
+    "PRIVATE_KEY",
+    "ACCESS_KEY",
+];
+
+static VALUES: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Mask `value` from now on.
+pub fn register(value: &str) {
+    let value = value.trim();
+    if value.len() < MIN_LEN {
+        return;
+    }
+    let mut values = VALUES.write().unwrap();
+    if !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+        // Longest first, so a value containing another is masked whole
+        values.sort_by_key(|v| std::cmp::Reverse(v.len()));
+    }
+}
+
+/// Whether an env var named `name` probably holds a credential.
+pub fn is_sensitive_name(name: &str) -> bool {
+    let name = name.to_ascii_uppercase();
+    SENSITIVE_NAMES.iter().any(|part| name.contains(part))
+}
+
+/// Mask the values of the sensitive-looking variables in `vars`.
+pub fn register_env<K: AsRef<str>, V: AsRef<str>>(vars: impl IntoIterator<Item = (K, V)>) {
+    for (name, value) in vars {
+        if is_sensitive_name(name.as_ref()) {
+            register(value.as_ref());
+        }
+    }
+}
+
+/// `text` with every registered value masked.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let values = VALUES.read().unwrap();
+    let mut text = Cow::Borrowed(text);
+    for value in values.iter() {
+        if text.contains(value.as_str()) {
+            text = Cow::Owned(text.replace(value.as_str(), MASK));
+        }
+    }
+    text
+}
+
+/// Redact a file in place, if anything in it needs it.
+pub fn redact_file(path: &Path) -> Result<()> {
+    let Ok(bytes) = fs::read(path) else {
+        return Ok(());
+    };
+    let text = String::from_utf8_lossy(&bytes);
+    if let Cow::Owned(redacted) = redact(&text) {
+        fs::write(path, redacted)
+            .wrap_err_with(|| format!("Couldn't redact {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Stderr for tracing, redacted. Each event arrives in one write.
+pub struct Stderr;
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for Stderr {
+    type Writer = Writer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        Writer(io::stderr())
+    }
+}
+
+pub struct Writer(io::Stderr);
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match std::str::from_utf8(buf) {
+            Ok(text) => self.0.write_all(redact(text).as_bytes())?,
+            Err(_) => self.0.write_all(buf)?,
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks_registered_values() {
+        register("redact-test-token-1234");
+        register("redact-test-token-1234-longer");
+        register("short");
+        assert_eq!(
+            redact("sent redact-test-token-1234-longer and redact-test-token-1234"),
+            "sent [redacted] and [redacted]"
+        );
+        assert_eq!(redact("short stays"), "short stays");
+        assert!(matches!(redact("nothing here"), Cow::Borrowed(_)));
+
+        register_env([
+            ("REDACT_TEST_API_KEY", "redact-test-api-key-value"),
+            ("REDACT_TEST_HOME", "redact-test-home-value"),
+        ]);
+        assert_eq!(redact("redact-test-api-key-value"), MASK);
+        assert_eq!(redact("redact-test-home-value"), "redact-test-home-value");
+        assert!(is_sensitive_name("gh_token"));
+        assert!(!is_sensitive_name("SSH_AUTH_SOCK"));
+    }
+
+    #[test]
+    fn redacts_files_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("output.log");
+        register("redact-test-file-secret");
+        fs::write(&log, "echo redact-test-file-secret\n").unwrap();
+        redact_file(&log).unwrap();
+        assert_eq!(fs::read_to_string(&log).unwrap(), "echo [redacted]\n");
+        redact_file(&dir.path().join("missing")).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+//! `remote_config`: a team's config kept in a git repo, cloned into XDG data
+//! by `contenant config sync` and layered between system and user config.
+//!
+//! Loading config never touches the network; it reads whatever the last sync
+//! left in the checkout.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use sha2::{Digest, Sha256};
+
+/// Directory under XDG data holding one checkout per URL.
+pub const CHECKOUTS_DIR: &str = "remote-config";
+
+/// Where `url` is checked out. Each URL gets its own directory, so pointing
+/// `remote_config` elsewhere never layers the old repo's config.
+pub fn checkout_dir(xdg_dirs: &xdg::BaseDirectories, url: &str) -> Result<PathBuf> {
+    let Some(data_home) = xdg_dirs.get_data_home() else {
+        bail!("Couldn't find the XDG data directory");
+    };
+    let hash = format!("{:x}", Sha256::digest(url.as_bytes()));
+    Ok(data_home.join(CHECKOUTS_DIR).join(&hash[..16]))
+}
+
+/// Clone `url`, or fast-forward its existing checkout, returning the
+/// checkout's directory.
+pub fn sync(xdg_dirs: &xdg::BaseDirectories, url: &str) -> Result<PathBuf> {
+    let dir = checkout_dir(xdg_dirs, url)?;
+    if dir.join(".git").exists() {
+        git(Command::new("git")
+            .arg("-C")
+            .arg(&dir)
+            .args(["pull", "--ff-only", "--quiet"]))
+        .wrap_err_with(|| format!("Couldn't update {}", url))?;
+    } else {
+        std::fs::create_dir_all(dir.parent().unwrap())?;
+        git(Command::new("git")
+            .args(["clone", "--quiet", "--depth", "1", url])
+            .arg(&dir))
+        .wrap_err_with(|| format!("Couldn't clone {}", url))?;
+    }
+    Ok(dir)
+}
+
+/// The commit a checkout is at, for reporting after a sync.
+pub fn head(dir: &Path) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()?;
+    if !output.status.success() {
+        bail!("{} isn't a git checkout", dir.display());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn git(cmd: &mut Command) -> Result<()> {
+    let status = cmd.status().wrap_err("Couldn't run git")?;
+    if !status.success() {
+        bail!("git exited with {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::isolated_app_dirs;
+
+    #[test]
+    fn each_url_gets_its_own_checkout() {
+        let dirs = isolated_app_dirs(Path::new("/scratch"));
+        let a = checkout_dir(&dirs, "git@github.com:org/policy.git").unwrap();
+        let b = checkout_dir(&dirs, "https://github.com/org/policy.git").unwrap();
+        assert!(a.starts_with("/scratch/data/contenant/remote-config"));
+        assert_ne!(a, b);
+        assert_eq!(
+            a,
+            checkout_dir(&dirs, "git@github.com:org/policy.git").unwrap()
+        );
+    }
+}
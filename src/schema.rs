@@ -0,0 +1,490 @@
+//! JSON Schema for config files, for editor completion and diagnostics, and
+//! the strict check behind `contenant config validate`.
+//!
+//! Loading config ignores unknown keys; validation reports them, by walking
+//! the YAML against the schema, alongside the errors loading would hit.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Result;
+use schemars::generate::SchemaSettings;
+use serde_json::{Value, json};
+use serde_yaml_ng::Value as Yaml;
+
+use crate::config::Config;
+
+/// The schema for `config.yml`, at any layer, derived from `Config` and
+/// the doc comments on its fields.
+pub fn config_schema() -> Value {
+    let mut schema = SchemaSettings::draft07()
+        .into_generator()
+        .into_root_schema_for::<Config>()
+        .to_value();
+    schema["title"] = json!("contenant config");
+    schema
+}
+
+/// A problem found in a config file.
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.message),
+            None => write!(f, "{}: {}", self.path.display(), self.message),
+        }
+    }
+}
+
+/// Check a config file strictly: it has to load, and every key has to be
+/// one contenant knows.
+pub fn validate_file(path: &Path) -> Result<Vec<Problem>> {
+    Ok(validate(path, &fs::read_to_string(path)?))
+}
+
+/// Check `text` as the contents of the config file at `path`.
+pub fn validate(path: &Path, text: &str) -> Vec<Problem> {
+    let value: Yaml = match serde_yaml_ng::from_str(text) {
+        Ok(value) => value,
+        Err(err) => {
+            let (line, message) = located(&err);
+            return vec![Problem {
+                path: path.to_path_buf(),
+                line,
+                message,
+            }];
+        }
+    };
+    let mut problems = unknown_key_problems(path, text, &value);
+    if let Err(err) = serde_yaml_ng::from_str::<Config>(text) {
+        let (line, message) = located(&err);
+        problems.push(Problem {
+            path: path.to_path_buf(),
+            line,
+            message,
+        });
+    }
+    problems.sort_by_key(|p| p.line);
+    problems
+}
+
+/// The keys in a config file that loading ignores because contenant
+/// doesn't know them. Syntax errors are left to loading.
+pub fn unknown_keys_in(path: &Path, text: &str) -> Vec<Problem> {
+    match serde_yaml_ng::from_str(text) {
+        Ok(value) => unknown_key_problems(path, text, &value),
+        Err(_) => vec![],
+    }
+}
+
+fn unknown_key_problems(path: &Path, text: &str, value: &Yaml) -> Vec<Problem> {
+    let schema = config_schema();
+    let mut unknown = vec![];
+    unknown_keys(&schema, &schema, value, &mut vec![], &mut unknown);
+    unknown
+        .into_iter()
+        .map(|key_path| Problem {
+            path: path.to_path_buf(),
+            line: locate(text, &key_path),
+            message: format!("unknown key `{}`", display_path(&key_path)),
+        })
+        .collect()
+}
+
+/// The error's line, and its message without the location it ends with.
+fn located(err: &serde_yaml_ng::Error) -> (Option<usize>, String) {
+    let message = err.to_string();
+    match err.location() {
+        Some(location) => {
+            let suffix = format!(" at line {} column {}", location.line(), location.column());
+            let message = message.strip_suffix(&suffix).unwrap_or(&message);
+            (Some(location.line()), message.to_string())
+        }
+        None => (None, message),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+fn display_path(path: &[Segment]) -> String {
+    let mut out = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(key) if out.is_empty() => out.push_str(key),
+            Segment::Key(key) => {
+                out.push('.');
+                out.push_str(key);
+            }
+            Segment::Index(i) => out.push_str(&format!("[{}]", i)),
+        }
+    }
+    out
+}
+
+/// Collect the paths of keys in `value` that `schema` doesn't allow.
+fn unknown_keys(
+    root: &Value,
+    schema: &Value,
+    value: &Yaml,
+    path: &mut Vec<Segment>,
+    out: &mut Vec<Vec<Segment>>,
+) {
+    let schema = resolve(root, schema);
+    if let Some(alternatives) = schema["anyOf"].as_array().or(schema["oneOf"].as_array()) {
+        let wanted = match value {
+            Yaml::Mapping(_) => "object",
+            Yaml::Sequence(_) => "array",
+            _ => return,
+        };
+        // The alternative the value fits best, like the `ip_ranges` entry
+        // with its `provider`
+        let best = alternatives
+            .iter()
+            .filter(|a| has_type(resolve(root, a), wanted) && tag_matches(resolve(root, a), value))
+            .map(|alternative| {
+                let mut unknown = vec![];
+                unknown_keys(root, alternative, value, path, &mut unknown);
+                unknown
+            })
+            .min_by_key(Vec::len);
+        out.extend(best.unwrap_or_default());
+        return;
+    }
+    match value {
+        Yaml::Mapping(mapping) => {
+            for (key, child) in mapping {
+                let Some(key) = key.as_str() else {
+                    continue;
+                };
+                path.push(Segment::Key(key.to_string()));
+                match (&schema["properties"][key], &schema["additionalProperties"]) {
+                    (Value::Null, Value::Bool(false)) => out.push(path.clone()),
+                    (Value::Null, Value::Object(_)) => {
+                        unknown_keys(root, &schema["additionalProperties"], child, path, out)
+                    }
+                    (Value::Null, _) => {}
+                    (property, _) => unknown_keys(root, property, child, path, out),
+                }
+                path.pop();
+            }
+        }
+        Yaml::Sequence(items) if schema["items"].is_object() => {
+            for (i, item) in items.iter().enumerate() {
+                path.push(Segment::Index(i));
+                unknown_keys(root, &schema["items"], item, path, out);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `schema` allows JSON values of type `wanted`, alone or among
+/// others like `["array", "null"]`.
+fn has_type(schema: &Value, wanted: &str) -> bool {
+    match &schema["type"] {
+        Value::Array(types) => types.iter().any(|t| t == wanted),
+        t => t == wanted,
+    }
+}
+
+/// Whether `value` has the tag, like `provider: aws`, that `schema` fixes
+/// with a `const`, if any.
+fn tag_matches(schema: &Value, value: &Yaml) -> bool {
+    let Some(properties) = schema["properties"].as_object() else {
+        return true;
+    };
+    properties.iter().all(|(key, property)| {
+        match (property.get("const"), value.get(key.as_str())) {
+            (Some(tag), Some(Yaml::String(given))) => tag == given.as_str(),
+            (Some(_), _) => false,
+            (None, _) => true,
+        }
+    })
+}
+
+/// Follow a `$ref` to `#/definitions/...`.
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema["$ref"].as_str() {
+        Some(reference) => reference
+            .strip_prefix("#/definitions/")
+            .map_or(schema, |name| &root["definitions"][name]),
+        None => schema,
+    }
+}
+
+/// The 1-based line of the key at `path` in block-style YAML, if it can be
+/// found. Flow-style mappings (`{a: 1}`) aren't followed.
+fn locate(text: &str, path: &[Segment]) -> Option<usize> {
+    // (line number, indent, content) of the lines in the current block
+    let mut block: Vec<(usize, usize, &str)> = text
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let content = line.trim_start();
+            (!content.is_empty() && !content.starts_with('#'))
+                .then(|| (i + 1, line.len() - content.len(), content))
+        })
+        .collect();
+    let is_item = |content: &str| content == "-" || content.starts_with("- ");
+
+    let mut line = None;
+    for segment in path {
+        let indent = block.first()?.1;
+        let at = match segment {
+            Segment::Key(key) => block.iter().position(|&(_, i, content)| {
+                i == indent
+                    && (content.starts_with(&format!("{}:", key))
+                        || content.starts_with(&format!("\"{}\":", key)))
+            })?,
+            Segment::Index(n) => {
+                block
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &(_, i, content))| i == indent && is_item(content))
+                    .nth(*n)?
+                    .0
+            }
+        };
+        let (number, _, content) = block[at];
+        line = Some(number);
+        // A key's block may be a sequence at the key's own indent
+        let rest = block[at + 1..]
+            .iter()
+            .copied()
+            .take_while(|&(_, i, content)| {
+                i > indent
+                    || (i == indent && is_item(content) && matches!(segment, Segment::Key(_)))
+            });
+        block = match segment {
+            Segment::Key(_) => rest.collect(),
+            Segment::Index(_) => {
+                // The item's first line continues after the `- `
+                let inner = content[1..].trim_start();
+                let first = (number, indent + content.len() - inner.len(), inner);
+                std::iter::once(first)
+                    .filter(|&(_, _, inner)| !inner.is_empty())
+                    .chain(rest)
+                    .collect()
+            }
+        };
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVERYTHING: &str = r#"
+include: [../shared/base.yml]
+strict: true
+remote_config: git@github.com:org/contenant-policy.git
+allow_docker: true
+forbid_docker: false
+claude:
+  version: 2.1.29
+  keychain: sync
+  credential_store: secret-service
+aws:
+  profile: dev
+ssh_agent:
+  forward: true
+  socket: ~/.1password/agent.sock
+  sign_commits: true
+  signing_key: ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIExample
+mounts:
+  - source: ~/.gitconfig
+    target: /home/claude/.gitconfig
+    readonly: true
+    create_missing: false
+    consistency: cached
+    propagation: rprivate
+    when: {os: macos, arch: aarch64, hostname: work-*}
+  - type: tmpfs
+    target: /tmp/build
+    size: 512m
+    mode: 1777
+  - type: volume
+    source: cargo
+    target: ~/.cargo/registry
+    readonly: false
+env:
+  EDITOR: vim
+  PAGER: {value: less, when: {os: linux}}
+  SSH_AUTH_SOCK:
+    - value: /run/1password.sock
+      when: {os: macos}
+    - value: /run/keyring.sock
+env_passthrough: [SSH_AUTH_SOCK]
+env_files: [../.env]
+secrets:
+  GH_TOKEN: env:GH_TOKEN
+  npmrc: {from: "op://dev/npm/token", file: true}
+secret_scan: block
+bridge:
+  port: 19432
+  socket: false
+  clipboard: true
+  open_urls: [https://github.com/]
+  artifacts_dir: ~/artifacts
+  artifacts_max_mb: 10
+  webhooks: [https://hooks.example.com/]
+  webhook_secret: s3cret
+  watch: true
+  address: 127.0.0.1
+  tls: true
+  client_certs: true
+  tls_names: [devbox]
+  github_tokens: true
+  github_app:
+    app_id: 12345
+    private_key: github-app.pem
+    permissions: {contents: write, pull_requests: write}
+  triggers:
+    build: make
+    deploy:
+      command: [make, deploy]
+      shell: false
+      description: Deploy
+      cwd: ..
+      env: {STAGE: prod}
+      allow_args: true
+      timeout_secs: 60
+      max_concurrent: 1
+      confirm: true
+allowed_domains: [example.com]
+ip_ranges:
+  - provider: github
+  - provider: aws
+    services: [S3]
+    regions: [us-east-1]
+presets: [rust]
+denied_domains: [evil.example.com]
+dns_proxy: true
+ports: ["3000"]
+security:
+  apparmor: contenant-default
+  selinux_label: disable
+  selinux_relabel: private
+  limits:
+    pids: 512
+    nofile: 4096
+    nproc: 256
+hardening:
+  drop_capabilities: true
+  no_new_privileges: true
+  seccomp: seccomp.json
+  read_only_root: true
+  writable_paths: [/tmp]
+engine:
+  backend: docker
+  kubernetes:
+    context: dev
+    namespace: agents
+    registry: registry.example.com
+    startup_timeout_secs: 60
+  autostart_vm: true
+network:
+  mode: proxy
+  proxy_port: 3128
+  proxy_allowed_ports: [443, 8443]
+  ip_cache_ttl_secs: 60
+  resolvers: [1.1.1.1]
+  domain_resolvers: {corp.example.com: [10.0.0.53]}
+  shared: true
+  group: team
+  proxy: http://proxy.corp:3128
+  no_proxy: [localhost]
+  allow_local: true
+  max_egress: 10mbit
+  max_ingress: 50mbit
+jj:
+  push: true
+git:
+  name: Contenant Agent
+  email: agent@example.com
+  signing_key: ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIExample
+  safe_directories: [/workspace]
+image:
+  ref: ghcr.io/kejadlen/contenant:0.1.0
+  platform: linux/amd64
+  cosign_key: cosign.pub
+  attestation: slsaprovenance
+workspace:
+  mode: copy
+  path: services/api
+  workdir: src
+  exclude: [node_modules]
+  writable: [docs]
+  host_user: false
+"#;
+
+    fn validate(text: &str) -> Vec<String> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.yml");
+        fs::write(&path, text).unwrap();
+        validate_file(&path)
+            .unwrap()
+            .into_iter()
+            .map(|p| format!("{:?}: {}", p.line, p.message))
+            .collect()
+    }
+
+    #[test]
+    fn schema_covers_every_key() {
+        assert_eq!(validate(EVERYTHING), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reports_unknown_keys_with_lines() {
+        let problems = validate(
+            "bridge:\n  triggers:\n    deploy:\n      command: make\n      confrim: true\n\
+             mounts:\n- source: a\n  readonly: true\n- source: b\n  read_only: true\n\
+             alowed_domains: [example.com]\n",
+        );
+        assert_eq!(
+            problems,
+            [
+                "Some(5): unknown key `bridge.triggers.deploy.confrim`",
+                "Some(10): unknown key `mounts[1].read_only`",
+                "Some(11): unknown key `alowed_domains`",
+            ]
+        );
+    }
+
+    #[test]
+    fn checks_keys_against_the_alternative_that_fits() {
+        let problems = validate(
+            "ip_ranges:\n- provider: aws\n  services: [S3]\n- provider: github\n  regions: [GLOBAL]\n\
+             env:\n  EDITOR:\n    value: vim\n    wehn: {os: linux}\n",
+        );
+        assert_eq!(
+            problems,
+            [
+                "Some(5): unknown key `ip_ranges[1].regions`",
+                "Some(9): unknown key `env.EDITOR.wehn`",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_type_errors_with_lines() {
+        let problems = validate("network:\n  mode: tunnel\n");
+        assert_eq!(
+            problems,
+            ["Some(2): network.mode: unknown variant `tunnel`, expected `firewall` or `proxy`"]
+        );
+    }
+}
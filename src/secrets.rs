@@ -0,0 +1,252 @@
+//! Resolving `secrets:` on the host at run time.
+//!
+//! Values are read when the container starts and written, one file each,
+//! to a directory mounted for the length of the run, so they stay out of the
+//! engine's command line and the container's config. The entrypoint exports
+//! them or copies them onto a tmpfs at `/run/secrets`. The directory is in
+//! `$XDG_RUNTIME_DIR`, in memory, where there is one.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+use crate::config::Secret;
+
+/// Where file secrets are written in the container, on a tmpfs.
+pub const CONTAINER_DIR: &str = "/run/secrets";
+
+/// Prefix of 1Password secret references, which are also read from `env`
+/// values.
+pub const OP_PREFIX: &str = "op://";
+
+/// Whether `value` is a 1Password secret reference:
+/// `op://vault/item/[section/]field`, optionally with a `?attribute=` query.
+pub fn is_op_reference(value: &str) -> bool {
+    let Some(path) = value.strip_prefix(OP_PREFIX) else {
+        return false;
+    };
+    let path = path.split_once('?').map_or(path, |(path, _)| path);
+    let parts: Vec<_> = path.split('/').collect();
+    (3..=4).contains(&parts.len()) && parts.iter().all(|part| !part.is_empty())
+}
+
+/// Where [`RunSecrets`] are written, under the runtime or state directory.
+pub const RUNS_DIR: &str = "secret-runs";
+
+/// Where a run's [`RunSecrets`] are mounted for the entrypoint.
+pub const CONTAINER_SOURCE_DIR: &str = "/run/contenant-secrets";
+
+/// Prefix marking the secrets the entrypoint puts in files under
+/// [`CONTAINER_DIR`], rather than in the environment.
+pub const FILE_ENV_PREFIX: &str = "CONTENANT_SECRET_FILE_";
+
+/// `secrets` by env var name, with each value resolved. With `dry_run`
+/// nothing is read and values are placeholders.
+pub fn env(secrets: &BTreeMap<String, Secret>, dry_run: bool) -> Result<HashMap<String, String>> {
+    let mut env = HashMap::new();
+    for (name, secret) in secrets {
+        if !is_env_name(name) {
+            bail!("Secret name `{}` isn't a valid env var name", name);
+        }
+        if secret.from.starts_with(OP_PREFIX) && !is_op_reference(&secret.from) {
+            bail!(
+                "Secret {} isn't a 1Password reference like op://vault/item/field: {}",
+                name,
+                secret.from
+            );
+        }
+        let value = if dry_run {
+            format!("<{}>", secret.from)
+        } else {
+            let value =
+                resolve(&secret.from).wrap_err_with(|| format!("Couldn't read secret {}", name))?;
+            crate::redact::register(&value);
+            value
+        };
+        let key = if secret.file {
+            format!("{}{}", FILE_ENV_PREFIX, name)
+        } else {
+            name.clone()
+        };
+        env.insert(key, value);
+    }
+    Ok(env)
+}
+
+/// Read a secret reference: `env:NAME`, `keychain:SERVICE`, `op://...`, or
+/// `cmd:COMMAND`.
+pub fn resolve(reference: &str) -> Result<String> {
+    if let Some(name) = reference.strip_prefix("env:") {
+        return std::env::var(name).wrap_err_with(|| format!("${} isn't set", name));
+    }
+    if let Some(service) = reference.strip_prefix("keychain:") {
+        return if cfg!(target_os = "macos") {
+            output(Command::new("security").args(["find-generic-password", "-w", "-s", service]))
+        } else {
+            output(Command::new("secret-tool").args(["lookup", "service", service]))
+        };
+    }
+    if reference.starts_with(OP_PREFIX) {
+        // The 1Password app prompts to unlock, with biometrics if enabled
+        if !crate::on_path("op") {
+            bail!(
+                "op:// references need the 1Password CLI; see \
+                 https://developer.1password.com/docs/cli/get-started/"
+            );
+        }
+        return output(Command::new("op").args(["read", "--no-newline", reference]));
+    }
+    if let Some(command) = reference.strip_prefix("cmd:") {
+        return output(Command::new("sh").args(["-c", command]));
+    }
+    bail!(
+        "Unknown secret reference `{}`; expected env:, keychain:, op://, or cmd:",
+        reference
+    )
+}
+
+/// The command's stdout without its trailing newline. Its stderr and stdin
+/// are the terminal's, so it can prompt to unlock.
+fn output(command: &mut Command) -> Result<String> {
+    let program = command.get_program().to_string_lossy().into_owned();
+    let output = command
+        .stdout(Stdio::piped())
+        .output()
+        .wrap_err_with(|| format!("Couldn't run {}", program))?;
+    if !output.status.success() {
+        bail!("{} exited with {}", program, output.status);
+    }
+    let mut value = String::from_utf8(output.stdout)
+        .wrap_err_with(|| format!("{} printed something other than UTF-8", program))?;
+    if value.ends_with('\n') {
+        value.pop();
+    }
+    Ok(value)
+}
+
+/// A run's resolved secrets, one file per secret: `env/NAME` for variables
+/// and `files/NAME` for file secrets. Removed when dropped.
+pub struct RunSecrets {
+    pub dir: PathBuf,
+}
+
+impl RunSecrets {
+    /// Write `secrets`, as returned by [`env`], to a new directory under
+    /// `runs_dir`, which should only be readable by us.
+    pub fn write(runs_dir: &Path, secrets: &HashMap<String, String>) -> Result<Self> {
+        let run = Self {
+            dir: runs_dir.join(crate::bridge::random_hex()?),
+        };
+        // Readable by the entrypoint, whose uid may not match ours and which
+        // may not be able to override permissions
+        for dir in [run.dir.clone(), run.dir.join("env"), run.dir.join("files")] {
+            fs::create_dir_all(&dir)?;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        }
+        for (key, value) in secrets {
+            let path = match key.strip_prefix(FILE_ENV_PREFIX) {
+                Some(name) => run.dir.join("files").join(name),
+                None => run.dir.join("env").join(key),
+            };
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o644)
+                .open(&path)
+                .and_then(|mut file| file.write_all(value.as_bytes()))
+                .wrap_err_with(|| format!("Couldn't write {}", path.display()))?;
+        }
+        Ok(run)
+    }
+}
+
+impl Drop for RunSecrets {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secret(from: &str, file: bool) -> Secret {
+        Secret {
+            from: from.to_string(),
+            file,
+        }
+    }
+
+    #[test]
+    fn resolves_references() {
+        assert_eq!(resolve("cmd:printf 'a b\\n'").unwrap(), "a b");
+        assert_eq!(resolve("env:PATH").unwrap(), std::env::var("PATH").unwrap());
+        assert!(resolve("env:CONTENANT_TEST_UNSET_SECRET").is_err());
+        assert!(resolve("cmd:exit 3").is_err());
+        assert!(resolve("vault:x").is_err());
+    }
+
+    #[test]
+    fn recognizes_1password_references() {
+        assert!(is_op_reference("op://dev/npm/token"));
+        assert!(is_op_reference("op://dev/aws/prod/secret-key"));
+        assert!(is_op_reference(
+            "op://dev/ssh/private key?ssh-format=openssh"
+        ));
+        assert!(!is_op_reference("op://dev/npm"));
+        assert!(!is_op_reference("op://dev//token"));
+        assert!(!is_op_reference("https://dev/npm/token"));
+
+        // Caught even on dry runs
+        let secrets = BTreeMap::from([("NPM".to_string(), secret("op://dev/npm", false))]);
+        assert!(env(&secrets, true).is_err());
+    }
+
+    #[test]
+    fn file_secrets_go_through_prefixed_vars() {
+        let secrets = BTreeMap::from([
+            ("GH_TOKEN".to_string(), secret("cmd:echo gho_123", false)),
+            ("npmrc".to_string(), secret("cmd:echo registry", true)),
+        ]);
+        let vars = env(&secrets, false).unwrap();
+        assert_eq!(vars["GH_TOKEN"], "gho_123");
+        assert_eq!(vars["CONTENANT_SECRET_FILE_npmrc"], "registry");
+
+        // A dry run doesn't run anything
+        let secrets = BTreeMap::from([("X".to_string(), secret("cmd:exit 1", false))]);
+        assert_eq!(env(&secrets, true).unwrap()["X"], "<cmd:exit 1>");
+
+        let secrets = BTreeMap::from([("bad-name".to_string(), secret("env:PATH", false))]);
+        assert!(env(&secrets, false).is_err());
+    }
+
+    #[test]
+    fn run_secrets_are_a_file_each_until_dropped() {
+        let runs = tempfile::tempdir().unwrap();
+        let secrets = HashMap::from([
+            ("GH_TOKEN".to_string(), "gho_123\n".to_string()),
+            (format!("{}npmrc", FILE_ENV_PREFIX), "registry".to_string()),
+        ]);
+        let run = RunSecrets::write(runs.path(), &secrets).unwrap();
+        let read = |path: &str| fs::read_to_string(run.dir.join(path)).unwrap();
+        assert_eq!(read("env/GH_TOKEN"), "gho_123\n");
+        assert_eq!(read("files/npmrc"), "registry");
+
+        let dir = run.dir.clone();
+        drop(run);
+        assert!(!dir.exists());
+    }
+}
@@ -0,0 +1,165 @@
+//! Workspace archives for `contenant snapshot` and `contenant restore`, so a
+//! destructive session can be rolled back even outside git.
+//!
+//! Each snapshot is a gzipped tarball, written by the system `tar`, of the
+//! files [`changes::workspace_files`] lists: what git doesn't ignore, or
+//! everything but dependency and build directories outside a repo.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+
+use crate::{bridge, changes, queue};
+
+/// Directory under the project's state holding its snapshots.
+pub const SNAPSHOTS_DIR: &str = "snapshots";
+
+const EXTENSION: &str = "tar.gz";
+
+/// A saved snapshot.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    /// `<unix-secs>-<hex>`, so ids sort by creation time.
+    pub id: String,
+    pub archive: PathBuf,
+    /// Compressed size in bytes.
+    pub size: u64,
+}
+
+/// Archive `workspace` into `snapshots_dir`, returning the new snapshot's id.
+pub fn create(workspace: &Path, snapshots_dir: &Path) -> Result<String> {
+    fs::create_dir_all(snapshots_dir)?;
+    let id = format!("{}-{}", queue::now(), &bridge::random_hex()?[..8]);
+    let archive = archive_path(snapshots_dir, &id);
+
+    let mut list = vec![];
+    for file in changes::workspace_files(workspace)? {
+        list.extend_from_slice(file.as_bytes());
+        list.push(0);
+    }
+    let mut child = Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(workspace)
+        .args(["--null", "-T", "-"])
+        .stdin(Stdio::piped())
+        .spawn()
+        .wrap_err("Couldn't run tar")?;
+    child.stdin.take().unwrap().write_all(&list)?;
+    let status = child.wait()?;
+    if !status.success() {
+        let _ = fs::remove_file(&archive);
+        bail!("tar exited with {}", status);
+    }
+    Ok(id)
+}
+
+/// Snapshots in `snapshots_dir`, oldest first.
+pub fn list(snapshots_dir: &Path) -> Result<Vec<Snapshot>> {
+    let Ok(entries) = fs::read_dir(snapshots_dir) else {
+        return Ok(vec![]);
+    };
+    let suffix = format!(".{}", EXTENSION);
+    let mut snapshots: Vec<_> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            Some(Snapshot {
+                id: name.strip_suffix(&suffix)?.to_string(),
+                size: entry.metadata().ok()?.len(),
+                archive: entry.path(),
+            })
+        })
+        .collect();
+    snapshots.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(snapshots)
+}
+
+/// Put `workspace` back the way snapshot `id` found it: files it didn't have
+/// are deleted, and the rest are extracted over what's there. Returns how
+/// many files were deleted.
+pub fn restore(workspace: &Path, snapshots_dir: &Path, id: &str) -> Result<usize> {
+    let archive = archive_path(snapshots_dir, id);
+    if !archive.exists() {
+        bail!("No snapshot {}", id);
+    }
+
+    let output = Command::new("tar")
+        .arg("-tzf")
+        .arg(&archive)
+        .output()
+        .wrap_err("Couldn't run tar")?;
+    if !output.status.success() {
+        bail!("Couldn't read {}", archive.display());
+    }
+    let archived: HashSet<_> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::to_string)
+        .collect();
+
+    let mut deleted = 0;
+    for file in changes::workspace_files(workspace)? {
+        if !archived.contains(&file) {
+            fs::remove_file(workspace.join(&file))?;
+            deleted += 1;
+        }
+    }
+
+    let status = Command::new("tar")
+        .arg("-xzf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(workspace)
+        .status()
+        .wrap_err("Couldn't run tar")?;
+    if !status.success() {
+        bail!("tar exited with {}", status);
+    }
+    Ok(deleted)
+}
+
+fn archive_path(snapshots_dir: &Path, id: &str) -> PathBuf {
+    snapshots_dir.join(format!("{}.{}", id, EXTENSION))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn restores_edited_added_and_deleted_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        let snapshots = tempfile::tempdir().unwrap();
+        let write = |path: &str, contents: &str| {
+            let path = workspace.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        let read = |path: &str| fs::read_to_string(workspace.path().join(path)).ok();
+        write("src/main.rs", "fn main() {}");
+        write("notes.txt", "keep me");
+        write("node_modules/dep/index.js", "v1");
+
+        let id = create(workspace.path(), snapshots.path()).unwrap();
+        assert_eq!(list(snapshots.path()).unwrap()[0].id, id);
+
+        write("src/main.rs", "broken");
+        fs::remove_file(workspace.path().join("notes.txt")).unwrap();
+        write("scratch.txt", "agent output");
+        write("node_modules/dep/index.js", "v2");
+
+        assert_eq!(restore(workspace.path(), snapshots.path(), &id).unwrap(), 1);
+        assert_eq!(read("src/main.rs").as_deref(), Some("fn main() {}"));
+        assert_eq!(read("notes.txt").as_deref(), Some("keep me"));
+        assert_eq!(read("scratch.txt"), None);
+        // Not archived, so left alone
+        assert_eq!(read("node_modules/dep/index.js").as_deref(), Some("v2"));
+
+        assert!(restore(workspace.path(), snapshots.path(), "0-missing").is_err());
+    }
+}
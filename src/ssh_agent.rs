@@ -0,0 +1,138 @@
+//! The host's SSH agent, forwarded for pushing and commit signing.
+//!
+//! [`find`] looks for the agent where each kind leaves its socket:
+//! `SSH_AUTH_SOCK`, then 1Password's agent, then gpg-agent's SSH socket.
+//! The socket is mounted at [`CONTAINER_SOCKET`], and with
+//! `ssh_agent.sign_commits`, git in the container signs with the agent's
+//! key through [`signing_config`].
+
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, bail};
+
+/// Where the socket is mounted, and `SSH_AUTH_SOCK` points.
+pub const CONTAINER_SOCKET: &str = "/run/ssh-agent.sock";
+
+/// 1Password's agent socket, relative to the home directory.
+const ONEPASSWORD_MACOS: &str = "Library/Group Containers/2BUA8C4S2C.com.1password/t/agent.sock";
+const ONEPASSWORD_LINUX: &str = ".1password/agent.sock";
+
+/// The host's agent socket: `configured` if given, otherwise the first of
+/// `SSH_AUTH_SOCK`, 1Password's, and gpg-agent's that exists.
+pub fn find(configured: Option<&str>) -> Result<PathBuf> {
+    if let Some(socket) = configured {
+        if !is_socket(Path::new(socket)) {
+            bail!("ssh_agent.socket isn't a socket: {}", socket);
+        }
+        return Ok(PathBuf::from(socket));
+    }
+    let home = dirs::home_dir().unwrap_or_default();
+    let onepassword = if cfg!(target_os = "macos") {
+        ONEPASSWORD_MACOS
+    } else {
+        ONEPASSWORD_LINUX
+    };
+    let candidates = [
+        std::env::var_os("SSH_AUTH_SOCK").map(PathBuf::from),
+        Some(home.join(onepassword)),
+        gpg_agent_socket(),
+    ];
+    match candidates
+        .into_iter()
+        .flatten()
+        .find(|path| is_socket(path))
+    {
+        Some(socket) => Ok(socket),
+        None => bail!(
+            "ssh_agent.forward found no SSH agent: set SSH_AUTH_SOCK, run 1Password's or \
+             gpg-agent's, or set ssh_agent.socket"
+        ),
+    }
+}
+
+fn is_socket(path: &Path) -> bool {
+    path.metadata().is_ok_and(|m| m.file_type().is_socket())
+}
+
+/// gpg-agent's SSH socket, when `enable-ssh-support` is on.
+fn gpg_agent_socket() -> Option<PathBuf> {
+    let output = Command::new("gpgconf")
+        .args(["--list-dirs", "agent-ssh-socket"])
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    let path = String::from_utf8(output.stdout).ok()?;
+    (output.status.success() && !path.trim().is_empty()).then(|| PathBuf::from(path.trim()))
+}
+
+/// The first public key the agent at `socket` holds.
+pub fn first_key(socket: &Path) -> Result<String> {
+    let output = Command::new("ssh-add")
+        .arg("-L")
+        .env("SSH_AUTH_SOCK", socket)
+        .stderr(Stdio::null())
+        .output()?;
+    let keys = String::from_utf8_lossy(&output.stdout);
+    match keys.lines().next() {
+        Some(key) if output.status.success() => Ok(key.trim().to_string()),
+        _ => bail!(
+            "The SSH agent at {} has no keys to sign commits with; add one or set \
+             ssh_agent.signing_key",
+            socket.display()
+        ),
+    }
+}
+
+/// Git config signing commits and tags with `key`, a public key the agent
+/// holds.
+pub fn signing_config(key: &str) -> Vec<(String, String)> {
+    let key = match key.strip_prefix("key::") {
+        Some(_) => key.to_string(),
+        None => format!("key::{}", key),
+    };
+    [
+        ("gpg.format", "ssh"),
+        ("user.signingKey", key.as_str()),
+        ("commit.gpgSign", "true"),
+        ("tag.gpgSign", "true"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn finds_the_configured_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("agent.sock");
+        let _listener = UnixListener::bind(&socket).unwrap();
+        assert_eq!(find(Some(socket.to_str().unwrap())).unwrap(), socket);
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert!(find(Some(file.to_str().unwrap())).is_err());
+    }
+
+    #[test]
+    fn signs_with_the_key_literally() {
+        let config = signing_config("ssh-ed25519 AAAA test");
+        assert_eq!(
+            config[1],
+            (
+                "user.signingKey".to_string(),
+                "key::ssh-ed25519 AAAA test".to_string()
+            )
+        );
+        assert_eq!(
+            signing_config("key::ssh-ed25519 AAAA")[1].1,
+            "key::ssh-ed25519 AAAA"
+        );
+    }
+}
@@ -0,0 +1,308 @@
+//! `workspace.mode: sync`: the container works on its own copy of the
+//! project, kept in sync with the host by [Mutagen](https://mutagen.io)
+//! while the session runs.
+//!
+//! The copy starts from the same archive as `workspace.mode: copy`, so the
+//! agent doesn't wait for an initial sync. Once the container is up,
+//! [`supervise`] creates a two-way-safe Mutagen session between the project
+//! and the container's `/workspace`: changes flow both ways, and a path
+//! changed on both sides is a conflict that keeps both versions where they
+//! are instead of overwriting either.
+//!
+//! When the agent exits, the entrypoint writes [`AGENT_EXITED`] to the sync
+//! directory mounted at [`CONTAINER_SYNC_DIR`] and waits for [`FLUSHED`], so
+//! the last changes are synced before the container goes away. Before
+//! writing it, [`supervise`] copies the container's side of each conflict
+//! into [`CONFLICTS_DIR`] in the session's log directory.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::SESSION_LABEL;
+
+/// Directory the entrypoint and [`supervise`] signal each other through, in
+/// the session's log directory.
+pub const SYNC_DIR: &str = "sync";
+
+/// Where [`SYNC_DIR`] is mounted in the container.
+pub const CONTAINER_SYNC_DIR: &str = "/var/lib/contenant/sync";
+
+/// Written by the entrypoint when the agent exits.
+pub const AGENT_EXITED: &str = "agent-exited";
+
+/// Written by [`supervise`] once the last changes are synced.
+pub const FLUSHED: &str = "flushed";
+
+/// The container's side of conflicting paths, in the session's log
+/// directory.
+pub const CONFLICTS_DIR: &str = "sync-conflicts";
+
+/// Mutagen label holding the contenant session id.
+const SESSION_KEY: &str = "contenant-session";
+
+/// Mutagen label holding the project id's hash.
+const PROJECT_KEY: &str = "contenant-project";
+
+/// How long to wait for the container to start, or for the entrypoint to
+/// hear back after the agent exits.
+const TIMEOUT: Duration = Duration::from_secs(120);
+
+const POLL: Duration = Duration::from_millis(250);
+
+/// A Mutagen session, as `mutagen sync list` reports it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Status {
+    pub name: String,
+    #[serde(default)]
+    pub labels: std::collections::BTreeMap<String, String>,
+    /// What Mutagen is doing, like `watching` or `scanning-beta`.
+    #[serde(default)]
+    pub status: String,
+    #[serde(default)]
+    pub conflicts: Vec<Conflict>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl Status {
+    /// The contenant session this syncs.
+    pub fn session(&self) -> Option<&str> {
+        self.labels.get(SESSION_KEY).map(String::as_str)
+    }
+}
+
+/// A path changed on both sides.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Conflict {
+    /// Relative to the workspace.
+    pub root: String,
+}
+
+/// Name of the Mutagen session for contenant session `session`.
+pub fn session_name(session: &str) -> String {
+    format!("contenant-{}", session)
+}
+
+/// Mutagen label value for a project id, which is short and safe where the
+/// project's directory name may not be.
+pub fn project_label(project_id: &str) -> &str {
+    project_id.split('-').next().unwrap_or(project_id)
+}
+
+/// Bail unless Mutagen is installed.
+pub fn check_installed() -> Result<()> {
+    if !crate::on_path("mutagen") {
+        bail!("workspace.mode: sync needs Mutagen; see https://mutagen.io to install it");
+    }
+    Ok(())
+}
+
+/// Arguments to `mutagen` creating the session for `session`.
+pub fn create_args(
+    session: &str,
+    project_id: &str,
+    workspace: &Path,
+    container: &str,
+    exclude: &[String],
+) -> Vec<String> {
+    let mut args = vec![
+        "sync".to_string(),
+        "create".to_string(),
+        format!("--name={}", session_name(session)),
+        format!("--label={}={}", SESSION_KEY, session),
+        format!("--label={}={}", PROJECT_KEY, project_label(project_id)),
+        "--sync-mode=two-way-safe".to_string(),
+    ];
+    // Anchored to the workspace root
+    args.extend(exclude.iter().map(|path| format!("--ignore=/{}", path)));
+    args.push(workspace.display().to_string());
+    args.push(format!("docker://claude@{}/workspace", container));
+    args
+}
+
+/// Sync `workspace` with session `session`'s container until the agent
+/// exits, returning the paths that conflicted. `dir` is the session's log
+/// directory.
+pub fn supervise(
+    session: &str,
+    project_id: &str,
+    workspace: &Path,
+    exclude: &[String],
+    dir: &Path,
+) -> Result<Vec<String>> {
+    let sync_dir = dir.join(SYNC_DIR);
+    let started = Instant::now();
+    let container = loop {
+        if let Some(container) = container(session)? {
+            break container;
+        }
+        if started.elapsed() > TIMEOUT || sync_dir.join(AGENT_EXITED).exists() {
+            bail!("The container didn't start, so the workspace wasn't synced");
+        }
+        thread::sleep(POLL);
+    };
+
+    mutagen(&create_args(
+        session, project_id, workspace, &container, exclude,
+    ))?;
+    let name = session_name(session);
+    let result = (|| {
+        while !sync_dir.join(AGENT_EXITED).exists() {
+            if container_gone(&container) {
+                bail!("The container exited before its last changes were synced");
+            }
+            thread::sleep(POLL);
+        }
+        mutagen(&["sync", "flush", &name])?;
+        let conflicts: Vec<String> = list(&name)?
+            .into_iter()
+            .flat_map(|status| status.conflicts)
+            .map(|conflict| conflict.root)
+            .collect();
+        save_conflicts(&container, &conflicts, &dir.join(CONFLICTS_DIR));
+        Ok(conflicts)
+    })();
+    // Let the entrypoint go whatever happened
+    let _ = fs::write(sync_dir.join(FLUSHED), "");
+    let _ = mutagen(&["sync", "terminate", &name]);
+    result
+}
+
+/// Mutagen sessions matching `selector`, a session name or a
+/// `--label-selector`.
+pub fn list(selector: &str) -> Result<Vec<Status>> {
+    let output = Command::new("mutagen")
+        .args(["sync", "list", "--template={{json .}}", selector])
+        .stderr(Stdio::inherit())
+        .output()
+        .wrap_err("Couldn't run mutagen")?;
+    if !output.status.success() {
+        bail!("mutagen sync list exited with {}", output.status);
+    }
+    parse_list(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// This project's Mutagen sessions, for `contenant sync status`.
+pub fn project_sessions(project_id: &str) -> Result<Vec<Status>> {
+    list(&format!(
+        "--label-selector={}={}",
+        PROJECT_KEY,
+        project_label(project_id)
+    ))
+}
+
+pub fn parse_list(json: &str) -> Result<Vec<Status>> {
+    if json.trim().is_empty() || json.trim() == "null" {
+        return Ok(vec![]);
+    }
+    serde_json::from_str(json).wrap_err("Couldn't parse mutagen sync list")
+}
+
+/// Copy the container's side of each conflict under `to`, so it survives
+/// the container.
+fn save_conflicts(container: &str, conflicts: &[String], to: &Path) {
+    for root in conflicts {
+        let target = to.join(root);
+        if let Some(parent) = target.parent()
+            && fs::create_dir_all(parent).is_ok()
+        {
+            let _ = Command::new("docker")
+                .arg("cp")
+                .arg(format!("{}:/workspace/{}", container, root))
+                .arg(&target)
+                .stdout(Stdio::null())
+                .status();
+        }
+    }
+}
+
+/// Id of session `session`'s running container.
+fn container(session: &str) -> Result<Option<String>> {
+    let output = Command::new("docker")
+        .args(["ps", "-q", "--filter"])
+        .arg(format!("label={}={}", SESSION_LABEL, session))
+        .output()
+        .wrap_err("Couldn't run docker")?;
+    let id = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((!id.is_empty()).then_some(id))
+}
+
+fn container_gone(container: &str) -> bool {
+    !Command::new("docker")
+        .args(["inspect", "--type=container", container])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+fn mutagen<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Result<()> {
+    let status = Command::new("mutagen")
+        .args(args)
+        .stdout(Stdio::null())
+        .status()
+        .wrap_err("Couldn't run mutagen")?;
+    if !status.success() {
+        bail!("mutagen exited with {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn creates_a_labeled_two_way_safe_session() {
+        let args = create_args(
+            "1760000000-abcdef12",
+            "0123abcd-my app",
+            Path::new("/src/my app"),
+            "f00d",
+            &["node_modules".to_string()],
+        );
+        assert_eq!(
+            args,
+            [
+                "sync",
+                "create",
+                "--name=contenant-1760000000-abcdef12",
+                "--label=contenant-session=1760000000-abcdef12",
+                "--label=contenant-project=0123abcd",
+                "--sync-mode=two-way-safe",
+                "--ignore=/node_modules",
+                "/src/my app",
+                "docker://claude@f00d/workspace",
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_session_lists() {
+        assert!(parse_list("").unwrap().is_empty());
+        assert!(parse_list("null\n").unwrap().is_empty());
+
+        let sessions = parse_list(
+            r#"[{
+                "identifier": "sync_abc",
+                "name": "contenant-1760000000-abcdef12",
+                "labels": {"contenant-session": "1760000000-abcdef12"},
+                "status": "watching",
+                "conflicts": [{"root": "src/lib.rs", "alphaChanges": [], "betaChanges": []}],
+                "successfulCycles": 3
+            }]"#,
+        )
+        .unwrap();
+        assert_eq!(sessions[0].session(), Some("1760000000-abcdef12"));
+        assert_eq!(sessions[0].status, "watching");
+        assert_eq!(sessions[0].conflicts[0].root, "src/lib.rs");
+        assert_eq!(sessions[0].last_error, None);
+    }
+}
@@ -0,0 +1,332 @@
+//! Test support for crates embedding contenant.
+//!
+//! `MockBackend` records every build, tag, pull, run, stop, attach, and image
+//! removal instead of touching a container engine, and `isolated_app_dirs`
+//! keeps config, cache, and state inside a scratch directory, so config
+//! layers and run options can be tested without Docker installed. Runs
+//! aren't dry runs unless the mock says so with `as_dry_run`, so they go
+//! through everything but starting the container:
+//!
+//! ```no_run
+//! use contenant::Contenant;
+//! use contenant::testing::{MockBackend, isolated_app_dirs};
+//!
+//! # fn main() -> color_eyre::Result<()> {
+//! let backend = MockBackend::default();
+//! let app_dirs = isolated_app_dirs("/tmp/scratch".as_ref());
+//! let contenant = Contenant::with_app_dirs(backend.clone(), app_dirs, "/tmp/project".as_ref())?;
+//! contenant.run(&["--print".to_string(), "hi".to_string()])?;
+//!
+//! let run = backend.last_run().unwrap();
+//! assert_eq!(run.args, ["--print", "hi"]);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use color_eyre::eyre::Result;
+
+use crate::images::Image;
+use crate::ps::Session;
+use crate::{Backend, BuildOptions, RunSpec};
+
+/// An image build the backend was asked to perform.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Build {
+    pub image: String,
+    pub context: PathBuf,
+    /// Contents of the context's Dockerfile at build time, if it had one.
+    pub dockerfile: Option<String>,
+    pub options: BuildOptions,
+}
+
+/// A single backend call, in the order it was made.
+#[derive(Clone, Debug)]
+pub enum Call {
+    Build(Build),
+    Tag {
+        source: String,
+        target: String,
+    },
+    Pull {
+        image: String,
+        platform: Option<String>,
+    },
+    Run(Box<RunSpec>),
+    Stop {
+        label: String,
+        grace_secs: u64,
+    },
+    RemoveImage {
+        reference: String,
+    },
+    Attach {
+        id: String,
+        detach_keys: String,
+    },
+}
+
+/// Backend that records calls instead of running containers.
+///
+/// Clones share their recordings, so keep a clone to inspect after handing
+/// one to `Contenant`.
+#[derive(Clone, Debug, Default)]
+pub struct MockBackend {
+    calls: Arc<Mutex<Vec<Call>>>,
+    exit_code: i32,
+    images: Vec<Image>,
+    /// `(image, key, value)`
+    labels: Vec<(String, String, String)>,
+    sessions: Vec<Session>,
+    dry_run: bool,
+}
+
+impl MockBackend {
+    /// A mock whose runs report `exit_code`.
+    pub fn with_exit_code(exit_code: i32) -> Self {
+        Self {
+            exit_code,
+            ..Self::default()
+        }
+    }
+
+    /// A mock whose engine holds `images`.
+    pub fn with_images(images: Vec<Image>) -> Self {
+        Self {
+            images,
+            ..Self::default()
+        }
+    }
+
+    /// A mock whose engine holds `image`, labeled `key=value`.
+    pub fn with_image_label(image: &str, key: &str, value: &str) -> Self {
+        Self {
+            labels: vec![(image.to_string(), key.to_string(), value.to_string())],
+            ..Self::default()
+        }
+    }
+
+    /// A mock whose engine is running `sessions`.
+    pub fn with_sessions(sessions: Vec<Session>) -> Self {
+        Self {
+            sessions,
+            ..Self::default()
+        }
+    }
+
+    /// This mock, reporting dry runs, so `run` skips what it only does for
+    /// real: session logs, credentials, bridge and proxy tokens, verifying
+    /// images, and resolving secrets.
+    pub fn as_dry_run(self) -> Self {
+        Self {
+            dry_run: true,
+            ..self
+        }
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn builds(&self) -> Vec<Build> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Build(build) => Some(build),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn tags(&self) -> Vec<(String, String)> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Tag { source, target } => Some((source, target)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn pulls(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Pull { image, .. } => Some(image),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn runs(&self) -> Vec<RunSpec> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::Run(spec) => Some(*spec),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn last_run(&self) -> Option<RunSpec> {
+        self.runs().pop()
+    }
+
+    pub fn removed_images(&self) -> Vec<String> {
+        self.calls()
+            .into_iter()
+            .filter_map(|call| match call {
+                Call::RemoveImage { reference } => Some(reference),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn record(&self, call: Call) {
+        self.calls.lock().unwrap().push(call);
+    }
+}
+
+impl Backend for MockBackend {
+    fn build(&self, image: &str, context: &Path, options: &BuildOptions) -> Result<()> {
+        self.record(Call::Build(Build {
+            image: image.to_string(),
+            context: context.to_path_buf(),
+            dockerfile: fs::read_to_string(context.join("Dockerfile")).ok(),
+            options: options.clone(),
+        }));
+        Ok(())
+    }
+
+    fn tag(&self, source: &str, target: &str) -> Result<()> {
+        self.record(Call::Tag {
+            source: source.to_string(),
+            target: target.to_string(),
+        });
+        Ok(())
+    }
+
+    fn run(&self, spec: &RunSpec) -> Result<i32> {
+        self.record(Call::Run(Box::new(spec.clone())));
+        Ok(self.exit_code)
+    }
+
+    fn detaches(&self) -> bool {
+        true
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn stop(&self, label: &str, grace_secs: u64) -> Result<usize> {
+        self.record(Call::Stop {
+            label: label.to_string(),
+            grace_secs,
+        });
+        Ok(0)
+    }
+
+    // Every session matches, whatever the label
+    fn sessions(&self, _label: &str) -> Result<Vec<Session>> {
+        Ok(self.sessions.clone())
+    }
+
+    fn attach(&self, id: &str, detach_keys: &str) -> Result<i32> {
+        self.record(Call::Attach {
+            id: id.to_string(),
+            detach_keys: detach_keys.to_string(),
+        });
+        Ok(self.exit_code)
+    }
+
+    fn images(&self) -> Result<Vec<Image>> {
+        Ok(self.images.clone())
+    }
+
+    fn remove_image(&self, reference: &str) -> Result<()> {
+        self.record(Call::RemoveImage {
+            reference: reference.to_string(),
+        });
+        Ok(())
+    }
+
+    fn pull(&self, image: &str, platform: Option<&str>) -> Result<()> {
+        self.record(Call::Pull {
+            image: image.to_string(),
+            platform: platform.map(str::to_string),
+        });
+        Ok(())
+    }
+
+    fn image_label(&self, image: &str, key: &str) -> Result<Option<String>> {
+        Ok(self
+            .labels
+            .iter()
+            .find(|(i, k, _)| i == image && k == key)
+            .map(|(_, _, value)| value.clone()))
+    }
+}
+
+/// XDG directories rooted under `root` (`config/`, `cache/`, `state/`, ...)
+/// with no system-wide fallbacks, so tests never read or write real user state.
+pub fn isolated_app_dirs(root: &Path) -> xdg::BaseDirectories {
+    let mut dirs = xdg::BaseDirectories::with_prefix("contenant");
+    dirs.config_home = Some(root.join("config"));
+    dirs.data_home = Some(root.join("data"));
+    dirs.cache_home = Some(root.join("cache"));
+    dirs.state_home = Some(root.join("state"));
+    dirs.runtime_dir = None;
+    dirs.config_dirs = vec![];
+    dirs.data_dirs = vec![];
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Contenant;
+
+    #[test]
+    fn records_a_run() {
+        let scratch = tempfile::tempdir().unwrap();
+        let project = tempfile::tempdir().unwrap();
+        fs::create_dir_all(project.path().join(".contenant")).unwrap();
+        fs::write(
+            project.path().join(".contenant/config.yml"),
+            "allowed_domains: []\nenv:\n  FROM_PROJECT: hello\n",
+        )
+        .unwrap();
+
+        let backend = MockBackend::with_exit_code(3);
+        let contenant = Contenant::with_app_dirs(
+            backend.clone(),
+            isolated_app_dirs(scratch.path()),
+            project.path(),
+        )
+        .unwrap();
+        let exit_code = contenant.run(&["--print".to_string()]).unwrap();
+
+        assert_eq!(exit_code, 3);
+        assert_eq!(backend.builds()[0].image, "contenant:base");
+        assert!(backend.builds()[0].dockerfile.is_some());
+        assert_eq!(
+            backend.tags(),
+            [("contenant:base".to_string(), "contenant:user".to_string())]
+        );
+
+        let run = backend.last_run().unwrap();
+        assert_eq!(run.image, "contenant:user");
+        assert_eq!(run.args, ["--print"]);
+        assert_eq!(run.env["FROM_PROJECT"], "hello");
+        assert!(run.allowed_ips.is_empty());
+        assert!(
+            run.mounts
+                .iter()
+                .all(|m| m.starts_with(scratch.path().to_str().unwrap()))
+        );
+    }
+}
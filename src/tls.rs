@@ -0,0 +1,315 @@
+//! Certificates for serving the bridge over TLS (`bridge.tls`), made with the
+//! `openssl` CLI and kept under XDG state.
+//!
+//! A private CA signs the bridge's server certificate and, with
+//! `bridge.client_certs`, a client certificate for each `contenant run`. The
+//! CA certificate is mounted into the container so the helpers can verify
+//! the bridge.
+
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{Result, bail};
+
+use crate::config::BridgeConfig;
+
+/// State subdirectory holding the CA, the server certificate, and the
+/// bridge's private socket behind the TLS listener.
+pub const TLS_DIR: &str = "bridge-tls";
+
+/// Where a run's CA and client certificate are mounted in the container.
+pub const CONTAINER_TLS_DIR: &str = "/run/contenant-tls";
+
+/// Names containers reach the host by, with the backends' defaults.
+const HOST_NAMES: &[&str] = &[
+    "localhost",
+    "host.docker.internal",
+    "host.containers.internal",
+    "host.lima.internal",
+    "127.0.0.1",
+    crate::apple::DEFAULT_HOST_ADDRESS,
+];
+
+const CA_DAYS: &str = "3650";
+const CERT_DAYS: &str = "825";
+
+pub struct BridgeTls {
+    pub dir: PathBuf,
+    /// Whether connections need a client certificate signed by the CA.
+    pub client_certs: bool,
+}
+
+impl BridgeTls {
+    /// The bridge's CA, generated if it's missing.
+    pub fn open(config: &BridgeConfig, app_dirs: &xdg::BaseDirectories) -> Result<Self> {
+        if !crate::on_path("openssl") {
+            bail!("bridge.tls needs openssl on PATH to generate certificates");
+        }
+        let dir = app_dirs.create_state_directory(TLS_DIR)?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o700))?;
+        let tls = Self {
+            dir,
+            client_certs: config.client_certs.unwrap_or(false),
+        };
+
+        if !tls.ca_cert().exists() || !tls.ca_key().exists() {
+            tls.generate_ca()?;
+        }
+        Ok(tls)
+    }
+
+    /// Issue the server certificate if it's missing or the names it should
+    /// cover changed.
+    pub fn ensure_server_cert(&self, config: &BridgeConfig) -> Result<()> {
+        let names = server_names(config);
+        let san_path = self.dir.join("server.san");
+        let current = fs::read_to_string(&san_path).unwrap_or_default();
+        if current != names || !self.server_cert().exists() || !self.server_key().exists() {
+            self.sign(
+                &self.server_key(),
+                &self.server_cert(),
+                "contenant bridge",
+                &format!(
+                    "subjectAltName = {}\nextendedKeyUsage = serverAuth\n",
+                    names
+                ),
+            )?;
+            fs::write(san_path, names)?;
+        }
+        Ok(())
+    }
+
+    pub fn ca_cert(&self) -> PathBuf {
+        self.dir.join("ca.crt")
+    }
+
+    fn ca_key(&self) -> PathBuf {
+        self.dir.join("ca.key")
+    }
+
+    pub fn server_cert(&self) -> PathBuf {
+        self.dir.join("server.crt")
+    }
+
+    pub fn server_key(&self) -> PathBuf {
+        self.dir.join("server.key")
+    }
+
+    /// The socket the bridge serves plain HTTP on, for the TLS listener to
+    /// relay to and for host-side clients.
+    pub fn plain_socket(&self) -> PathBuf {
+        self.dir.join("bridge.sock")
+    }
+
+    fn generate_ca(&self) -> Result<()> {
+        let config = self.dir.join("ca.cnf");
+        fs::write(
+            &config,
+            "[req]\ndistinguished_name = dn\nprompt = no\n\
+             [dn]\nCN = contenant bridge CA\n\
+             [ca]\nbasicConstraints = critical, CA:TRUE\n\
+             keyUsage = critical, keyCertSign, cRLSign\n",
+        )?;
+        run(Command::new("openssl")
+            .args(["req", "-x509", "-config"])
+            .arg(&config)
+            .args(["-extensions", "ca", "-newkey", "rsa:2048", "-nodes"])
+            .arg("-keyout")
+            .arg(self.ca_key())
+            .arg("-out")
+            .arg(self.ca_cert())
+            .args(["-days", CA_DAYS]))?;
+        fs::set_permissions(self.ca_key(), fs::Permissions::from_mode(0o600))?;
+        Ok(())
+    }
+
+    /// Write a new key and a certificate for it signed by the CA, with
+    /// `extensions` in openssl config syntax.
+    fn sign(&self, key: &Path, cert: &Path, name: &str, extensions: &str) -> Result<()> {
+        let csr = cert.with_extension("csr");
+        let ext = cert.with_extension("ext");
+        fs::write(&ext, extensions)?;
+        run(Command::new("openssl")
+            .args(["req", "-new", "-newkey", "rsa:2048", "-nodes", "-keyout"])
+            .arg(key)
+            .arg("-out")
+            .arg(&csr)
+            .arg("-subj")
+            .arg(format!("/CN={}", name)))?;
+        run(Command::new("openssl")
+            .args(["x509", "-req", "-in"])
+            .arg(&csr)
+            .arg("-CA")
+            .arg(self.ca_cert())
+            .arg("-CAkey")
+            .arg(self.ca_key())
+            .arg("-CAcreateserial")
+            .arg("-CAserial")
+            .arg(self.dir.join("ca.srl"))
+            .args(["-days", CERT_DAYS, "-extfile"])
+            .arg(&ext)
+            .arg("-out")
+            .arg(cert))?;
+        let _ = fs::remove_file(csr);
+        let _ = fs::remove_file(ext);
+        Ok(())
+    }
+}
+
+/// `subjectAltName` entries for the server certificate: the usual host
+/// names, the listen address, and `bridge.tls_names`.
+fn server_names(config: &BridgeConfig) -> String {
+    let mut names: Vec<&str> = HOST_NAMES.to_vec();
+    names.extend(config.address.as_deref());
+    names.extend(config.tls_names.iter().map(String::as_str));
+    let mut entries: Vec<String> = vec![];
+    for name in names {
+        let entry = if name.parse::<std::net::IpAddr>().is_ok() {
+            format!("IP:{}", name)
+        } else {
+            format!("DNS:{}", name)
+        };
+        // 0.0.0.0 and :: aren't names anyone connects to
+        if name != "0.0.0.0" && name != "::" && !entries.contains(&entry) {
+            entries.push(entry);
+        }
+    }
+    entries.join(", ")
+}
+
+fn run(command: &mut Command) -> Result<()> {
+    let output = command.stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        bail!(
+            "{:?} failed: {}",
+            command,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// The files a run mounts at [`CONTAINER_TLS_DIR`]: the CA certificate and,
+/// with client certificates, one for this run. Removed when dropped, though
+/// the certificate itself stays valid until it expires.
+pub struct RunCerts {
+    pub dir: PathBuf,
+    pub client_cert: bool,
+}
+
+impl RunCerts {
+    pub fn issue(tls: &BridgeTls) -> Result<Self> {
+        let dir = tls.dir.join("runs").join(crate::bridge::random_hex()?);
+        fs::create_dir_all(&dir)?;
+        // Readable by the container user, whose uid may not match ours
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        fs::copy(tls.ca_cert(), dir.join("ca.crt"))?;
+        let certs = Self {
+            dir,
+            client_cert: tls.client_certs,
+        };
+        if certs.client_cert {
+            let key = certs.dir.join("client.key");
+            tls.sign(
+                &key,
+                &certs.dir.join("client.crt"),
+                "contenant session",
+                "extendedKeyUsage = clientAuth\n",
+            )?;
+            fs::set_permissions(&key, fs::Permissions::from_mode(0o644))?;
+        }
+        Ok(certs)
+    }
+
+    /// `CONTENANT_BRIDGE_*` variables pointing the helpers at the mounted
+    /// files.
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![(
+            "CONTENANT_BRIDGE_CACERT".to_string(),
+            format!("{}/ca.crt", CONTAINER_TLS_DIR),
+        )];
+        if self.client_cert {
+            env.push((
+                "CONTENANT_BRIDGE_CERT".to_string(),
+                format!("{}/client.crt", CONTAINER_TLS_DIR),
+            ));
+            env.push((
+                "CONTENANT_BRIDGE_KEY".to_string(),
+                format!("{}/client.key", CONTAINER_TLS_DIR),
+            ));
+        }
+        env
+    }
+}
+
+impl Drop for RunCerts {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(tls: &BridgeTls, cert: &Path, purpose: &str) -> bool {
+        Command::new("openssl")
+            .args(["verify", "-purpose", purpose, "-CAfile"])
+            .arg(tls.ca_cert())
+            .arg(cert)
+            .output()
+            .unwrap()
+            .status
+            .success()
+    }
+
+    #[test]
+    fn issues_server_and_client_certs_from_one_ca() {
+        if !crate::on_path("openssl") {
+            return;
+        }
+        let scratch = tempfile::tempdir().unwrap();
+        let app_dirs = crate::testing::isolated_app_dirs(scratch.path());
+        let config = BridgeConfig {
+            client_certs: Some(true),
+            tls_names: vec!["devbox.example.com".to_string()],
+            ..Default::default()
+        };
+        let tls = BridgeTls::open(&config, &app_dirs).unwrap();
+        tls.ensure_server_cert(&config).unwrap();
+        assert!(verify(&tls, &tls.server_cert(), "sslserver"));
+
+        // Unchanged names keep the certificate; new ones reissue it
+        let issued = fs::read(tls.server_cert()).unwrap();
+        tls.ensure_server_cert(&config).unwrap();
+        assert_eq!(fs::read(tls.server_cert()).unwrap(), issued);
+        let config = BridgeConfig {
+            address: Some("192.168.1.20".to_string()),
+            ..config
+        };
+        tls.ensure_server_cert(&config).unwrap();
+        assert_ne!(fs::read(tls.server_cert()).unwrap(), issued);
+
+        let certs = RunCerts::issue(&tls).unwrap();
+        assert!(verify(&tls, &certs.dir.join("client.crt"), "sslclient"));
+        assert_eq!(certs.env().len(), 3);
+        let dir = certs.dir.clone();
+        drop(certs);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn server_names_cover_hosts_and_address() {
+        let config = BridgeConfig {
+            address: Some("0.0.0.0".to_string()),
+            tls_names: vec!["devbox".to_string(), "localhost".to_string()],
+            ..Default::default()
+        };
+        let names = server_names(&config);
+        assert!(names.starts_with("DNS:localhost, DNS:host.docker.internal"));
+        assert!(names.contains("IP:127.0.0.1"));
+        assert!(names.ends_with("IP:192.168.64.1, DNS:devbox"));
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::AddAssign;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::Result;
+use serde::{Deserialize, Serialize};
+
+/// Token counts and estimated cost for one or more sessions.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_creation_input_tokens: u64,
+    pub cache_read_input_tokens: u64,
+    /// Estimated from list prices; not a bill.
+    pub cost_usd: f64,
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+        self.cache_creation_input_tokens += other.cache_creation_input_tokens;
+        self.cache_read_input_tokens += other.cache_read_input_tokens;
+        self.cost_usd += other.cost_usd;
+    }
+}
+
+/// A ledger entry for a single `contenant run`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionUsage {
+    pub started_at: u64,
+    pub finished_at: u64,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+/// Append-only per-project usage ledger stored as JSON lines.
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+        }
+    }
+
+    pub fn record(&self, session: &SessionUsage) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(session)?)?;
+        Ok(())
+    }
+
+    pub fn sessions(&self) -> Result<Vec<SessionUsage>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        fs::read_to_string(&self.path)?
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Ok(serde_json::from_str(line)?))
+            .collect()
+    }
+
+    /// Session count and summed usage.
+    pub fn totals(&self) -> Result<(usize, Usage)> {
+        let sessions = self.sessions()?;
+        let mut total = Usage::default();
+        for session in &sessions {
+            total += session.usage;
+        }
+        Ok((sessions.len(), total))
+    }
+}
+
+/// Directory, in a project's state directory, mounted over Claude's
+/// `~/.claude/projects` so each project's transcripts are kept apart.
+pub const TRANSCRIPTS_DIR: &str = "transcripts";
+
+/// Sizes of all transcript files, taken before a session starts so only
+/// lines appended during the session are counted afterward.
+pub type TranscriptSnapshot = HashMap<PathBuf, u64>;
+
+pub fn snapshot(transcripts_dir: &Path) -> TranscriptSnapshot {
+    transcripts(transcripts_dir)
+        .into_iter()
+        .filter_map(|path| Some((path.clone(), fs::metadata(&path).ok()?.len())))
+        .collect()
+}
+
+/// Usage from transcript lines written since `before` was taken.
+pub fn collect(transcripts_dir: &Path, before: &TranscriptSnapshot) -> Usage {
+    let mut usage = Usage::default();
+    let mut seen = HashSet::new();
+
+    for path in transcripts(transcripts_dir) {
+        let offset = before.get(&path).copied().unwrap_or(0);
+        let Ok(appended) = read_from(&path, offset) else {
+            continue;
+        };
+        usage += parse_transcript(&appended, &mut seen);
+    }
+
+    usage
+}
+
+fn read_from(path: &Path, offset: u64) -> std::io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+/// Session transcript files in a project's transcripts directory, which
+/// has one subdirectory per working directory Claude ran in.
+pub(crate) fn transcripts(transcripts_dir: &Path) -> Vec<PathBuf> {
+    let Ok(projects) = fs::read_dir(transcripts_dir) else {
+        return vec![];
+    };
+
+    projects
+        .flatten()
+        .filter_map(|project| fs::read_dir(project.path()).ok())
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct TranscriptLine {
+    message: Option<TranscriptMessage>,
+}
+
+#[derive(Deserialize)]
+struct TranscriptMessage {
+    id: Option<String>,
+    #[serde(default)]
+    model: String,
+    usage: Option<TokenCounts>,
+}
+
+#[derive(Deserialize)]
+struct TokenCounts {
+    #[serde(default)]
+    input_tokens: u64,
+    #[serde(default)]
+    output_tokens: u64,
+    #[serde(default)]
+    cache_creation_input_tokens: u64,
+    #[serde(default)]
+    cache_read_input_tokens: u64,
+}
+
+/// Sum usage over transcript lines. A single API message is written as
+/// several lines sharing an id, so each id is only counted once.
+fn parse_transcript(contents: &str, seen: &mut HashSet<String>) -> Usage {
+    let mut usage = Usage::default();
+
+    for line in contents.lines() {
+        let Ok(TranscriptLine {
+            message: Some(message),
+        }) = serde_json::from_str(line)
+        else {
+            continue;
+        };
+        let Some(counts) = message.usage else {
+            continue;
+        };
+        if let Some(id) = message.id
+            && !seen.insert(id)
+        {
+            continue;
+        }
+
+        let (input_price, output_price) = price_per_mtok(&message.model);
+        let cost = (counts.input_tokens as f64 * input_price
+            + counts.output_tokens as f64 * output_price
+            + counts.cache_creation_input_tokens as f64 * input_price * 1.25
+            + counts.cache_read_input_tokens as f64 * input_price * 0.1)
+            / 1_000_000.0;
+
+        usage += Usage {
+            input_tokens: counts.input_tokens,
+            output_tokens: counts.output_tokens,
+            cache_creation_input_tokens: counts.cache_creation_input_tokens,
+            cache_read_input_tokens: counts.cache_read_input_tokens,
+            cost_usd: cost,
+        };
+    }
+
+    usage
+}
+
+/// List prices (USD per million input/output tokens) by model family. These
+/// are fixed here, so costs are only estimates and can fall behind price
+/// changes; `report --costs` says as much.
+fn price_per_mtok(model: &str) -> (f64, f64) {
+    if model.contains("opus") {
+        (15.0, 75.0)
+    } else if model.contains("haiku") {
+        (0.8, 4.0)
+    } else if model.contains("sonnet") {
+        (3.0, 15.0)
+    } else {
+        (0.0, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_transcript_dedupes_message_ids() {
+        let transcript = r#"{"type":"user","message":{"role":"user","content":"hi"}}
+{"type":"assistant","message":{"id":"msg_1","model":"claude-sonnet-4","usage":{"input_tokens":1000,"output_tokens":100}}}
+{"type":"assistant","message":{"id":"msg_1","model":"claude-sonnet-4","usage":{"input_tokens":1000,"output_tokens":100}}}
+{"type":"assistant","message":{"id":"msg_2","model":"claude-sonnet-4","usage":{"input_tokens":0,"output_tokens":0,"cache_read_input_tokens":2000}}}
+"#;
+        let usage = parse_transcript(transcript, &mut HashSet::new());
+        assert_eq!(usage.input_tokens, 1000);
+        assert_eq!(usage.output_tokens, 100);
+        assert_eq!(usage.cache_read_input_tokens, 2000);
+        assert!((usage.cost_usd - 0.0051).abs() < 1e-9);
+    }
+
+    #[test]
+    fn collect_only_counts_appended_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("-workspace");
+        fs::create_dir_all(&project).unwrap();
+        let transcript = project.join("session.jsonl");
+        fs::write(
+            &transcript,
+            "{\"message\":{\"id\":\"a\",\"usage\":{\"input_tokens\":5}}}\n",
+        )
+        .unwrap();
+
+        let before = snapshot(dir.path());
+        let mut file = fs::OpenOptions::new()
+            .append(true)
+            .open(&transcript)
+            .unwrap();
+        writeln!(
+            file,
+            "{{\"message\":{{\"id\":\"b\",\"usage\":{{\"input_tokens\":7}}}}}}"
+        )
+        .unwrap();
+
+        assert_eq!(collect(dir.path(), &before).input_tokens, 7);
+    }
+
+    #[test]
+    fn ledger_totals() {
+        let dir = tempfile::tempdir().unwrap();
+        let ledger = Ledger::new(&dir.path().join("usage.jsonl"));
+        for tokens in [10, 20] {
+            ledger
+                .record(&SessionUsage {
+                    started_at: 0,
+                    finished_at: 1,
+                    usage: Usage {
+                        output_tokens: tokens,
+                        ..Default::default()
+                    },
+                })
+                .unwrap();
+        }
+
+        let (sessions, total) = ledger.totals().unwrap();
+        assert_eq!(sessions, 2);
+        assert_eq!(total.output_tokens, 30);
+    }
+}
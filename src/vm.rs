@@ -0,0 +1,164 @@
+//! Detection and startup of the Colima or Lima VM hosting the Docker daemon.
+
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use color_eyre::eyre::{Result, bail};
+use serde::Serialize;
+use tracing::info;
+
+const SOCKET_TIMEOUT: Duration = Duration::from_secs(120);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VmKind {
+    Colima,
+    Lima,
+}
+
+impl std::fmt::Display for VmKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VmKind::Colima => write!(f, "colima"),
+            VmKind::Lima => write!(f, "lima"),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct Vm {
+    pub kind: VmKind,
+    /// Colima profile or Lima instance name.
+    pub name: String,
+    pub socket: PathBuf,
+}
+
+impl Vm {
+    /// The VM behind the current Docker endpoint, if it's Colima or Lima.
+    pub fn detect() -> Option<Self> {
+        let host = match std::env::var("DOCKER_HOST") {
+            Ok(host) => host,
+            Err(_) => {
+                let output = Command::new("docker")
+                    .args([
+                        "context",
+                        "inspect",
+                        "--format",
+                        "{{.Endpoints.docker.Host}}",
+                    ])
+                    .stderr(Stdio::null())
+                    .output()
+                    .ok()?;
+                String::from_utf8_lossy(&output.stdout).trim().to_string()
+            }
+        };
+
+        Self::from_socket(Path::new(host.strip_prefix("unix://")?))
+    }
+
+    /// Recognize `~/.colima/<profile>/docker.sock` and
+    /// `~/.lima/<instance>/sock/docker.sock`.
+    pub fn from_socket(socket: &Path) -> Option<Self> {
+        let parts: Vec<_> = socket
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(part) => part.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        let vm = |kind, index: usize| {
+            Some(Self {
+                kind,
+                name: parts.get(index + 1)?.to_string(),
+                socket: socket.to_path_buf(),
+            })
+        };
+
+        if let Some(index) = parts.iter().position(|p| *p == ".colima") {
+            return vm(VmKind::Colima, index);
+        }
+        if let Some(index) = parts.iter().position(|p| *p == ".lima") {
+            return vm(VmKind::Lima, index);
+        }
+        None
+    }
+
+    pub fn is_running(&self) -> bool {
+        match self.kind {
+            VmKind::Colima => Command::new("colima")
+                .args(["status", "--profile", &self.name])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|s| s.success()),
+            VmKind::Lima => Command::new("limactl")
+                .args(["list", &self.name, "--format", "{{.Status}}"])
+                .stderr(Stdio::null())
+                .output()
+                .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).trim() == "Running"),
+        }
+    }
+
+    /// Start the VM and wait until its Docker socket accepts connections.
+    pub fn start(&self) -> Result<()> {
+        info!(vm = %self.kind, name = %self.name, "Starting VM");
+
+        let status = match self.kind {
+            VmKind::Colima => Command::new("colima")
+                .args(["start", "--profile", &self.name])
+                .status()?,
+            VmKind::Lima => Command::new("limactl")
+                .args(["start", &self.name])
+                .status()?,
+        };
+        if !status.success() {
+            bail!("Failed to start {} VM {}", self.kind, self.name);
+        }
+
+        let started = Instant::now();
+        while started.elapsed() < SOCKET_TIMEOUT {
+            let ready = Command::new("docker")
+                .args(["-H", &format!("unix://{}", self.socket.display()), "info"])
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|s| s.success());
+            if ready {
+                return Ok(());
+            }
+            sleep(Duration::from_secs(1));
+        }
+
+        bail!(
+            "Timed out waiting for Docker socket {}",
+            self.socket.display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn colima_socket() {
+        let vm = Vm::from_socket(Path::new("/Users/me/.colima/default/docker.sock")).unwrap();
+        assert_eq!(vm.kind, VmKind::Colima);
+        assert_eq!(vm.name, "default");
+    }
+
+    #[test]
+    fn lima_socket() {
+        let vm = Vm::from_socket(Path::new("/Users/me/.lima/docker/sock/docker.sock")).unwrap();
+        assert_eq!(vm.kind, VmKind::Lima);
+        assert_eq!(vm.name, "docker");
+    }
+
+    #[test]
+    fn native_socket() {
+        assert_eq!(Vm::from_socket(Path::new("/var/run/docker.sock")), None);
+    }
+}
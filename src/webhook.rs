@@ -0,0 +1,194 @@
+//! Forwarding of bridge and session events to `bridge.webhooks`, such as
+//! Slack incoming webhooks or a team dashboard.
+//!
+//! Each event is POSTed as JSON with a `text` summary (which Slack shows) and
+//! retried a few times. With `bridge.webhook_secret`, the body is signed with
+//! HMAC-SHA256 in `X-Contenant-Signature: sha256=<hex>`.
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use color_eyre::eyre::{Result, bail};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::warn;
+
+const ATTEMPTS: u32 = 3;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "event")]
+pub enum Event {
+    #[serde(rename = "trigger.invoked")]
+    TriggerInvoked {
+        project: Option<String>,
+        trigger: String,
+    },
+    #[serde(rename = "run.started")]
+    RunStarted { project: String },
+    #[serde(rename = "run.finished")]
+    RunFinished { project: String, exit_code: i32 },
+}
+
+impl Event {
+    fn text(&self) -> String {
+        match self {
+            Self::TriggerInvoked {
+                project: Some(project),
+                trigger,
+            } => format!("contenant: {} ran trigger {}", project, trigger),
+            Self::TriggerInvoked {
+                project: None,
+                trigger,
+            } => format!("contenant: trigger {} ran", trigger),
+            Self::RunStarted { project } => format!("contenant: session started in {}", project),
+            Self::RunFinished { project, exit_code } => format!(
+                "contenant: session in {} finished with exit code {}",
+                project, exit_code
+            ),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    #[serde(flatten)]
+    event: &'a Event,
+    text: String,
+    timestamp: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Webhooks {
+    pub urls: Vec<String>,
+    pub secret: Option<String>,
+}
+
+impl Webhooks {
+    /// Deliver `event` to every URL in the background.
+    pub fn send(&self, event: &Event) {
+        if self.urls.is_empty() {
+            return;
+        }
+        let payload = Payload {
+            event,
+            text: event.text(),
+            timestamp: crate::queue::now(),
+        };
+        let Ok(body) = serde_json::to_vec(&payload) else {
+            return;
+        };
+        let signature = self
+            .secret
+            .as_ref()
+            .map(|secret| format!("sha256={}", hex(&hmac_sha256(secret.as_bytes(), &body))));
+
+        for url in &self.urls {
+            let (url, body, signature) = (url.clone(), body.clone(), signature.clone());
+            tokio::spawn(async move {
+                for attempt in 1..=ATTEMPTS {
+                    match post(&url, &body, signature.as_deref()).await {
+                        Ok(()) => return,
+                        Err(err) if attempt == ATTEMPTS => {
+                            warn!(%url, %err, "Giving up on webhook");
+                        }
+                        Err(_) => {
+                            tokio::time::sleep(Duration::from_secs(1 << (attempt - 1))).await;
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
+
+async fn post(url: &str, body: &[u8], signature: Option<&str>) -> Result<()> {
+    let mut curl = Command::new("curl");
+    curl.args([
+        "-fsS",
+        "--max-time",
+        "10",
+        "-X",
+        "POST",
+        "--data-binary",
+        "@-",
+    ])
+    .args(["-H", "Content-Type: application/json"]);
+    if let Some(signature) = signature {
+        curl.arg("-H")
+            .arg(format!("X-Contenant-Signature: {}", signature));
+    }
+    let mut child = curl
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body).await?;
+    }
+    let output = child.wait_with_output().await?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = Sha256::new();
+    inner.update(block.map(|b| b ^ 0x36));
+    inner.update(message);
+    let mut outer = Sha256::new();
+    outer.update(block.map(|b| b ^ 0x5c));
+    outer.update(inner.finalize());
+    outer.finalize().into()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hmac_matches_rfc_4231() {
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn payload_is_tagged_with_text() {
+        let event = Event::RunFinished {
+            project: "1234abcd-app".to_string(),
+            exit_code: 1,
+        };
+        let payload = serde_json::to_value(Payload {
+            event: &event,
+            text: event.text(),
+            timestamp: 7,
+        })
+        .unwrap();
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "event": "run.finished",
+                "project": "1234abcd-app",
+                "exit_code": 1,
+                "text": "contenant: session in 1234abcd-app finished with exit code 1",
+                "timestamp": 7,
+            })
+        );
+        assert_eq!(serde_json::from_value::<Event>(payload).unwrap(), event);
+    }
+}
@@ -0,0 +1,241 @@
+//! `workspace.mode: copy`: the project is copied into the container instead
+//! of bind-mounted, and the agent's changes are copied back when it exits
+//! successfully.
+//!
+//! [`pack`] tars the whole project into the session's log directory, which
+//! is mounted read-only at [`CONTAINER_ARCHIVE`]. The entrypoint unpacks it
+//! into `/workspace` and, once the agent exits, packs `/workspace` into
+//! [`CONTAINER_OUT_DIR`], which only root in the container can write to.
+//! [`apply`] then writes the files whose contents differ from the
+//! [`Manifest`] taken at start, and deletes the ones the agent deleted, so
+//! files edited on the host during the run are kept unless the agent edited
+//! them too.
+//!
+//! Paths in `workspace.exclude` are left out of the archive and never copied
+//! back or deleted.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use color_eyre::eyre::{Result, WrapErr, bail};
+use sha2::{Digest, Sha256};
+
+/// Archive of the project, in the session's log directory.
+pub const ARCHIVE: &str = "workspace-in.tar";
+
+/// Manifest of the archived files, in the session's log directory.
+pub const MANIFEST: &str = "workspace-in.json";
+
+/// Directory the entrypoint writes [`OUT_ARCHIVE`] to, in the session's log
+/// directory.
+pub const OUT_DIR: &str = "workspace-out";
+
+/// The container's `/workspace` when the agent exited.
+pub const OUT_ARCHIVE: &str = "workspace.tar";
+
+/// Where the archive is mounted in the container.
+pub const CONTAINER_ARCHIVE: &str = "/etc/contenant/workspace.tar";
+
+/// Parent of the directories contenant hears back through in the container,
+/// which the entrypoint locks away from the agent.
+pub const CONTAINER_STATE_DIR: &str = "/var/lib/contenant";
+
+/// Where [`OUT_DIR`] is mounted in the container.
+pub const CONTAINER_OUT_DIR: &str = "/var/lib/contenant/workspace";
+
+/// SHA-256 of each file, or of each symlink's target, by path relative to
+/// the workspace.
+pub type Manifest = BTreeMap<String, String>;
+
+/// What [`apply`] changed in the workspace.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Applied {
+    pub written: usize,
+    pub deleted: usize,
+}
+
+/// Archive all of `workspace` but the `exclude`d paths to `archive`,
+/// returning what it held.
+pub fn pack(workspace: &Path, archive: &Path, exclude: &[String]) -> Result<Manifest> {
+    let manifest = manifest(workspace, exclude)?;
+    let status = Command::new("tar")
+        .arg("-cf")
+        .arg(archive)
+        .args(exclude.iter().map(|path| format!("--exclude=./{}", path)))
+        .arg("-C")
+        .arg(workspace)
+        .arg(".")
+        .status()
+        .wrap_err("Couldn't run tar")?;
+    if !status.success() {
+        bail!("tar exited with {}", status);
+    }
+    Ok(manifest)
+}
+
+/// Bring the agent's changes in `out_archive` into `workspace`, unpacking
+/// it under `scratch` first. `before` is what [`pack`] returned.
+pub fn apply(
+    workspace: &Path,
+    before: &Manifest,
+    out_archive: &Path,
+    scratch: &Path,
+    exclude: &[String],
+) -> Result<Applied> {
+    let unpacked = scratch.join("workspace-out.d");
+    let _ = fs::remove_dir_all(&unpacked);
+    fs::create_dir_all(&unpacked)?;
+    let status = Command::new("tar")
+        .arg("-xf")
+        .arg(out_archive)
+        .arg("-C")
+        .arg(&unpacked)
+        .status()
+        .wrap_err("Couldn't run tar")?;
+    if !status.success() {
+        bail!("tar exited with {}", status);
+    }
+
+    let result = (|| {
+        let after = manifest(&unpacked, exclude)?;
+        let mut applied = Applied::default();
+        for (path, hash) in &after {
+            if before.get(path) == Some(hash) {
+                continue;
+            }
+            copy_entry(&unpacked.join(path), &workspace.join(path))
+                .wrap_err_with(|| format!("Couldn't copy back {}", path))?;
+            applied.written += 1;
+        }
+        for path in before.keys().filter(|path| !after.contains_key(*path)) {
+            let target = workspace.join(path);
+            if target.symlink_metadata().is_ok() {
+                fs::remove_file(&target).wrap_err_with(|| format!("Couldn't delete {}", path))?;
+                applied.deleted += 1;
+            }
+        }
+        Ok(applied)
+    })();
+    let _ = fs::remove_dir_all(&unpacked);
+    result
+}
+
+/// Hash every file and symlink under `root` outside `exclude`, without
+/// following symlinks.
+fn manifest(root: &Path, exclude: &[String]) -> Result<Manifest> {
+    let mut manifest = Manifest::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let file_type = entry.file_type()?;
+            let relative = path.strip_prefix(root)?.to_string_lossy().into_owned();
+            if exclude.contains(&relative) {
+                continue;
+            }
+            let contents = if file_type.is_dir() {
+                pending.push(path);
+                continue;
+            } else if file_type.is_symlink() {
+                fs::read_link(&path)?.into_os_string().into_encoded_bytes()
+            } else if file_type.is_file() {
+                fs::read(&path)?
+            } else {
+                continue;
+            };
+            manifest.insert(relative, format!("{:x}", Sha256::digest(&contents)));
+        }
+    }
+    Ok(manifest)
+}
+
+/// Replace `to` with `from`, recreating symlinks rather than following them.
+fn copy_entry(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if to.symlink_metadata().is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(to)?;
+    }
+    if from.symlink_metadata()?.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+    } else {
+        fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn copies_back_only_what_the_agent_changed() {
+        let workspace = tempfile::tempdir().unwrap();
+        let session = tempfile::tempdir().unwrap();
+        let container = tempfile::tempdir().unwrap();
+        let write = |root: &Path, path: &str, contents: &str| {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        };
+        let read = |path: &str| fs::read_to_string(workspace.path().join(path)).ok();
+        write(workspace.path(), "src/lib.rs", "v1");
+        write(workspace.path(), "notes.txt", "mine");
+        write(workspace.path(), "old.txt", "stale");
+        write(workspace.path(), ".git/HEAD", "ref: refs/heads/main");
+        write(workspace.path(), "node_modules/dep/index.js", "host");
+
+        let archive = session.path().join(ARCHIVE);
+        let exclude = ["node_modules".to_string()];
+        let before = pack(workspace.path(), &archive, &exclude).unwrap();
+        assert!(before.contains_key(".git/HEAD"));
+        assert!(!before.contains_key("node_modules/dep/index.js"));
+
+        // What the entrypoint does around the agent
+        let status = Command::new("tar")
+            .arg("-xf")
+            .arg(&archive)
+            .arg("-C")
+            .arg(container.path())
+            .status()
+            .unwrap();
+        assert!(status.success());
+        assert!(!container.path().join("node_modules").exists());
+        write(container.path(), "src/lib.rs", "v2");
+        write(container.path(), "node_modules/dep/index.js", "container");
+        write(container.path(), "src/new.rs", "new");
+        fs::remove_file(container.path().join("old.txt")).unwrap();
+        let out = session.path().join(OUT_ARCHIVE);
+        let status = Command::new("tar")
+            .arg("-cf")
+            .arg(&out)
+            .arg("-C")
+            .arg(container.path())
+            .arg(".")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // Edited on the host meanwhile
+        write(workspace.path(), "notes.txt", "mine, edited");
+
+        let applied = apply(workspace.path(), &before, &out, session.path(), &exclude).unwrap();
+        assert_eq!(
+            applied,
+            Applied {
+                written: 2,
+                deleted: 1
+            }
+        );
+        assert_eq!(read("src/lib.rs").as_deref(), Some("v2"));
+        assert_eq!(read("src/new.rs").as_deref(), Some("new"));
+        assert_eq!(read("old.txt"), None);
+        assert_eq!(read("notes.txt").as_deref(), Some("mine, edited"));
+        assert_eq!(read("node_modules/dep/index.js").as_deref(), Some("host"));
+        assert!(!session.path().join("workspace-out.d").exists());
+    }
+}
@@ -0,0 +1,145 @@
+//! Git worktrees as workspaces.
+//!
+//! A worktree's `.git` is a file pointing at its private git directory
+//! inside the main repo's, which in turn points at the shared one through
+//! its `commondir` file. Neither is in the workspace, so [`detect`] finds
+//! them for [`Worktree::mounts`] to mount where git in the container will
+//! look: at the same absolute path, or where a relative path leads from
+//! `/workspace`.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+
+/// A worktree's git directories, on the host and in the container.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Worktree {
+    /// The worktree's own `HEAD`, index, and so on.
+    pub git_dir: PathBuf,
+    pub container_git_dir: PathBuf,
+    /// The main repo's git directory, with the objects and refs.
+    pub common_dir: PathBuf,
+    pub container_common_dir: PathBuf,
+}
+
+impl Worktree {
+    /// The shared git directory read-only, and the worktree's private one
+    /// writable on top of it.
+    pub fn mounts(&self) -> Vec<String> {
+        vec![
+            format!(
+                "{}:{}:ro",
+                self.common_dir.display(),
+                self.container_common_dir.display()
+            ),
+            format!(
+                "{}:{}",
+                self.git_dir.display(),
+                self.container_git_dir.display()
+            ),
+        ]
+    }
+}
+
+/// The worktree `workspace` is, if it's one.
+pub fn detect(workspace: &Path) -> Result<Option<Worktree>> {
+    let Ok(dot_git) = fs::read_to_string(workspace.join(".git")) else {
+        return Ok(None);
+    };
+    let Some(git_dir) = dot_git.trim().strip_prefix("gitdir:") else {
+        return Ok(None);
+    };
+    let git_dir = Path::new(git_dir.trim());
+    // Submodules have `.git` files too, but no common dir
+    let Ok(common_dir) = fs::read_to_string(workspace.join(git_dir).join("commondir")) else {
+        return Ok(None);
+    };
+    let common_dir = Path::new(common_dir.trim());
+
+    let container_git_dir = normalize(&Path::new("/workspace").join(git_dir));
+    Ok(Some(Worktree {
+        git_dir: fs::canonicalize(workspace.join(git_dir))
+            .wrap_err("Couldn't find the worktree's git directory")?,
+        common_dir: fs::canonicalize(workspace.join(git_dir).join(common_dir))
+            .wrap_err("Couldn't find the main repo's git directory")?,
+        container_common_dir: normalize(&container_git_dir.join(common_dir)),
+        container_git_dir,
+    }))
+}
+
+/// `path` with `.` and `..` resolved lexically.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=t", "-c", "user.email=t@example.com"])
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn finds_the_main_repos_git_dir() {
+        let root = tempfile::tempdir().unwrap();
+        let root = fs::canonicalize(root.path()).unwrap();
+        let main = root.join("main");
+        fs::create_dir(&main).unwrap();
+        git(&main, &["init", "--quiet"]);
+        git(&main, &["commit", "--quiet", "--allow-empty", "-m", "init"]);
+        git(&main, &["worktree", "add", "--quiet", "../feature"]);
+
+        assert_eq!(detect(&main).unwrap(), None);
+        let worktree = detect(&root.join("feature")).unwrap().unwrap();
+        assert_eq!(worktree.common_dir, main.join(".git"));
+        assert_eq!(worktree.container_common_dir, main.join(".git"));
+        assert_eq!(worktree.git_dir, main.join(".git/worktrees/feature"));
+        assert_eq!(
+            worktree.mounts()[0],
+            format!("{0}:{0}:ro", main.join(".git").display())
+        );
+    }
+
+    #[test]
+    fn follows_relative_paths_from_the_container_workspace() {
+        let root = tempfile::tempdir().unwrap();
+        let git_dir = root.path().join("main/.git/worktrees/feature");
+        fs::create_dir_all(&git_dir).unwrap();
+        fs::write(git_dir.join("commondir"), "../..\n").unwrap();
+        fs::create_dir(root.path().join("feature")).unwrap();
+        fs::write(
+            root.path().join("feature/.git"),
+            "gitdir: ../main/.git/worktrees/feature\n",
+        )
+        .unwrap();
+
+        let worktree = detect(&root.path().join("feature")).unwrap().unwrap();
+        assert_eq!(
+            worktree.container_git_dir,
+            Path::new("/main/.git/worktrees/feature")
+        );
+        assert_eq!(worktree.container_common_dir, Path::new("/main/.git"));
+    }
+}